@@ -1,15 +1,29 @@
-use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use hf_hub::{api::sync::ApiBuilder, Cache, Repo, RepoType};
 use std::collections::HashMap;
-use std::fs::exists;
-use std::path::{self, PathBuf};
+use std::path::{Path, PathBuf};
 use tokenizers::{EncodeInput, Tokenizer};
 
+/// Environment variable that disables all Hugging Face Hub access when set to `1`/`true`
+pub const OFFLINE_ENV: &str = "HF_HUB_OFFLINE";
+
+/// Chat template file used by newer checkpoints instead of `tokenizer_config.json`
+const CHAT_TEMPLATE_FILE: &str = "chat_template.jinja";
+
+fn offline_from_env() -> bool {
+    std::env::var(OFFLINE_ENV)
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
 /// Defines the aditional parameters available for the `from_pretrained` function
 #[derive(Debug, Clone)]
 pub struct FromPretrainedParameters {
     pub revision: String,
     pub user_agent: HashMap<String, String>,
     pub token: Option<String>,
+    /// Only use files already in the local Hugging Face cache.
+    /// Defaults to the `HF_HUB_OFFLINE` environment variable.
+    pub offline: bool,
 }
 
 impl Default for FromPretrainedParameters {
@@ -18,12 +32,14 @@ impl Default for FromPretrainedParameters {
             revision: "main".into(),
             user_agent: HashMap::new(),
             token: None,
+            offline: offline_from_env(),
         }
     }
 }
 
 /// Downloads and cache the identified tokenizer if it exists on
-/// the Hugging Face Hub, and returns a local path to the file
+/// the Hugging Face Hub, and returns a local path to its `tokenizer_config.json`.
+/// In offline mode only the local cache is consulted.
 pub fn from_pretrained<S: AsRef<str>>(
     identifier: S,
     params: Option<FromPretrainedParameters>,
@@ -60,27 +76,49 @@ pub fn from_pretrained<S: AsRef<str>>(
         .into());
     }
 
+    let repo = Repo::with_revision(identifier.clone(), RepoType::Model, params.revision);
+    if params.offline || offline_from_env() {
+        let cache = Cache::from_env().repo(repo);
+        return match (cache.get("tokenizer_config.json"), cache.get("tokenizer.json")) {
+            (Some(config), Some(_)) => Ok(config),
+            _ => Err(format!(
+                "Tokenizer for \"{identifier}\" is not in the local cache and offline mode is enabled"
+            )
+            .into()),
+        };
+    }
+
     let mut builder = ApiBuilder::new();
     if let Some(token) = params.token {
         builder = builder.with_token(Some(token));
     }
     let api = builder.build()?;
-    let repo = Repo::with_revision(identifier, RepoType::Model, params.revision);
     let api = api.repo(repo);
+    api.get("tokenizer.json")?;
+    // Optional, only present in newer checkpoints
+    let _ = api.get(CHAT_TEMPLATE_FILE);
     Ok(api.get("tokenizer_config.json")?)
 }
 
-use minijinja::context;
+use crate::chat_template::{self, ChatTemplateOptions};
 
+/// Token written as an object, either a special token (`AddedToken` with `__type`)
+/// or an entry of `added_tokens_decoder`
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct TokenObj {
-    #[serde(rename = "__type")]
+    #[serde(rename = "__type", default)]
     pub token_type: String,
     pub content: String,
+    #[serde(default)]
     pub lstrip: bool,
+    #[serde(default)]
     pub normalized: bool,
+    #[serde(default)]
     pub rstrip: bool,
+    #[serde(default)]
     pub single_word: bool,
+    #[serde(default)]
+    pub special: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
@@ -90,19 +128,162 @@ pub enum Token {
     TokenObj(TokenObj),
 }
 
+impl Token {
+    pub fn content(&self) -> &str {
+        match self {
+            Token::String(content) => content,
+            Token::TokenObj(token_obj) => &token_obj.content,
+        }
+    }
+}
+
+/// One entry of a `chat_template` given as a list of named templates
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct NamedChatTemplate {
+    pub name: String,
+    pub template: String,
+}
+
+/// `chat_template` is either a single template or a list of named templates
+/// (`default`, `tool_use`, `rag`, ...)
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ChatTemplate {
+    Single(String),
+    Named(Vec<NamedChatTemplate>),
+}
+
+impl ChatTemplate {
+    /// Template called `name`, or the default one when `name` is `None`
+    pub fn get(&self, name: Option<&str>) -> Option<&str> {
+        match (self, name) {
+            (ChatTemplate::Single(template), None | Some("default")) => Some(template),
+            (ChatTemplate::Single(_), Some(_)) => None,
+            (ChatTemplate::Named(templates), name) => {
+                let name = name.unwrap_or("default");
+                templates
+                    .iter()
+                    .find(|t| t.name == name)
+                    .or_else(|| (templates.len() == 1 && name == "default").then(|| &templates[0]))
+                    .map(|t| t.template.as_str())
+            }
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            ChatTemplate::Single(_) => vec!["default"],
+            ChatTemplate::Named(templates) => templates.iter().map(|t| t.name.as_str()).collect(),
+        }
+    }
+}
+
+/// `model_max_length` is written as a float (`1e30`) when unset; treat anything that
+/// is not a sensible length as unknown (0)
+fn deserialize_max_length<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let value: Option<serde_json::Value> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value
+        .and_then(|v| v.as_f64())
+        .filter(|&n| n.is_finite() && n > 0.0 && n < 1e12)
+        .map(|n| n as usize)
+        .unwrap_or(0))
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
 pub struct AutoTokenizerConfig {
     pub add_bos_token: Option<bool>,
     pub add_eos_token: Option<bool>,
+    #[serde(default)]
     pub clean_up_tokenization_spaces: bool,
     pub legacy: Option<bool>,
-    pub tokenizer_class: String,
+    pub tokenizer_class: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_max_length")]
     pub model_max_length: usize,
     pub bos_token: Option<Token>,
     pub eos_token: Option<Token>,
     pub pad_token: Option<Token>,
     pub unk_token: Option<Token>,
-    pub chat_template: String,
+    pub chat_template: Option<ChatTemplate>,
+    /// Added tokens keyed by id (as a string, like in the JSON file)
+    #[serde(default)]
+    pub added_tokens_decoder: HashMap<String, TokenObj>,
+}
+
+impl AutoTokenizerConfig {
+    /// Parse a `tokenizer_config.json`, taking the chat template from a sibling
+    /// `chat_template.jinja` when the config has none
+    pub fn from_file<P: AsRef<Path>>(file: P) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.as_ref();
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read {}: {e}", file.display()))?;
+        let mut config: AutoTokenizerConfig = serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {e}", file.display()))?;
+        if config.chat_template.is_none() {
+            config.chat_template = Self::template_file(file.parent().unwrap_or(Path::new(".")))?;
+        }
+        Ok(config)
+    }
+
+    fn template_file(dir: &Path) -> Result<Option<ChatTemplate>, Box<dyn std::error::Error + Send + Sync>> {
+        let path = dir.join(CHAT_TEMPLATE_FILE);
+        if path.is_file() {
+            Ok(Some(ChatTemplate::Single(std::fs::read_to_string(path)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn special_token(token: &Option<Token>) -> &str {
+        token.as_ref().map(Token::content).unwrap_or_default()
+    }
+
+    /// Render the chat template for `messages` the way `transformers` does
+    pub fn apply_chat_template<S: serde::Serialize>(
+        &self,
+        messages: S,
+        options: &ChatTemplateOptions,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let chat_template = self
+            .chat_template
+            .as_ref()
+            .ok_or("Tokenizer has no chat template")?;
+        let template_name = options.template_name.as_deref().or_else(|| {
+            (options.tools.is_some() && chat_template.names().contains(&"tool_use")).then_some("tool_use")
+        });
+        let source = chat_template.get(template_name).ok_or_else(|| {
+            format!(
+                "Chat template \"{}\" not found, available: {}",
+                template_name.unwrap_or("default"),
+                chat_template.names().join(", ")
+            )
+        })?;
+
+        let mut env = chat_template::environment();
+        env.add_template("default", source)
+            .map_err(|e| format!("Invalid chat template: {e}"))?;
+        let tmpl = env.get_template("default")?;
+
+        let mut ctx = serde_json::Map::new();
+        ctx.insert("messages".into(), serde_json::to_value(messages)?);
+        ctx.insert("tools".into(), serde_json::to_value(&options.tools)?);
+        ctx.insert("documents".into(), serde_json::to_value(&options.documents)?);
+        ctx.insert("add_generation_prompt".into(), options.add_generation_prompt.into());
+        for (name, token) in [
+            ("bos_token", &self.bos_token),
+            ("eos_token", &self.eos_token),
+            ("pad_token", &self.pad_token),
+            ("unk_token", &self.unk_token),
+        ] {
+            ctx.insert(name.into(), Self::special_token(token).into());
+        }
+        if let Some(enable_thinking) = options.enable_thinking {
+            ctx.insert("enable_thinking".into(), enable_thinking.into());
+        }
+        ctx.extend(options.extra.clone());
+
+        tmpl.render(minijinja::Value::from_serialize(&ctx))
+            .map_err(|e| format!("Failed to render chat template: {e}").into())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -116,17 +297,104 @@ impl AutoTokenizer {
         file: P,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let file = file.as_ref();
-        let content = std::fs::read_to_string(file)?;
-        let config: AutoTokenizerConfig = serde_json::from_str(&content)?;
+        let config = AutoTokenizerConfig::from_file(file)?;
 
         // Load actual tokenizer model
-        let d = file.parent();
-        let tokenizer = Tokenizer::from_file(d.unwrap().join("tokenizer.json"))?;
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let tokenizer = Self::load_tokenizer(&dir.join("tokenizer.json"))?;
+        Ok(Self { config, tokenizer })
+    }
+
+    /// Load from a checkpoint directory; `tokenizer_config.json` is optional
+    pub fn from_dir<P: AsRef<std::path::Path>>(
+        dir: P,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let dir = dir.as_ref();
+        let config_file = dir.join("tokenizer_config.json");
+        if config_file.is_file() {
+            return AutoTokenizer::from_file(config_file);
+        }
+        let config = AutoTokenizerConfig {
+            chat_template: AutoTokenizerConfig::template_file(dir)?,
+            ..Default::default()
+        };
+        let tokenizer = Self::load_tokenizer(&dir.join("tokenizer.json"))?;
+        Ok(Self { config, tokenizer })
+    }
+
+    fn load_tokenizer(path: &Path) -> Result<Tokenizer, Box<dyn std::error::Error + Send + Sync>> {
+        Tokenizer::from_file(path)
+            .map_err(|e| format!("Failed to load {}: {e}", path.display()).into())
+    }
+
+    /// Load the tokenizer and chat template of a GGUF model. A `tokenizer_config.json`
+    /// (and `tokenizer.json`) next to the file take precedence over the GGUF metadata.
+    pub fn from_gguf<P: AsRef<std::path::Path>>(
+        file: P,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.as_ref();
+        let config_file = file.with_file_name("tokenizer_config.json");
+        let tokenizer_file = file.with_file_name("tokenizer.json");
+        if config_file.exists() && tokenizer_file.exists() {
+            return AutoTokenizer::from_file(config_file);
+        }
+
+        let content = crate::utils::gguf::read_content(file)?;
+        let tokenizer = if tokenizer_file.exists() {
+            Tokenizer::from_file(tokenizer_file)?
+        } else {
+            crate::utils::gguf::tokenizer_from_gguf(&content)?
+        };
+
+        let config = if config_file.exists() {
+            AutoTokenizerConfig::from_file(config_file)?
+        } else {
+            use crate::utils::gguf;
+            let token = |key: &str| gguf::special_token(&content, key).map(Token::String);
+            AutoTokenizerConfig {
+                add_bos_token: None,
+                add_eos_token: None,
+                clean_up_tokenization_spaces: false,
+                legacy: None,
+                tokenizer_class: Some("PreTrainedTokenizerFast".to_string()),
+                model_max_length: gguf::arch_u32(&content, "context_length").unwrap_or_default() as usize,
+                bos_token: token("tokenizer.ggml.bos_token_id"),
+                eos_token: token("tokenizer.ggml.eos_token_id"),
+                pad_token: token("tokenizer.ggml.padding_token_id"),
+                unk_token: token("tokenizer.ggml.unknown_token_id"),
+                chat_template: gguf::chat_template(&content).map(ChatTemplate::Single),
+                added_tokens_decoder: HashMap::new(),
+            }
+        };
         Ok(Self { config, tokenizer })
     }
 
     pub fn get_token(&self, token_s: &str) -> Option<u32> {
-        self.tokenizer.get_vocab(true).get(token_s).copied()
+        self.tokenizer
+            .get_vocab(true)
+            .get(token_s)
+            .copied()
+            .or_else(|| {
+                self.config
+                    .added_tokens_decoder
+                    .iter()
+                    .find(|(_, token)| token.content == token_s)
+                    .and_then(|(id, _)| id.parse().ok())
+            })
+    }
+
+    /// Content of an added token from `added_tokens_decoder`
+    pub fn added_token(&self, id: u32) -> Option<&TokenObj> {
+        self.config.added_tokens_decoder.get(&id.to_string())
+    }
+
+    /// Names of the available chat templates
+    pub fn chat_template_names(&self) -> Vec<&str> {
+        self.config
+            .chat_template
+            .as_ref()
+            .map(ChatTemplate::names)
+            .unwrap_or_default()
     }
 
     pub fn encode(
@@ -155,11 +423,14 @@ impl AutoTokenizer {
         let try_path = std::path::Path::new(identifier);
 
         if try_path.exists() {
-            if try_path.is_file() {
+            if crate::utils::gguf::is_gguf(try_path) {
+                AutoTokenizer::from_gguf(try_path)
+            } else if try_path.is_file() {
                 AutoTokenizer::from_file(identifier)
+            } else if let Some(file) = crate::utils::gguf::single_gguf_in_dir(try_path) {
+                AutoTokenizer::from_gguf(file)
             } else {
-                let tokenizer_config_file = try_path.join("tokenizer_config.json");
-                AutoTokenizer::from_file(tokenizer_config_file)
+                AutoTokenizer::from_dir(try_path)
             }
         } else {
             let tokenizer_file = from_pretrained(identifier, params)?;
@@ -172,56 +443,27 @@ impl AutoTokenizer {
         ctx: S,
         add_generation_prompt: bool,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let mut env = minijinja::Environment::new();
-        env.add_template("default", &self.config.chat_template)
-            .unwrap();
-        let tmpl = env.get_template("default").unwrap();
-        let eos = if let Some(eos) = &self.config.eos_token {
-            match eos {
-                Token::String(realeos) => realeos,
-                Token::TokenObj(token_obj) => &token_obj.content,
-            }
-        } else {
-            &String::new()
-        };
-        let bos = if let Some(bos) = &self.config.bos_token {
-            match bos {
-                Token::String(realbos) => realbos,
-                Token::TokenObj(token_obj) => &token_obj.content,
-            }
-        } else {
-            &String::new()
-        };
-        let pad = if let Some(pad) = &self.config.pad_token {
-            match pad {
-                Token::String(realpad) => realpad,
-                Token::TokenObj(token_obj) => &token_obj.content,
-            }
-        } else {
-            &String::new()
-        };
-        let unk: &String = if let Some(unk) = &self.config.unk_token {
-            match unk {
-                Token::String(realunk) => realunk,
-                Token::TokenObj(token_obj) => &token_obj.content,
-            }
-        } else {
-            &String::new()
-        };
+        self.apply_named_chat_template(ctx, add_generation_prompt, None)
+    }
 
-        match tmpl.render(context! {
-            messages=> ctx,
-            unk_token=> *unk,
-            pad_token=> *pad,
-            bos_token=> *bos,
-            eos_token=> *eos,
-            add_generation_prompt=> add_generation_prompt
-        }) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                e.to_string(),
-            ))),
-        }
+    /// Render the chat template called `template_name` (`None` for the default one)
+    pub fn apply_named_chat_template<S: serde::Serialize>(
+        &self,
+        ctx: S,
+        add_generation_prompt: bool,
+        template_name: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut options = ChatTemplateOptions::new().with_generation_prompt(add_generation_prompt);
+        options.template_name = template_name.map(str::to_string);
+        self.config.apply_chat_template(ctx, &options)
+    }
+
+    /// Render the chat template with tools, documents and extra variables
+    pub fn apply_chat_template_with_options<S: serde::Serialize>(
+        &self,
+        ctx: S,
+        options: &ChatTemplateOptions,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        self.config.apply_chat_template(ctx, options)
     }
 }
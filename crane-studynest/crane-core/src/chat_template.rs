@@ -0,0 +1,145 @@
+//! Chat template rendering compatible with Hugging Face `transformers`.
+//!
+//! Templates are rendered with the settings `apply_chat_template` uses in Python:
+//! `trim_blocks`/`lstrip_blocks`, loop controls, the `raise_exception` and
+//! `strftime_now` globals, a `tojson` filter matching `json.dumps`, and Python
+//! string/list/dict methods such as `.strip()`, `.startswith()` and `.items()`.
+
+use std::fmt::Write;
+
+use minijinja::value::{Kwargs, Value};
+use minijinja::{Environment, Error, ErrorKind};
+
+/// Inputs of `apply_chat_template` besides the messages
+#[derive(Debug, Clone, Default)]
+pub struct ChatTemplateOptions {
+    pub add_generation_prompt: bool,
+    /// Named template to use (`tool_use`, `rag`, ...). When `None` the `tool_use`
+    /// template is picked if tools are given and one exists, otherwise `default`.
+    pub template_name: Option<String>,
+    /// JSON schemas of the functions the model may call
+    pub tools: Option<Vec<serde_json::Value>>,
+    /// Documents for retrieval-augmented generation templates
+    pub documents: Option<Vec<serde_json::Value>>,
+    /// Qwen3 thinking switch; left undefined in the template when `None`
+    pub enable_thinking: Option<bool>,
+    /// Additional template variables, e.g. `date_string` for Llama 3
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ChatTemplateOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_generation_prompt(mut self, add_generation_prompt: bool) -> Self {
+        self.add_generation_prompt = add_generation_prompt;
+        self
+    }
+
+    pub fn with_template_name(mut self, name: impl Into<String>) -> Self {
+        self.template_name = Some(name.into());
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<serde_json::Value>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    pub fn with_documents(mut self, documents: Vec<serde_json::Value>) -> Self {
+        self.documents = Some(documents);
+        self
+    }
+
+    pub fn with_enable_thinking(mut self, enable_thinking: bool) -> Self {
+        self.enable_thinking = Some(enable_thinking);
+        self
+    }
+
+    pub fn with_var(mut self, name: impl Into<String>, value: serde_json::Value) -> Self {
+        self.extra.insert(name.into(), value);
+        self
+    }
+}
+
+/// Environment configured like the one `transformers` renders chat templates with
+pub fn environment<'source>() -> Environment<'source> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_filter("tojson", tojson);
+    env.add_function("raise_exception", raise_exception);
+    env.add_function("strftime_now", strftime_now);
+    env
+}
+
+fn raise_exception(message: String) -> Result<Value, Error> {
+    Err(Error::new(ErrorKind::InvalidOperation, message))
+}
+
+/// Current local time formatted with a `strftime` format string
+fn strftime_now(format: String) -> Result<String, Error> {
+    let mut out = String::new();
+    write!(out, "{}", chrono::Local::now().format(&format)).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("invalid strftime format {format:?}"),
+        )
+    })?;
+    Ok(out)
+}
+
+/// `json.dumps(value, ensure_ascii=False, indent=indent)`, keeping Python's separators
+fn tojson(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+    let indent: Option<usize> = kwargs.get("indent")?;
+    // Non-ASCII characters are always written as-is
+    let _: Option<bool> = kwargs.get("ensure_ascii")?;
+    kwargs.assert_all_used()?;
+
+    let json = serde_json::to_value(&value)
+        .map_err(|e| Error::new(ErrorKind::BadSerialization, e.to_string()))?;
+    let mut out = String::new();
+    write_json(&mut out, &json, indent, 0);
+    Ok(Value::from_safe_string(out))
+}
+
+fn write_json(out: &mut String, value: &serde_json::Value, indent: Option<usize>, depth: usize) {
+    let newline = |out: &mut String, depth: usize| {
+        if let Some(indent) = indent {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent * depth));
+        }
+    };
+    let separator = if indent.is_some() { "," } else { ", " };
+    match value {
+        serde_json::Value::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(separator);
+                }
+                newline(out, depth + 1);
+                write_json(out, item, indent, depth + 1);
+            }
+            newline(out, depth);
+            out.push(']');
+        }
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            out.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(separator);
+                }
+                newline(out, depth + 1);
+                out.push_str(&serde_json::Value::from(key.as_str()).to_string());
+                out.push_str(": ");
+                write_json(out, item, indent, depth + 1);
+            }
+            newline(out, depth);
+            out.push('}');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
@@ -0,0 +1,228 @@
+//! Streaming speech: codec frames are decoded in sliding windows while the LM is still
+//! generating, and the pieces are joined with a short cross-fade.
+//!
+//! Each chunk is decoded together with a few frames before it, so the codec's convolutions
+//! see the same context as in a full decode, and only once a few frames after it exist.
+//! What windows still disagree on at their edges is hidden by the cross-fade.
+
+use anyhow::Result;
+use std::sync::mpsc;
+
+use crate::generation::streamer::TokenStreamer;
+
+/// Codec side of streaming: reads generated tokens into frames and decodes runs of frames
+pub trait FrameCodec {
+    /// Audio samples each frame decodes to
+    fn samples_per_frame(&self) -> usize;
+
+    /// Read one generated token, returning the frame it completes, if any
+    fn push_token(&mut self, token: u32) -> Option<Vec<u32>>;
+
+    /// Decode consecutive frames to `samples_per_frame` samples each
+    fn decode(&mut self, frames: &[Vec<u32>]) -> Result<Vec<f32>>;
+}
+
+/// How audio is cut into chunks, in codec frames
+#[derive(Debug, Clone, Copy)]
+pub struct StreamingConfig {
+    /// Frames in the first chunk; small so that playback starts early
+    pub first_chunk_frames: usize,
+    /// Frames in each later chunk
+    pub chunk_frames: usize,
+    /// Frames before a chunk decoded along with it
+    pub left_context: usize,
+    /// Frames after a chunk waited for before decoding it
+    pub lookahead: usize,
+    /// Samples cross-faded at each chunk boundary, at most the left context and the
+    /// first chunk
+    pub crossfade_samples: usize,
+}
+
+/// PCM samples in `[-1, 1]`, in order
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    pub index: usize,
+    pub samples: Vec<f32>,
+    /// Nothing follows; may carry no samples
+    pub last: bool,
+}
+
+type Sink<'a> = Box<dyn FnMut(AudioChunk) -> Result<()> + 'a>;
+
+/// Overlap-add of consecutive pieces: the end of each piece is held back and faded into
+/// the start of the next one
+#[derive(Debug, Clone, Default)]
+pub struct CrossFader {
+    overlap: usize,
+    tail: Vec<f32>,
+}
+
+impl CrossFader {
+    pub fn new(overlap: usize) -> Self {
+        Self {
+            overlap,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Samples held back from the previous piece; the next piece must start that much
+    /// before where the previous one ended
+    pub fn held(&self) -> usize {
+        self.tail.len()
+    }
+
+    /// Add the next piece and return what is final
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        let n = self.tail.len().min(samples.len());
+        let mut out: Vec<f32> = (0..n)
+            .map(|i| {
+                let w = (i + 1) as f32 / (n + 1) as f32;
+                self.tail[i] * (1.0 - w) + samples[i] * w
+            })
+            .collect();
+        // a piece shorter than the held tail leaves the rest of the tail as it was
+        out.extend_from_slice(&self.tail[n..]);
+        let rest = &samples[n..];
+        let keep = self.overlap.min(rest.len());
+        out.extend_from_slice(&rest[..rest.len() - keep]);
+        self.tail = rest[rest.len() - keep..].to_vec();
+        out
+    }
+
+    /// The held tail, once no piece follows
+    pub fn finish(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.tail)
+    }
+}
+
+/// Token streamer that decodes speech while it is generated and hands out PCM chunks
+pub struct AudioStreamer<'a, C: FrameCodec> {
+    codec: C,
+    config: StreamingConfig,
+    frames: Vec<Vec<u32>>,
+    /// Frames whose audio has been handed out
+    emitted: usize,
+    fader: CrossFader,
+    sink: Sink<'a>,
+    chunks: usize,
+    finished: bool,
+}
+
+impl<'a, C: FrameCodec> AudioStreamer<'a, C> {
+    /// Call `sink` with each chunk
+    pub fn new(
+        codec: C,
+        config: StreamingConfig,
+        sink: impl FnMut(AudioChunk) -> Result<()> + 'a,
+    ) -> Self {
+        let mut config = config;
+        config.first_chunk_frames = config.first_chunk_frames.max(1);
+        config.chunk_frames = config.chunk_frames.max(1);
+        // the overlap is taken from the left context of the next window
+        let context = config.left_context.min(config.first_chunk_frames);
+        config.crossfade_samples = config
+            .crossfade_samples
+            .min(context * codec.samples_per_frame());
+        Self {
+            fader: CrossFader::new(config.crossfade_samples),
+            codec,
+            config,
+            frames: Vec::new(),
+            emitted: 0,
+            sink: Box::new(sink),
+            chunks: 0,
+            finished: false,
+        }
+    }
+
+    /// Send each chunk through a channel instead
+    pub fn with_channel(codec: C, config: StreamingConfig) -> (Self, mpsc::Receiver<AudioChunk>) {
+        let (sender, receiver) = mpsc::channel();
+        let streamer = Self::new(codec, config, move |chunk| {
+            sender
+                .send(chunk)
+                .map_err(|e| anyhow::anyhow!("Failed to send audio through channel: {}", e))
+        });
+        (streamer, receiver)
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Frames read so far
+    pub fn frames(&self) -> &[Vec<u32>] {
+        &self.frames
+    }
+
+    /// Chunks handed out so far
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    fn send(&mut self, samples: Vec<f32>, last: bool) -> Result<()> {
+        if samples.is_empty() && !last {
+            return Ok(());
+        }
+        let chunk = AudioChunk {
+            index: self.chunks,
+            samples,
+            last,
+        };
+        self.chunks += 1;
+        (self.sink)(chunk)
+    }
+
+    /// Decode the chunks that have enough frames after them, or everything when `flush`
+    fn decode_ready(&mut self, flush: bool) -> Result<()> {
+        let spf = self.codec.samples_per_frame();
+        loop {
+            let available = self.frames.len();
+            let size = if self.emitted == 0 {
+                self.config.first_chunk_frames
+            } else {
+                self.config.chunk_frames
+            };
+            let end = if flush {
+                available
+            } else if available >= self.emitted + size + self.config.lookahead {
+                self.emitted + size
+            } else {
+                return Ok(());
+            };
+            if end <= self.emitted {
+                return Ok(());
+            }
+
+            let start = self.emitted.saturating_sub(self.config.left_context);
+            let window_end = (end + self.config.lookahead).min(available);
+            let audio = self.codec.decode(&self.frames[start..window_end])?;
+            let from = ((self.emitted - start) * spf).saturating_sub(self.fader.held());
+            let to = ((end - start) * spf).min(audio.len());
+            let samples = self.fader.push(&audio[from.min(to)..to]);
+            self.emitted = end;
+            self.send(samples, false)?;
+        }
+    }
+}
+
+impl<C: FrameCodec> TokenStreamer for AudioStreamer<'_, C> {
+    fn append(&mut self, token_id: u32) -> Result<()> {
+        if let Some(frame) = self.codec.push_token(token_id) {
+            self.frames.push(frame);
+            self.decode_ready(false)?;
+        }
+        Ok(())
+    }
+
+    /// Decode what is left; later calls do nothing
+    fn finalize(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.decode_ready(true)?;
+        let tail = self.fader.finish();
+        self.send(tail, true)
+    }
+}
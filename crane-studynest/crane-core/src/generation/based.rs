@@ -13,6 +13,9 @@ pub trait ModelForCausalLM {
         // a default implementation
         let mut output = Vec::with_capacity(config.max_new_tokens);
         for _ in 0..config.max_new_tokens {
+            if config.interruption().is_some() {
+                break;
+            }
             let next_token = self.generate_next_token(input_ids)?;
             if let Some(streamer) = streamer.as_deref_mut() {
                 streamer.append(next_token)?;
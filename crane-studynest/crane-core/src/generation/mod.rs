@@ -1,6 +1,43 @@
+pub mod audio_streamer;
 pub mod based;
 pub mod streamer;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Shared flag used to stop a generation between decoding steps
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Why a generation ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FinishReason {
+    /// The model produced an end-of-sequence token
+    Stop,
+    /// `max_new_tokens` was reached
+    Length,
+    /// The cancellation token was triggered
+    Cancelled,
+    /// The deadline passed
+    Timeout,
+}
+
 #[derive(Clone, Debug)]
 pub struct GenerationConfig {
     pub max_new_tokens: usize,
@@ -12,6 +49,8 @@ pub struct GenerationConfig {
     pub pad_token_id: Option<u32>,
     pub eos_token_id: Option<u32>,
     pub report_speed: bool,
+    pub cancellation: Option<CancellationToken>,
+    pub deadline: Option<Instant>,
 }
 
 impl Default for GenerationConfig {
@@ -26,6 +65,8 @@ impl Default for GenerationConfig {
             pad_token_id: None,
             eos_token_id: None,
             report_speed: false,
+            cancellation: None,
+            deadline: None,
         }
     }
 }
@@ -37,4 +78,20 @@ impl GenerationConfig {
             ..Default::default()
         }
     }
+
+    /// Returns why generation must stop early, if the request was cancelled or timed out.
+    /// Checked between decoding steps.
+    pub fn interruption(&self) -> Option<FinishReason> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Some(FinishReason::Cancelled);
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(FinishReason::Timeout);
+        }
+        None
+    }
 }
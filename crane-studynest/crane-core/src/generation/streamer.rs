@@ -24,7 +24,6 @@ impl TokenStreamer for TextStreamer {
     }
 
     fn finalize(&mut self) -> Result<()> {
-        println!();
         Ok(())
     }
 }
@@ -5,3 +5,5 @@ pub mod utils;
 pub mod autotokenizer;
 pub mod bins;
 pub mod chat;
+pub mod chat_template;
+pub mod quantize;
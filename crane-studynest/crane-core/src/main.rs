@@ -0,0 +1,56 @@
+//! Command line tools for crane-core models
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use crane_core::quantize::{quantize_checkpoint, QuantizationType, QuantizeOptions};
+
+#[derive(Parser)]
+#[command(name = "crane-core", about = "Crane model tools")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a safetensors checkpoint into a quantized GGUF checkpoint
+    Quantize {
+        /// Checkpoint directory (model.safetensors or a sharded index)
+        model_path: String,
+        /// Output directory for the GGUF file, tokenizer and config
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(short, long, value_enum, default_value = "q4_k_m")]
+        quantization: QuantizationType,
+        /// Text file used to compare perplexity before and after quantization
+        #[arg(long)]
+        sample: Option<PathBuf>,
+        /// Maximum number of sample tokens to evaluate
+        #[arg(long, default_value_t = 256)]
+        max_eval_tokens: usize,
+    },
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Quantize {
+            model_path,
+            output,
+            quantization,
+            sample,
+            max_eval_tokens,
+        } => {
+            let sample_text = sample.map(std::fs::read_to_string).transpose()?;
+            let options = QuantizeOptions {
+                quantization,
+                sample_text,
+                max_eval_tokens,
+            };
+            let report = quantize_checkpoint(&model_path, &output, &options)?;
+            println!("{report}");
+        }
+    }
+    Ok(())
+}
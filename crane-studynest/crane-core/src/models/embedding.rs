@@ -0,0 +1,241 @@
+//! Text embedding models.
+//!
+//! BERT-style encoders (BGE, MiniLM, E5, ...) run through `candle_transformers`' BERT
+//! with CLS or mean pooling. Decoder embedders such as Qwen3-Embedding run through
+//! `quantized_qwen` (safetensors or GGUF) with last-token pooling. Sequences of a batch
+//! are padded on the right, so the causal mask of decoders already hides the padding.
+
+use std::path::Path;
+
+use anyhow::{Context, Error as E, Result};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use tokenizers::{Tokenizer, TruncationParams};
+
+use crate::models::quantized_qwen::{HParams, ModelWeights};
+use crate::utils::{gguf, utils};
+
+/// How token states are reduced to one vector per text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    /// First token (`[CLS]`), used by BGE
+    Cls,
+    /// Average of all tokens, the sentence-transformers default
+    Mean,
+    /// Last token, used by decoder embedders such as Qwen3-Embedding
+    LastToken,
+}
+
+impl Pooling {
+    /// Pooling declared in sentence-transformers' `1_Pooling/config.json`
+    pub fn from_sentence_transformers(model_dir: &Path) -> Option<Self> {
+        let data = std::fs::read(model_dir.join("1_Pooling").join("config.json")).ok()?;
+        let config: serde_json::Value = serde_json::from_slice(&data).ok()?;
+        let enabled = |key: &str| config[key].as_bool().unwrap_or(false);
+        if enabled("pooling_mode_lasttoken") {
+            Some(Pooling::LastToken)
+        } else if enabled("pooling_mode_cls_token") {
+            Some(Pooling::Cls)
+        } else if enabled("pooling_mode_mean_tokens") {
+            Some(Pooling::Mean)
+        } else {
+            None
+        }
+    }
+}
+
+enum Encoder {
+    Bert(BertModel),
+    Decoder(ModelWeights),
+}
+
+pub struct EmbeddingModel {
+    pub tokenizer: Tokenizer,
+    pub device: Device,
+    pub pooling: Pooling,
+    /// L2-normalize the embeddings, so dot products are cosine similarities
+    pub normalize: bool,
+    /// Longer inputs are truncated to this many tokens
+    pub max_length: usize,
+    /// Size of the embedding vectors
+    pub dimension: usize,
+    pad_id: u32,
+    encoder: Encoder,
+}
+
+impl EmbeddingModel {
+    /// Load a BERT-style or Qwen2/Qwen3 embedding checkpoint directory, or a `.gguf`
+    /// file of a decoder embedder. Weights are run in F32 (F16 matmuls for decoders).
+    pub fn new(model_path: &str, device: &Device) -> Result<Self> {
+        let path = Path::new(model_path);
+        if gguf::is_gguf(path) {
+            return Self::from_gguf(path, device);
+        }
+
+        let config: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.join("config.json"))?)?;
+        let tokenizer = Tokenizer::from_file(path.join("tokenizer.json")).map_err(E::msg)?;
+        let files = utils::get_safetensors_files(model_path)?;
+        let declared_pooling = Pooling::from_sentence_transformers(path);
+
+        let (encoder, pooling, dimension, max_length) = match config["model_type"].as_str() {
+            Some("bert") => {
+                let bert_config: BertConfig = serde_json::from_value(config.clone())?;
+                // The BERT attention mask is built in F32
+                let vb = unsafe { VarBuilder::from_mmaped_safetensors(&files, DType::F32, device)? };
+                let model = BertModel::load(vb, &bert_config)?;
+                (
+                    Encoder::Bert(model),
+                    declared_pooling.unwrap_or(Pooling::Mean),
+                    bert_config.hidden_size,
+                    bert_config.max_position_embeddings,
+                )
+            }
+            _ => {
+                let hparams = HParams::from_config(&config)
+                    .context("expected a BERT or Qwen2/Qwen3 embedding model")?;
+                let st = unsafe { MmapedSafetensors::multi(&files)? };
+                let (dimension, max_length) = (hparams.embedding_length, hparams.context_length);
+                let model = ModelWeights::from_safetensors(&st, hparams, device)?;
+                (
+                    Encoder::Decoder(model),
+                    declared_pooling.unwrap_or(Pooling::LastToken),
+                    dimension,
+                    max_length,
+                )
+            }
+        };
+
+        Ok(Self::with_encoder(encoder, tokenizer, pooling, dimension, max_length, device))
+    }
+
+    fn from_gguf(path: &Path, device: &Device) -> Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let content = candle_core::quantized::gguf_file::Content::read(&mut file)
+            .map_err(|e| e.with_path(path))?;
+        let tokenizer_file = path.with_file_name("tokenizer.json");
+        let tokenizer = if tokenizer_file.exists() {
+            Tokenizer::from_file(tokenizer_file).map_err(E::msg)?
+        } else {
+            gguf::tokenizer_from_gguf(&content)?
+        };
+        let hparams = HParams::from_gguf(&content)?;
+        let model = ModelWeights::from_gguf(content, &mut file, device)?;
+        Ok(Self::with_encoder(
+            Encoder::Decoder(model),
+            tokenizer,
+            Pooling::LastToken,
+            hparams.embedding_length,
+            hparams.context_length,
+            device,
+        ))
+    }
+
+    fn with_encoder(
+        encoder: Encoder,
+        tokenizer: Tokenizer,
+        pooling: Pooling,
+        dimension: usize,
+        max_length: usize,
+        device: &Device,
+    ) -> Self {
+        let pad_id = tokenizer
+            .get_padding()
+            .map(|p| p.pad_id)
+            .or_else(|| tokenizer.token_to_id("[PAD]"))
+            .or_else(|| tokenizer.token_to_id("<|endoftext|>"))
+            .unwrap_or(0);
+        Self {
+            tokenizer,
+            device: device.clone(),
+            pooling,
+            normalize: true,
+            max_length,
+            dimension,
+            pad_id,
+            encoder,
+        }
+    }
+
+    /// Embed `texts`, running at most `batch_size` of them through the model at once
+    pub fn embed(&mut self, texts: &[&str], batch_size: usize) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(batch_size.max(1)) {
+            embeddings.extend(self.embed_batch(batch)?.to_vec2::<f32>()?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Embeddings of one batch, shaped `(texts.len(), dimension)`
+    pub fn embed_batch(&mut self, texts: &[&str]) -> Result<Tensor> {
+        if texts.is_empty() {
+            return Ok(Tensor::zeros((0, self.dimension), DType::F32, &self.device)?);
+        }
+        // Padding is done below, on the right
+        self.tokenizer.with_padding(None);
+        self.tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: self.max_length,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(E::msg)?;
+        let lengths: Vec<usize> = encodings.iter().map(|e| e.get_ids().len().max(1)).collect();
+        let seq_len = lengths.iter().copied().max().unwrap_or(1);
+
+        let mut ids = Vec::with_capacity(texts.len() * seq_len);
+        let mut mask = Vec::with_capacity(texts.len() * seq_len);
+        for (encoding, &len) in encodings.iter().zip(&lengths) {
+            let tokens = encoding.get_ids();
+            ids.extend(tokens);
+            ids.extend(std::iter::repeat_n(self.pad_id, seq_len - tokens.len()));
+            mask.extend(std::iter::repeat_n(1u32, len));
+            mask.extend(std::iter::repeat_n(0u32, seq_len - len));
+        }
+        let ids = Tensor::from_vec(ids, (texts.len(), seq_len), &self.device)?;
+        let mask = Tensor::from_vec(mask, (texts.len(), seq_len), &self.device)?;
+
+        let hidden = match &mut self.encoder {
+            Encoder::Bert(model) => model.forward(&ids, &ids.zeros_like()?, Some(&mask))?,
+            Encoder::Decoder(model) => {
+                model.clear_kv_cache();
+                let hidden = model.hidden_states(&ids, 0);
+                model.clear_kv_cache();
+                hidden?
+            }
+        }
+        .to_dtype(DType::F32)?;
+
+        let pooled = match self.pooling {
+            Pooling::Cls => hidden.i((.., 0))?,
+            Pooling::Mean => {
+                let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+                hidden
+                    .broadcast_mul(&mask)?
+                    .sum(1)?
+                    .broadcast_div(&mask.sum(1)?)?
+            }
+            Pooling::LastToken => {
+                let rows = lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &len)| hidden.i((i, len - 1)))
+                    .collect::<candle_core::Result<Vec<_>>>()?;
+                Tensor::stack(&rows, 0)?
+            }
+        };
+
+        if self.normalize {
+            let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?.clamp(1e-12, f32::MAX)?;
+            Ok(pooled.broadcast_div(&norm)?)
+        } else {
+            Ok(pooled)
+        }
+    }
+}
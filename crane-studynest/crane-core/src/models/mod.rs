@@ -1,9 +1,13 @@
+pub mod embedding;
+pub mod reranker;
 pub mod qwen25;
 pub mod siglip2;
 #[cfg(feature = "onnx")]
 pub mod snac_onnx;
 pub mod orpheus;
+pub mod sparktts;
 pub mod qwen3;
+pub mod quantized_qwen;
 #[cfg(feature = "onnx")]
 pub mod moonshine_asr;
 #[cfg(feature = "onnx")]
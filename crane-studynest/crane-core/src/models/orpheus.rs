@@ -1,3 +1,393 @@
 // model for Orpheus-TTS model
-// which using Qwen2.5 as LLM, SNAC for decoding wavs
+// which using Llama-3.2 as LLM (speaking in SNAC codes), SNAC for decoding wavs
+// https://huggingface.co/canopylabs/orpheus-3b-0.1-ft
 
+use anyhow::{Error as E, Result};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::llama::{Cache, Config, Llama, LlamaConfig};
+use tokenizers::Tokenizer;
+
+#[cfg(feature = "onnx")]
+use crate::generation::audio_streamer::{AudioChunk, AudioStreamer, FrameCodec, StreamingConfig};
+use crate::generation::based::ModelForCausalLM;
+use crate::generation::streamer::TokenStreamer;
+use crate::generation::GenerationConfig;
+use crate::utils::utils;
+
+#[cfg(feature = "onnx")]
+use crate::models::snac_onnx::SNAC24DecoderONNX;
+
+/// Sample rate of the SNAC 24 kHz decoder
+pub const SAMPLE_RATE: u32 = 24000;
+
+/// Speakers of the finetuned model, in order of conversational realism
+pub const VOICES: [&str; 8] = ["tara", "leah", "jess", "leo", "dan", "mia", "zac", "zoe"];
+pub const DEFAULT_VOICE: &str = "tara";
+
+// special tokens, appended after the 128256 tokens of the Llama-3 vocabulary
+pub const END_OF_TEXT: u32 = 128009;
+pub const START_OF_SPEECH: u32 = 128257;
+pub const END_OF_SPEECH: u32 = 128258;
+pub const START_OF_HUMAN: u32 = 128259;
+pub const END_OF_HUMAN: u32 = 128260;
+pub const START_OF_AI: u32 = 128261;
+pub const END_OF_AI: u32 = 128262;
+/// `<custom_token_10>`, the first audio code
+pub const AUDIO_TOKENS_START: u32 = 128266;
+
+/// Samples one SNAC frame decodes to: four codes of the finest layer, 512 samples each
+pub const FRAME_SAMPLES: usize = 2048;
+
+/// Codebook size of each SNAC layer
+const CODEBOOK_SIZE: u32 = 4096;
+/// One SNAC frame is written as 7 tokens: 1 code of layer 0, 2 of layer 1 and 4 of layer 2
+const FRAME_TOKENS: usize = 7;
+
+pub struct OrpheusLM {
+    pub tokenizer: Tokenizer,
+    pub device: Device,
+    model: Llama,
+    config: Config,
+    dtype: DType,
+    cache: Cache,
+}
+
+impl OrpheusLM {
+    pub fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
+        let tokenizer_path = std::path::Path::new(model_path).join("tokenizer.json");
+        if !tokenizer_path.exists() {
+            anyhow::bail!("Tokenizer not found at {}", tokenizer_path.display());
+        }
+        let tokenizer = Tokenizer::from_file(&tokenizer_path).map_err(E::msg)?;
+
+        let config_file = std::path::Path::new(model_path).join("config.json");
+        let config: LlamaConfig = serde_json::from_slice(&std::fs::read(config_file)?)?;
+        let config = config.into_config(false);
+
+        let filenames = utils::get_safetensors_files(model_path)?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, *dtype, device) }?;
+        let model = Llama::load(vb, &config)?;
+        let cache = Cache::new(true, *dtype, &config, device)?;
+
+        Ok(Self {
+            tokenizer,
+            device: device.clone(),
+            model,
+            config,
+            dtype: *dtype,
+            cache,
+        })
+    }
+
+    pub fn clear_kv_cache(&mut self) -> Result<()> {
+        self.cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+        Ok(())
+    }
+
+    /// Wrap `voice: text` in the human/AI turn markers, ending right where the model starts speaking
+    pub fn prepare_inputs(&self, text: &str, voice: Option<&str>) -> Result<Vec<u32>> {
+        let prompt = match voice {
+            Some(voice) => format!("{voice}: {text}"),
+            None => text.to_string(),
+        };
+        let encoded = self.tokenizer.encode(prompt, true).map_err(E::msg)?;
+        let mut input_ids = vec![START_OF_HUMAN];
+        input_ids.extend_from_slice(encoded.get_ids());
+        input_ids.extend_from_slice(&[END_OF_TEXT, END_OF_HUMAN, START_OF_AI, START_OF_SPEECH]);
+        Ok(input_ids)
+    }
+
+    /// Sampling settings the model was tuned for; greedy decoding loops on the same frame
+    pub fn generation_config(max_new_tokens: usize) -> GenerationConfig {
+        GenerationConfig {
+            max_new_tokens,
+            temperature: Some(0.6),
+            top_p: Some(0.8),
+            repetition_penalty: 1.1,
+            repeat_last_n: 64,
+            do_sample: true,
+            eos_token_id: Some(END_OF_SPEECH),
+            ..Default::default()
+        }
+    }
+}
+
+impl ModelForCausalLM for OrpheusLM {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn generate(
+        &mut self,
+        input_ids: &[u32],
+        config: &GenerationConfig,
+        mut streamer: Option<&mut dyn TokenStreamer>,
+    ) -> Result<Vec<u32>> {
+        self.clear_kv_cache()?;
+        let mut logits_processor = LogitsProcessor::new(1024, config.temperature, config.top_p);
+        let eos_token = config.eos_token_id.unwrap_or(END_OF_SPEECH);
+        // rotary tables are only precomputed up to the model's context
+        let max_new_tokens = config.max_new_tokens.min(
+            self.config
+                .max_position_embeddings
+                .saturating_sub(input_ids.len()),
+        );
+        let mut tokens = input_ids.to_vec();
+        let mut start_pos = 0;
+        let start_gen = std::time::Instant::now();
+        for _ in 0..max_new_tokens {
+            if config.interruption().is_some() {
+                break;
+            }
+            let ctxt = &tokens[start_pos..];
+            let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
+            let logits = self.model.forward(&input, start_pos, &mut self.cache)?;
+            start_pos = tokens.len();
+            let logits = logits.squeeze(0)?;
+            let logits = if config.repetition_penalty == 1. {
+                logits
+            } else {
+                let start_at = tokens.len().saturating_sub(config.repeat_last_n);
+                candle_transformers::utils::apply_repeat_penalty(
+                    &logits,
+                    config.repetition_penalty,
+                    &tokens[start_at..],
+                )?
+            };
+            let next_token = logits_processor.sample(&logits)?;
+            tokens.push(next_token);
+            if next_token == eos_token || next_token == END_OF_AI {
+                break;
+            }
+            if let Some(ref mut s) = streamer {
+                s.append(next_token)?;
+            }
+        }
+        if let Some(ref mut s) = streamer {
+            s.finalize()?;
+        }
+
+        if config.report_speed {
+            let generated_tokens = tokens.len() - input_ids.len();
+            eprintln!(
+                "\n{generated_tokens} tokens generated ({:.2} token/s)\n",
+                generated_tokens as f64 / start_gen.elapsed().as_secs_f64(),
+            );
+        }
+        Ok(tokens)
+    }
+}
+
+/// Split generated tokens into the three SNAC code layers.
+///
+/// Only tokens after the last start-of-speech marker are read. Frames that are cut short
+/// or hold a code outside the layer's codebook are dropped, as the reference decoder does.
+pub fn parse_audio_tokens(tokens: &[u32]) -> [Vec<u32>; 3] {
+    let start = tokens
+        .iter()
+        .rposition(|&t| t == START_OF_SPEECH)
+        .map_or(0, |i| i + 1);
+    let audio: Vec<u32> = tokens[start..]
+        .iter()
+        .take_while(|&&t| t != END_OF_SPEECH)
+        .filter(|&&t| t >= AUDIO_TOKENS_START)
+        .map(|&t| t - AUDIO_TOKENS_START)
+        .collect();
+
+    let mut layers: [Vec<u32>; 3] = Default::default();
+    for frame in audio.chunks_exact(FRAME_TOKENS) {
+        // each position in the frame has its own 4096-wide slice of the custom tokens
+        let codes: Vec<u32> = frame
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| t.wrapping_sub(i as u32 * CODEBOOK_SIZE))
+            .collect();
+        if codes.iter().any(|&c| c >= CODEBOOK_SIZE) {
+            continue;
+        }
+        layers[0].push(codes[0]);
+        layers[1].extend_from_slice(&[codes[1], codes[4]]);
+        layers[2].extend_from_slice(&[codes[2], codes[3], codes[5], codes[6]]);
+    }
+    layers
+}
+
+/// Code layers as the `[1, n]`, `[1, 2n]` and `[1, 4n]` int64 tensors the decoder takes
+pub fn codes_to_tensors(codes: &[Vec<u32>; 3], device: &Device) -> Result<[Tensor; 3]> {
+    let tensor = |layer: &Vec<u32>| -> Result<Tensor> {
+        let values: Vec<i64> = layer.iter().map(|&c| c as i64).collect();
+        Ok(Tensor::from_vec(values, (1, layer.len()), device)?)
+    };
+    Ok([tensor(&codes[0])?, tensor(&codes[1])?, tensor(&codes[2])?])
+}
+
+/// Reads Orpheus tokens into SNAC frames for `AudioStreamer`, with the same rules as
+/// `parse_audio_tokens`
+#[cfg(feature = "onnx")]
+pub struct SnacFrameCodec<'a> {
+    snac: &'a SNAC24DecoderONNX,
+    pending: Vec<u32>,
+    done: bool,
+}
+
+#[cfg(feature = "onnx")]
+impl<'a> SnacFrameCodec<'a> {
+    pub fn new(snac: &'a SNAC24DecoderONNX) -> Self {
+        Self {
+            snac,
+            pending: Vec::with_capacity(FRAME_TOKENS),
+            done: false,
+        }
+    }
+
+    /// Chunking that starts playback after two frames (~170 ms of audio)
+    pub fn streaming_config() -> StreamingConfig {
+        StreamingConfig {
+            first_chunk_frames: 2,
+            chunk_frames: 4,
+            left_context: 2,
+            lookahead: 1,
+            crossfade_samples: 256,
+        }
+    }
+}
+
+#[cfg(feature = "onnx")]
+impl FrameCodec for SnacFrameCodec<'_> {
+    fn samples_per_frame(&self) -> usize {
+        FRAME_SAMPLES
+    }
+
+    fn push_token(&mut self, token: u32) -> Option<Vec<u32>> {
+        match token {
+            START_OF_SPEECH => {
+                self.pending.clear();
+                self.done = false;
+                return None;
+            }
+            END_OF_SPEECH => self.done = true,
+            _ => {}
+        }
+        if self.done || token < AUDIO_TOKENS_START {
+            return None;
+        }
+        let position = self.pending.len() as u32;
+        self.pending
+            .push((token - AUDIO_TOKENS_START).wrapping_sub(position * CODEBOOK_SIZE));
+        if self.pending.len() < FRAME_TOKENS {
+            return None;
+        }
+        let frame = std::mem::take(&mut self.pending);
+        frame.iter().all(|&c| c < CODEBOOK_SIZE).then_some(frame)
+    }
+
+    fn decode(&mut self, frames: &[Vec<u32>]) -> Result<Vec<f32>> {
+        let mut codes: [Vec<u32>; 3] = Default::default();
+        for f in frames {
+            codes[0].push(f[0]);
+            codes[1].extend_from_slice(&[f[1], f[4]]);
+            codes[2].extend_from_slice(&[f[2], f[3], f[5], f[6]]);
+        }
+        // the onnx graph is evaluated on cpu
+        let [c1, c2, c3] = codes_to_tensors(&codes, &Device::Cpu)?;
+        let audio = self.snac.forward(&c1, &c2, &c3)?;
+        Ok(audio.flatten_all()?.to_dtype(DType::F32)?.to_vec1()?)
+    }
+}
+
+/// Orpheus LM and SNAC decoder: text in, 24 kHz audio out
+#[cfg(feature = "onnx")]
+pub struct OrpheusTTS {
+    pub lm: OrpheusLM,
+    pub snac: SNAC24DecoderONNX,
+}
+
+#[cfg(feature = "onnx")]
+impl OrpheusTTS {
+    const SNAC_FILE: &str = "snac_24khz_sim.onnx";
+
+    /// Load the LM from `model_path`. Without `snac_path`, the decoder is looked for in the
+    /// model directory, next to it, and then at the decoder's default location.
+    pub fn new(
+        model_path: &str,
+        snac_path: Option<&str>,
+        device: &Device,
+        dtype: &DType,
+    ) -> Result<Self> {
+        let lm = OrpheusLM::from_pretrained(model_path, device, dtype)?;
+        let model_dir = std::path::Path::new(model_path);
+        let found = [
+            Some(model_dir.join(Self::SNAC_FILE)),
+            model_dir.parent().map(|p| p.join(Self::SNAC_FILE)),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|p| p.exists());
+        let snac_path = snac_path
+            .map(str::to_string)
+            .or_else(|| found.map(|p| p.to_string_lossy().into_owned()));
+        let snac = SNAC24DecoderONNX::new(snac_path.as_deref(), Some(device))?;
+        Ok(Self { lm, snac })
+    }
+
+    /// Generate the SNAC codes for one utterance
+    pub fn generate_codes(
+        &mut self,
+        text: &str,
+        voice: Option<&str>,
+        config: &GenerationConfig,
+    ) -> Result<[Vec<u32>; 3]> {
+        let input_ids = self.lm.prepare_inputs(text, voice)?;
+        let tokens = self.lm.generate(&input_ids, config, None)?;
+        Ok(parse_audio_tokens(&tokens[input_ids.len()..]))
+    }
+
+    /// Decode SNAC codes to a `[1, 1, samples]` waveform
+    pub fn decode(&self, codes: &[Vec<u32>; 3]) -> Result<Tensor> {
+        if codes[0].is_empty() {
+            anyhow::bail!("the model produced no audio frames");
+        }
+        // the onnx graph is evaluated on cpu
+        let [c1, c2, c3] = codes_to_tensors(codes, &Device::Cpu)?;
+        self.snac.forward(&c1, &c2, &c3)
+    }
+
+    /// Speak one utterance; Orpheus keeps a steady voice for up to ~15 seconds per call
+    pub fn synthesize(
+        &mut self,
+        text: &str,
+        voice: Option<&str>,
+        config: &GenerationConfig,
+    ) -> Result<Tensor> {
+        let codes = self.generate_codes(text, voice, config)?;
+        self.decode(&codes)
+    }
+
+    /// Speak one utterance, handing `sink` 24 kHz chunks while the LM is still generating
+    pub fn synthesize_streaming(
+        &mut self,
+        text: &str,
+        voice: Option<&str>,
+        config: &GenerationConfig,
+        streaming: StreamingConfig,
+        sink: impl FnMut(AudioChunk) -> Result<()>,
+    ) -> Result<()> {
+        let input_ids = self.lm.prepare_inputs(text, voice)?;
+        let mut streamer = AudioStreamer::new(SnacFrameCodec::new(&self.snac), streaming, sink);
+        self.lm.generate(&input_ids, config, Some(&mut streamer))?;
+        streamer.finalize()
+    }
+
+    pub fn text_to_speech(
+        &mut self,
+        text: &str,
+        voice: Option<&str>,
+        filename: &str,
+    ) -> Result<String> {
+        let audio = self.synthesize(text, voice, &OrpheusLM::generation_config(1200))?;
+        self.snac
+            .save_audio_data_to_file(&audio, filename, Some(SAMPLE_RATE))
+    }
+}
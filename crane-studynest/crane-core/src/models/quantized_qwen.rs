@@ -0,0 +1,477 @@
+//! Quantized Qwen2/Qwen2.5 and Qwen3 loaded from GGUF files.
+//!
+//! Follows `candle_transformers::models::quantized_qwen2` but also handles the
+//! Qwen3 layout (per-head q/k norms, no qkv bias, explicit head dim), can be cloned
+//! to share weights between kv caches, and supports extending a cached prompt by
+//! several tokens at once. The same graph can run unquantized checkpoint weights,
+//! which `crate::quantize` uses to compare perplexity before and after conversion
+//! and `embedding` uses for Qwen3-Embedding checkpoints.
+
+use std::collections::HashMap;
+
+use candle_core::quantized::{gguf_file, QMatMul, QTensor};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor};
+use candle_nn::Embedding;
+use candle_nn::RmsNorm;
+use candle_transformers::utils::repeat_kv;
+
+#[derive(Debug, Clone)]
+struct Mlp {
+    gate: QMatMul,
+    down: QMatMul,
+    up: QMatMul,
+}
+
+impl Module for Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let gate = self.gate.forward(xs)?;
+        let up = self.up.forward(xs)?;
+        self.down.forward(&(candle_nn::ops::silu(&gate)? * up)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LayerWeights {
+    attention_wq: QMatMul,
+    attention_wk: QMatMul,
+    attention_wv: QMatMul,
+    attention_wo: QMatMul,
+    // Qwen2 only
+    attention_bias: Option<(Tensor, Tensor, Tensor)>,
+    // Qwen3 only
+    q_norm: Option<RmsNorm>,
+    k_norm: Option<RmsNorm>,
+    attention_norm: RmsNorm,
+    mlp: Mlp,
+    ffn_norm: RmsNorm,
+    n_head: usize,
+    n_kv_head: usize,
+    head_dim: usize,
+    cos: Tensor,
+    sin: Tensor,
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl LayerWeights {
+    fn apply_rotary_emb(&self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let (_b_sz, _n_head, seq_len, _head_dim) = x.dims4()?;
+        let cos = self.cos.narrow(0, index_pos, seq_len)?;
+        let sin = self.sin.narrow(0, index_pos, seq_len)?;
+        candle_nn::rotary_emb::rope(&x.contiguous()?, &cos, &sin)
+    }
+
+    fn forward_attn(&mut self, x: &Tensor, mask: Option<&Tensor>, index_pos: usize) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = x.dims3()?;
+
+        let mut q = self.attention_wq.forward(x)?;
+        let mut k = self.attention_wk.forward(x)?;
+        let mut v = self.attention_wv.forward(x)?;
+        if let Some((bq, bk, bv)) = &self.attention_bias {
+            q = q.broadcast_add(bq)?;
+            k = k.broadcast_add(bk)?;
+            v = v.broadcast_add(bv)?;
+        }
+
+        let q = q
+            .reshape((b_sz, seq_len, self.n_head, self.head_dim))?
+            .transpose(1, 2)?;
+        let k = k
+            .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+            .transpose(1, 2)?;
+        let v = v
+            .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+
+        let q = match &self.q_norm {
+            Some(norm) => norm.forward(&q.contiguous()?)?,
+            None => q,
+        };
+        let k = match &self.k_norm {
+            Some(norm) => norm.forward(&k.contiguous()?)?,
+            None => k,
+        };
+
+        let q = self.apply_rotary_emb(&q, index_pos)?;
+        let k = self.apply_rotary_emb(&k, index_pos)?;
+
+        let (k, v) = match &self.kv_cache {
+            Some((k_cache, v_cache)) if index_pos > 0 => (
+                Tensor::cat(&[k_cache, &k], 2)?,
+                Tensor::cat(&[v_cache, &v], 2)?,
+            ),
+            _ => (k, v),
+        };
+        self.kv_cache = Some((k.clone(), v.clone()));
+
+        let k = repeat_kv(k, self.n_head / self.n_kv_head)?;
+        let v = repeat_kv(v, self.n_head / self.n_kv_head)?;
+
+        let att = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?;
+        let att = match mask {
+            None => att,
+            Some(mask) => att.broadcast_add(mask)?,
+        };
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+        let y = att.matmul(&v)?;
+        let y = y
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, self.n_head * self.head_dim))?;
+        self.attention_wo.forward(&y)
+    }
+}
+
+/// Quantized Qwen weights together with their kv cache
+#[derive(Debug, Clone)]
+pub struct ModelWeights {
+    tok_embeddings: Embedding,
+    layers: Vec<LayerWeights>,
+    norm: RmsNorm,
+    output: QMatMul,
+    /// `qwen2` or `qwen3`
+    pub architecture: String,
+    pub context_length: usize,
+}
+
+fn precompute_freqs_cis(
+    head_dim: usize,
+    freq_base: f32,
+    context_length: usize,
+    device: &Device,
+) -> Result<(Tensor, Tensor)> {
+    let theta: Vec<_> = (0..head_dim)
+        .step_by(2)
+        .map(|i| 1f32 / freq_base.powf(i as f32 / head_dim as f32))
+        .collect();
+    let theta = Tensor::new(theta.as_slice(), device)?;
+    let idx_theta = Tensor::arange(0, context_length as u32, device)?
+        .to_dtype(DType::F32)?
+        .reshape((context_length, 1))?
+        .matmul(&theta.reshape((1, theta.elem_count()))?)?;
+    Ok((idx_theta.cos()?, idx_theta.sin()?))
+}
+
+/// GGUF name of a Hugging Face Qwen2/Qwen3 tensor, `None` for tensors that are not stored.
+/// Checkpoints saved without the LM head class (e.g. Qwen3-Embedding) have no `model.` prefix.
+pub fn hf_to_gguf_name(name: &str) -> Option<String> {
+    if name == "lm_head.weight" {
+        return Some("output.weight".to_string());
+    }
+    let name = name.strip_prefix("model.").unwrap_or(name);
+    match name {
+        "embed_tokens.weight" => return Some("token_embd.weight".to_string()),
+        "norm.weight" => return Some("output_norm.weight".to_string()),
+        _ => {}
+    }
+    let (layer, rest) = name.strip_prefix("layers.")?.split_once('.')?;
+    let suffix = match rest {
+        "self_attn.q_proj.weight" => "attn_q.weight",
+        "self_attn.k_proj.weight" => "attn_k.weight",
+        "self_attn.v_proj.weight" => "attn_v.weight",
+        "self_attn.q_proj.bias" => "attn_q.bias",
+        "self_attn.k_proj.bias" => "attn_k.bias",
+        "self_attn.v_proj.bias" => "attn_v.bias",
+        "self_attn.o_proj.weight" => "attn_output.weight",
+        "self_attn.q_norm.weight" => "attn_q_norm.weight",
+        "self_attn.k_norm.weight" => "attn_k_norm.weight",
+        "input_layernorm.weight" => "attn_norm.weight",
+        "post_attention_layernorm.weight" => "ffn_norm.weight",
+        "mlp.gate_proj.weight" => "ffn_gate.weight",
+        "mlp.up_proj.weight" => "ffn_up.weight",
+        "mlp.down_proj.weight" => "ffn_down.weight",
+        _ => return None,
+    };
+    Some(format!("blk.{layer}.{suffix}"))
+}
+
+/// Hyper-parameters of a Qwen2/Qwen3 decoder, named after the GGUF metadata keys
+#[derive(Debug, Clone)]
+pub struct HParams {
+    /// `qwen2` or `qwen3`
+    pub architecture: String,
+    pub head_count: usize,
+    pub head_count_kv: usize,
+    pub head_dim: usize,
+    pub embedding_length: usize,
+    pub context_length: usize,
+    pub block_count: usize,
+    pub rms_norm_eps: f64,
+    pub rope_freq_base: f32,
+}
+
+impl HParams {
+    pub fn from_gguf(ct: &gguf_file::Content) -> Result<Self> {
+        let architecture = match ct.metadata.get("general.architecture") {
+            Some(v) => v.to_string()?.clone(),
+            None => "qwen2".to_string(),
+        };
+        let md_get = |s: &str| {
+            let key = format!("{architecture}.{s}");
+            match ct.metadata.get(&key) {
+                None => candle_core::bail!("cannot find {key} in metadata"),
+                Some(v) => Ok(v),
+            }
+        };
+
+        let head_count = md_get("attention.head_count")?.to_u32()? as usize;
+        let embedding_length = md_get("embedding_length")?.to_u32()? as usize;
+        Ok(Self {
+            head_count,
+            head_count_kv: md_get("attention.head_count_kv")?.to_u32()? as usize,
+            // Qwen3 heads are wider than embedding_length / head_count
+            head_dim: md_get("attention.key_length")
+                .and_then(|m| m.to_u32())
+                .map(|d| d as usize)
+                .unwrap_or(embedding_length / head_count),
+            embedding_length,
+            context_length: md_get("context_length")?.to_u32()? as usize,
+            block_count: md_get("block_count")?.to_u32()? as usize,
+            rms_norm_eps: md_get("attention.layer_norm_rms_epsilon")?.to_f32()? as f64,
+            rope_freq_base: md_get("rope.freq_base")
+                .and_then(|m| m.to_f32())
+                .unwrap_or(10000f32),
+            architecture,
+        })
+    }
+
+    /// Read the Hugging Face `config.json` of a dense Qwen2/Qwen2.5/Qwen3 model
+    pub fn from_config(config: &serde_json::Value) -> Result<Self> {
+        let architecture = match (config["architectures"][0].as_str(), config["model_type"].as_str()) {
+            (Some("Qwen3ForCausalLM" | "Qwen3Model"), _) | (_, Some("qwen3")) => "qwen3",
+            (Some("Qwen2ForCausalLM" | "Qwen2Model"), _) | (_, Some("qwen2")) => "qwen2",
+            (arch, model_type) => candle_core::bail!(
+                "unsupported model {arch:?} ({model_type:?}), only dense Qwen2/Qwen2.5 and Qwen3 are supported"
+            ),
+        };
+        let get = |key: &str| match config[key].as_u64() {
+            Some(v) => Ok(v as usize),
+            None => candle_core::bail!("config.json has no {key}"),
+        };
+        let embedding_length = get("hidden_size")?;
+        let head_count = get("num_attention_heads")?;
+
+        Ok(Self {
+            architecture: architecture.to_string(),
+            head_count,
+            head_count_kv: get("num_key_value_heads").unwrap_or(head_count),
+            head_dim: get("head_dim").unwrap_or(embedding_length / head_count),
+            embedding_length,
+            context_length: get("max_position_embeddings")?,
+            block_count: get("num_hidden_layers")?,
+            rms_norm_eps: config["rms_norm_eps"].as_f64().unwrap_or(1e-6),
+            rope_freq_base: config["rope_theta"].as_f64().unwrap_or(10000.0) as f32,
+        })
+    }
+}
+
+/// A weight read from a GGUF file or an unquantized checkpoint
+pub enum Weight {
+    Quantized(QTensor),
+    Dense(Tensor),
+}
+
+impl Weight {
+    fn matmul(self) -> Result<QMatMul> {
+        match self {
+            Weight::Quantized(t) => QMatMul::from_qtensor(t),
+            Weight::Dense(t) => Ok(QMatMul::TensorF16(t.to_dtype(DType::F16)?)),
+        }
+    }
+
+    fn dense(self, device: &Device) -> Result<Tensor> {
+        match self {
+            Weight::Quantized(t) => t.dequantize(device),
+            Weight::Dense(t) => t.to_device(device)?.to_dtype(DType::F32),
+        }
+    }
+
+    fn rms_norm(self, eps: f64, device: &Device) -> Result<RmsNorm> {
+        Ok(RmsNorm::new(self.dense(device)?, eps))
+    }
+}
+
+impl ModelWeights {
+    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+        ct: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        let hparams = HParams::from_gguf(&ct)?;
+        Self::from_weights(hparams, device, |name| {
+            Ok(Weight::Quantized(ct.tensor(reader, name, device)?))
+        })
+    }
+
+    /// Build the model from unquantized Hugging Face safetensors weights
+    pub fn from_safetensors(st: &MmapedSafetensors, hparams: HParams, device: &Device) -> Result<Self> {
+        let names: HashMap<String, String> = st
+            .tensors()
+            .into_iter()
+            .filter_map(|(name, _)| Some((hf_to_gguf_name(&name)?, name)))
+            .collect();
+        Self::from_weights(hparams, device, |name| match names.get(name) {
+            Some(hf_name) => Ok(Weight::Dense(st.load(hf_name, device)?)),
+            None => candle_core::bail!("missing tensor {name}"),
+        })
+    }
+
+    /// Build the model from weights looked up by their GGUF tensor names
+    pub fn from_weights(
+        hparams: HParams,
+        device: &Device,
+        mut get: impl FnMut(&str) -> Result<Weight>,
+    ) -> Result<Self> {
+        let HParams {
+            architecture,
+            head_count,
+            head_count_kv,
+            head_dim,
+            embedding_length,
+            context_length,
+            block_count,
+            rms_norm_eps,
+            rope_freq_base,
+        } = hparams;
+        if architecture != "qwen2" && architecture != "qwen3" {
+            candle_core::bail!("unsupported architecture {architecture}, expected qwen2 or qwen3")
+        }
+
+        let tok_embeddings = get("token_embd.weight")?.dense(device)?;
+        let norm = get("output_norm.weight")?.rms_norm(rms_norm_eps, device)?;
+        let output = match get("output.weight") {
+            Ok(v) => v.matmul()?,
+            // tied word embeddings
+            Err(_) => get("token_embd.weight")?.matmul()?,
+        };
+
+        let (cos, sin) = precompute_freqs_cis(head_dim, rope_freq_base, context_length, device)?;
+
+        let mut layers = Vec::with_capacity(block_count);
+        for layer_idx in 0..block_count {
+            let prefix = format!("blk.{layer_idx}");
+            let mut tensor = |name: &str| get(&format!("{prefix}.{name}"));
+
+            let attention_bias = if architecture == "qwen2" {
+                Some((
+                    tensor("attn_q.bias")?.dense(device)?,
+                    tensor("attn_k.bias")?.dense(device)?,
+                    tensor("attn_v.bias")?.dense(device)?,
+                ))
+            } else {
+                None
+            };
+            let (q_norm, k_norm) = if architecture == "qwen3" {
+                (
+                    Some(tensor("attn_q_norm.weight")?.rms_norm(rms_norm_eps, device)?),
+                    Some(tensor("attn_k_norm.weight")?.rms_norm(rms_norm_eps, device)?),
+                )
+            } else {
+                (None, None)
+            };
+
+            layers.push(LayerWeights {
+                attention_wq: tensor("attn_q.weight")?.matmul()?,
+                attention_wk: tensor("attn_k.weight")?.matmul()?,
+                attention_wv: tensor("attn_v.weight")?.matmul()?,
+                attention_wo: tensor("attn_output.weight")?.matmul()?,
+                attention_bias,
+                q_norm,
+                k_norm,
+                attention_norm: tensor("attn_norm.weight")?.rms_norm(rms_norm_eps, device)?,
+                mlp: Mlp {
+                    gate: tensor("ffn_gate.weight")?.matmul()?,
+                    down: tensor("ffn_down.weight")?.matmul()?,
+                    up: tensor("ffn_up.weight")?.matmul()?,
+                },
+                ffn_norm: tensor("ffn_norm.weight")?.rms_norm(rms_norm_eps, device)?,
+                n_head: head_count,
+                n_kv_head: head_count_kv,
+                head_dim,
+                cos: cos.clone(),
+                sin: sin.clone(),
+                kv_cache: None,
+            });
+        }
+
+        Ok(Self {
+            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            layers,
+            norm,
+            output,
+            architecture,
+            context_length,
+        })
+    }
+
+    /// Causal mask for `seq_len` new tokens following `index_pos` cached ones
+    fn mask(seq_len: usize, index_pos: usize, device: &Device) -> Result<Tensor> {
+        let mask: Vec<f32> = (0..seq_len)
+            .flat_map(|i| {
+                (0..seq_len + index_pos).map(move |j| {
+                    if j > i + index_pos {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        Tensor::from_slice(&mask, (seq_len, seq_len + index_pos), device)
+    }
+
+    /// Final hidden states of all positions, shaped `(batch, seq_len, hidden)`
+    pub fn hidden_states(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let (_b_sz, seq_len) = x.dims2()?;
+        let mask = if seq_len == 1 {
+            None
+        } else {
+            Some(Self::mask(seq_len, index_pos, x.device())?)
+        };
+        let mut layer_in = self.tok_embeddings.forward(x)?;
+        for layer in self.layers.iter_mut() {
+            let residual = &layer_in;
+            let x = layer.attention_norm.forward(&layer_in)?;
+            let attn = layer.forward_attn(&x, mask.as_ref(), index_pos)?;
+            let x = (attn + residual)?;
+
+            let residual = &x;
+            let h = layer.ffn_norm.forward(&x)?;
+            let h = layer.mlp.forward(&h)?;
+            layer_in = (h + residual)?;
+        }
+        self.norm.forward(&layer_in)
+    }
+
+    /// Logits of the last position, shaped `(batch, 1, vocab)` like the dense models
+    pub fn forward(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let (_b_sz, seq_len) = x.dims2()?;
+        let x = self.hidden_states(x, index_pos)?;
+        let x = x.i((.., seq_len - 1, ..))?.contiguous()?;
+        self.output.forward(&x)?.unsqueeze(1)
+    }
+
+    /// Logits of every position, shaped `(batch, seq_len, vocab)`
+    pub fn forward_all(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let x = self.hidden_states(x, index_pos)?;
+        self.output.forward(&x.contiguous()?)
+    }
+
+    /// Logits at one position per row, shaped `(batch, vocab)`; for right-padded
+    /// batches whose rows end at different positions
+    pub fn forward_at(&mut self, x: &Tensor, positions: &[usize]) -> Result<Tensor> {
+        let hidden = self.hidden_states(x, 0)?;
+        let rows = positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| hidden.i((i, position)))
+            .collect::<Result<Vec<_>>>()?;
+        self.output.forward(&Tensor::stack(&rows, 0)?.contiguous()?)
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.kv_cache = None;
+        }
+    }
+}
@@ -4,8 +4,6 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use std::io::Write;
-
 use anyhow::{Error as E, Result};
 
 use candle_transformers::models::qwen2::{Config as ConfigBase, ModelForCausalLM as ModelBase};
@@ -17,9 +15,10 @@ use candle_transformers::generation::LogitsProcessor;
 use tokenizers::Tokenizer;
 
 use crate::generation::based::ModelForCausalLM;
-use crate::generation::GenerationConfig;
+use crate::generation::{FinishReason, GenerationConfig};
+use crate::models::quantized_qwen::ModelWeights as ModelQuantized;
 use crate::utils::token_output_stream::TokenOutputStream;
-use crate::utils::utils;
+use crate::utils::{gguf, utils};
 
 pub struct TextGeneration {
     pub model: Model,
@@ -66,10 +65,10 @@ impl TextGeneration {
             .to_vec();
         for &t in tokens.iter() {
             if let Some(t) = self.tokenizer.next_token(t)? {
-                print!("{t}")
+                eprint!("{t}")
             }
         }
-        std::io::stdout().flush()?;
+        std::io::stderr().flush()?;
 
         let mut generated_tokens = 0usize;
         let eos_token = match self.tokenizer.get_token("<|endoftext|>") {
@@ -102,16 +101,16 @@ impl TextGeneration {
                 break;
             }
             if let Some(t) = self.tokenizer.next_token(next_token)? {
-                print!("{t}");
-                std::io::stdout().flush()?;
+                eprint!("{t}");
+                std::io::stderr().flush()?;
             }
         }
         let dt = start_gen.elapsed();
         if let Some(rest) = self.tokenizer.decode_rest().map_err(E::msg)? {
-            print!("{rest}");
+            eprint!("{rest}");
         }
-        std::io::stdout().flush()?;
-        println!(
+        std::io::stderr().flush()?;
+        eprintln!(
             "\n{generated_tokens} tokens generated ({:.2} token/s)",
             generated_tokens as f64 / dt.as_secs_f64(),
         );
@@ -123,32 +122,72 @@ pub struct Model {
     pub tokenizer: TokenOutputStream,
     pub device: Device,
     model_typed: ModelTyped,
+    // tokens whose keys/values are currently held in the kv cache
+    cached_tokens: Vec<u32>,
 }
 
+#[derive(Clone)]
 pub enum ModelTyped {
     Base(ModelBase),
     Moe(ModelMoe),
+    Quantized(ModelQuantized),
 }
 
 impl Model {
+    /// Load from a checkpoint directory, or from a `.gguf` file (`dtype` is then ignored).
+    /// A directory holding a single `.gguf` file and no safetensors loads that file.
     pub fn new(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
-        Self::from_pretrained(model_path, device, dtype)
+        if gguf::is_gguf(model_path) {
+            Self::from_gguf(model_path, device)
+        } else if let Some(file) = gguf::single_gguf_in_dir(model_path) {
+            Self::from_gguf(&file.to_string_lossy(), device)
+        } else {
+            Self::from_pretrained(model_path, device, dtype)
+        }
     }
 
     fn forward(&mut self, xs: &Tensor, s: usize) -> candle_core::Result<Tensor> {
         match self.model_typed {
             ModelTyped::Moe(ref mut m) => m.forward(xs, s),
-            ModelTyped::Base(ref mut m) => m.forward(xs, s),
+            ModelTyped::Base(ref mut m) => {
+                // candle's qwen2 builds the mask for several tokens after a cached prefix
+                // from an F32 and a model dtype tensor, which fails unless the weights are
+                // F32, so those tokens go through one at a time
+                let len = xs.dim(1)?;
+                if s == 0 || len == 1 {
+                    return m.forward(xs, s);
+                }
+                for i in 0..len - 1 {
+                    m.forward(&xs.narrow(1, i, 1)?, s + i)?;
+                }
+                m.forward(&xs.narrow(1, len - 1, 1)?, s + len - 1)
+            }
+            ModelTyped::Quantized(ref mut m) => m.forward(xs, s),
         }
     }
 
     pub fn clear_kv_cache(&mut self) {
+        self.cached_tokens.clear();
         match self.model_typed {
             ModelTyped::Moe(ref mut m) => m.clear_kv_cache(),
             ModelTyped::Base(ref mut m) => m.clear_kv_cache(),
+            ModelTyped::Quantized(ref mut m) => m.clear_kv_cache(),
         }
     }
 
+    /// Create another handle on the same weights with its own, empty kv cache.
+    /// Weight tensors are reference counted so nothing is copied or re-read from disk.
+    pub fn fork(&self) -> Self {
+        let mut forked = Self {
+            tokenizer: TokenOutputStream::new(self.tokenizer.tokenizer.clone()),
+            device: self.device.clone(),
+            model_typed: self.model_typed.clone(),
+            cached_tokens: Vec::new(),
+        };
+        forked.clear_kv_cache();
+        forked
+    }
+
     fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Model> {
         let tokenizer_path = std::path::Path::new(model_path).join("tokenizer.json");
         if !tokenizer_path.exists() {
@@ -170,9 +209,39 @@ impl Model {
             tokenizer: TokenOutputStream::new(tokenizer),
             device: device.clone(),
             model_typed,
+            cached_tokens: Vec::new(),
         })
     }
 
+    /// Load quantized weights from a GGUF file. The tokenizer is read from a
+    /// `tokenizer.json` next to the file, or rebuilt from the GGUF metadata.
+    fn from_gguf(model_path: &str, device: &Device) -> Result<Model> {
+        let path = std::path::Path::new(model_path);
+        let mut file = std::fs::File::open(path)?;
+        let content = candle_core::quantized::gguf_file::Content::read(&mut file)
+            .map_err(|e| e.with_path(path))?;
+
+        let tokenizer_path = path.with_file_name("tokenizer.json");
+        let tokenizer = if tokenizer_path.exists() {
+            Tokenizer::from_file(&tokenizer_path).map_err(E::msg)?
+        } else {
+            gguf::tokenizer_from_gguf(&content)?
+        };
+
+        let model_typed = ModelTyped::Quantized(ModelQuantized::from_gguf(content, &mut file, device)?);
+
+        Ok(Self {
+            tokenizer: TokenOutputStream::new(tokenizer),
+            device: device.clone(),
+            model_typed,
+            cached_tokens: Vec::new(),
+        })
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        matches!(self.model_typed, ModelTyped::Quantized(_))
+    }
+
     pub fn prepare_inputs(&self, inputs: &str) -> Result<Vec<u32>> {
         let input_ids = self
             .tokenizer
@@ -194,26 +263,32 @@ impl Model {
             )
             .unwrap();
     }
-}
-
-impl ModelForCausalLM for Model {
-    fn device(&self) -> &Device {
-        &self.device
-    }
 
-    fn generate(
+    /// Like `generate`, also returning why the loop stopped
+    pub fn generate_with_reason(
         &mut self,
         input_ids: &[u32],
         config: &crate::generation::GenerationConfig,
         mut streamer: Option<&mut dyn crate::generation::streamer::TokenStreamer>,
-    ) -> Result<Vec<u32>> {
+    ) -> Result<(Vec<u32>, FinishReason)> {
         self.tokenizer.clear();
-        self.clear_kv_cache();
+        // Keep the kv cache when the new input extends what is already cached,
+        // e.g. the next turn of the same conversation. The record is taken so that
+        // an error mid-generation leaves it empty and the cache gets rebuilt.
+        let cached = std::mem::take(&mut self.cached_tokens);
+        let reuse = !cached.is_empty()
+            && input_ids.len() > cached.len()
+            && input_ids.starts_with(&cached);
+        let mut start_pos = if reuse {
+            cached.len()
+        } else {
+            self.clear_kv_cache();
+            0
+        };
 
         let mut logits_processor = LogitsProcessor::new(1024, config.temperature, config.top_p);
 
         let mut tokens = input_ids.to_vec();
-        std::io::stdout().flush()?;
 
         let mut generated_tokens = 0usize;
         let eos_token = match self.tokenizer.get_token("<|endoftext|>") {
@@ -221,13 +296,20 @@ impl ModelForCausalLM for Model {
             None => anyhow::bail!("cannot find the <|endoftext|> token"),
         };
         let start_gen = std::time::Instant::now();
-        for index in 0..config.max_new_tokens {
-            let context_size = if index > 0 { 1 } else { tokens.len() };
-            let start_pos = tokens.len().saturating_sub(context_size);
+        let mut finish_reason = FinishReason::Length;
+        for _ in 0..config.max_new_tokens {
+            if let Some(reason) = config.interruption() {
+                finish_reason = reason;
+                if let Some(ref mut s) = streamer {
+                    s.finalize()?;
+                }
+                break;
+            }
             let ctxt = &tokens[start_pos..];
             let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
 
             let logits = self.forward(&input, start_pos)?;
+            start_pos = tokens.len();
             let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
             let logits = if config.repetition_penalty == 1. {
                 logits
@@ -243,27 +325,47 @@ impl ModelForCausalLM for Model {
             let next_token = logits_processor.sample(&logits)?;
             tokens.push(next_token);
             generated_tokens += 1;
-            if next_token == eos_token {
+
+            // Handle end-of-sequence token
+            if next_token == eos_token || Some(next_token) == config.eos_token_id {
+                if let Some(ref mut s) = streamer {
+                    s.finalize()?;
+                }
+                finish_reason = FinishReason::Stop;
                 break;
             }
-            if let Some(t) = self.tokenizer.next_token(next_token)? {
-                print!("{t}");
-                std::io::stdout().flush()?;
+
+            // Send token to streamer
+            if let Some(ref mut s) = streamer {
+                s.append(next_token)?;
             }
         }
+        self.cached_tokens = tokens[..start_pos].to_vec();
         let dt = start_gen.elapsed();
-        if let Some(rest) = self.tokenizer.decode_rest().map_err(E::msg)? {
-            print!("{rest}");
-        }
-        std::io::stdout().flush()?;
 
         if config.report_speed {
-            println!(
+            eprintln!(
                 "\n{generated_tokens} tokens generated ({:.2} token/s)\n",
                 generated_tokens as f64 / dt.as_secs_f64(),
             );
         }
 
-        Ok(tokens)
+        Ok((tokens, finish_reason))
+    }
+}
+
+impl ModelForCausalLM for Model {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn generate(
+        &mut self,
+        input_ids: &[u32],
+        config: &crate::generation::GenerationConfig,
+        streamer: Option<&mut dyn crate::generation::streamer::TokenStreamer>,
+    ) -> Result<Vec<u32>> {
+        self.generate_with_reason(input_ids, config, streamer)
+            .map(|(tokens, _)| tokens)
     }
 }
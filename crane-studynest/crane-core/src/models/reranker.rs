@@ -0,0 +1,310 @@
+//! Cross-encoder rerankers.
+//!
+//! A reranker reads a query and a passage together and scores how well the passage
+//! answers the query, which is slower but more precise than comparing embeddings.
+//! BGE-reranker (XLM-RoBERTa) and MS MARCO MiniLM (BERT) cross-encoders put a
+//! classification head on the first token. Qwen3-Reranker is a decoder asked whether
+//! the document meets the query, scored by the probability of answering "yes". All
+//! scores are in `[0, 1]`; classifier logits go through a sigmoid.
+
+use std::path::Path;
+
+use anyhow::{Context, Error as E, Result};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{D, DType, Device, IndexOp, Module, Tensor};
+use candle_nn::{Linear, VarBuilder, linear};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use candle_transformers::models::xlm_roberta::{Config as XlmRobertaConfig, XLMRobertaModel};
+use tokenizers::{EncodeInput, Tokenizer, TruncationParams};
+
+use crate::models::quantized_qwen::{HParams, ModelWeights};
+use crate::utils::{gguf, utils};
+
+/// Instruction Qwen3-Reranker was trained with for passage retrieval
+pub const DEFAULT_INSTRUCTION: &str =
+    "Given a web search query, retrieve relevant passages that answer the query";
+
+const DECODER_PREFIX: &str = "<|im_start|>system\nJudge whether the Document meets the requirements \
+    based on the Query and the Instruct provided. Note that the answer can only be \"yes\" or \
+    \"no\".<|im_end|>\n<|im_start|>user\n";
+const DECODER_SUFFIX: &str = "<|im_end|>\n<|im_start|>assistant\n<think>\n\n</think>\n\n";
+
+enum Scorer {
+    /// BertForSequenceClassification: pooler (dense + tanh) on `[CLS]`, then a linear head
+    Bert {
+        model: BertModel,
+        pooler: Option<Linear>,
+        classifier: Linear,
+    },
+    /// XLMRobertaForSequenceClassification: dense + tanh + projection on `<s>`
+    XlmRoberta {
+        model: XLMRobertaModel,
+        dense: Linear,
+        out_proj: Linear,
+    },
+    /// Causal LM judging relevance with "yes" / "no"
+    Decoder { model: ModelWeights, yes: u32, no: u32 },
+}
+
+pub struct RerankerModel {
+    pub tokenizer: Tokenizer,
+    pub device: Device,
+    /// Longer query-passage pairs are truncated to this many tokens
+    pub max_length: usize,
+    /// Task description given to instruction-following rerankers (Qwen3-Reranker)
+    pub instruction: String,
+    pad_id: u32,
+    scorer: Scorer,
+}
+
+impl RerankerModel {
+    /// Load a BERT or XLM-RoBERTa sequence classification checkpoint, a Qwen2/Qwen3
+    /// reranker directory, or a `.gguf` file of a decoder reranker
+    pub fn new(model_path: &str, device: &Device) -> Result<Self> {
+        let path = Path::new(model_path);
+        if gguf::is_gguf(path) {
+            return Self::from_gguf(path, device);
+        }
+
+        let config: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.join("config.json"))?)?;
+        let tokenizer = Tokenizer::from_file(path.join("tokenizer.json")).map_err(E::msg)?;
+        let files = utils::get_safetensors_files(model_path)?;
+        let num_labels = config["id2label"].as_object().map_or(1, |labels| labels.len().max(1));
+
+        match config["model_type"].as_str() {
+            Some("bert") => {
+                let bert_config: BertConfig = serde_json::from_value(config.clone())?;
+                let vb = unsafe { VarBuilder::from_mmaped_safetensors(&files, DType::F32, device)? };
+                let model = BertModel::load(vb.clone(), &bert_config)?;
+                let hidden = bert_config.hidden_size;
+                let pooler = linear(hidden, hidden, vb.pp("bert.pooler.dense")).ok();
+                let classifier = linear(hidden, num_labels, vb.pp("classifier"))?;
+                let pad_id = bert_config.pad_token_id as u32;
+                Ok(Self::with_scorer(
+                    Scorer::Bert {
+                        model,
+                        pooler,
+                        classifier,
+                    },
+                    tokenizer,
+                    pad_id,
+                    bert_config.max_position_embeddings,
+                    device,
+                ))
+            }
+            Some("xlm-roberta") => {
+                let roberta_config: XlmRobertaConfig = serde_json::from_value(config.clone())?;
+                let vb = unsafe { VarBuilder::from_mmaped_safetensors(&files, DType::F32, device)? };
+                let model = XLMRobertaModel::new(&roberta_config, vb.pp("roberta"))?;
+                let hidden = roberta_config.hidden_size;
+                let dense = linear(hidden, hidden, vb.pp("classifier.dense"))?;
+                let out_proj = linear(hidden, num_labels, vb.pp("classifier.out_proj"))?;
+                // Positions start after the padding index
+                let max_length = roberta_config.max_position_embeddings
+                    - roberta_config.pad_token_id as usize
+                    - 1;
+                Ok(Self::with_scorer(
+                    Scorer::XlmRoberta {
+                        model,
+                        dense,
+                        out_proj,
+                    },
+                    tokenizer,
+                    roberta_config.pad_token_id,
+                    max_length,
+                    device,
+                ))
+            }
+            _ => {
+                let hparams = HParams::from_config(&config)
+                    .context("expected a BERT, XLM-RoBERTa or Qwen2/Qwen3 reranker")?;
+                let st = unsafe { MmapedSafetensors::multi(&files)? };
+                let max_length = hparams.context_length;
+                let model = ModelWeights::from_safetensors(&st, hparams, device)?;
+                Self::decoder(model, tokenizer, max_length, device)
+            }
+        }
+    }
+
+    fn from_gguf(path: &Path, device: &Device) -> Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let content = candle_core::quantized::gguf_file::Content::read(&mut file)
+            .map_err(|e| e.with_path(path))?;
+        let tokenizer_file = path.with_file_name("tokenizer.json");
+        let tokenizer = if tokenizer_file.exists() {
+            Tokenizer::from_file(tokenizer_file).map_err(E::msg)?
+        } else {
+            gguf::tokenizer_from_gguf(&content)?
+        };
+        let hparams = HParams::from_gguf(&content)?;
+        let model = ModelWeights::from_gguf(content, &mut file, device)?;
+        Self::decoder(model, tokenizer, hparams.context_length, device)
+    }
+
+    fn decoder(model: ModelWeights, tokenizer: Tokenizer, max_length: usize, device: &Device) -> Result<Self> {
+        let token = |text: &str| {
+            tokenizer
+                .token_to_id(text)
+                .with_context(|| format!("the tokenizer has no \"{text}\" token"))
+        };
+        let (yes, no) = (token("yes")?, token("no")?);
+        let pad_id = tokenizer.token_to_id("<|endoftext|>").unwrap_or(0);
+        Ok(Self::with_scorer(
+            Scorer::Decoder { model, yes, no },
+            tokenizer,
+            pad_id,
+            max_length,
+            device,
+        ))
+    }
+
+    fn with_scorer(scorer: Scorer, tokenizer: Tokenizer, pad_id: u32, max_length: usize, device: &Device) -> Self {
+        Self {
+            tokenizer,
+            device: device.clone(),
+            max_length,
+            instruction: DEFAULT_INSTRUCTION.to_string(),
+            pad_id,
+            scorer,
+        }
+    }
+
+    /// Relevance of each passage to `query`, running at most `batch_size` pairs through
+    /// the model at once
+    pub fn score(&mut self, query: &str, passages: &[&str], batch_size: usize) -> Result<Vec<f32>> {
+        let mut scores = Vec::with_capacity(passages.len());
+        for batch in passages.chunks(batch_size.max(1)) {
+            scores.extend(self.score_batch(query, batch)?);
+        }
+        Ok(scores)
+    }
+
+    /// Relevance of one batch of passages to `query`
+    pub fn score_batch(&mut self, query: &str, passages: &[&str]) -> Result<Vec<f32>> {
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (tokens, type_ids) = match &self.scorer {
+            Scorer::Decoder { .. } => (self.decoder_inputs(query, passages)?, None),
+            _ => {
+                let (tokens, type_ids) = self.pair_inputs(query, passages)?;
+                (tokens, Some(type_ids))
+            }
+        };
+        let lengths: Vec<usize> = tokens.iter().map(|t| t.len().max(1)).collect();
+        let seq_len = lengths.iter().copied().max().unwrap_or(1);
+        let batch = passages.len();
+
+        // Right padding; the masks (and the causal mask of decoders) hide it
+        let mut ids = Vec::with_capacity(batch * seq_len);
+        let mut types = Vec::with_capacity(batch * seq_len);
+        let mut mask = Vec::with_capacity(batch * seq_len);
+        for (i, row) in tokens.iter().enumerate() {
+            ids.extend(row);
+            ids.extend(std::iter::repeat_n(self.pad_id, seq_len - row.len()));
+            match &type_ids {
+                Some(type_ids) => types.extend(&type_ids[i]),
+                None => types.extend(std::iter::repeat_n(0u32, row.len())),
+            }
+            types.extend(std::iter::repeat_n(0u32, seq_len - row.len()));
+            mask.extend(std::iter::repeat_n(1u32, row.len()));
+            mask.extend(std::iter::repeat_n(0u32, seq_len - row.len()));
+        }
+        let ids = Tensor::from_vec(ids, (batch, seq_len), &self.device)?;
+        let types = Tensor::from_vec(types, (batch, seq_len), &self.device)?;
+        let mask = Tensor::from_vec(mask, (batch, seq_len), &self.device)?;
+
+        let scores = match &mut self.scorer {
+            Scorer::Bert {
+                model,
+                pooler,
+                classifier,
+            } => {
+                let hidden = model.forward(&ids, &types, Some(&mask))?;
+                let mut cls = hidden.i((.., 0))?.contiguous()?;
+                if let Some(pooler) = pooler {
+                    cls = pooler.forward(&cls)?.tanh()?;
+                }
+                relevance(&classifier.forward(&cls)?)?
+            }
+            Scorer::XlmRoberta { model, dense, out_proj } => {
+                let hidden = model.forward(&ids, &mask, &ids.zeros_like()?, None, None, None)?;
+                let cls = hidden.i((.., 0))?.contiguous()?;
+                relevance(&out_proj.forward(&dense.forward(&cls)?.tanh()?)?)?
+            }
+            Scorer::Decoder { model, yes, no } => {
+                let last: Vec<usize> = lengths.iter().map(|len| len - 1).collect();
+                model.clear_kv_cache();
+                let logits = model.forward_at(&ids, &last);
+                model.clear_kv_cache();
+                let logits = logits?.to_dtype(DType::F32)?;
+                let pair = Tensor::cat(&[logits.i((.., *yes as usize))?, logits.i((.., *no as usize))?], 0)?
+                    .reshape((2, batch))?
+                    .t()?;
+                candle_nn::ops::softmax_last_dim(&pair.contiguous()?)?.i((.., 0))?
+            }
+        };
+        Ok(scores.to_dtype(DType::F32)?.to_vec1::<f32>()?)
+    }
+
+    /// Token and type ids of `[CLS] query [SEP] passage [SEP]` pairs, truncating the
+    /// longer side first
+    fn pair_inputs(&mut self, query: &str, passages: &[&str]) -> Result<(Vec<Vec<u32>>, Vec<Vec<u32>>)> {
+        self.tokenizer.with_padding(None);
+        self.tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: self.max_length,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+        let inputs: Vec<EncodeInput> = passages
+            .iter()
+            .map(|passage| EncodeInput::Dual(query.into(), (*passage).into()))
+            .collect();
+        let encodings = self.tokenizer.encode_batch(inputs, true).map_err(E::msg)?;
+        Ok(encodings
+            .iter()
+            .map(|e| (e.get_ids().to_vec(), e.get_type_ids().to_vec()))
+            .unzip())
+    }
+
+    /// Qwen3-Reranker prompts, truncating the document to fit `max_length`
+    fn decoder_inputs(&mut self, query: &str, passages: &[&str]) -> Result<Vec<Vec<u32>>> {
+        self.tokenizer.with_padding(None);
+        self.tokenizer.with_truncation(None).map_err(E::msg)?;
+        let encode = |tokenizer: &Tokenizer, text: &str| -> Result<Vec<u32>> {
+            Ok(tokenizer.encode(text, false).map_err(E::msg)?.get_ids().to_vec())
+        };
+        let head = encode(
+            &self.tokenizer,
+            &format!("{DECODER_PREFIX}<Instruct>: {}\n<Query>: {query}\n<Document>: ", self.instruction),
+        )?;
+        let tail = encode(&self.tokenizer, DECODER_SUFFIX)?;
+        let room = self.max_length.saturating_sub(head.len() + tail.len()).max(1);
+
+        passages
+            .iter()
+            .map(|passage| {
+                let mut tokens = head.clone();
+                let document = encode(&self.tokenizer, passage)?;
+                tokens.extend(&document[..document.len().min(room)]);
+                tokens.extend(&tail);
+                Ok(tokens)
+            })
+            .collect()
+    }
+}
+
+/// Scores in `[0, 1]` from classifier logits: sigmoid of a single logit, or the
+/// probability of the last ("relevant") label
+fn relevance(logits: &Tensor) -> Result<Tensor> {
+    let logits = logits.to_dtype(DType::F32)?;
+    if logits.dim(D::Minus1)? == 1 {
+        Ok(candle_nn::ops::sigmoid(&logits.squeeze(1)?)?)
+    } else {
+        let probs = candle_nn::ops::softmax_last_dim(&logits)?;
+        let last = probs.dim(1)? - 1;
+        Ok(probs.i((.., last))?)
+    }
+}
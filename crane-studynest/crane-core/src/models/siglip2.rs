@@ -1,17 +1,25 @@
 // implement Siglip2 model
-// to support Namo-500M-v2
-// Note that this Siglip2 doesn't contains original text part.
-// Just vision part.
+// to support Namo-500M-v2, and image-text similarity with the full checkpoint
+// (e.g. google/siglip2-base-patch16-naflex).
+// The vision tower is the NaFlex variant: images keep their aspect ratio and are cut into
+// at most `max_num_patches` patches. The text tower pools the last token of a sequence
+// padded to `max_position_embeddings`, as it was trained.
 
 use crate::utils::utils;
-use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor, D};
 use candle_nn::{
-    conv2d_no_bias, embedding, func, layer_norm, linear, Activation, Conv2d, Conv2dConfig,
-    Embedding, LayerNorm, LayerNormConfig, Linear, VarBuilder,
+    embedding, layer_norm, linear, Activation, Embedding, LayerNorm, Linear, VarBuilder,
 };
+use image::{imageops::FilterType, DynamicImage};
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// Prompt labels are put into for zero-shot classification
+pub const LABEL_TEMPLATE: &str = "this is a photo of {}.";
 
 #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
-pub struct Siglip2Config {
+#[serde(default)]
+pub struct Siglip2VisionConfig {
     pub hidden_size: usize,
     pub num_attention_heads: usize,
     pub intermediate_size: usize,
@@ -19,30 +27,184 @@ pub struct Siglip2Config {
     pub layer_norm_eps: f64,
     pub attention_dropout: f32,
     pub hidden_act: Activation,
-    pub image_size: usize,
     pub patch_size: usize,
     pub num_channels: usize,
-    pub projection_dim: usize,
+    /// Patches of the learned position grid, a square
     pub num_patches: usize,
     pub vision_use_head: bool,
 }
 
+impl Default for Siglip2VisionConfig {
+    fn default() -> Self {
+        Self {
+            hidden_size: 768,
+            num_attention_heads: 12,
+            intermediate_size: 3072,
+            num_hidden_layers: 12,
+            layer_norm_eps: 1e-6,
+            attention_dropout: 0.0,
+            hidden_act: Activation::GeluPytorchTanh,
+            patch_size: 16,
+            num_channels: 3,
+            num_patches: 256,
+            vision_use_head: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Siglip2TextConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub layer_norm_eps: f64,
+    pub attention_dropout: f32,
+    pub hidden_act: Activation,
+    /// Texts are padded and truncated to this many tokens
+    pub max_position_embeddings: usize,
+    pub pad_token_id: u32,
+    /// Size of the embeddings; the hidden size when not given
+    pub projection_size: Option<usize>,
+}
+
+impl Default for Siglip2TextConfig {
+    fn default() -> Self {
+        Self {
+            vocab_size: 256000,
+            hidden_size: 768,
+            num_attention_heads: 12,
+            intermediate_size: 3072,
+            num_hidden_layers: 12,
+            layer_norm_eps: 1e-6,
+            attention_dropout: 0.0,
+            hidden_act: Activation::GeluPytorchTanh,
+            max_position_embeddings: 64,
+            pad_token_id: 0,
+            projection_size: None,
+        }
+    }
+}
+
+/// `config.json` of a full Siglip2 checkpoint
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Siglip2Config {
+    pub text_config: Siglip2TextConfig,
+    pub vision_config: Siglip2VisionConfig,
+}
+
+impl Siglip2Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config_data = std::fs::read(path)?;
+        serde_json::from_slice(&config_data).map_err(candle_core::Error::wrap)
+    }
+}
+
+// Shape of the transformer layers, shared by both towers
+#[derive(Debug, Clone)]
+struct EncoderConfig {
+    hidden_size: usize,
+    num_attention_heads: usize,
+    intermediate_size: usize,
+    num_hidden_layers: usize,
+    layer_norm_eps: f64,
+    hidden_act: Activation,
+}
+
+impl From<&Siglip2VisionConfig> for EncoderConfig {
+    fn from(config: &Siglip2VisionConfig) -> Self {
+        Self {
+            hidden_size: config.hidden_size,
+            num_attention_heads: config.num_attention_heads,
+            intermediate_size: config.intermediate_size,
+            num_hidden_layers: config.num_hidden_layers,
+            layer_norm_eps: config.layer_norm_eps,
+            hidden_act: config.hidden_act,
+        }
+    }
+}
+
+impl From<&Siglip2TextConfig> for EncoderConfig {
+    fn from(config: &Siglip2TextConfig) -> Self {
+        Self {
+            hidden_size: config.hidden_size,
+            num_attention_heads: config.num_attention_heads,
+            intermediate_size: config.intermediate_size,
+            num_hidden_layers: config.num_hidden_layers,
+            layer_norm_eps: config.layer_norm_eps,
+            hidden_act: config.hidden_act,
+        }
+    }
+}
+
+/// Additive attention mask, (batch, 1, 1, keys), from a (batch, keys) mask of ones and
+/// zeros; padding gets the lowest value of `dtype`
+fn expand_mask(mask: &Tensor, dtype: DType) -> Result<Tensor> {
+    let (b_size, keys) = mask.dims2()?;
+    mask.to_dtype(DType::F32)?
+        .affine(f32::MAX as f64, f32::MIN as f64)?
+        .to_dtype(dtype)?
+        .reshape((b_size, 1, 1, keys))
+}
+
+/// Scaled dot-product attention over (batch, len, heads * head_dim) projections
+fn attend(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    num_heads: usize,
+    attention_mask: Option<&Tensor>,
+) -> Result<Tensor> {
+    let (b_size, q_len, embed_dim) = q.dims3()?;
+    let kv_len = k.dim(1)?;
+    let head_dim = embed_dim / num_heads;
+    let scale = 1.0 / (head_dim as f64).sqrt();
+
+    let q = q
+        .reshape((b_size, q_len, num_heads, head_dim))?
+        .transpose(1, 2)?
+        .contiguous()?;
+    let k = k
+        .reshape((b_size, kv_len, num_heads, head_dim))?
+        .transpose(1, 2)?
+        .contiguous()?;
+    let v = v
+        .reshape((b_size, kv_len, num_heads, head_dim))?
+        .transpose(1, 2)?
+        .contiguous()?;
+
+    let mut attn_weights = (q.matmul(&k.t()?)? * scale)?;
+
+    if let Some(mask) = attention_mask {
+        attn_weights = attn_weights.broadcast_add(mask)?;
+    }
+
+    let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+    attn_weights
+        .matmul(&v)?
+        .transpose(1, 2)?
+        .reshape((b_size, q_len, embed_dim))
+}
+
 #[derive(Clone, Debug)]
-pub struct Siglip2MLP {
+struct Siglip2MLP {
     fc1: Linear,
     fc2: Linear,
     activation: Activation,
 }
 
 impl Siglip2MLP {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    fn new(config: &EncoderConfig, vb: VarBuilder) -> Result<Self> {
         let fc1 = linear(config.hidden_size, config.intermediate_size, vb.pp("fc1"))?;
-        let fc2 = linear(config.hidden_size, config.intermediate_size, vb.pp("fc2"))?;
+        let fc2 = linear(config.intermediate_size, config.hidden_size, vb.pp("fc2"))?;
 
         Ok(Self {
             fc1,
             fc2,
-            activation: config.hidden_act.clone(),
+            activation: config.hidden_act,
         })
     }
 }
@@ -56,22 +218,17 @@ impl Module for Siglip2MLP {
 }
 
 #[derive(Clone, Debug)]
-pub struct Siglip2Attention {
+struct Siglip2Attention {
     q_proj: Linear,
     k_proj: Linear,
     v_proj: Linear,
     out_proj: Linear,
     num_heads: usize,
-    head_dim: usize,
-    scale: f64,
-    dropout: f32,
 }
 
 impl Siglip2Attention {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    fn new(config: &EncoderConfig, vb: VarBuilder) -> Result<Self> {
         let embed_dim = config.hidden_size;
-        let num_heads = config.num_attention_heads;
-        let head_dim = embed_dim / num_heads;
 
         let q_proj = linear(embed_dim, embed_dim, vb.pp("q_proj"))?;
         let k_proj = linear(embed_dim, embed_dim, vb.pp("k_proj"))?;
@@ -83,50 +240,22 @@ impl Siglip2Attention {
             k_proj,
             v_proj,
             out_proj,
-            num_heads,
-            head_dim,
-            scale: 1.0 / (head_dim as f64).sqrt(),
-            dropout: config.attention_dropout,
+            num_heads: config.num_attention_heads,
         })
     }
 
     fn forward(&self, xs: &Tensor, attention_mask: Option<&Tensor>) -> Result<Tensor> {
-        let (b_size, q_len, _) = xs.dims3()?;
-
         let q = self.q_proj.forward(xs)?;
         let k = self.k_proj.forward(xs)?;
         let v = self.v_proj.forward(xs)?;
 
-        let q = q
-            .reshape((b_size, q_len, self.num_heads, self.head_dim))?
-            .permute((0, 2, 1, 3))?;
-        let k = k
-            .reshape((b_size, q_len, self.num_heads, self.head_dim))?
-            .permute((0, 2, 1, 3))?;
-        let v = v
-            .reshape((b_size, q_len, self.num_heads, self.head_dim))?
-            .permute((0, 2, 1, 3))?;
-
-        let mut attn_weights = (q.matmul(&k.t()?)? * self.scale)?;
-
-        if let Some(mask) = attention_mask {
-            attn_weights = attn_weights.broadcast_add(mask)?;
-        }
-
-        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
-        let attn_output = attn_weights.matmul(&v)?;
-
-        let attn_output = attn_output.permute((0, 2, 1, 3))?.reshape((
-            b_size,
-            q_len,
-            self.num_heads * self.head_dim,
-        ))?;
+        let attn_output = attend(&q, &k, &v, self.num_heads, attention_mask)?;
         self.out_proj.forward(&attn_output)
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct Siglip2EncoderLayer {
+struct Siglip2EncoderLayer {
     self_attn: Siglip2Attention,
     layer_norm1: LayerNorm,
     mlp: Siglip2MLP,
@@ -134,7 +263,7 @@ pub struct Siglip2EncoderLayer {
 }
 
 impl Siglip2EncoderLayer {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    fn new(config: &EncoderConfig, vb: VarBuilder) -> Result<Self> {
         let self_attn = Siglip2Attention::new(config, vb.pp("self_attn"))?;
 
         let layer_norm1 = layer_norm(
@@ -171,22 +300,18 @@ impl Siglip2EncoderLayer {
 }
 
 #[derive(Debug)]
-pub struct Siglip2Encoder {
+struct Siglip2Encoder {
     layers: Vec<Siglip2EncoderLayer>,
-    gradient_checkpointing: bool,
 }
 
 impl Siglip2Encoder {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    fn new(config: &EncoderConfig, vb: VarBuilder) -> Result<Self> {
         let mut layers = Vec::with_capacity(config.num_hidden_layers);
         for i in 0..config.num_hidden_layers {
-            let layer = Siglip2EncoderLayer::new(config, vb.pp(&format!("layers.{i}")))?;
+            let layer = Siglip2EncoderLayer::new(config, vb.pp(format!("layers.{i}")))?;
             layers.push(layer);
         }
-        Ok(Self {
-            layers,
-            gradient_checkpointing: false,
-        })
+        Ok(Self { layers })
     }
 
     fn forward(&self, xs: &Tensor, attention_mask: Option<&Tensor>) -> Result<Tensor> {
@@ -198,133 +323,125 @@ impl Siglip2Encoder {
     }
 }
 
+/// Weights of antialiased bilinear resampling from `input` to `output` points, as
+/// PyTorch's `interpolate(mode="bilinear", antialias=True)`: a triangle filter widened by
+/// the scale when shrinking
+fn resample_weights(input: usize, output: usize) -> Vec<f32> {
+    let scale = input as f32 / output as f32;
+    let support = scale.max(1.0);
+    let mut weights = vec![0f32; output * input];
+    for i in 0..output {
+        let center = (i as f32 + 0.5) * scale;
+        let row = &mut weights[i * input..(i + 1) * input];
+        let start = (center - support).floor().max(0.0) as usize;
+        let end = ((center + support).ceil() as usize).min(input);
+        for (j, w) in row.iter_mut().enumerate().take(end).skip(start) {
+            *w = (1.0 - ((j as f32 + 0.5 - center) / support).abs()).max(0.0);
+        }
+        let total: f32 = row.iter().sum();
+        if total > 0.0 {
+            row.iter_mut().for_each(|w| *w /= total);
+        }
+    }
+    weights
+}
+
 #[derive(Debug)]
-pub struct Siglip2VisionEmbeddings {
-    patch_embedding: Conv2d,
+struct Siglip2VisionEmbeddings {
+    patch_embedding: Linear,
     position_embedding: Embedding,
     position_embedding_size: usize,
-    num_patches: usize,
-    patch_size: usize,
 }
 
 impl Siglip2VisionEmbeddings {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
-        let conv2dconfig = Conv2dConfig {
-            stride: config.patch_size,
-            ..Default::default()
-        };
-
-        let patch_embedding = conv2d_no_bias(
-            config.num_channels,
+    fn new(config: &Siglip2VisionConfig, vb: VarBuilder) -> Result<Self> {
+        // Patches come flattened, so the patch embedding is a linear layer
+        let patch_embedding = linear(
+            config.num_channels * config.patch_size * config.patch_size,
             config.hidden_size,
-            config.patch_size,
-            conv2dconfig,
             vb.pp("patch_embedding"),
         )?;
         let position_embedding = embedding(
-            config.num_channels * config.patch_size * config.patch_size,
+            config.num_patches,
             config.hidden_size,
-            vb.pp("patch_embedding"),
+            vb.pp("position_embedding"),
         )?;
 
         Ok(Self {
             patch_embedding,
             position_embedding,
-            position_embedding_size: (config.image_size / config.patch_size) as usize,
-            num_patches: config.num_patches,
-            patch_size: config.patch_size,
+            position_embedding_size: (config.num_patches as f64).sqrt() as usize,
         })
     }
 
+    /// Resize the position grid to each image's patch grid, (batch, max_length, dim);
+    /// positions past the image repeat the first one
     fn resize_positional_embeddings(
         &self,
-        positional_embeddings: &Tensor,
-        spatial_shapes: &Tensor,
+        spatial_shapes: &[(usize, usize)],
         max_length: usize,
     ) -> Result<Tensor> {
-        let (b_size, _) = spatial_shapes.dims2()?;
-        let embed_dim = positional_embeddings.dim(D::Minus1)?;
-        let device = positional_embeddings.device();
-
-        // 转换为图像格式 (H, W, C) -> (C, H, W)
-        let embeddings = positional_embeddings.permute((2, 0, 1))?.unsqueeze(0)?;
-
-        let mut resized = Vec::with_capacity(b_size);
-        for i in 0..b_size {
-            // let (h, w) = spatial_shapes.i((i, 0..2))?.to_vec2::<usize>()?;
-            let row_tensor = spatial_shapes.narrow(0, i, 1)?; // 提取第i行
-            let hw_tensor = row_tensor.narrow(1, 0, 2)?; // 提取前两列
-            let hw_vec = hw_tensor.to_vec1::<i64>()?; // 转换为i64类型
-            let (h, w) = (hw_vec[0] as usize, hw_vec[1] as usize);
-
-            // 双线性插值
-            // candle not support bilinear?
-            let resized_emb = embeddings.upsample_nearest2d(h, w)?;
-
-            // 转换回序列格式 (C, H, W) -> (H*W, C)
-            let seq_emb = resized_emb
-                .squeeze(0)?
-                .permute((1, 2, 0))?
+        let size = self.position_embedding_size;
+        // Resampled in F32, as the interpolation is in the reference implementation
+        let grid = self.position_embedding.embeddings().to_dtype(DType::F32)?;
+        let embed_dim = grid.dim(D::Minus1)?;
+        let device = grid.device();
+
+        let mut resized = Vec::with_capacity(spatial_shapes.len());
+        for &(h, w) in spatial_shapes {
+            let rows = Tensor::from_vec(resample_weights(size, h), (h, size), device)?;
+            let cols = Tensor::from_vec(resample_weights(size, w), (w, size), device)?;
+            // (size, size * dim) -> (h, size, dim)
+            let emb = rows
+                .matmul(&grid.reshape((size, size * embed_dim))?)?
+                .reshape((h, size, embed_dim))?;
+            // (size, h * dim) -> (w, h, dim) -> (h * w, dim)
+            let emb = cols
+                .matmul(&emb.transpose(0, 1)?.reshape((size, h * embed_dim))?)?
+                .reshape((w, h, embed_dim))?
+                .transpose(0, 1)?
                 .reshape((h * w, embed_dim))?;
-            resized.push(seq_emb);
-        }
 
-        // 填充到最大长度
-        let mut padded = Vec::with_capacity(b_size);
-        for emb in resized {
-            let len = emb.dim(0)?;
-            if len < max_length {
-                let pad = Tensor::zeros((max_length - len, embed_dim), emb.dtype(), device)?;
-                padded.push(Tensor::cat(&[emb, pad], 0)?);
+            let len = h * w;
+            let emb = if len < max_length {
+                let pad = emb.i(0..1)?.repeat((max_length - len, 1))?;
+                Tensor::cat(&[emb, pad], 0)?
             } else {
-                padded.push(emb);
-            }
+                emb.narrow(0, 0, max_length)?
+            };
+            resized.push(emb);
         }
 
-        Tensor::stack(&padded, 0)
+        Tensor::stack(&resized, 0)
     }
 
-    fn forward(&self, pixel_values: &Tensor, spatial_shapes: &Tensor) -> Result<Tensor> {
-        // 投影到嵌入空间
-        let patch_embeds = self.patch_embedding.forward(pixel_values)?;
-
-        // 获取原始位置编码
-        let positions = Tensor::arange(0u32, self.num_patches as u32, pixel_values.device())?;
-        let mut pos_embeddings = self.position_embedding.forward(&positions)?;
+    fn forward(&self, pixel_values: &Tensor, spatial_shapes: &[(usize, usize)]) -> Result<Tensor> {
+        let dtype = self.patch_embedding.weight().dtype();
+        let patch_embeds = self
+            .patch_embedding
+            .forward(&pixel_values.to_dtype(dtype)?)?;
 
-        // 调整形状用于插值 (num_patches, dim) -> (H, W, dim)
-        pos_embeddings = pos_embeddings.reshape((
-            self.position_embedding_size,
-            self.position_embedding_size,
-            self.position_embedding.hidden_size(),
-        ))?;
+        let resized_pos =
+            self.resize_positional_embeddings(spatial_shapes, pixel_values.dim(1)?)?;
 
-        // 调整位置编码尺寸
-        let resized_pos = self.resize_positional_embeddings(
-            &pos_embeddings,
-            spatial_shapes,
-            pixel_values.dim(1)?,
-        )?;
-
-        // 合并嵌入
-        patch_embeds.add(&resized_pos)
+        patch_embeds.add(&resized_pos.to_dtype(dtype)?)
     }
 }
 
 // 视觉Transformer模块
 #[derive(Debug)]
-pub struct Siglip2VisionTransformer {
+struct Siglip2VisionTransformer {
     embeddings: Siglip2VisionEmbeddings,
     encoder: Siglip2Encoder,
     post_layernorm: LayerNorm,
-    use_head: bool,
     head: Option<Siglip2MultiheadAttentionPoolingHead>,
 }
 
 impl Siglip2VisionTransformer {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    fn new(config: &Siglip2VisionConfig, vb: VarBuilder) -> Result<Self> {
+        let encoder_config = EncoderConfig::from(config);
         let embeddings = Siglip2VisionEmbeddings::new(config, vb.pp("embeddings"))?;
-        let encoder = Siglip2Encoder::new(config, vb.pp("encoder"))?;
+        let encoder = Siglip2Encoder::new(&encoder_config, vb.pp("encoder"))?;
 
         let post_layernorm = layer_norm(
             config.hidden_size,
@@ -334,7 +451,7 @@ impl Siglip2VisionTransformer {
 
         let head = if config.vision_use_head {
             Some(Siglip2MultiheadAttentionPoolingHead::new(
-                config,
+                &encoder_config,
                 vb.pp("head"),
             )?)
         } else {
@@ -345,7 +462,6 @@ impl Siglip2VisionTransformer {
             embeddings,
             encoder,
             post_layernorm,
-            use_head: config.vision_use_head,
             head,
         })
     }
@@ -353,48 +469,95 @@ impl Siglip2VisionTransformer {
     fn forward(
         &self,
         pixel_values: &Tensor,
-        attention_mask: Option<&Tensor>,
+        attention_mask: &Tensor,
         spatial_shapes: &Tensor,
     ) -> Result<Tensor> {
+        let spatial_shapes: Vec<(usize, usize)> = spatial_shapes
+            .to_dtype(DType::I64)?
+            .to_vec2::<i64>()?
+            .into_iter()
+            .map(|hw| (hw[0] as usize, hw[1] as usize))
+            .collect();
+
         // 嵌入处理
-        let mut hidden_states = self.embeddings.forward(pixel_values, spatial_shapes)?;
+        let mut hidden_states = self.embeddings.forward(pixel_values, &spatial_shapes)?;
+        let mask = expand_mask(attention_mask, hidden_states.dtype())?;
 
         // 编码器处理
-        hidden_states = self.encoder.forward(&hidden_states, attention_mask)?;
+        hidden_states = self.encoder.forward(&hidden_states, Some(&mask))?;
 
         // 后层归一化
         hidden_states = self.post_layernorm.forward(&hidden_states)?;
 
         // 池化头
         if let Some(head) = &self.head {
-            head.forward(&hidden_states, attention_mask)
+            head.forward(&hidden_states, Some(&mask))
         } else {
             Ok(hidden_states)
         }
     }
 }
 
-// 注意力池化头（示例实现）
+// 注意力池化头: a learned probe attends over the patches
 #[derive(Debug)]
-pub struct Siglip2MultiheadAttentionPoolingHead {
-    attn: Siglip2Attention,
-    output_proj: Linear,
+struct Siglip2MultiheadAttentionPoolingHead {
+    probe: Tensor,
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+    layernorm: LayerNorm,
+    mlp: Siglip2MLP,
+    num_heads: usize,
 }
 
 impl Siglip2MultiheadAttentionPoolingHead {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
-        let attn = Siglip2Attention::new(config, vb.pp("attn"))?;
-        let output_proj = linear(
-            config.hidden_size,
-            config.projection_dim,
-            vb.pp("output_proj"),
-        )?;
-        Ok(Self { attn, output_proj })
+    fn new(config: &EncoderConfig, vb: VarBuilder) -> Result<Self> {
+        let hidden = config.hidden_size;
+        let probe = vb.get((1, 1, hidden), "probe")?;
+
+        // torch.nn.MultiheadAttention keeps the q, k and v projections in one matrix
+        let attention = vb.pp("attention");
+        let in_proj_weight = attention.get((3 * hidden, hidden), "in_proj_weight")?;
+        let in_proj_bias = attention.get(3 * hidden, "in_proj_bias")?;
+        let proj = |i: usize| -> Result<Linear> {
+            Ok(Linear::new(
+                in_proj_weight.narrow(0, i * hidden, hidden)?,
+                Some(in_proj_bias.narrow(0, i * hidden, hidden)?),
+            ))
+        };
+        let out_proj = linear(hidden, hidden, attention.pp("out_proj"))?;
+
+        let layernorm = layer_norm(hidden, config.layer_norm_eps, vb.pp("layernorm"))?;
+        let mlp = Siglip2MLP::new(config, vb.pp("mlp"))?;
+
+        Ok(Self {
+            probe,
+            q_proj: proj(0)?,
+            k_proj: proj(1)?,
+            v_proj: proj(2)?,
+            out_proj,
+            layernorm,
+            mlp,
+            num_heads: config.num_attention_heads,
+        })
     }
 
+    /// Pooled states, (batch, hidden)
     fn forward(&self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
-        let pooled = self.attn.forward(xs, mask)?;
-        self.output_proj.forward(&pooled)
+        let (b_size, _, hidden) = xs.dims3()?;
+        let probe = self.probe.broadcast_as((b_size, 1, hidden))?;
+
+        let q = self.q_proj.forward(&probe)?;
+        let k = self.k_proj.forward(xs)?;
+        let v = self.v_proj.forward(xs)?;
+        let pooled = attend(&q, &k, &v, self.num_heads, mask)?;
+        let pooled = self.out_proj.forward(&pooled)?;
+
+        let residual = &pooled;
+        let xs = self.layernorm.forward(&pooled)?;
+        let xs = (residual + self.mlp.forward(&xs)?)?;
+        xs.squeeze(1)
     }
 }
 
@@ -404,11 +567,13 @@ pub struct Siglip2VisionModel {
 }
 
 impl Siglip2VisionModel {
-    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+    pub fn new(config: &Siglip2VisionConfig, vb: VarBuilder) -> Result<Self> {
         let vision_model = Siglip2VisionTransformer::new(config, vb.pp("vision_model"))?;
         Ok(Self { vision_model })
     }
 
+    /// Pooled image features, (batch, hidden), or the patch states when the config has
+    /// no pooling head
     pub fn forward(
         &self,
         pixel_values: &Tensor,
@@ -416,53 +581,410 @@ impl Siglip2VisionModel {
         spatial_shapes: &Tensor,
     ) -> Result<Tensor> {
         self.vision_model
-            .forward(pixel_values, Some(pixel_attention_mask), spatial_shapes)
+            .forward(pixel_values, pixel_attention_mask, spatial_shapes)
     }
 
     pub fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
-        let config_file = std::path::Path::new(model_path).join("config.json");
-        let config_data = std::fs::read(config_file)?;
-        let config: Siglip2Config =
-            serde_json::from_slice(&config_data).expect("siglip2 config parse error.");
+        let config = Siglip2Config::from_file(Path::new(model_path).join("config.json"))?;
+        let filenames =
+            utils::get_safetensors_files(model_path).map_err(candle_core::Error::msg)?;
 
-        let filenames = utils::get_safetensors_files(model_path).unwrap();
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, *dtype, device) }?;
+        Self::new(&config.vision_config, vb)
+    }
+}
+
+#[derive(Debug)]
+struct Siglip2TextTransformer {
+    token_embedding: Embedding,
+    position_embedding: Embedding,
+    encoder: Siglip2Encoder,
+    final_layer_norm: LayerNorm,
+    head: Linear,
+}
+
+impl Siglip2TextTransformer {
+    fn new(config: &Siglip2TextConfig, vb: VarBuilder) -> Result<Self> {
+        let embeddings = vb.pp("embeddings");
+        let token_embedding = embedding(
+            config.vocab_size,
+            config.hidden_size,
+            embeddings.pp("token_embedding"),
+        )?;
+        let position_embedding = embedding(
+            config.max_position_embeddings,
+            config.hidden_size,
+            embeddings.pp("position_embedding"),
+        )?;
+        let encoder = Siglip2Encoder::new(&EncoderConfig::from(config), vb.pp("encoder"))?;
+        let final_layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("final_layer_norm"),
+        )?;
+        let head = linear(
+            config.hidden_size,
+            config.projection_size.unwrap_or(config.hidden_size),
+            vb.pp("head"),
+        )?;
+
+        Ok(Self {
+            token_embedding,
+            position_embedding,
+            encoder,
+            final_layer_norm,
+            head,
+        })
+    }
+
+    /// Text features of (batch, seq) ids, (batch, projection_size), from the last token
+    fn forward(&self, input_ids: &Tensor) -> Result<Tensor> {
+        let seq_len = input_ids.dim(1)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, input_ids.device())?;
+        let xs = self
+            .token_embedding
+            .forward(input_ids)?
+            .broadcast_add(&self.position_embedding.forward(&positions)?)?;
+
+        let xs = self.encoder.forward(&xs, None)?;
+        let xs = self.final_layer_norm.forward(&xs)?;
+        self.head.forward(&xs.i((.., seq_len - 1))?)
+    }
+}
+
+/// Both towers of a Siglip2 checkpoint, which embed images and texts in one space
+#[derive(Debug)]
+pub struct Siglip2Model {
+    pub config: Siglip2Config,
+    text_model: Siglip2TextTransformer,
+    vision_model: Siglip2VisionTransformer,
+    logit_scale: f32,
+    logit_bias: f32,
+}
+
+impl Siglip2Model {
+    pub fn new(config: &Siglip2Config, vb: VarBuilder) -> Result<Self> {
+        if !config.vision_config.vision_use_head {
+            candle_core::bail!("image-text similarity needs the vision pooling head");
+        }
+        let text_model = Siglip2TextTransformer::new(&config.text_config, vb.pp("text_model"))?;
+        let vision_model =
+            Siglip2VisionTransformer::new(&config.vision_config, vb.pp("vision_model"))?;
+        let scalar = |name: &str| -> Result<f32> {
+            vb.get(1, name)?
+                .to_dtype(DType::F32)?
+                .squeeze(0)?
+                .to_scalar::<f32>()
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            text_model,
+            vision_model,
+            logit_scale: scalar("logit_scale")?.exp(),
+            logit_bias: scalar("logit_bias")?,
+        })
+    }
+
+    pub fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
+        let config = Siglip2Config::from_file(Path::new(model_path).join("config.json"))?;
+        let filenames =
+            utils::get_safetensors_files(model_path).map_err(candle_core::Error::msg)?;
 
         let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, *dtype, device) }?;
-        let vision_model = Siglip2VisionTransformer::new(&config, vb.pp("vision_model"))?;
+        Self::new(&config, vb)
+    }
 
-        Ok(Self { vision_model })
+    /// Unnormalized text embeddings of padded (batch, seq) ids
+    pub fn get_text_features(&self, input_ids: &Tensor) -> Result<Tensor> {
+        self.text_model.forward(input_ids)
+    }
+
+    /// Unnormalized image embeddings of processed images
+    pub fn get_image_features(
+        &self,
+        pixel_values: &Tensor,
+        pixel_attention_mask: &Tensor,
+        spatial_shapes: &Tensor,
+    ) -> Result<Tensor> {
+        self.vision_model
+            .forward(pixel_values, pixel_attention_mask, spatial_shapes)
+    }
+
+    /// Logits of (images, texts) pairs from normalized embeddings, (n_images, n_texts).
+    /// Siglip2 is trained with a sigmoid loss, so `sigmoid(logit)` is the probability
+    /// that a text matches an image, independently of the other texts.
+    pub fn logits(&self, image_embeds: &Tensor, text_embeds: &Tensor) -> Result<Tensor> {
+        let image_embeds = image_embeds.to_dtype(DType::F32)?;
+        let text_embeds = text_embeds.to_dtype(DType::F32)?;
+        image_embeds
+            .matmul(&text_embeds.t()?)?
+            .affine(self.logit_scale as f64, self.logit_bias as f64)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct Siglip2ImageProcessor {
+    pub patch_size: usize,
+    /// Images are resized to fit this many patches, keeping their aspect ratio
+    pub max_num_patches: usize,
+    pub image_mean: [f32; 3],
+    pub image_std: [f32; 3],
+}
+
+impl Default for Siglip2ImageProcessor {
+    fn default() -> Self {
+        Self {
+            patch_size: 16,
+            max_num_patches: 256,
+            image_mean: [0.5; 3],
+            image_std: [0.5; 3],
+        }
     }
 }
 
-// Siglip2 Processor?
+/// Processed batch of images
+#[derive(Debug, Clone)]
+pub struct Siglip2ImageInputs {
+    /// Flattened patches, (batch, max_num_patches, patch_size * patch_size * 3)
+    pub pixel_values: Tensor,
+    /// 1 for patches of the image, 0 for padding, (batch, max_num_patches)
+    pub pixel_attention_mask: Tensor,
+    /// Patch rows and columns of each image, (batch, 2)
+    pub spatial_shapes: Tensor,
+}
+
+impl Siglip2ImageProcessor {
+    /// `preprocessor_config.json` of the model, or the defaults when there is none
+    pub fn from_pretrained(model_path: &str) -> Result<Self> {
+        let path = Path::new(model_path).join("preprocessor_config.json");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let config_data = std::fs::read(path)?;
+        serde_json::from_slice(&config_data).map_err(candle_core::Error::wrap)
+    }
+
+    /// Size the image is resized to: the largest one, in whole patches, that keeps the
+    /// aspect ratio and fits `max_num_patches`
+    pub fn target_size(&self, height: usize, width: usize) -> (usize, usize) {
+        let patch = self.patch_size as f64;
+        let scaled = |scale: f64, size: usize| -> usize {
+            let size = ((size as f64 * scale) / patch).ceil() * patch;
+            size.max(patch) as usize
+        };
+        let fits = |scale: f64| {
+            let (h, w) = (scaled(scale, height), scaled(scale, width));
+            (h / self.patch_size) * (w / self.patch_size) <= self.max_num_patches
+        };
+
+        let eps = 1e-5;
+        let (mut low, mut high) = (eps / 10.0, 100.0);
+        while high - low >= eps {
+            let scale = (low + high) / 2.0;
+            if fits(scale) {
+                low = scale;
+            } else {
+                high = scale;
+            }
+        }
+        (scaled(low, height), scaled(low, width))
+    }
 
+    pub fn preprocess(
+        &self,
+        images: &[DynamicImage],
+        device: &Device,
+    ) -> Result<Siglip2ImageInputs> {
+        let patch = self.patch_size;
+        let patch_dim = patch * patch * 3;
+        let mut pixel_values = vec![0f32; images.len() * self.max_num_patches * patch_dim];
+        let mut mask = vec![0u8; images.len() * self.max_num_patches];
+        let mut shapes = Vec::with_capacity(images.len() * 2);
+
+        for (b, image) in images.iter().enumerate() {
+            let (height, width) = self.target_size(image.height() as usize, image.width() as usize);
+            let image = image
+                .resize_exact(width as u32, height as u32, FilterType::Triangle)
+                .to_rgb8();
+            let (rows, cols) = (height / patch, width / patch);
+
+            // Patches row by row, each flattened as (y, x, channel)
+            for r in 0..rows {
+                for c in 0..cols {
+                    let index = b * self.max_num_patches + r * cols + c;
+                    mask[index] = 1;
+                    let values = &mut pixel_values[index * patch_dim..(index + 1) * patch_dim];
+                    for y in 0..patch {
+                        for x in 0..patch {
+                            let pixel =
+                                image.get_pixel((c * patch + x) as u32, (r * patch + y) as u32);
+                            for ch in 0..3 {
+                                values[(y * patch + x) * 3 + ch] = (pixel[ch] as f32 / 255.0
+                                    - self.image_mean[ch])
+                                    / self.image_std[ch];
+                            }
+                        }
+                    }
+                }
+            }
+            shapes.extend([rows as i64, cols as i64]);
+        }
 
-pub fn test_main() {
-    // let device = Device::Cpu;
-    // let vb = VarBuilder::from_pretrained("model.safetensors", DType::F32, &device)?;
+        Ok(Siglip2ImageInputs {
+            pixel_values: Tensor::from_vec(
+                pixel_values,
+                (images.len(), self.max_num_patches, patch_dim),
+                device,
+            )?,
+            pixel_attention_mask: Tensor::from_vec(
+                mask,
+                (images.len(), self.max_num_patches),
+                device,
+            )?,
+            spatial_shapes: Tensor::from_vec(shapes, (images.len(), 2), device)?,
+        })
+    }
+}
 
-    // let config = Siglip2Config {
-    //     hidden_size: 768,
-    //     num_attention_heads: 12,
-    //     intermediate_size: 3072,
-    //     num_hidden_layers: 12,
-    //     layer_norm_eps: 1e-5,
-    //     attention_dropout: 0.1,
-    //     hidden_act: Activation::Gelu,
-    //     image_size: 224,
-    //     patch_size: 16,
-    //     num_channels: 3,
-    //     projection_dim: 512,
-    //     vision_use_head: true,
-    //     num_patches: 1024,
-    // };
+/// Open an image file for `Siglip2::embed_images`
+pub fn load_image<P: AsRef<Path>>(path: P) -> Result<DynamicImage> {
+    let path = path.as_ref();
+    image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| candle_core::Error::msg(format!("{}: {e}", path.display())))
+}
 
-    // let model = Siglip2VisionModel::new(&config, vb.pp("vision_model"))?;
+/// Siglip2 with its tokenizer and image processor: embeddings of images and texts in a
+/// shared space, zero-shot classification and text-to-image search
+pub struct Siglip2 {
+    pub model: Siglip2Model,
+    pub tokenizer: Tokenizer,
+    pub processor: Siglip2ImageProcessor,
+    pub device: Device,
+    pad_id: u32,
+}
 
-    // // 示例输入
-    // let pixel_values = Tensor::randn(0f32, 1.0, (2, 196, 768), &device)?; // [batch, seq, dim]
-    // let attention_mask = Tensor::ones((2, 196), DType::U8, &device)?;
-    // let spatial_shapes = Tensor::new(&[[14i64, 14], [16, 12]], &device)?; // 示例空间形状
+impl Siglip2 {
+    pub fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
+        let model = Siglip2Model::from_pretrained(model_path, device, dtype)?;
+        let tokenizer = Tokenizer::from_file(Path::new(model_path).join("tokenizer.json"))
+            .map_err(candle_core::Error::msg)?;
+        let mut processor = Siglip2ImageProcessor::from_pretrained(model_path)?;
+        // Patches must be the size the patch embedding takes
+        processor.patch_size = model.config.vision_config.patch_size;
+        let pad_id = tokenizer
+            .token_to_id("<pad>")
+            .unwrap_or(model.config.text_config.pad_token_id);
+
+        Ok(Self {
+            model,
+            tokenizer,
+            processor,
+            device: device.clone(),
+            pad_id,
+        })
+    }
+
+    /// Size of the embeddings
+    pub fn dimension(&self) -> usize {
+        let text = &self.model.config.text_config;
+        text.projection_size.unwrap_or(text.hidden_size)
+    }
+
+    /// L2-normalized image embeddings, (images.len(), dimension), in F32
+    pub fn embed_images(&self, images: &[DynamicImage]) -> Result<Tensor> {
+        if images.is_empty() {
+            return Tensor::zeros((0, self.dimension()), DType::F32, &self.device);
+        }
+        let inputs = self.processor.preprocess(images, &self.device)?;
+        let features = self.model.get_image_features(
+            &inputs.pixel_values,
+            &inputs.pixel_attention_mask,
+            &inputs.spatial_shapes,
+        )?;
+        normalize(&features)
+    }
+
+    /// L2-normalized text embeddings, (texts.len(), dimension), in F32. Texts are
+    /// lowercased, as the model was trained on lowercase text.
+    pub fn embed_texts(&self, texts: &[&str]) -> Result<Tensor> {
+        if texts.is_empty() {
+            return Tensor::zeros((0, self.dimension()), DType::F32, &self.device);
+        }
+        let max_length = self.model.config.text_config.max_position_embeddings;
+        let mut ids = Vec::with_capacity(texts.len() * max_length);
+        for text in texts {
+            let encoding = self
+                .tokenizer
+                .encode(text.to_lowercase(), true)
+                .map_err(candle_core::Error::msg)?;
+            let mut tokens = encoding.get_ids().to_vec();
+            tokens.resize(max_length, self.pad_id);
+            ids.extend(tokens);
+        }
+        let input_ids = Tensor::from_vec(ids, (texts.len(), max_length), &self.device)?;
+        normalize(&self.model.get_text_features(&input_ids)?)
+    }
+
+    /// Embeddings of `images` and `texts` in the shared space
+    pub fn embed(&self, images: &[DynamicImage], texts: &[&str]) -> Result<(Tensor, Tensor)> {
+        Ok((self.embed_images(images)?, self.embed_texts(texts)?))
+    }
+
+    /// Probability that each text describes each image, (n_images, n_texts)
+    pub fn similarity(&self, image_embeds: &Tensor, text_embeds: &Tensor) -> Result<Tensor> {
+        candle_nn::ops::sigmoid(&self.model.logits(image_embeds, text_embeds)?)
+    }
+
+    /// Zero-shot classification: the probability of each label, put into
+    /// `LABEL_TEMPLATE`, most likely first. Labels are scored independently, so the
+    /// probabilities need not sum to one.
+    pub fn classify(&self, image: &DynamicImage, labels: &[&str]) -> Result<Vec<(String, f32)>> {
+        let prompts: Vec<String> = labels
+            .iter()
+            .map(|label| LABEL_TEMPLATE.replace("{}", label))
+            .collect();
+        let prompts: Vec<&str> = prompts.iter().map(String::as_str).collect();
+        let (image_embeds, text_embeds) = self.embed(std::slice::from_ref(image), &prompts)?;
+        let probs = self
+            .similarity(&image_embeds, &text_embeds)?
+            .squeeze(0)?
+            .to_vec1::<f32>()?;
+
+        let mut scored: Vec<(String, f32)> = labels
+            .iter()
+            .map(|label| label.to_string())
+            .zip(probs)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(scored)
+    }
+
+    /// Images matching `query` best, as (row of `image_embeds`, probability), at most
+    /// `top_k` of them
+    pub fn search(
+        &self,
+        query: &str,
+        image_embeds: &Tensor,
+        top_k: usize,
+    ) -> Result<Vec<(usize, f32)>> {
+        let text_embeds = self.embed_texts(&[query])?;
+        let probs = self
+            .similarity(image_embeds, &text_embeds)?
+            .squeeze(1)?
+            .to_vec1::<f32>()?;
+
+        let mut ranked: Vec<(usize, f32)> = probs.into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top_k);
+        Ok(ranked)
+    }
+}
 
-    // let output = model.forward(&pixel_values, &attention_mask, &spatial_shapes)?;
+fn normalize(xs: &Tensor) -> Result<Tensor> {
+    let xs = xs.to_dtype(DType::F32)?;
+    xs.broadcast_div(&xs.sqr()?.sum_keepdim(1)?.sqrt()?)
 }
@@ -379,6 +379,11 @@ impl Vad {
         self.segments.is_empty() && !self.triggered
     }
 
+    /// Returns `true` while speech is going on, before its segment is complete.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+
     /// Resets the VAD state.
     pub fn reset(&mut self) -> Result<()> {
         let sr = Tensor::new(self.sample_rate as i64, &self.device)?;
@@ -0,0 +1,1876 @@
+// model for Spark-TTS
+// Qwen2.5-0.5B writes BiCodec tokens: 32 global tokens for the speaker and 50 semantic tokens
+// per second for the content. BiCodec decodes them to 16 kHz audio. The semantic tokens of a
+// reference clip are read from wav2vec2-large-xlsr-53 features, its global tokens from a mel
+// spectrogram.
+// https://huggingface.co/SparkAudio/Spark-TTS-0.5B
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Error as E, Result};
+use candle_core::{DType, Device, ModuleT, Tensor, D};
+use candle_nn::{
+    batch_norm, conv1d, embedding, layer_norm, linear, linear_no_bias, BatchNorm, Conv1d,
+    Conv1dConfig, ConvTranspose1d, ConvTranspose1dConfig, Embedding, LayerNorm, Linear, Module,
+    VarBuilder,
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::generation::audio_streamer::{AudioChunk, AudioStreamer, FrameCodec, StreamingConfig};
+use crate::generation::based::ModelForCausalLM;
+use crate::generation::streamer::TokenStreamer;
+use crate::generation::GenerationConfig;
+use crate::models::qwen25;
+use crate::utils::utils;
+
+/// Sample rate of BiCodec, for both the reference clip and the generated audio
+pub const SAMPLE_RATE: u32 = 16000;
+/// Number of global tokens describing a speaker
+pub const GLOBAL_TOKENS: usize = 32;
+
+/// Samples per semantic token
+const HOP_LENGTH: usize = 320;
+/// The speaker is read from the first 6 seconds of the reference
+const REF_SEGMENT_SAMPLES: usize = 6 * SAMPLE_RATE as usize / HOP_LENGTH * HOP_LENGTH;
+/// wav2vec2 hidden states averaged into the semantic features
+const FEATURE_LAYERS: [usize; 3] = [11, 14, 16];
+
+/// Speaker gender of the controllable mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gender {
+    #[default]
+    Female,
+    Male,
+}
+
+impl Gender {
+    fn id(self) -> usize {
+        match self {
+            Gender::Female => 0,
+            Gender::Male => 1,
+        }
+    }
+}
+
+impl std::str::FromStr for Gender {
+    type Err = E;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "female" => Ok(Gender::Female),
+            "male" => Ok(Gender::Male),
+            _ => anyhow::bail!("unknown gender {s:?}, expected female or male"),
+        }
+    }
+}
+
+/// Pitch or speed level of the controllable mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    VeryLow,
+    Low,
+    #[default]
+    Moderate,
+    High,
+    VeryHigh,
+}
+
+impl Level {
+    pub const NAMES: [&str; 5] = ["very_low", "low", "moderate", "high", "very_high"];
+
+    fn id(self) -> usize {
+        self as usize
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = E;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "very_low" => Ok(Level::VeryLow),
+            "low" => Ok(Level::Low),
+            "moderate" => Ok(Level::Moderate),
+            "high" => Ok(Level::High),
+            "very_high" => Ok(Level::VeryHigh),
+            _ => anyhow::bail!(
+                "unknown level {s:?}, expected one of {}",
+                Self::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// BiCodec tokens of a reference recording
+#[derive(Debug, Clone)]
+pub struct VoicePrompt {
+    pub global_tokens: Vec<u32>,
+    pub semantic_tokens: Vec<u32>,
+    /// What is said in the recording. With it the model continues the recording, which
+    /// copies the voice more closely; without it only the global tokens are used.
+    pub transcript: Option<String>,
+}
+
+/// Who speaks
+#[derive(Debug, Clone)]
+pub enum SparkVoice {
+    /// Zero-shot cloning of a reference recording. Pitch and pace follow the reference.
+    Clone(VoicePrompt),
+    /// A voice described by gender, pitch and speed
+    Control {
+        gender: Gender,
+        pitch: Level,
+        speed: Level,
+    },
+}
+
+impl Default for SparkVoice {
+    fn default() -> Self {
+        SparkVoice::Control {
+            gender: Gender::default(),
+            pitch: Level::default(),
+            speed: Level::default(),
+        }
+    }
+}
+
+/// Structure of the released BiCodec checkpoint (`BiCodec/config.yaml`)
+#[derive(Debug, Clone)]
+pub struct BiCodecConfig {
+    pub n_fft: usize,
+    pub win_length: usize,
+    pub hop_length: usize,
+    pub mel_fmin: f64,
+    pub mel_fmax: f64,
+    pub num_mels: usize,
+    pub feature_dim: usize,
+    pub vocos_dim: usize,
+    pub vocos_intermediate_dim: usize,
+    pub vocos_num_layers: usize,
+    pub encoder_sample_ratios: Vec<usize>,
+    pub prenet_sample_ratios: Vec<usize>,
+    pub codebook_size: usize,
+    pub codebook_dim: usize,
+    pub speaker_latent_dim: usize,
+    pub speaker_out_dim: usize,
+    pub fsq_levels: Vec<u32>,
+    pub decoder_channels: usize,
+    pub decoder_rates: Vec<usize>,
+    pub decoder_kernel_sizes: Vec<usize>,
+}
+
+impl Default for BiCodecConfig {
+    fn default() -> Self {
+        Self {
+            n_fft: 1024,
+            win_length: 640,
+            hop_length: HOP_LENGTH,
+            mel_fmin: 10.0,
+            mel_fmax: SAMPLE_RATE as f64 / 2.0,
+            num_mels: 128,
+            feature_dim: 1024,
+            vocos_dim: 384,
+            vocos_intermediate_dim: 2048,
+            vocos_num_layers: 12,
+            encoder_sample_ratios: vec![1, 1],
+            prenet_sample_ratios: vec![1, 1],
+            codebook_size: 8192,
+            codebook_dim: 8,
+            speaker_latent_dim: 128,
+            speaker_out_dim: 1024,
+            fsq_levels: vec![4; 6],
+            decoder_channels: 1536,
+            decoder_rates: vec![8, 5, 4, 2],
+            decoder_kernel_sizes: vec![16, 11, 8, 4],
+        }
+    }
+}
+
+/// Weight of a layer trained with weight normalization, `g * v / |v|`. The norm is taken over
+/// every dimension but `dim`. Checkpoints with the normalization removed keep a plain `weight`.
+fn weight_norm(vb: &VarBuilder, shape: (usize, usize, usize), dim: usize) -> Result<Tensor> {
+    if vb.contains_tensor("weight") {
+        return Ok(vb.get(shape, "weight")?);
+    }
+    let dims = [shape.0, shape.1, shape.2];
+    let mut g_shape = [1, 1, 1];
+    g_shape[dim] = dims[dim];
+    let (g, v) = if vb.contains_tensor("parametrizations.weight.original0") {
+        (
+            vb.get(g_shape.to_vec(), "parametrizations.weight.original0")?,
+            vb.get(shape, "parametrizations.weight.original1")?,
+        )
+    } else {
+        (
+            vb.get(g_shape.to_vec(), "weight_g")?,
+            vb.get(shape, "weight_v")?,
+        )
+    };
+    let mut norm = v.sqr()?;
+    for d in (0..3).filter(|&d| d != dim) {
+        norm = norm.sum_keepdim(d)?;
+    }
+    Ok(v.broadcast_mul(&g.broadcast_div(&norm.sqrt()?)?)?)
+}
+
+fn wn_conv1d(
+    in_c: usize,
+    out_c: usize,
+    kernel_size: usize,
+    config: Conv1dConfig,
+    vb: VarBuilder,
+) -> Result<Conv1d> {
+    let weight = weight_norm(&vb, (out_c, in_c / config.groups, kernel_size), 0)?;
+    let bias = vb.get(out_c, "bias")?;
+    Ok(Conv1d::new(weight, Some(bias), config))
+}
+
+fn wn_conv_transpose1d(
+    in_c: usize,
+    out_c: usize,
+    kernel_size: usize,
+    config: ConvTranspose1dConfig,
+    vb: VarBuilder,
+) -> Result<ConvTranspose1d> {
+    let weight = weight_norm(&vb, (in_c, out_c, kernel_size), 0)?;
+    let bias = vb.get(out_c, "bias")?;
+    Ok(ConvTranspose1d::new(weight, Some(bias), config))
+}
+
+/// Layer norm over the last dimension without affine parameters
+fn normalize_last_dim(xs: &Tensor, eps: f64) -> Result<Tensor> {
+    let mean = xs.mean_keepdim(D::Minus1)?;
+    let xs = xs.broadcast_sub(&mean)?;
+    let var = xs.sqr()?.mean_keepdim(D::Minus1)?;
+    Ok(xs.broadcast_div(&(var + eps)?.sqrt()?)?)
+}
+
+/// Apply a layer norm over the channels of a `[batch, channels, time]` tensor
+fn channel_layer_norm(norm: &LayerNorm, xs: &Tensor) -> Result<Tensor> {
+    Ok(norm.forward(&xs.transpose(1, 2)?)?.transpose(1, 2)?)
+}
+
+// ---------------------------------------------------------------------------------------------
+// wav2vec2-large-xlsr-53, up to the last layer that feeds the semantic features
+
+#[derive(Debug, Clone)]
+struct FeatureConvLayer {
+    conv: Conv1d,
+    norm: LayerNorm,
+}
+
+impl FeatureConvLayer {
+    fn new(in_c: usize, kernel_size: usize, stride: usize, vb: VarBuilder) -> Result<Self> {
+        let config = Conv1dConfig {
+            stride,
+            ..Default::default()
+        };
+        Ok(Self {
+            conv: conv1d(in_c, 512, kernel_size, config, vb.pp("conv"))?,
+            norm: layer_norm(512, 1e-5, vb.pp("layer_norm"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.conv.forward(xs)?;
+        Ok(channel_layer_norm(&self.norm, &xs)?.gelu_erf()?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Wav2Vec2Layer {
+    layer_norm: LayerNorm,
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+    final_layer_norm: LayerNorm,
+    intermediate_dense: Linear,
+    output_dense: Linear,
+    num_heads: usize,
+}
+
+impl Wav2Vec2Layer {
+    fn new(hidden: usize, intermediate: usize, num_heads: usize, vb: VarBuilder) -> Result<Self> {
+        let attn = vb.pp("attention");
+        let ff = vb.pp("feed_forward");
+        Ok(Self {
+            layer_norm: layer_norm(hidden, 1e-5, vb.pp("layer_norm"))?,
+            q_proj: linear(hidden, hidden, attn.pp("q_proj"))?,
+            k_proj: linear(hidden, hidden, attn.pp("k_proj"))?,
+            v_proj: linear(hidden, hidden, attn.pp("v_proj"))?,
+            out_proj: linear(hidden, hidden, attn.pp("out_proj"))?,
+            final_layer_norm: layer_norm(hidden, 1e-5, vb.pp("final_layer_norm"))?,
+            intermediate_dense: linear(hidden, intermediate, ff.pp("intermediate_dense"))?,
+            output_dense: linear(intermediate, hidden, ff.pp("output_dense"))?,
+            num_heads,
+        })
+    }
+
+    fn attention(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b, t, hidden) = xs.dims3()?;
+        let head_dim = hidden / self.num_heads;
+        let heads = |x: Tensor| -> Result<Tensor> {
+            Ok(x.reshape((b, t, self.num_heads, head_dim))?
+                .transpose(1, 2)?
+                .contiguous()?)
+        };
+        let q = (heads(self.q_proj.forward(xs)?)? / (head_dim as f64).sqrt())?;
+        let k = heads(self.k_proj.forward(xs)?)?;
+        let v = heads(self.v_proj.forward(xs)?)?;
+        let weights = candle_nn::ops::softmax_last_dim(&q.matmul(&k.t()?)?)?;
+        let out = weights
+            .matmul(&v)?
+            .transpose(1, 2)?
+            .reshape((b, t, hidden))?;
+        Ok(self.out_proj.forward(&out)?)
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = (xs + self.attention(&self.layer_norm.forward(xs)?)?)?;
+        let ff = self.final_layer_norm.forward(&xs)?;
+        let ff = self.intermediate_dense.forward(&ff)?.gelu_erf()?;
+        Ok((&xs + self.output_dense.forward(&ff)?)?)
+    }
+}
+
+/// The stable-layer-norm wav2vec2 encoder, truncated after the deepest layer BiCodec reads
+#[derive(Debug, Clone)]
+pub struct Wav2Vec2 {
+    conv_layers: Vec<FeatureConvLayer>,
+    projection_norm: LayerNorm,
+    projection: Linear,
+    pos_conv: Conv1d,
+    layers: Vec<Wav2Vec2Layer>,
+}
+
+impl Wav2Vec2 {
+    const HIDDEN: usize = 1024;
+
+    pub fn new(vb: VarBuilder) -> Result<Self> {
+        // pretraining checkpoints nest the encoder under `wav2vec2.`
+        let vb = if vb.contains_tensor("wav2vec2.feature_projection.projection.weight") {
+            vb.pp("wav2vec2")
+        } else {
+            vb
+        };
+        let kernels = [10, 3, 3, 3, 3, 2, 2];
+        let strides = [5, 2, 2, 2, 2, 2, 2];
+        let conv_layers = (0..kernels.len())
+            .map(|i| {
+                let in_c = if i == 0 { 1 } else { 512 };
+                let vb = vb.pp(format!("feature_extractor.conv_layers.{i}"));
+                FeatureConvLayer::new(in_c, kernels[i], strides[i], vb)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let fp = vb.pp("feature_projection");
+        let projection_norm = layer_norm(512, 1e-5, fp.pp("layer_norm"))?;
+        let projection = linear(512, Self::HIDDEN, fp.pp("projection"))?;
+
+        let encoder = vb.pp("encoder");
+        let pos_config = Conv1dConfig {
+            padding: 64,
+            groups: 16,
+            ..Default::default()
+        };
+        let pos_vb = encoder.pp("pos_conv_embed.conv");
+        let pos_weight = weight_norm(&pos_vb, (Self::HIDDEN, Self::HIDDEN / 16, 128), 2)?;
+        let pos_bias = pos_vb.get(Self::HIDDEN, "bias")?;
+        let pos_conv = Conv1d::new(pos_weight, Some(pos_bias), pos_config);
+
+        let depth = FEATURE_LAYERS.iter().max().copied().unwrap_or_default();
+        let layers = (0..depth)
+            .map(|i| Wav2Vec2Layer::new(Self::HIDDEN, 4096, 16, encoder.pp(format!("layers.{i}"))))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            conv_layers,
+            projection_norm,
+            projection,
+            pos_conv,
+            layers,
+        })
+    }
+
+    /// Semantic features `[1, frames, 1024]` of 16 kHz mono audio, one frame per 320 samples
+    pub fn features(&self, wav: &[f32], device: &Device) -> Result<Tensor> {
+        // zero mean, unit variance, as the feature extractor's preprocessor does
+        let mean = wav.iter().sum::<f32>() / wav.len().max(1) as f32;
+        let var = wav.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / wav.len().max(1) as f32;
+        let scale = 1.0 / (var + 1e-7).sqrt();
+        let input: Vec<f32> = wav.iter().map(|x| (x - mean) * scale).collect();
+
+        let mut xs = Tensor::from_vec(input, (1, 1, wav.len()), device)?;
+        for layer in &self.conv_layers {
+            xs = layer.forward(&xs)?;
+        }
+        let xs = self.projection_norm.forward(&xs.transpose(1, 2)?)?;
+        let xs = self.projection.forward(&xs)?;
+
+        // the even kernel leaves one frame too many
+        let frames = xs.dim(1)?;
+        let pos = self
+            .pos_conv
+            .forward(&xs.transpose(1, 2)?)?
+            .narrow(2, 0, frames)?
+            .gelu_erf()?
+            .transpose(1, 2)?;
+        let mut xs = (xs + pos)?;
+
+        let mut mixed: Option<Tensor> = None;
+        for (i, layer) in self.layers.iter().enumerate() {
+            xs = layer.forward(&xs)?;
+            if FEATURE_LAYERS.contains(&(i + 1)) {
+                mixed = Some(match mixed {
+                    Some(m) => (m + &xs)?,
+                    None => xs.clone(),
+                });
+            }
+        }
+        let mixed = mixed.ok_or_else(|| E::msg("no wav2vec2 layers"))?;
+        Ok((mixed / FEATURE_LAYERS.len() as f64)?)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Vocos backbone, used by the BiCodec encoder and prenet
+
+#[derive(Debug, Clone)]
+enum BackboneNorm {
+    Plain(LayerNorm),
+    /// Scale and shift predicted from the speaker vector
+    Ada {
+        scale: Linear,
+        shift: Linear,
+    },
+}
+
+impl BackboneNorm {
+    fn new(dim: usize, condition_dim: Option<usize>, vb: VarBuilder) -> Result<Self> {
+        Ok(match condition_dim {
+            Some(c) => BackboneNorm::Ada {
+                scale: linear(c, dim, vb.pp("scale"))?,
+                shift: linear(c, dim, vb.pp("shift"))?,
+            },
+            None => BackboneNorm::Plain(layer_norm(dim, 1e-6, vb)?),
+        })
+    }
+
+    /// `xs` is `[batch, time, channels]`
+    fn forward(&self, xs: &Tensor, condition: Option<&Tensor>) -> Result<Tensor> {
+        match (self, condition) {
+            (BackboneNorm::Plain(norm), _) => Ok(norm.forward(xs)?),
+            (BackboneNorm::Ada { scale, shift }, Some(c)) => {
+                let xs = normalize_last_dim(xs, 1e-6)?;
+                let scale = scale.forward(c)?.unsqueeze(1)?;
+                let shift = shift.forward(c)?.unsqueeze(1)?;
+                Ok(xs.broadcast_mul(&scale)?.broadcast_add(&shift)?)
+            }
+            (BackboneNorm::Ada { .. }, None) => anyhow::bail!("adaptive norm needs a condition"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ConvNeXtBlock {
+    dwconv: Conv1d,
+    norm: BackboneNorm,
+    pwconv1: Linear,
+    pwconv2: Linear,
+    gamma: Tensor,
+}
+
+impl ConvNeXtBlock {
+    fn new(
+        dim: usize,
+        intermediate_dim: usize,
+        condition_dim: Option<usize>,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let config = Conv1dConfig {
+            padding: 3,
+            groups: dim,
+            ..Default::default()
+        };
+        Ok(Self {
+            dwconv: conv1d(dim, dim, 7, config, vb.pp("dwconv"))?,
+            norm: BackboneNorm::new(dim, condition_dim, vb.pp("norm"))?,
+            pwconv1: linear(dim, intermediate_dim, vb.pp("pwconv1"))?,
+            pwconv2: linear(intermediate_dim, dim, vb.pp("pwconv2"))?,
+            gamma: vb.get(dim, "gamma")?,
+        })
+    }
+
+    /// `xs` is `[batch, channels, time]`
+    fn forward(&self, xs: &Tensor, condition: Option<&Tensor>) -> Result<Tensor> {
+        let ys = self.dwconv.forward(xs)?.transpose(1, 2)?;
+        let ys = self.norm.forward(&ys, condition)?;
+        let ys = self.pwconv1.forward(&ys)?.gelu_erf()?;
+        let ys = self.pwconv2.forward(&ys)?.broadcast_mul(&self.gamma)?;
+        Ok((xs + ys.transpose(1, 2)?)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VocosBackbone {
+    embed: Conv1d,
+    norm: BackboneNorm,
+    convnext: Vec<ConvNeXtBlock>,
+    final_layer_norm: LayerNorm,
+}
+
+impl VocosBackbone {
+    fn new(
+        input_channels: usize,
+        dim: usize,
+        intermediate_dim: usize,
+        num_layers: usize,
+        condition_dim: Option<usize>,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let config = Conv1dConfig {
+            padding: 3,
+            ..Default::default()
+        };
+        let convnext = (0..num_layers)
+            .map(|i| {
+                ConvNeXtBlock::new(
+                    dim,
+                    intermediate_dim,
+                    condition_dim,
+                    vb.pp(format!("convnext.{i}")),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            embed: conv1d(input_channels, dim, 7, config, vb.pp("embed"))?,
+            norm: BackboneNorm::new(dim, condition_dim, vb.pp("norm"))?,
+            convnext,
+            final_layer_norm: layer_norm(dim, 1e-6, vb.pp("final_layer_norm"))?,
+        })
+    }
+
+    /// `[batch, channels, time]` in, `[batch, time, dim]` out
+    fn forward(&self, xs: &Tensor, condition: Option<&Tensor>) -> Result<Tensor> {
+        let xs = self.embed.forward(xs)?;
+        let mut xs = self
+            .norm
+            .forward(&xs.transpose(1, 2)?, condition)?
+            .transpose(1, 2)?;
+        for block in &self.convnext {
+            xs = block.forward(&xs, condition)?;
+        }
+        Ok(self.final_layer_norm.forward(&xs.transpose(1, 2)?)?)
+    }
+}
+
+/// Resampling in time: repeat plus a learned transposed conv to go up, average pooling plus a
+/// strided conv to go down. At a ratio of 1 it sums three copies of its input.
+#[derive(Debug, Clone)]
+struct SamplingBlock {
+    upsample: Option<(usize, ConvTranspose1d)>,
+    downsample: Option<(usize, Conv1d)>,
+}
+
+impl SamplingBlock {
+    fn new(dim: usize, upsample: usize, downsample: usize, vb: VarBuilder) -> Result<Self> {
+        let upsample = if upsample > 1 {
+            let config = ConvTranspose1dConfig {
+                padding: upsample / 2 + upsample % 2,
+                output_padding: upsample % 2,
+                stride: upsample,
+                dilation: 1,
+                groups: dim,
+            };
+            let vb = vb.pp("de_conv_upsampler.1");
+            let weight = vb.get((dim, 1, upsample * 2), "weight")?;
+            let bias = vb.get(dim, "bias")?;
+            Some((upsample, ConvTranspose1d::new(weight, Some(bias), config)))
+        } else {
+            None
+        };
+        let downsample = if downsample > 1 {
+            let config = Conv1dConfig {
+                padding: downsample / 2 + downsample % 2,
+                stride: downsample,
+                groups: dim,
+                ..Default::default()
+            };
+            let conv = conv1d(
+                dim,
+                dim,
+                downsample * 2,
+                config,
+                vb.pp("conv_downsampler.1"),
+            )?;
+            Some((downsample, conv))
+        } else {
+            None
+        };
+        Ok(Self {
+            upsample,
+            downsample,
+        })
+    }
+
+    fn avg_pool(xs: &Tensor, scale: usize) -> Result<Tensor> {
+        let (b, c, t) = xs.dims3()?;
+        let t = t / scale;
+        Ok(xs
+            .narrow(2, 0, t * scale)?
+            .reshape((b, c, t, scale))?
+            .mean(3)?)
+    }
+
+    /// `[batch, time, dim]` in, `[batch, dim, time]` out
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = xs.transpose(1, 2)?.contiguous()?;
+        let (repeated, merged) = match &self.upsample {
+            Some((scale, deconv)) => {
+                let (b, c, t) = xs.dims3()?;
+                let repeated =
+                    xs.unsqueeze(3)?
+                        .broadcast_as((b, c, t, *scale))?
+                        .reshape((b, c, t * scale))?;
+                let deconv = deconv.forward(&candle_nn::ops::leaky_relu(&xs, 0.2)?)?;
+                let merged = (&repeated + deconv)?;
+                (repeated, merged)
+            }
+            None => (xs.clone(), xs),
+        };
+        match &self.downsample {
+            Some((scale, conv)) => {
+                let conv_res = conv.forward(&candle_nn::ops::leaky_relu(&merged, 0.2)?)?;
+                let skip2 = Self::avg_pool(&merged, *scale)?;
+                let skip1 = Self::avg_pool(&repeated, *scale)?;
+                Ok(((conv_res + skip1)? + skip2)?)
+            }
+            None => Ok(((&merged + &repeated)? + &merged)?),
+        }
+    }
+}
+
+/// A sampling block followed by a two-layer backbone
+#[derive(Debug, Clone)]
+struct SamplingStage {
+    sampling: SamplingBlock,
+    backbone: VocosBackbone,
+}
+
+impl SamplingStage {
+    fn new(
+        config: &BiCodecConfig,
+        upsample: usize,
+        downsample: usize,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let dim = config.vocos_dim;
+        Ok(Self {
+            sampling: SamplingBlock::new(dim, upsample, downsample, vb.pp("0"))?,
+            backbone: VocosBackbone::new(
+                dim,
+                dim,
+                config.vocos_intermediate_dim,
+                2,
+                None,
+                vb.pp("1"),
+            )?,
+        })
+    }
+
+    /// `[batch, time, dim]` in and out
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.sampling.forward(xs)?;
+        self.backbone.forward(&xs, None)
+    }
+}
+
+/// wav2vec2 features to the latents the semantic quantizer reads
+#[derive(Debug, Clone)]
+struct FeatureEncoder {
+    encoder: VocosBackbone,
+    downsample: Vec<SamplingStage>,
+    project: Linear,
+}
+
+impl FeatureEncoder {
+    fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        let downsample = config
+            .encoder_sample_ratios
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| SamplingStage::new(config, 1, r, vb.pp(format!("downsample.{i}"))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            encoder: VocosBackbone::new(
+                config.feature_dim,
+                config.vocos_dim,
+                config.vocos_intermediate_dim,
+                config.vocos_num_layers,
+                None,
+                vb.pp("encoder"),
+            )?,
+            downsample,
+            project: linear(config.vocos_dim, config.feature_dim, vb.pp("project"))?,
+        })
+    }
+
+    /// `[batch, 1024, frames]` in and out
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = self.encoder.forward(xs, None)?;
+        for stage in &self.downsample {
+            xs = stage.forward(&xs)?;
+        }
+        Ok(self.project.forward(&xs)?.transpose(1, 2)?)
+    }
+}
+
+/// Quantized latents plus the speaker vector to the features the wave generator reads
+#[derive(Debug, Clone)]
+struct Prenet {
+    linear_pre: Linear,
+    upsample: Vec<SamplingStage>,
+    backbone: VocosBackbone,
+    linear: Linear,
+}
+
+impl Prenet {
+    fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        let upsample = config
+            .prenet_sample_ratios
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| SamplingStage::new(config, r, 1, vb.pp(format!("downsample.{i}"))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            linear_pre: linear(config.feature_dim, config.vocos_dim, vb.pp("linear_pre"))?,
+            upsample,
+            backbone: VocosBackbone::new(
+                config.vocos_dim,
+                config.vocos_dim,
+                config.vocos_intermediate_dim,
+                config.vocos_num_layers,
+                Some(config.speaker_out_dim),
+                vb.pp("vocos_backbone"),
+            )?,
+            linear: linear(config.vocos_dim, config.feature_dim, vb.pp("linear"))?,
+        })
+    }
+
+    /// `[batch, 1024, frames]` and a `[batch, 1024]` speaker vector in, `[batch, 1024, frames]` out
+    fn forward(&self, xs: &Tensor, d_vector: &Tensor) -> Result<Tensor> {
+        let mut xs = self.linear_pre.forward(&xs.transpose(1, 2)?)?;
+        for stage in &self.upsample {
+            xs = stage.forward(&xs)?;
+        }
+        let xs = self
+            .backbone
+            .forward(&xs.transpose(1, 2)?, Some(d_vector))?;
+        Ok(self.linear.forward(&xs)?.transpose(1, 2)?)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Semantic quantizer
+
+/// Factorized VQ: project to 8 dims and look up the closest of 8192 L2-normalized codes
+#[derive(Debug, Clone)]
+struct FactorizedVQ {
+    in_project: Conv1d,
+    out_project: Conv1d,
+    codebook: Embedding,
+}
+
+impl FactorizedVQ {
+    fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        let (dim, code_dim) = (config.feature_dim, config.codebook_dim);
+        Ok(Self {
+            in_project: wn_conv1d(dim, code_dim, 1, Default::default(), vb.pp("in_project"))?,
+            out_project: wn_conv1d(code_dim, dim, 1, Default::default(), vb.pp("out_project"))?,
+            codebook: embedding(config.codebook_size, code_dim, vb.pp("codebook"))?,
+        })
+    }
+
+    fn l2_normalize(xs: &Tensor) -> Result<Tensor> {
+        let norm = xs
+            .sqr()?
+            .sum_keepdim(D::Minus1)?
+            .sqrt()?
+            .clamp(1e-12, f64::MAX)?;
+        Ok(xs.broadcast_div(&norm)?)
+    }
+
+    /// `[1, 1024, frames]` latents to one code per frame
+    fn tokenize(&self, xs: &Tensor) -> Result<Vec<u32>> {
+        let latents = self.in_project.forward(xs)?.squeeze(0)?.t()?;
+        let latents = Self::l2_normalize(&latents)?;
+        let codebook = Self::l2_normalize(self.codebook.embeddings())?;
+        // on the unit sphere the nearest code is the one with the largest dot product
+        let similarity = latents.matmul(&codebook.t()?)?;
+        Ok(similarity.argmax(D::Minus1)?.to_vec1::<u32>()?)
+    }
+
+    /// Codes to `[1, 1024, frames]` latents
+    fn detokenize(&self, codes: &Tensor) -> Result<Tensor> {
+        let xs = self.codebook.forward(codes)?.t()?.unsqueeze(0)?;
+        Ok(self.out_project.forward(&xs)?)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Speaker encoder: ECAPA-TDNN features, a perceiver resampler to 32 latents and FSQ
+
+#[derive(Debug, Clone)]
+struct ConvReluBn {
+    conv: Conv1d,
+    bn: BatchNorm,
+}
+
+impl ConvReluBn {
+    fn new(
+        in_c: usize,
+        out_c: usize,
+        kernel_size: usize,
+        config: Conv1dConfig,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        Ok(Self {
+            conv: conv1d(in_c, out_c, kernel_size, config, vb.pp("conv"))?,
+            bn: batch_norm(out_c, 1e-5, vb.pp("bn"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        Ok(self.bn.forward_t(&self.conv.forward(xs)?.relu()?, false)?)
+    }
+}
+
+/// Res2Net conv: channel groups are convolved in turn, each one fed the previous group's output
+#[derive(Debug, Clone)]
+struct Res2Conv {
+    convs: Vec<Conv1d>,
+    bns: Vec<BatchNorm>,
+    width: usize,
+}
+
+impl Res2Conv {
+    fn new(channels: usize, dilation: usize, scale: usize, vb: VarBuilder) -> Result<Self> {
+        let width = channels / scale;
+        let config = Conv1dConfig {
+            padding: dilation,
+            dilation,
+            ..Default::default()
+        };
+        let convs = (0..scale - 1)
+            .map(|i| conv1d(width, width, 3, config, vb.pp(format!("convs.{i}"))))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let bns = (0..scale - 1)
+            .map(|i| batch_norm(width, 1e-5, vb.pp(format!("bns.{i}"))))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        Ok(Self { convs, bns, width })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut outs = Vec::with_capacity(self.convs.len() + 1);
+        let mut sp: Option<Tensor> = None;
+        for (i, (conv, bn)) in self.convs.iter().zip(&self.bns).enumerate() {
+            let split = xs.narrow(1, i * self.width, self.width)?;
+            let input = match sp {
+                Some(prev) => (prev + split)?,
+                None => split,
+            };
+            let out = bn.forward_t(&conv.forward(&input)?.relu()?, false)?;
+            sp = Some(out.clone());
+            outs.push(out);
+        }
+        outs.push(xs.narrow(1, self.convs.len() * self.width, self.width)?);
+        Ok(Tensor::cat(&outs, 1)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SeRes2Block {
+    conv_in: ConvReluBn,
+    res2: Res2Conv,
+    conv_out: ConvReluBn,
+    se_linear1: Linear,
+    se_linear2: Linear,
+}
+
+impl SeRes2Block {
+    fn new(channels: usize, dilation: usize, vb: VarBuilder) -> Result<Self> {
+        let vb = vb.pp("se_res2block");
+        Ok(Self {
+            conv_in: ConvReluBn::new(channels, channels, 1, Default::default(), vb.pp("0"))?,
+            res2: Res2Conv::new(channels, dilation, 8, vb.pp("1"))?,
+            conv_out: ConvReluBn::new(channels, channels, 1, Default::default(), vb.pp("2"))?,
+            se_linear1: linear(channels, 128, vb.pp("3.linear1"))?,
+            se_linear2: linear(128, channels, vb.pp("3.linear2"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.conv_in.forward(xs)?;
+        let ys = self.res2.forward(&ys)?;
+        let ys = self.conv_out.forward(&ys)?;
+        // squeeze-and-excitation: reweight channels from their mean over time
+        let se = self.se_linear1.forward(&ys.mean(2)?)?.relu()?;
+        let se = candle_nn::ops::sigmoid(&self.se_linear2.forward(&se)?)?;
+        let ys = ys.broadcast_mul(&se.unsqueeze(2)?)?;
+        Ok((xs + ys)?)
+    }
+}
+
+/// ECAPA-TDNN up to its frame-level features; the pooled x-vector is not used by BiCodec
+#[derive(Debug, Clone)]
+struct EcapaTdnn {
+    layer1: ConvReluBn,
+    blocks: Vec<SeRes2Block>,
+    conv: Conv1d,
+}
+
+impl EcapaTdnn {
+    const CHANNELS: usize = 512;
+
+    fn new(feat_dim: usize, vb: VarBuilder) -> Result<Self> {
+        let c = Self::CHANNELS;
+        let config = Conv1dConfig {
+            padding: 2,
+            ..Default::default()
+        };
+        let blocks = [2, 3, 4]
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| SeRes2Block::new(c, d, vb.pp(format!("layer{}", i + 2))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            layer1: ConvReluBn::new(feat_dim, c, 5, config, vb.pp("layer1"))?,
+            blocks,
+            conv: conv1d(c * 3, c * 3, 1, Default::default(), vb.pp("conv"))?,
+        })
+    }
+
+    /// `[batch, mels, frames]` to `[batch, 1536, frames]`
+    fn forward(&self, mels: &Tensor) -> Result<Tensor> {
+        let mut xs = self.layer1.forward(mels)?;
+        let mut outs = Vec::with_capacity(self.blocks.len());
+        for block in &self.blocks {
+            xs = block.forward(&xs)?;
+            outs.push(xs.clone());
+        }
+        Ok(self.conv.forward(&Tensor::cat(&outs, 1)?)?.relu()?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PerceiverLayer {
+    to_q: Linear,
+    to_kv: Linear,
+    to_out: Linear,
+    ff_in: Linear,
+    ff_out: Linear,
+}
+
+/// Cross-attention of 32 learned latents over the ECAPA features
+#[derive(Debug, Clone)]
+struct PerceiverResampler {
+    proj_context: Linear,
+    latents: Tensor,
+    layers: Vec<PerceiverLayer>,
+    norm_gamma: Tensor,
+}
+
+impl PerceiverResampler {
+    const HEADS: usize = 8;
+    const HEAD_DIM: usize = 64;
+
+    fn new(dim: usize, dim_context: usize, vb: VarBuilder) -> Result<Self> {
+        let inner = Self::HEADS * Self::HEAD_DIM;
+        let ff_inner = dim * 4 * 2 / 3;
+        let layers = (0..2)
+            .map(|i| -> Result<PerceiverLayer> {
+                let vb = vb.pp(format!("layers.{i}"));
+                Ok(PerceiverLayer {
+                    to_q: linear_no_bias(dim, inner, vb.pp("0.to_q"))?,
+                    to_kv: linear_no_bias(dim, inner * 2, vb.pp("0.to_kv"))?,
+                    to_out: linear_no_bias(inner, dim, vb.pp("0.to_out"))?,
+                    ff_in: linear(dim, ff_inner * 2, vb.pp("1.0"))?,
+                    ff_out: linear(ff_inner, dim, vb.pp("1.2"))?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            proj_context: linear(dim_context, dim, vb.pp("proj_context"))?,
+            latents: vb.get((GLOBAL_TOKENS, dim), "latents")?,
+            layers,
+            norm_gamma: vb.get(dim, "norm.gamma")?,
+        })
+    }
+
+    fn heads(xs: &Tensor) -> Result<Tensor> {
+        let (b, n, _) = xs.dims3()?;
+        Ok(xs
+            .reshape((b, n, Self::HEADS, Self::HEAD_DIM))?
+            .transpose(1, 2)?
+            .contiguous()?)
+    }
+
+    /// `[batch, frames, 1536]` to `[batch, 32, dim]`
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let b = xs.dim(0)?;
+        let context = self.proj_context.forward(xs)?;
+        let mut latents = self.latents.unsqueeze(0)?.repeat((b, 1, 1))?;
+        for layer in &self.layers {
+            // the latents attend to themselves as well as to the context
+            let kv_input = Tensor::cat(&[&latents, &context], 1)?;
+            let q = Self::heads(&layer.to_q.forward(&latents)?)?;
+            let kv = layer.to_kv.forward(&kv_input)?;
+            let inner = Self::HEADS * Self::HEAD_DIM;
+            let k = Self::heads(&kv.narrow(D::Minus1, 0, inner)?)?;
+            let v = Self::heads(&kv.narrow(D::Minus1, inner, inner)?)?;
+            let q = (q / (Self::HEAD_DIM as f64).sqrt())?;
+            let weights = candle_nn::ops::softmax_last_dim(&q.matmul(&k.t()?)?)?;
+            let n = latents.dim(1)?;
+            let attn = weights
+                .matmul(&v)?
+                .transpose(1, 2)?
+                .reshape((b, n, inner))?;
+            latents = (layer.to_out.forward(&attn)? + latents)?;
+
+            // GEGLU feed-forward
+            let ff = layer.ff_in.forward(&latents)?;
+            let half = ff.dim(D::Minus1)? / 2;
+            let gated =
+                (ff.narrow(D::Minus1, 0, half)? * ff.narrow(D::Minus1, half, half)?.gelu_erf()?)?;
+            latents = (layer.ff_out.forward(&gated)? + latents)?;
+        }
+        let dim = latents.dim(D::Minus1)?;
+        let normed = FactorizedVQ::l2_normalize(&latents)?;
+        Ok((normed.broadcast_mul(&self.norm_gamma)? * (dim as f64).sqrt())?)
+    }
+}
+
+/// Finite scalar quantization: every latent dimension is bounded and rounded to a few levels,
+/// and the levels combine into one integer per token
+#[derive(Debug, Clone)]
+struct ScalarQuantizer {
+    project_in: Linear,
+    project_out: Linear,
+    levels: Vec<u32>,
+}
+
+impl ScalarQuantizer {
+    fn new(dim: usize, levels: &[u32], vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            project_in: linear(dim, levels.len(), vb.pp("project_in"))?,
+            project_out: linear(levels.len(), dim, vb.pp("project_out"))?,
+            levels: levels.to_vec(),
+        })
+    }
+
+    fn basis(&self) -> Vec<u32> {
+        self.levels
+            .iter()
+            .scan(1, |acc, &l| {
+                let b = *acc;
+                *acc *= l;
+                Some(b)
+            })
+            .collect()
+    }
+
+    /// `[tokens, dim]` latents to one index per token
+    fn tokenize(&self, xs: &Tensor) -> Result<Vec<u32>> {
+        let zs = self
+            .project_in
+            .forward(xs)?
+            .to_dtype(DType::F32)?
+            .to_vec2::<f32>()?;
+        let basis = self.basis();
+        Ok(zs
+            .iter()
+            .map(|z| {
+                z.iter()
+                    .zip(&self.levels)
+                    .zip(&basis)
+                    .map(|((&z, &l), &b)| {
+                        let half_l = (l as f64 - 1.0) * (1.0 + 1e-3) / 2.0;
+                        let offset = if l % 2 == 0 { 0.5 } else { 0.0 };
+                        let shift = (offset / half_l).atanh();
+                        let bounded = (z as f64 + shift).tanh() * half_l - offset;
+                        let level = bounded.round() + (l / 2) as f64;
+                        level.clamp(0.0, l as f64 - 1.0) as u32 * b
+                    })
+                    .sum()
+            })
+            .collect())
+    }
+
+    /// Indices to `[tokens, dim]` latents
+    fn detokenize(&self, indices: &[u32], device: &Device) -> Result<Tensor> {
+        let basis = self.basis();
+        let codes: Vec<f32> = indices
+            .iter()
+            .flat_map(|&idx| {
+                self.levels.iter().zip(&basis).map(move |(&l, &b)| {
+                    let half_width = (l / 2) as f32;
+                    ((idx / b) % l) as f32 / half_width - 1.0
+                })
+            })
+            .collect();
+        let codes = Tensor::from_vec(codes, (indices.len(), self.levels.len()), device)?;
+        Ok(self.project_out.forward(&codes)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpeakerEncoder {
+    ecapa: EcapaTdnn,
+    perceiver: PerceiverResampler,
+    quantizer: ScalarQuantizer,
+    project: Linear,
+}
+
+impl SpeakerEncoder {
+    fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        let latent = config.speaker_latent_dim;
+        Ok(Self {
+            ecapa: EcapaTdnn::new(config.num_mels, vb.pp("speaker_encoder"))?,
+            perceiver: PerceiverResampler::new(
+                latent,
+                EcapaTdnn::CHANNELS * 3,
+                vb.pp("perceiver_sampler"),
+            )?,
+            quantizer: ScalarQuantizer::new(latent, &config.fsq_levels, vb.pp("quantizer"))?,
+            project: linear(
+                latent * GLOBAL_TOKENS,
+                config.speaker_out_dim,
+                vb.pp("project"),
+            )?,
+        })
+    }
+
+    /// `[1, mels, frames]` to the 32 global tokens
+    fn tokenize(&self, mels: &Tensor) -> Result<Vec<u32>> {
+        let features = self.ecapa.forward(mels)?.transpose(1, 2)?;
+        let latents = self.perceiver.forward(&features)?;
+        self.quantizer.tokenize(&latents.squeeze(0)?)
+    }
+
+    /// Global tokens to the `[1, 1024]` speaker vector
+    fn detokenize(&self, tokens: &[u32], device: &Device) -> Result<Tensor> {
+        // the projection reads the latents channel-major
+        let latents = self.quantizer.detokenize(tokens, device)?;
+        let flat = latents.t()?.flatten_all()?.unsqueeze(0)?;
+        Ok(self.project.forward(&flat)?)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Wave generator
+
+#[derive(Debug, Clone)]
+struct Snake {
+    alpha: Tensor,
+    inv_alpha: Tensor,
+}
+
+impl Snake {
+    fn new(channels: usize, vb: VarBuilder) -> Result<Self> {
+        let alpha = vb.get((1, channels, 1), "alpha")?;
+        let inv_alpha = (&alpha + 1e-9)?.recip()?;
+        Ok(Self { alpha, inv_alpha })
+    }
+
+    /// `x + sin²(αx) / α`
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let sin = xs.broadcast_mul(&self.alpha)?.sin()?.sqr()?;
+        Ok((xs + sin.broadcast_mul(&self.inv_alpha)?)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ResidualUnit {
+    snake1: Snake,
+    conv1: Conv1d,
+    snake2: Snake,
+    conv2: Conv1d,
+}
+
+impl ResidualUnit {
+    fn new(dim: usize, dilation: usize, vb: VarBuilder) -> Result<Self> {
+        let config = Conv1dConfig {
+            padding: 3 * dilation,
+            dilation,
+            ..Default::default()
+        };
+        Ok(Self {
+            snake1: Snake::new(dim, vb.pp("block.0"))?,
+            conv1: wn_conv1d(dim, dim, 7, config, vb.pp("block.1"))?,
+            snake2: Snake::new(dim, vb.pp("block.2"))?,
+            conv2: wn_conv1d(dim, dim, 1, Default::default(), vb.pp("block.3"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.conv1.forward(&self.snake1.forward(xs)?)?;
+        let ys = self.conv2.forward(&self.snake2.forward(&ys)?)?;
+        Ok((xs + ys)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DecoderBlock {
+    snake: Snake,
+    conv_t: ConvTranspose1d,
+    residuals: Vec<ResidualUnit>,
+}
+
+impl DecoderBlock {
+    fn new(
+        in_c: usize,
+        out_c: usize,
+        kernel_size: usize,
+        stride: usize,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let config = ConvTranspose1dConfig {
+            padding: (kernel_size - stride) / 2,
+            output_padding: 0,
+            stride,
+            dilation: 1,
+            groups: 1,
+        };
+        let residuals = [1, 3, 9]
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| ResidualUnit::new(out_c, d, vb.pp(format!("block.{}", i + 2))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            snake: Snake::new(in_c, vb.pp("block.0"))?,
+            conv_t: wn_conv_transpose1d(in_c, out_c, kernel_size, config, vb.pp("block.1"))?,
+            residuals,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = self.conv_t.forward(&self.snake.forward(xs)?)?;
+        for unit in &self.residuals {
+            xs = unit.forward(&xs)?;
+        }
+        Ok(xs)
+    }
+}
+
+/// DAC-style generator upsampling the 50 Hz features to 16 kHz samples
+#[derive(Debug, Clone)]
+struct WaveGenerator {
+    conv_in: Conv1d,
+    blocks: Vec<DecoderBlock>,
+    snake: Snake,
+    conv_out: Conv1d,
+}
+
+impl WaveGenerator {
+    fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        let vb = vb.pp("model");
+        let channels = config.decoder_channels;
+        let pad3 = Conv1dConfig {
+            padding: 3,
+            ..Default::default()
+        };
+        let blocks = config
+            .decoder_kernel_sizes
+            .iter()
+            .zip(&config.decoder_rates)
+            .enumerate()
+            .map(|(i, (&k, &s))| {
+                let in_c = channels >> i;
+                DecoderBlock::new(in_c, in_c / 2, k, s, vb.pp(i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let n = blocks.len();
+        let out_c = channels >> n;
+        Ok(Self {
+            conv_in: wn_conv1d(config.feature_dim, channels, 7, pad3, vb.pp("0"))?,
+            blocks,
+            snake: Snake::new(out_c, vb.pp(n + 1))?,
+            conv_out: wn_conv1d(out_c, 1, 7, pad3, vb.pp(n + 2))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = self.conv_in.forward(xs)?;
+        for block in &self.blocks {
+            xs = block.forward(&xs)?;
+        }
+        Ok(self.conv_out.forward(&self.snake.forward(&xs)?)?.tanh()?)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// BiCodec
+
+/// Audio tokenizer and decoder between 16 kHz audio and global + semantic tokens
+#[derive(Debug, Clone)]
+pub struct BiCodec {
+    config: BiCodecConfig,
+    encoder: FeatureEncoder,
+    quantizer: FactorizedVQ,
+    speaker_encoder: SpeakerEncoder,
+    prenet: Prenet,
+    decoder: WaveGenerator,
+    device: Device,
+}
+
+impl BiCodec {
+    pub fn new(config: &BiCodecConfig, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            encoder: FeatureEncoder::new(config, vb.pp("encoder"))?,
+            quantizer: FactorizedVQ::new(config, vb.pp("quantizer"))?,
+            speaker_encoder: SpeakerEncoder::new(config, vb.pp("speaker_encoder"))?,
+            prenet: Prenet::new(config, vb.pp("prenet"))?,
+            decoder: WaveGenerator::new(config, vb.pp("decoder"))?,
+            device: vb.device().clone(),
+        })
+    }
+
+    /// Load `model.safetensors` from the `BiCodec` directory of the checkpoint
+    pub fn from_pretrained(model_path: &str, device: &Device) -> Result<Self> {
+        let filenames = utils::get_safetensors_files(model_path)?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, DType::F32, device) }?;
+        Self::new(&BiCodecConfig::default(), vb)
+    }
+
+    /// Semantic tokens of wav2vec2 `features` and global tokens of the reference clip
+    pub fn tokenize(&self, features: &Tensor, ref_wav: &[f32]) -> Result<VoicePrompt> {
+        let latents = self.encoder.forward(&features.transpose(1, 2)?)?;
+        let semantic_tokens = self.quantizer.tokenize(&latents)?;
+        let mels = self.mel_spectrogram(ref_wav)?;
+        let global_tokens = self.speaker_encoder.tokenize(&mels)?;
+        Ok(VoicePrompt {
+            global_tokens,
+            semantic_tokens,
+            transcript: None,
+        })
+    }
+
+    /// Decode tokens to 16 kHz samples, `[samples]`
+    pub fn detokenize(&self, global_tokens: &[u32], semantic_tokens: &[u32]) -> Result<Tensor> {
+        if global_tokens.len() != GLOBAL_TOKENS {
+            anyhow::bail!(
+                "expected {GLOBAL_TOKENS} global tokens, got {}",
+                global_tokens.len()
+            );
+        }
+        if semantic_tokens.is_empty() {
+            anyhow::bail!("no semantic tokens to decode");
+        }
+        let codes = Tensor::new(semantic_tokens, &self.device)?;
+        let z_q = self.quantizer.detokenize(&codes)?;
+        let d_vector = self
+            .speaker_encoder
+            .detokenize(global_tokens, &self.device)?;
+        let xs = self.prenet.forward(&z_q, &d_vector)?;
+        let xs = xs.broadcast_add(&d_vector.unsqueeze(2)?)?;
+        Ok(self.decoder.forward(&xs)?.flatten_all()?)
+    }
+
+    /// Magnitude mel spectrogram `[1, mels, frames]` with slaney mel scale and norm
+    fn mel_spectrogram(&self, wav: &[f32]) -> Result<Tensor> {
+        let c = &self.config;
+        let n_fft = c.n_fft;
+        let n_freqs = n_fft / 2 + 1;
+        let pad = n_fft / 2;
+        if wav.len() <= pad {
+            anyhow::bail!("reference clip is too short");
+        }
+
+        // centered frames with reflect padding, windowed by a periodic hann of win_length
+        // samples placed in the middle of the fft window
+        let reflect = |i: isize| -> f32 {
+            let n = wav.len() as isize;
+            let i = if i < 0 {
+                -i
+            } else if i >= n {
+                2 * (n - 1) - i
+            } else {
+                i
+            };
+            wav[i as usize]
+        };
+        let win_offset = (n_fft - c.win_length) / 2;
+        let window: Vec<f32> = (0..n_fft)
+            .map(|i| match i.checked_sub(win_offset) {
+                Some(j) if j < c.win_length => {
+                    let phase = 2.0 * std::f64::consts::PI * j as f64 / c.win_length as f64;
+                    (0.5 - 0.5 * phase.cos()) as f32
+                }
+                _ => 0.0,
+            })
+            .collect();
+        let n_frames = wav.len() / c.hop_length + 1;
+        let mut frames = Vec::with_capacity(n_frames * n_fft);
+        for f in 0..n_frames {
+            let start = (f * c.hop_length) as isize - pad as isize;
+            frames.extend((0..n_fft).map(|i| reflect(start + i as isize) * window[i]));
+        }
+        let frames = Tensor::from_vec(frames, (n_frames, n_fft), &self.device)?;
+
+        let mut cos = Vec::with_capacity(n_fft * n_freqs);
+        let mut sin = Vec::with_capacity(n_fft * n_freqs);
+        for n in 0..n_fft {
+            for k in 0..n_freqs {
+                let phase = 2.0 * std::f64::consts::PI * ((n * k) % n_fft) as f64 / n_fft as f64;
+                cos.push(phase.cos() as f32);
+                sin.push(phase.sin() as f32);
+            }
+        }
+        let cos = Tensor::from_vec(cos, (n_fft, n_freqs), &self.device)?;
+        let sin = Tensor::from_vec(sin, (n_fft, n_freqs), &self.device)?;
+        let re = frames.matmul(&cos)?;
+        let im = frames.matmul(&sin)?;
+        let magnitude = (re.sqr()? + im.sqr()?)?.sqrt()?;
+
+        let filters = self.mel_filters(n_freqs)?;
+        Ok(magnitude.matmul(&filters)?.t()?.unsqueeze(0)?)
+    }
+
+    /// Triangular slaney-normalized filters `[n_freqs, mels]`
+    fn mel_filters(&self, n_freqs: usize) -> Result<Tensor> {
+        let c = &self.config;
+        const F_SP: f64 = 200.0 / 3.0;
+        const MIN_LOG_HZ: f64 = 1000.0;
+        const MIN_LOG_MEL: f64 = MIN_LOG_HZ / F_SP;
+        let log_step = 6.4f64.ln() / 27.0;
+        let hz_to_mel = |f: f64| {
+            if f >= MIN_LOG_HZ {
+                MIN_LOG_MEL + (f / MIN_LOG_HZ).ln() / log_step
+            } else {
+                f / F_SP
+            }
+        };
+        let mel_to_hz = |m: f64| {
+            if m >= MIN_LOG_MEL {
+                MIN_LOG_HZ * (log_step * (m - MIN_LOG_MEL)).exp()
+            } else {
+                m * F_SP
+            }
+        };
+
+        let (m_min, m_max) = (hz_to_mel(c.mel_fmin), hz_to_mel(c.mel_fmax));
+        let points: Vec<f64> = (0..c.num_mels + 2)
+            .map(|i| mel_to_hz(m_min + (m_max - m_min) * i as f64 / (c.num_mels + 1) as f64))
+            .collect();
+        let nyquist = SAMPLE_RATE as f64 / 2.0;
+        let mut filters = vec![0f32; n_freqs * c.num_mels];
+        for k in 0..n_freqs {
+            let freq = nyquist * k as f64 / (n_freqs - 1) as f64;
+            for m in 0..c.num_mels {
+                let down = (freq - points[m]) / (points[m + 1] - points[m]);
+                let up = (points[m + 2] - freq) / (points[m + 2] - points[m + 1]);
+                let enorm = 2.0 / (points[m + 2] - points[m]);
+                filters[k * c.num_mels + m] = (down.min(up).max(0.0) * enorm) as f32;
+            }
+        }
+        Ok(Tensor::from_vec(
+            filters,
+            (n_freqs, c.num_mels),
+            &self.device,
+        )?)
+    }
+}
+
+/// Scale speech so its loud parts sit around `coeff`, as BiCodec was trained on
+pub fn volume_normalize(wav: &[f32], coeff: f32) -> Vec<f32> {
+    let mut audio = wav.to_vec();
+    let mut sorted: Vec<f32> = wav.iter().map(|x| x.abs()).collect();
+    sorted.sort_by(f32::total_cmp);
+    let Some(&peak) = sorted.last() else {
+        return audio;
+    };
+    if peak < 0.1 {
+        let scale = 0.1 / peak.max(1e-3);
+        audio.iter_mut().for_each(|x| *x *= scale);
+    }
+    let loud: Vec<f32> = sorted.into_iter().filter(|&x| x > 0.01).collect();
+    if loud.len() <= 10 {
+        return audio;
+    }
+    let range = &loud[(0.9 * loud.len() as f32) as usize..(0.99 * loud.len() as f32) as usize];
+    let volume = range.iter().sum::<f32>() / range.len().max(1) as f32;
+    let gain = (coeff / volume).clamp(0.1, 10.0);
+    audio.iter_mut().for_each(|x| *x *= gain);
+    let max = audio.iter().fold(0f32, |m, x| m.max(x.abs()));
+    if max > 1.0 {
+        audio.iter_mut().for_each(|x| *x /= max);
+    }
+    audio
+}
+
+/// The first 6 seconds of the reference, looped when it is shorter
+fn reference_clip(wav: &[f32]) -> Vec<f32> {
+    wav.iter()
+        .cycle()
+        .take(REF_SEGMENT_SAMPLES)
+        .copied()
+        .collect()
+}
+
+/// Read a WAV file as mono samples at 16 kHz
+pub fn load_wav(path: &str) -> Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<std::result::Result<_, _>>()?,
+        SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = samples
+        .chunks(channels)
+        .map(|c| c.iter().sum::<f32>() / c.len() as f32)
+        .collect();
+    if spec.sample_rate == SAMPLE_RATE {
+        return Ok(mono);
+    }
+    // linear interpolation is enough for a speaker reference
+    let ratio = spec.sample_rate as f64 / SAMPLE_RATE as f64;
+    let len = (mono.len() as f64 / ratio) as usize;
+    Ok((0..len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let j = pos as usize;
+            let frac = (pos - j as f64) as f32;
+            let a = mono[j];
+            let b = mono.get(j + 1).copied().unwrap_or(a);
+            a + (b - a) * frac
+        })
+        .collect())
+}
+
+/// Write `[.., samples]` audio in `[-1, 1]` as 16-bit mono WAV
+pub fn save_wav(audio: &Tensor, filename: &str, sample_rate: u32) -> Result<String> {
+    let samples = audio
+        .to_dtype(DType::F32)?
+        .flatten_all()?
+        .to_vec1::<f32>()?;
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(filename, spec)?;
+    for sample in samples {
+        writer.write_sample((sample * 32767.0).round().clamp(-32768.0, 32767.0) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(filename.to_string())
+}
+
+// ---------------------------------------------------------------------------------------------
+// Spark-TTS
+
+/// What a `<|bicodec_..._N|>` token of the LM vocabulary stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AudioToken {
+    Global(u32),
+    Semantic(u32),
+}
+
+/// Audio tokens of the LM vocabulary by id
+fn audio_tokens(tokenizer: &tokenizers::Tokenizer) -> HashMap<u32, AudioToken> {
+    tokenizer
+        .get_vocab(true)
+        .into_iter()
+        .filter_map(|(token, id)| {
+            let number = |prefix: &str| -> Option<u32> {
+                token.strip_prefix(prefix)?.strip_suffix("|>")?.parse().ok()
+            };
+            number("<|bicodec_global_")
+                .map(AudioToken::Global)
+                .or_else(|| number("<|bicodec_semantic_").map(AudioToken::Semantic))
+                .map(|t| (id, t))
+        })
+        .collect()
+}
+
+/// Reads Spark-TTS tokens for `AudioStreamer`: each semantic token is a frame of 320 samples,
+/// decoded once the 32 global tokens are known
+pub struct BiCodecFrameCodec<'a> {
+    bicodec: &'a BiCodec,
+    tokens: &'a HashMap<u32, AudioToken>,
+    globals: Vec<u32>,
+    /// Global tokens come from the voice prompt, not the LM
+    preset: bool,
+}
+
+impl<'a> BiCodecFrameCodec<'a> {
+    fn new(bicodec: &'a BiCodec, tokens: &'a HashMap<u32, AudioToken>, voice: &SparkVoice) -> Self {
+        let globals = match voice {
+            SparkVoice::Clone(prompt) => prompt.global_tokens.clone(),
+            SparkVoice::Control { .. } => Vec::with_capacity(GLOBAL_TOKENS),
+        };
+        Self {
+            bicodec,
+            tokens,
+            preset: !globals.is_empty(),
+            globals,
+        }
+    }
+
+    /// Chunking that starts playback after 15 tokens (~300 ms of audio)
+    pub fn streaming_config() -> StreamingConfig {
+        StreamingConfig {
+            first_chunk_frames: 15,
+            chunk_frames: 25,
+            left_context: 20,
+            lookahead: 8,
+            crossfade_samples: HOP_LENGTH,
+        }
+    }
+
+    /// Global tokens of the voice being spoken, all 32 once the LM has written them
+    pub fn global_tokens(&self) -> &[u32] {
+        &self.globals
+    }
+}
+
+impl FrameCodec for BiCodecFrameCodec<'_> {
+    fn samples_per_frame(&self) -> usize {
+        HOP_LENGTH
+    }
+
+    fn push_token(&mut self, token: u32) -> Option<Vec<u32>> {
+        match self.tokens.get(&token)? {
+            AudioToken::Global(t) => {
+                if !self.preset && self.globals.len() < GLOBAL_TOKENS {
+                    self.globals.push(*t);
+                }
+                None
+            }
+            AudioToken::Semantic(t) => Some(vec![*t]),
+        }
+    }
+
+    fn decode(&mut self, frames: &[Vec<u32>]) -> Result<Vec<f32>> {
+        let semantic: Vec<u32> = frames.iter().map(|f| f[0]).collect();
+        let audio = self.bicodec.detokenize(&self.globals, &semantic)?;
+        Ok(audio.to_dtype(DType::F32)?.to_vec1()?)
+    }
+}
+
+/// Qwen2.5 LM, BiCodec, and wav2vec2 for reading reference voices
+pub struct SparkTTS {
+    pub lm: qwen25::Model,
+    pub bicodec: BiCodec,
+    /// Only needed for voice cloning; loaded when the checkpoint ships it
+    pub wav2vec2: Option<Wav2Vec2>,
+    pub device: Device,
+    audio_tokens: HashMap<u32, AudioToken>,
+}
+
+impl SparkTTS {
+    /// Load the `LLM`, `BiCodec` and `wav2vec2-large-xlsr-53` directories of a Spark-TTS
+    /// checkpoint. The LM runs in `dtype`, the audio models in f32.
+    pub fn from_pretrained(model_path: &str, device: &Device, dtype: &DType) -> Result<Self> {
+        let root = Path::new(model_path);
+        let llm_path = root.join("LLM");
+        let lm = qwen25::Model::new(&llm_path.to_string_lossy(), device, dtype)?;
+        let bicodec = BiCodec::from_pretrained(&root.join("BiCodec").to_string_lossy(), device)?;
+
+        let w2v_path = root.join("wav2vec2-large-xlsr-53");
+        let wav2vec2 = if w2v_path.join("model.safetensors").exists() {
+            let filenames = utils::get_safetensors_files(&w2v_path.to_string_lossy())?;
+            let vb =
+                unsafe { VarBuilder::from_mmaped_safetensors(&filenames, DType::F32, device) }?;
+            Some(Wav2Vec2::new(vb)?)
+        } else if w2v_path.join("pytorch_model.bin").exists() {
+            let vb = VarBuilder::from_pth(w2v_path.join("pytorch_model.bin"), DType::F32, device)?;
+            Some(Wav2Vec2::new(vb)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            audio_tokens: audio_tokens(&lm.tokenizer.tokenizer),
+            lm,
+            bicodec,
+            wav2vec2,
+            device: device.clone(),
+        })
+    }
+
+    /// Sampling settings of the reference inference script
+    pub fn generation_config(max_new_tokens: usize) -> GenerationConfig {
+        GenerationConfig {
+            max_new_tokens,
+            temperature: Some(0.8),
+            top_p: Some(0.95),
+            do_sample: true,
+            ..Default::default()
+        }
+    }
+
+    /// Tokenize a 16 kHz mono reference recording. Pass its `transcript` to have the model
+    /// continue the recording, which follows the voice more closely.
+    pub fn voice_prompt(&self, wav: &[f32], transcript: Option<&str>) -> Result<VoicePrompt> {
+        let wav2vec2 = self.wav2vec2.as_ref().ok_or_else(|| {
+            E::msg("voice cloning needs the wav2vec2-large-xlsr-53 directory of the checkpoint")
+        })?;
+        if wav.len() < HOP_LENGTH * 10 {
+            anyhow::bail!("reference recording is too short");
+        }
+        let wav = volume_normalize(wav, 0.2);
+        let features = wav2vec2.features(&wav, &self.device)?;
+        let mut prompt = self.bicodec.tokenize(&features, &reference_clip(&wav))?;
+        prompt.transcript = transcript
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string);
+        Ok(prompt)
+    }
+
+    pub fn voice_prompt_from_file(
+        &self,
+        path: &str,
+        transcript: Option<&str>,
+    ) -> Result<VoicePrompt> {
+        self.voice_prompt(&load_wav(path)?, transcript)
+    }
+
+    /// The LM prompt for speaking `text` in `voice`
+    pub fn prompt(text: &str, voice: &SparkVoice) -> String {
+        match voice {
+            SparkVoice::Control {
+                gender,
+                pitch,
+                speed,
+            } => format!(
+                "<|task_controllable_tts|><|start_content|>{text}<|end_content|>\
+                 <|start_style_label|><|gender_{}|><|pitch_label_{}|><|speed_label_{}|>\
+                 <|end_style_label|>",
+                gender.id(),
+                pitch.id(),
+                speed.id()
+            ),
+            SparkVoice::Clone(prompt) => {
+                let globals: String = prompt
+                    .global_tokens
+                    .iter()
+                    .map(|t| format!("<|bicodec_global_{t}|>"))
+                    .collect();
+                match &prompt.transcript {
+                    Some(transcript) => {
+                        let semantics: String = prompt
+                            .semantic_tokens
+                            .iter()
+                            .map(|t| format!("<|bicodec_semantic_{t}|>"))
+                            .collect();
+                        format!(
+                            "<|task_tts|><|start_content|>{transcript}{text}<|end_content|>\
+                             <|start_global_token|>{globals}<|end_global_token|>\
+                             <|start_semantic_token|>{semantics}"
+                        )
+                    }
+                    None => format!(
+                        "<|task_tts|><|start_content|>{text}<|end_content|>\
+                         <|start_global_token|>{globals}<|end_global_token|>"
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Input ids of the prompt, with `<|im_end|>` as eos unless `config` has one
+    fn prepare(
+        &self,
+        text: &str,
+        voice: &SparkVoice,
+        config: &GenerationConfig,
+    ) -> Result<(Vec<u32>, GenerationConfig)> {
+        let input_ids = self.lm.prepare_inputs(&Self::prompt(text, voice))?;
+        let mut config = config.clone();
+        if config.eos_token_id.is_none() {
+            config.eos_token_id = self.lm.tokenizer.get_token("<|im_end|>");
+        }
+        Ok((input_ids, config))
+    }
+
+    /// Run the LM and return the global and semantic tokens to decode. A cloned voice keeps
+    /// the reference's global tokens; in controllable mode the model writes its own first.
+    pub fn generate_tokens(
+        &mut self,
+        text: &str,
+        voice: &SparkVoice,
+        config: &GenerationConfig,
+    ) -> Result<(Vec<u32>, Vec<u32>)> {
+        let (input_ids, config) = self.prepare(text, voice, config)?;
+        let tokens = self.lm.generate(&input_ids, &config, None)?;
+
+        let mut codec = BiCodecFrameCodec::new(&self.bicodec, &self.audio_tokens, voice);
+        let semantic = tokens[input_ids.len()..]
+            .iter()
+            .filter_map(|&t| codec.push_token(t))
+            .map(|frame| frame[0])
+            .collect();
+        Ok((codec.globals, semantic))
+    }
+
+    /// Speak one utterance, returning `[samples]` at 16 kHz
+    pub fn synthesize(
+        &mut self,
+        text: &str,
+        voice: &SparkVoice,
+        config: &GenerationConfig,
+    ) -> Result<Tensor> {
+        let (global, semantic) = self.generate_tokens(text, voice, config)?;
+        if semantic.is_empty() {
+            anyhow::bail!("the model produced no speech tokens");
+        }
+        self.bicodec.detokenize(&global, &semantic)
+    }
+
+    /// Speak one utterance, handing `sink` 16 kHz chunks while the LM is still generating.
+    /// Returns the global tokens spoken with, so that later utterances can keep the voice;
+    /// only the final, empty chunk is sent when the model wrote no speech.
+    pub fn synthesize_streaming(
+        &mut self,
+        text: &str,
+        voice: &SparkVoice,
+        config: &GenerationConfig,
+        streaming: StreamingConfig,
+        sink: impl FnMut(AudioChunk) -> Result<()>,
+    ) -> Result<Vec<u32>> {
+        let (input_ids, config) = self.prepare(text, voice, config)?;
+        let codec = BiCodecFrameCodec::new(&self.bicodec, &self.audio_tokens, voice);
+        let mut streamer = AudioStreamer::new(codec, streaming, sink);
+        self.lm.generate(&input_ids, &config, Some(&mut streamer))?;
+        // generation may stop at the token limit without finalizing
+        streamer.finalize()?;
+        Ok(streamer.codec().global_tokens().to_vec())
+    }
+
+    pub fn text_to_speech(
+        &mut self,
+        text: &str,
+        voice: &SparkVoice,
+        filename: &str,
+    ) -> Result<String> {
+        let audio = self.synthesize(text, voice, &Self::generation_config(3000))?;
+        save_wav(&audio, filename, SAMPLE_RATE)
+    }
+}
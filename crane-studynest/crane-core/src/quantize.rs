@@ -0,0 +1,444 @@
+//! Offline conversion of safetensors checkpoints into quantized GGUF files.
+//!
+//! Qwen2/Qwen2.5 and Qwen3 checkpoints (single file or sharded) are converted to a
+//! GGUF file using llama.cpp tensor names and metadata, with the tokenizer and chat
+//! template embedded. The original tokenizer and config files are copied next to it,
+//! so the output directory loads with `qwen25::Model::new` / `qwen3::Model::new`,
+//! which pick up the single `.gguf` file of a directory without safetensors.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use candle_core::quantized::gguf_file::{self, Value};
+use candle_core::quantized::{GgmlDType, QTensor};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{DType, Device, Tensor, D};
+use tokenizers::Tokenizer;
+
+use crate::models::quantized_qwen::{hf_to_gguf_name, HParams, ModelWeights};
+use crate::utils::utils::get_safetensors_files;
+
+/// Files copied from the source checkpoint into the output directory
+const COPIED_FILES: &[&str] = &[
+    "config.json",
+    "generation_config.json",
+    "tokenizer.json",
+    "tokenizer_config.json",
+    "vocab.json",
+    "merges.txt",
+];
+
+/// GGUF `tokenizer.ggml.token_type` values
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_UNUSED: i32 = 5;
+
+/// Quantization recipes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QuantizationType {
+    /// 4-bit k-quants, with attn_v/ffn_down/output kept at 6 bits
+    #[value(name = "q4_k_m")]
+    Q4KM,
+    /// 5-bit k-quants, with attn_v/ffn_down/output kept at 6 bits
+    #[value(name = "q5_k_m")]
+    Q5KM,
+    /// 6-bit k-quants
+    #[value(name = "q6_k")]
+    Q6K,
+    /// 8-bit round-to-nearest
+    #[value(name = "q8_0")]
+    Q8_0,
+}
+
+impl QuantizationType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuantizationType::Q4KM => "Q4_K_M",
+            QuantizationType::Q5KM => "Q5_K_M",
+            QuantizationType::Q6K => "Q6_K",
+            QuantizationType::Q8_0 => "Q8_0",
+        }
+    }
+
+    /// llama.cpp `general.file_type`
+    fn file_type(&self) -> u32 {
+        match self {
+            QuantizationType::Q4KM => 15,
+            QuantizationType::Q5KM => 17,
+            QuantizationType::Q6K => 18,
+            QuantizationType::Q8_0 => 7,
+        }
+    }
+
+    /// Type for a 2D weight; rows whose length is not a multiple of the block size
+    /// fall back to Q8_0 (32-element blocks) or F16
+    fn dtype_for(&self, gguf_name: &str, row_len: usize) -> GgmlDType {
+        let sensitive = gguf_name.ends_with("attn_v.weight")
+            || gguf_name.ends_with("ffn_down.weight")
+            || gguf_name == "output.weight";
+        let dtype = match self {
+            QuantizationType::Q4KM if sensitive => GgmlDType::Q6K,
+            QuantizationType::Q5KM if sensitive => GgmlDType::Q6K,
+            QuantizationType::Q4KM => GgmlDType::Q4K,
+            QuantizationType::Q5KM => GgmlDType::Q5K,
+            QuantizationType::Q6K => GgmlDType::Q6K,
+            QuantizationType::Q8_0 => GgmlDType::Q8_0,
+        };
+        if row_len.is_multiple_of(dtype.block_size()) {
+            dtype
+        } else if row_len.is_multiple_of(GgmlDType::Q8_0.block_size()) {
+            GgmlDType::Q8_0
+        } else {
+            GgmlDType::F16
+        }
+    }
+}
+
+impl fmt::Display for QuantizationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Options for `quantize_checkpoint`
+#[derive(Debug, Clone)]
+pub struct QuantizeOptions {
+    pub quantization: QuantizationType,
+    /// Text used to compare perplexity of the original and quantized model.
+    /// The original model is run from F16 weights, so this needs that much memory.
+    pub sample_text: Option<String>,
+    /// Maximum number of sample tokens evaluated
+    pub max_eval_tokens: usize,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            quantization: QuantizationType::Q4KM,
+            sample_text: None,
+            max_eval_tokens: 256,
+        }
+    }
+}
+
+/// Perplexity of the original and quantized model on the sample text
+#[derive(Debug, Clone)]
+pub struct PerplexityComparison {
+    pub tokens: usize,
+    pub original: f64,
+    pub quantized: f64,
+}
+
+/// Result of a conversion
+#[derive(Debug, Clone)]
+pub struct QuantizeReport {
+    pub output: PathBuf,
+    pub quantization: QuantizationType,
+    /// Size of the source safetensors files in bytes
+    pub source_bytes: u64,
+    /// Size of the GGUF file in bytes
+    pub output_bytes: u64,
+    /// Weights stored with the requested quantization
+    pub quantized_tensors: usize,
+    /// Weights stored with another type (norms, biases, and rows not divisible by the block size)
+    pub other_tensors: usize,
+    pub perplexity: Option<PerplexityComparison>,
+}
+
+impl fmt::Display for QuantizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        writeln!(f, "Wrote {}", self.output.display())?;
+        writeln!(
+            f,
+            "  quantization: {} ({} quantized tensors, {} kept at other types)",
+            self.quantization, self.quantized_tensors, self.other_tensors
+        )?;
+        write!(
+            f,
+            "  size: {:.1} MB -> {:.1} MB ({:.2}x smaller)",
+            mb(self.source_bytes),
+            mb(self.output_bytes),
+            self.source_bytes as f64 / self.output_bytes.max(1) as f64
+        )?;
+        if let Some(ppl) = &self.perplexity {
+            write!(
+                f,
+                "\n  perplexity: {:.3} -> {:.3} ({:+.2}%) on {} tokens",
+                ppl.original,
+                ppl.quantized,
+                (ppl.quantized / ppl.original - 1.0) * 100.0,
+                ppl.tokens
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn read_json(path: &Path) -> Result<serde_json::Value> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+fn model_metadata(hparams: &HParams, config: &serde_json::Value, name: &str, q: QuantizationType) -> Vec<(String, Value)> {
+    let arch = &hparams.architecture;
+    let key = |k: &str| format!("{arch}.{k}");
+    let mut md = vec![
+        ("general.architecture".to_string(), Value::String(arch.clone())),
+        ("general.name".to_string(), Value::String(name.to_string())),
+        ("general.file_type".to_string(), Value::U32(q.file_type())),
+        ("general.quantization_version".to_string(), Value::U32(2)),
+        (key("context_length"), Value::U32(hparams.context_length as u32)),
+        (key("embedding_length"), Value::U32(hparams.embedding_length as u32)),
+        (key("block_count"), Value::U32(hparams.block_count as u32)),
+        (key("attention.head_count"), Value::U32(hparams.head_count as u32)),
+        (key("attention.head_count_kv"), Value::U32(hparams.head_count_kv as u32)),
+        (key("attention.key_length"), Value::U32(hparams.head_dim as u32)),
+        (key("attention.value_length"), Value::U32(hparams.head_dim as u32)),
+        (key("attention.layer_norm_rms_epsilon"), Value::F32(hparams.rms_norm_eps as f32)),
+        (key("rope.freq_base"), Value::F32(hparams.rope_freq_base)),
+    ];
+    if let Some(ff) = config["intermediate_size"].as_u64() {
+        md.push((key("feed_forward_length"), Value::U32(ff as u32)));
+    }
+    md
+}
+
+/// Text of a special token in `tokenizer_config.json` (plain string or AddedToken object)
+fn config_token(config: &serde_json::Value, key: &str) -> Option<String> {
+    let token = &config[key];
+    token
+        .as_str()
+        .or_else(|| token["content"].as_str())
+        .map(str::to_string)
+}
+
+/// `tokenizer.ggml.*` metadata rebuilt from `tokenizer.json` and `tokenizer_config.json`
+fn tokenizer_metadata(model_dir: &Path, config: &serde_json::Value) -> Result<Vec<(String, Value)>> {
+    let tokenizer = read_json(&model_dir.join("tokenizer.json"))?;
+    let tokenizer_config = read_json(&model_dir.join("tokenizer_config.json")).unwrap_or_default();
+    let model = &tokenizer["model"];
+    if model["type"].as_str() != Some("BPE") {
+        anyhow::bail!("Only byte-level BPE tokenizers can be embedded in GGUF");
+    }
+
+    let vocab = model["vocab"].as_object().context("tokenizer.json has no vocab")?;
+    let added = tokenizer["added_tokens"].as_array().cloned().unwrap_or_default();
+    let max_id = vocab
+        .values()
+        .filter_map(|id| id.as_u64())
+        .chain(added.iter().filter_map(|t| t["id"].as_u64()))
+        .max()
+        .unwrap_or(0) as usize;
+    // The embedding matrix is often padded beyond the last real token
+    let n_tokens = (max_id + 1).max(config["vocab_size"].as_u64().unwrap_or(0) as usize);
+
+    let mut tokens: Vec<String> = (0..n_tokens).map(|i| format!("[PAD{i}]")).collect();
+    let mut types = vec![TOKEN_TYPE_UNUSED; n_tokens];
+    for (token, id) in vocab {
+        if let Some(id) = id.as_u64() {
+            tokens[id as usize] = token.clone();
+            types[id as usize] = TOKEN_TYPE_NORMAL;
+        }
+    }
+    for token in &added {
+        if let (Some(id), Some(content)) = (token["id"].as_u64(), token["content"].as_str()) {
+            tokens[id as usize] = content.to_string();
+            types[id as usize] = if token["special"].as_bool().unwrap_or(false) {
+                TOKEN_TYPE_CONTROL
+            } else {
+                TOKEN_TYPE_USER_DEFINED
+            };
+        }
+    }
+    let merges: Vec<Value> = model["merges"]
+        .as_array()
+        .map(|merges| {
+            merges
+                .iter()
+                .filter_map(|m| match m {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Array(pair) => Some(format!(
+                        "{} {}",
+                        pair.first()?.as_str()?,
+                        pair.get(1)?.as_str()?
+                    )),
+                    _ => None,
+                })
+                .map(Value::String)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let token_id = |token: Option<String>| {
+        let token = token?;
+        tokens.iter().position(|t| *t == token).map(|id| id as u32)
+    };
+    let eos = token_id(config_token(&tokenizer_config, "eos_token"))
+        .or_else(|| config["eos_token_id"].as_u64().map(|id| id as u32));
+    let bos = config["bos_token_id"]
+        .as_u64()
+        .map(|id| id as u32)
+        .or_else(|| token_id(config_token(&tokenizer_config, "bos_token")));
+    let pad = token_id(config_token(&tokenizer_config, "pad_token"));
+
+    let mut md = vec![
+        ("tokenizer.ggml.model".to_string(), Value::String("gpt2".to_string())),
+        ("tokenizer.ggml.pre".to_string(), Value::String("qwen2".to_string())),
+        (
+            "tokenizer.ggml.tokens".to_string(),
+            Value::Array(tokens.iter().cloned().map(Value::String).collect()),
+        ),
+        (
+            "tokenizer.ggml.token_type".to_string(),
+            Value::Array(types.into_iter().map(Value::I32).collect()),
+        ),
+        ("tokenizer.ggml.merges".to_string(), Value::Array(merges)),
+        ("tokenizer.ggml.add_bos_token".to_string(), Value::Bool(false)),
+    ];
+    for (key, id) in [
+        ("tokenizer.ggml.eos_token_id", eos),
+        ("tokenizer.ggml.bos_token_id", bos),
+        ("tokenizer.ggml.padding_token_id", pad),
+    ] {
+        if let Some(id) = id {
+            md.push((key.to_string(), Value::U32(id)));
+        }
+    }
+    if let Some(template) = tokenizer_config["chat_template"].as_str() {
+        md.push(("tokenizer.chat_template".to_string(), Value::String(template.to_string())));
+    }
+    Ok(md)
+}
+
+/// Perplexity of `tokens` under the model, from a single forward pass
+fn perplexity(model: &mut ModelWeights, tokens: &[u32], device: &Device) -> Result<f64> {
+    model.clear_kv_cache();
+    let input = Tensor::new(&tokens[..tokens.len() - 1], device)?.unsqueeze(0)?;
+    let logits = model.forward_all(&input, 0)?.squeeze(0)?.to_dtype(DType::F32)?;
+    let log_probs = candle_nn::ops::log_softmax(&logits, D::Minus1)?;
+    let targets = Tensor::new(&tokens[1..], device)?.unsqueeze(1)?;
+    let nll = log_probs
+        .gather(&targets, 1)?
+        .neg()?
+        .mean_all()?
+        .to_scalar::<f32>()?;
+    model.clear_kv_cache();
+    Ok((nll as f64).exp())
+}
+
+/// Convert a safetensors checkpoint directory into a quantized GGUF checkpoint in `output_dir`
+pub fn quantize_checkpoint(
+    model_path: &str,
+    output_dir: impl AsRef<Path>,
+    options: &QuantizeOptions,
+) -> Result<QuantizeReport> {
+    let model_dir = Path::new(model_path);
+    let output_dir = output_dir.as_ref();
+    let device = Device::Cpu;
+
+    let config = read_json(&model_dir.join("config.json"))?;
+    let hparams = HParams::from_config(&config)?;
+    let files = get_safetensors_files(model_path)?;
+    let source_bytes = files
+        .iter()
+        .map(|f| std::fs::metadata(f).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let st = unsafe { MmapedSafetensors::multi(&files)? };
+
+    let name = model_dir
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "model".to_string());
+    let mut metadata = model_metadata(&hparams, &config, &name, options.quantization);
+    metadata.extend(tokenizer_metadata(model_dir, &config)?);
+
+    let mut names: Vec<String> = st.tensors().into_iter().map(|(name, _)| name).collect();
+    names.sort();
+    let mut tensors = Vec::with_capacity(names.len());
+    let (mut quantized_tensors, mut other_tensors) = (0, 0);
+    for (i, hf_name) in names.iter().enumerate() {
+        let Some(gguf_name) = hf_to_gguf_name(hf_name) else {
+            println!("Skipping {hf_name}");
+            continue;
+        };
+        let tensor = st.load(hf_name, &device)?.to_dtype(DType::F32)?;
+        let dtype = match tensor.dims() {
+            [_, row_len] => options.quantization.dtype_for(&gguf_name, *row_len),
+            _ => GgmlDType::F32,
+        };
+        if matches!(dtype, GgmlDType::F32 | GgmlDType::F16) {
+            other_tensors += 1;
+        } else {
+            quantized_tensors += 1;
+        }
+        println!("[{}/{}] {gguf_name} {:?} -> {dtype:?}", i + 1, names.len(), tensor.dims());
+        tensors.push((gguf_name, QTensor::quantize(&tensor, dtype)?));
+    }
+
+    std::fs::create_dir_all(output_dir)?;
+    let output = output_dir.join(format!("{name}-{}.gguf", options.quantization.name()));
+    {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&output)?);
+        let metadata: Vec<(&str, &Value)> = metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let tensors: Vec<(&str, &QTensor)> = tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        gguf_file::write(&mut writer, &metadata, &tensors)?;
+    }
+    drop(tensors);
+    for file in COPIED_FILES {
+        let src = model_dir.join(file);
+        if src.exists() {
+            std::fs::copy(&src, output_dir.join(file))?;
+        }
+    }
+    let output_bytes = std::fs::metadata(&output)?.len();
+
+    let perplexity = match &options.sample_text {
+        Some(text) => {
+            let tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json")).map_err(anyhow::Error::msg)?;
+            let mut tokens = tokenizer
+                .encode(text.as_str(), false)
+                .map_err(anyhow::Error::msg)?
+                .get_ids()
+                .to_vec();
+            tokens.truncate(options.max_eval_tokens.max(2));
+            if tokens.len() < 2 {
+                anyhow::bail!("The sample text needs at least two tokens");
+            }
+
+            println!("Evaluating the original model on {} tokens...", tokens.len());
+            let original = {
+                let mut model = ModelWeights::from_safetensors(&st, hparams, &device)?;
+                perplexity(&mut model, &tokens, &device)?
+            };
+
+            println!("Evaluating the quantized model...");
+            let quantized = {
+                let mut file = std::fs::File::open(&output)?;
+                let content = gguf_file::Content::read(&mut file)?;
+                let mut model = ModelWeights::from_gguf(content, &mut file, &device)?;
+                perplexity(&mut model, &tokens, &device)?
+            };
+
+            Some(PerplexityComparison {
+                tokens: tokens.len(),
+                original,
+                quantized,
+            })
+        }
+        None => None,
+    };
+
+    Ok(QuantizeReport {
+        output,
+        quantization: options.quantization,
+        source_bytes,
+        output_bytes,
+        quantized_tensors,
+        other_tensors,
+        perplexity,
+    })
+}
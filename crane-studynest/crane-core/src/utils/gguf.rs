@@ -0,0 +1,205 @@
+//! Helpers for reading GGUF files: metadata lookups and rebuilding the
+//! Hugging Face tokenizer and chat template embedded by llama.cpp.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use candle_core::quantized::gguf_file::{Content, Value};
+use tokenizers::Tokenizer;
+
+/// Pre-tokenizer split used by the Qwen2/Qwen2.5/Qwen3 byte-level BPE tokenizers
+const QWEN2_PRETOKENIZE_REGEX: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// GGUF `tokenizer.ggml.token_type` values
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+
+pub fn is_gguf(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    path.is_file() && path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gguf"))
+}
+
+/// The only `.gguf` file of a directory without safetensors weights, e.g. the output of
+/// `quantize_checkpoint`, so such a directory loads like a full-precision checkpoint
+pub fn single_gguf_in_dir(dir: impl AsRef<Path>) -> Option<PathBuf> {
+    let mut found = None;
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "safetensors") {
+            return None;
+        }
+        if is_gguf(&path) && found.replace(path).is_some() {
+            return None;
+        }
+    }
+    found
+}
+
+/// Read the header (metadata and tensor infos) of a GGUF file
+pub fn read_content(path: impl AsRef<Path>) -> Result<Content> {
+    let path = path.as_ref();
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Content::read(&mut file).map_err(|e| e.with_path(path).into())
+}
+
+pub fn metadata_string(ct: &Content, key: &str) -> Option<String> {
+    ct.metadata.get(key)?.to_string().ok().cloned()
+}
+
+pub fn metadata_u32(ct: &Content, key: &str) -> Option<u32> {
+    ct.metadata.get(key)?.to_u32().ok()
+}
+
+/// `general.architecture`, e.g. `qwen2` or `qwen3`
+pub fn architecture(ct: &Content) -> Option<String> {
+    metadata_string(ct, "general.architecture")
+}
+
+/// A key under the architecture namespace, e.g. `qwen2.context_length`
+pub fn arch_u32(ct: &Content, key: &str) -> Option<u32> {
+    metadata_u32(ct, &format!("{}.{}", architecture(ct)?, key))
+}
+
+/// Name of the quantization from `general.file_type` (llama.cpp `LLAMA_FTYPE_*`)
+pub fn file_type_name(ct: &Content) -> Option<&'static str> {
+    let name = match metadata_u32(ct, "general.file_type")? {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        32 => "BF16",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Total number of weights stored in the file
+pub fn parameter_count(ct: &Content) -> u64 {
+    ct.tensor_infos
+        .values()
+        .map(|t| t.shape.elem_count() as u64)
+        .sum()
+}
+
+pub fn chat_template(ct: &Content) -> Option<String> {
+    metadata_string(ct, "tokenizer.chat_template")
+}
+
+fn tokens(ct: &Content) -> Result<Vec<String>> {
+    ct.metadata
+        .get("tokenizer.ggml.tokens")
+        .context("GGUF file has no tokenizer.ggml.tokens")?
+        .to_vec()?
+        .iter()
+        .map(|t| Ok(t.to_string()?.clone()))
+        .collect()
+}
+
+/// Text of the token with the id stored under `key`, e.g. `tokenizer.ggml.eos_token_id`
+pub fn special_token(ct: &Content, key: &str) -> Option<String> {
+    let id = metadata_u32(ct, key)? as usize;
+    tokens(ct).ok()?.into_iter().nth(id)
+}
+
+/// Rebuild a byte-level BPE tokenizer from the `tokenizer.ggml.*` metadata
+pub fn tokenizer_from_gguf(ct: &Content) -> Result<Tokenizer> {
+    let model = metadata_string(ct, "tokenizer.ggml.model").unwrap_or_default();
+    if model != "gpt2" {
+        anyhow::bail!("Unsupported GGUF tokenizer model '{model}', only byte-level BPE (gpt2) is supported");
+    }
+
+    let tokens = tokens(ct)?;
+    let merges: Vec<&String> = match ct.metadata.get("tokenizer.ggml.merges") {
+        Some(merges) => merges
+            .to_vec()?
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<candle_core::Result<_>>()?,
+        None => Vec::new(),
+    };
+    let token_types: Vec<i32> = match ct.metadata.get("tokenizer.ggml.token_type") {
+        Some(types) => types
+            .to_vec()?
+            .iter()
+            .map(Value::to_i32)
+            .collect::<candle_core::Result<_>>()?,
+        None => Vec::new(),
+    };
+
+    let vocab: serde_json::Map<String, serde_json::Value> = tokens
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.clone(), id.into()))
+        .collect();
+    let added_tokens: Vec<serde_json::Value> = token_types
+        .iter()
+        .enumerate()
+        .filter(|(_, &t)| t == TOKEN_TYPE_CONTROL || t == TOKEN_TYPE_USER_DEFINED)
+        .map(|(id, &t)| {
+            serde_json::json!({
+                "id": id,
+                "content": tokens[id],
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": t == TOKEN_TYPE_CONTROL,
+            })
+        })
+        .collect();
+    let byte_level = serde_json::json!({
+        "type": "ByteLevel",
+        "add_prefix_space": false,
+        "trim_offsets": false,
+        "use_regex": false,
+    });
+
+    let tokenizer_json = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": { "type": "NFC" },
+        "pre_tokenizer": {
+            "type": "Sequence",
+            "pretokenizers": [
+                {
+                    "type": "Split",
+                    "pattern": { "Regex": QWEN2_PRETOKENIZE_REGEX },
+                    "behavior": "Isolated",
+                    "invert": false,
+                },
+                byte_level,
+            ],
+        },
+        "post_processor": byte_level,
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": "",
+            "end_of_word_suffix": "",
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": false,
+            "vocab": vocab,
+            "merges": merges,
+        },
+    });
+
+    Tokenizer::from_bytes(serde_json::to_vec(&tokenizer_json)?).map_err(anyhow::Error::msg)
+}
@@ -1,3 +1,3 @@
 pub mod token_output_stream;
 pub mod utils;
-pub mod candle_utils;
\ No newline at end of file
+pub mod candle_utils;pub mod gguf;
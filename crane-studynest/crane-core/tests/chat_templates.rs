@@ -0,0 +1,102 @@
+//! Chat templates rendered against reference outputs.
+//!
+//! Each directory in `tests/fixtures/chat_templates` holds a model's
+//! `tokenizer_config.json` and a `cases.json` whose expected outputs are produced by
+//! `render.py` with jinja2 configured like `transformers`.
+
+use std::path::PathBuf;
+
+use crane_core::autotokenizer::{AutoTokenizerConfig, ChatTemplate};
+use crane_core::chat_template::ChatTemplateOptions;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Case {
+    name: String,
+    messages: serde_json::Value,
+    #[serde(default)]
+    add_generation_prompt: bool,
+    tools: Option<Vec<serde_json::Value>>,
+    documents: Option<Vec<serde_json::Value>>,
+    enable_thinking: Option<bool>,
+    template_name: Option<String>,
+    #[serde(default)]
+    extra: serde_json::Map<String, serde_json::Value>,
+    expected: Option<String>,
+    error: Option<String>,
+}
+
+impl Case {
+    fn options(&self) -> ChatTemplateOptions {
+        ChatTemplateOptions {
+            add_generation_prompt: self.add_generation_prompt,
+            template_name: self.template_name.clone(),
+            tools: self.tools.clone(),
+            documents: self.documents.clone(),
+            enable_thinking: self.enable_thinking,
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+fn check_fixtures(model: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/chat_templates")
+        .join(model);
+    let config = AutoTokenizerConfig::from_file(dir.join("tokenizer_config.json")).unwrap();
+    let cases: Vec<Case> =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("cases.json")).unwrap()).unwrap();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let rendered = config.apply_chat_template(&case.messages, &case.options());
+        match (&case.expected, &case.error) {
+            (Some(expected), _) => {
+                let rendered = rendered.unwrap_or_else(|e| panic!("{model}/{}: {e}", case.name));
+                assert_eq!(&rendered, expected, "{model}/{}", case.name);
+            }
+            (None, Some(error)) => {
+                let err = rendered.expect_err(&format!("{model}/{} should fail", case.name));
+                assert!(err.to_string().contains(error.as_str()), "{model}/{}: {err}", case.name);
+            }
+            (None, None) => panic!("{model}/{} has no expected output", case.name),
+        }
+    }
+}
+
+#[test]
+fn qwen25_templates() {
+    check_fixtures("qwen2.5");
+}
+
+#[test]
+fn qwen3_templates() {
+    check_fixtures("qwen3");
+}
+
+#[test]
+fn llama3_templates() {
+    check_fixtures("llama3");
+}
+
+#[test]
+fn deepseek_r1_templates() {
+    check_fixtures("deepseek-r1");
+}
+
+#[test]
+fn template_features() {
+    check_fixtures("features");
+}
+
+#[test]
+fn strftime_now_uses_local_time() {
+    let config = AutoTokenizerConfig {
+        chat_template: Some(ChatTemplate::Single("{{ strftime_now('%d %b %Y') }}".into())),
+        ..Default::default()
+    };
+    let rendered = config
+        .apply_chat_template(Vec::<serde_json::Value>::new(), &ChatTemplateOptions::new())
+        .unwrap();
+    assert_eq!(rendered, chrono::Local::now().format("%d %b %Y").to_string());
+}
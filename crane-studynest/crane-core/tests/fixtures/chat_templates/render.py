@@ -0,0 +1,85 @@
+"""Regenerate the expected outputs in */cases.json.
+
+Renders every case with jinja2 configured the way `transformers`
+`apply_chat_template` does, so the fixtures hold the reference output:
+
+    python3 render.py
+"""
+
+import json
+from datetime import datetime
+from pathlib import Path
+
+from jinja2.exceptions import TemplateError
+from jinja2.ext import loopcontrols
+from jinja2.sandbox import ImmutableSandboxedEnvironment
+
+
+def raise_exception(message):
+    raise TemplateError(message)
+
+
+def tojson(x, ensure_ascii=False, indent=None, separators=None, sort_keys=False):
+    return json.dumps(x, ensure_ascii=ensure_ascii, indent=indent, separators=separators, sort_keys=sort_keys)
+
+
+def environment():
+    env = ImmutableSandboxedEnvironment(trim_blocks=True, lstrip_blocks=True, extensions=[loopcontrols])
+    env.filters["tojson"] = tojson
+    env.globals["raise_exception"] = raise_exception
+    env.globals["strftime_now"] = lambda fmt: datetime.now().strftime(fmt)
+    return env
+
+
+def select_template(chat_template, case):
+    if isinstance(chat_template, str):
+        return chat_template
+    templates = {t["name"]: t["template"] for t in chat_template}
+    name = case.get("template_name")
+    if name is None:
+        name = "tool_use" if case.get("tools") and "tool_use" in templates else "default"
+    return templates[name]
+
+
+def special_tokens(config):
+    tokens = {}
+    for key in ("bos_token", "eos_token", "unk_token", "pad_token"):
+        token = config.get(key)
+        if isinstance(token, dict):
+            token = token["content"]
+        tokens[key] = token or ""
+    return tokens
+
+
+def render(config, case):
+    template = environment().from_string(select_template(config["chat_template"], case))
+    kwargs = dict(special_tokens(config))
+    if "enable_thinking" in case:
+        kwargs["enable_thinking"] = case["enable_thinking"]
+    kwargs.update(case.get("extra", {}))
+    return template.render(
+        messages=case["messages"],
+        tools=case.get("tools"),
+        documents=case.get("documents"),
+        add_generation_prompt=case.get("add_generation_prompt", False),
+        **kwargs,
+    )
+
+
+def main():
+    for cases_file in sorted(Path(__file__).parent.glob("*/cases.json")):
+        config = json.loads((cases_file.parent / "tokenizer_config.json").read_text())
+        cases = json.loads(cases_file.read_text())
+        for case in cases:
+            case.pop("expected", None)
+            case.pop("error", None)
+            try:
+                case["expected"] = render(config, case)
+            except TemplateError as e:
+                case["error"] = str(e)
+        cases_file.write_text(json.dumps(cases, indent=2, ensure_ascii=False) + "\n")
+        print(f"{cases_file.parent.name}: {len(cases)} cases")
+
+
+if __name__ == "__main__":
+    main()
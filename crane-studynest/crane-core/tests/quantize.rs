@@ -0,0 +1,156 @@
+//! Quantizing a checkpoint and loading the output directory back.
+//!
+//! The checkpoint is a tiny Qwen2 model with random weights and a byte-level BPE
+//! tokenizer over lowercase letters, written to a temporary directory.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use candle_core::{DType, Device, Tensor};
+use crane_core::autotokenizer::AutoTokenizer;
+use crane_core::generation::based::ModelForCausalLM;
+use crane_core::generation::GenerationConfig;
+use crane_core::models::qwen25;
+use crane_core::quantize::{quantize_checkpoint, QuantizeOptions};
+use crane_core::utils::gguf;
+use serde_json::json;
+
+const HIDDEN: usize = 256;
+const INTERMEDIATE: usize = 512;
+const HEADS: usize = 4;
+const KV_HEADS: usize = 2;
+const VOCAB: usize = 32;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crane-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_tiny_qwen2(dir: &Path) {
+    let head_dim = HIDDEN / HEADS;
+    let config = json!({
+        "architectures": ["Qwen2ForCausalLM"],
+        "model_type": "qwen2",
+        "hidden_size": HIDDEN,
+        "intermediate_size": INTERMEDIATE,
+        "num_attention_heads": HEADS,
+        "num_key_value_heads": KV_HEADS,
+        "num_hidden_layers": 1,
+        "max_position_embeddings": 128,
+        "max_window_layers": 1,
+        "sliding_window": 128,
+        "use_sliding_window": false,
+        "vocab_size": VOCAB,
+        "rms_norm_eps": 1e-6,
+        "rope_theta": 10000.0,
+        "hidden_act": "silu",
+        "tie_word_embeddings": false,
+        "eos_token_id": 27
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+    let mut vocab: serde_json::Map<String, serde_json::Value> = ('a'..='z')
+        .enumerate()
+        .map(|(i, c)| (c.to_string(), json!(i)))
+        .collect();
+    vocab.insert("Ġ".to_string(), json!(26));
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [{
+            "id": 27,
+            "content": "<|endoftext|>",
+            "single_word": false,
+            "lstrip": false,
+            "rstrip": false,
+            "normalized": false,
+            "special": true
+        }],
+        "normalizer": null,
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": false, "use_regex": true},
+        "post_processor": null,
+        "decoder": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": false, "use_regex": true},
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "vocab": vocab,
+            "merges": []
+        }
+    });
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+    let tokenizer_config = json!({ "eos_token": "<|endoftext|>", "pad_token": "<|endoftext|>" });
+    std::fs::write(dir.join("tokenizer_config.json"), tokenizer_config.to_string()).unwrap();
+
+    let device = Device::Cpu;
+    let randn = |shape: &[usize]| {
+        Tensor::randn(0f32, 0.02, shape, &device)
+            .unwrap()
+            .to_dtype(DType::BF16)
+            .unwrap()
+    };
+    let ones = |len: usize| Tensor::ones(len, DType::BF16, &device).unwrap();
+    let kv = KV_HEADS * head_dim;
+    let mut tensors = HashMap::from([
+        ("model.embed_tokens.weight".to_string(), randn(&[VOCAB, HIDDEN])),
+        ("model.norm.weight".to_string(), ones(HIDDEN)),
+        ("lm_head.weight".to_string(), randn(&[VOCAB, HIDDEN])),
+    ]);
+    for (name, tensor) in [
+        ("self_attn.q_proj.weight", randn(&[HIDDEN, HIDDEN])),
+        ("self_attn.q_proj.bias", randn(&[HIDDEN])),
+        ("self_attn.k_proj.weight", randn(&[kv, HIDDEN])),
+        ("self_attn.k_proj.bias", randn(&[kv])),
+        ("self_attn.v_proj.weight", randn(&[kv, HIDDEN])),
+        ("self_attn.v_proj.bias", randn(&[kv])),
+        ("self_attn.o_proj.weight", randn(&[HIDDEN, HIDDEN])),
+        ("mlp.gate_proj.weight", randn(&[INTERMEDIATE, HIDDEN])),
+        ("mlp.up_proj.weight", randn(&[INTERMEDIATE, HIDDEN])),
+        ("mlp.down_proj.weight", randn(&[HIDDEN, INTERMEDIATE])),
+        ("input_layernorm.weight", ones(HIDDEN)),
+        ("post_attention_layernorm.weight", ones(HIDDEN)),
+    ] {
+        tensors.insert(format!("model.layers.0.{name}"), tensor);
+    }
+    candle_core::safetensors::save(&tensors, dir.join("model.safetensors")).unwrap();
+}
+
+#[test]
+fn quantized_directory_loads_back() {
+    let source = temp_dir("quantize-source");
+    let output = temp_dir("quantize-output");
+    write_tiny_qwen2(&source);
+
+    let options = QuantizeOptions {
+        sample_text: Some("the quick brown fox jumps over the lazy dog".to_string()),
+        ..Default::default()
+    };
+    let report = quantize_checkpoint(source.to_str().unwrap(), &output, &options).unwrap();
+    assert!(report.quantized_tensors > 0);
+    let perplexity = report.perplexity.as_ref().unwrap();
+    assert!(perplexity.original.is_finite() && perplexity.quantized.is_finite());
+
+    // The output directory resolves to its GGUF file
+    assert_eq!(gguf::single_gguf_in_dir(&output), Some(report.output.clone()));
+    let output_path = output.to_str().unwrap();
+    let mut model = qwen25::Model::new(output_path, &Device::Cpu, &DType::F32).unwrap();
+    assert!(model.is_quantized());
+    let tokenizer = AutoTokenizer::from_pretrained(output_path, None).unwrap();
+    let input_ids = tokenizer.encode("abc", false).unwrap();
+    assert_eq!(input_ids, [0, 1, 2]);
+
+    let tokens = model
+        .generate(&input_ids, &GenerationConfig::with_max_tokens(4), None)
+        .unwrap();
+    assert!(tokens.len() > input_ids.len() && tokens.len() <= input_ids.len() + 4);
+
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
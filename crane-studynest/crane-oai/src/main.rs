@@ -1,3 +1,93 @@
+//! OpenAI-compatible HTTP server
+//!
+//! Serves `POST /v1/embeddings` on top of the crane-studynest service, so existing
+//! OpenAI clients and vector-store tooling can use local embedding models.
+
+mod openai_api;
+
+use clap::Parser;
+use crane_studynest::service::{ChatService, ServiceConfig};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use openai_api::{ApiError, EmbeddingRequest};
+
+#[derive(Parser, Debug)]
+#[command(name = "crane-oai", about = "OpenAI-compatible API for local Crane models")]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// Default embedding model (path or name under the checkpoints directory)
+    #[arg(long)]
+    embedding_model: Option<String>,
+
+    #[arg(long, default_value = "checkpoints")]
+    checkpoints_dir: String,
+
+    /// cpu, metal, cuda:N or auto
+    #[arg(long, default_value = "auto")]
+    device: String,
+}
+
 fn main() {
-    println!("Hello, world!");
+    if let Err(e) = run() {
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let config = ServiceConfig {
+        device: args.device,
+        checkpoints_dir: args.checkpoints_dir,
+        embedding_model: args.embedding_model,
+        ..ServiceConfig::default()
+    };
+    let service = ChatService::new(config)?;
+
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address).map_err(|e| e.to_string())?;
+    eprintln!("[crane-oai] Listening on http://{}", address);
+
+    for request in server.incoming_requests() {
+        handle(&service, request);
+    }
+    Ok(())
+}
+
+fn handle(service: &ChatService, mut request: Request) {
+    let result = match (request.method(), request.url()) {
+        (Method::Post, "/v1/embeddings") => read_json::<EmbeddingRequest>(&mut request)
+            .and_then(|body| openai_api::embeddings(service, body))
+            .and_then(|list| serde_json::to_value(list).map_err(|e| ApiError::server(e.to_string()))),
+        (_, url) => Err(ApiError::not_found(format!("Unknown endpoint: {}", url))),
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err(e) => {
+            eprintln!("[crane-oai] {} {}: {}", request.method(), request.url(), e.message);
+            (e.status, e.to_json())
+        }
+    };
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("[crane-oai] Failed to send response: {}", e);
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<T, ApiError> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| ApiError::invalid_request(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| ApiError::invalid_request(format!("Invalid request body: {}", e)))
 }
@@ -0,0 +1,190 @@
+//! OpenAI-compatible request and response types
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+
+use crane_studynest::service::{ChatService, EmbeddingResponse};
+
+/// A single text or a batch of texts
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::Single(text) => vec![text],
+            EmbeddingInput::Batch(texts) => texts,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    /// Little-endian f32 values, base64 encoded
+    Base64,
+}
+
+/// `POST /v1/embeddings`
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingRequest {
+    pub input: EmbeddingInput,
+    /// Embedding model; the server's default model when absent
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub encoding_format: EncodingFormat,
+    /// Keep the first `dimensions` values (Matryoshka truncation), renormalized
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingData {
+    pub object: &'static str,
+    pub index: usize,
+    pub embedding: EmbeddingVector,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingList {
+    pub object: &'static str,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: Usage,
+}
+
+/// Error returned in OpenAI's `{"error": {...}}` shape
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+    pub kind: &'static str,
+}
+
+impl ApiError {
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self {
+            status: 400,
+            message: message.into(),
+            kind: "invalid_request_error",
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: 404,
+            message: message.into(),
+            kind: "invalid_request_error",
+        }
+    }
+
+    pub fn server(message: impl Into<String>) -> Self {
+        Self {
+            status: 500,
+            message: message.into(),
+            kind: "server_error",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "message": self.message,
+                "type": self.kind,
+                "param": null,
+                "code": null
+            }
+        })
+    }
+}
+
+/// Handle an embeddings request
+pub fn embeddings(service: &ChatService, request: EmbeddingRequest) -> Result<EmbeddingList, ApiError> {
+    let input = request.input.into_vec();
+    if input.is_empty() || input.iter().any(|text| text.is_empty()) {
+        return Err(ApiError::invalid_request("input must be a non-empty string or array of non-empty strings"));
+    }
+    if request.dimensions == Some(0) {
+        return Err(ApiError::invalid_request("dimensions must be at least 1"));
+    }
+
+    let response = service
+        .embed(&input, request.model.as_deref())
+        .map_err(|e| match e {
+            crane_studynest::StudyNestError::ModelNotFound(_) => ApiError::not_found(e.to_string()),
+            crane_studynest::StudyNestError::ConfigError(_) => ApiError::invalid_request(e.to_string()),
+            _ => ApiError::server(e.to_string()),
+        })?;
+    if let Some(dimensions) = request.dimensions.filter(|&d| d > response.dimension) {
+        return Err(ApiError::invalid_request(format!(
+            "dimensions must be at most {} for {}, got {}",
+            response.dimension, response.model, dimensions
+        )));
+    }
+
+    Ok(to_embedding_list(response, request.encoding_format, request.dimensions))
+}
+
+fn to_embedding_list(
+    response: EmbeddingResponse,
+    format: EncodingFormat,
+    dimensions: Option<usize>,
+) -> EmbeddingList {
+    let data = response
+        .embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut embedding)| {
+            if let Some(dimensions) = dimensions {
+                embedding.truncate(dimensions);
+                let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    embedding.iter_mut().for_each(|x| *x /= norm);
+                }
+            }
+            let embedding = match format {
+                EncodingFormat::Float => EmbeddingVector::Float(embedding),
+                EncodingFormat::Base64 => {
+                    let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+                    EmbeddingVector::Base64(STANDARD.encode(bytes))
+                }
+            };
+            EmbeddingData {
+                object: "embedding",
+                index,
+                embedding,
+            }
+        })
+        .collect();
+
+    EmbeddingList {
+        object: "list",
+        data,
+        model: response.model,
+        usage: Usage {
+            prompt_tokens: response.prompt_tokens,
+            total_tokens: response.prompt_tokens,
+        },
+    }
+}
@@ -1,10 +1,21 @@
 use crate::common::{CraneResult, CraneError, config::{CommonConfig, DataType, DeviceConfig}};
+use crane_core::generation::audio_streamer::AudioChunk;
+use crane_core::models::sparktts::{BiCodecFrameCodec, Gender, Level, SparkTTS, SparkVoice};
 use std::path::Path;
 
 /// Text-to-Speech client
+///
+/// Runs Spark-TTS when `model_path` holds a Spark-TTS checkpoint (it has a `BiCodec`
+/// directory), and Orpheus otherwise.
 pub struct TtsClient {
     config: CommonConfig,
-    // Store the actual TTS model here
+    #[cfg_attr(not(feature = "onnx"), allow(dead_code))]
+    voice: String,
+    prompt_audio: Option<String>,
+    prompt_text: Option<String>,
+    gender: Gender,
+    pitch: Level,
+    speed: Level,
 }
 
 impl TtsClient {
@@ -12,14 +23,49 @@ impl TtsClient {
     pub fn new(config: CommonConfig) -> CraneResult<Self> {
         Ok(Self {
             config,
+            voice: crane_core::models::orpheus::DEFAULT_VOICE.to_string(),
+            prompt_audio: None,
+            prompt_text: None,
+            gender: Gender::default(),
+            pitch: Level::default(),
+            speed: Level::default(),
         })
     }
-    
-    /// Convert text to speech and save to file
-    pub fn text_to_speech<P: AsRef<Path>>(&self, text: &str, output_file: P) -> CraneResult<()> {
-        // For now, we'll use the SparkTTS model as an example
-        // In a real implementation, we would use the appropriate TTS model
-        let device = match &self.config.device {
+
+    /// Speaker to use with Orpheus, one of `crane_core::models::orpheus::VOICES`
+    pub fn with_voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = voice.into();
+        self
+    }
+
+    /// Clone the voice of a reference WAV with Spark-TTS. The transcript of the recording is
+    /// optional but makes the copy closer.
+    pub fn with_prompt_audio(mut self, path: impl Into<String>, transcript: Option<String>) -> Self {
+        self.prompt_audio = Some(path.into());
+        self.prompt_text = transcript;
+        self
+    }
+
+    /// Gender of the Spark-TTS voice when no reference is given
+    pub fn with_gender(mut self, gender: Gender) -> Self {
+        self.gender = gender;
+        self
+    }
+
+    /// Pitch of the Spark-TTS voice when no reference is given
+    pub fn with_pitch(mut self, pitch: Level) -> Self {
+        self.pitch = pitch;
+        self
+    }
+
+    /// Speaking rate of the Spark-TTS voice when no reference is given
+    pub fn with_speed(mut self, speed: Level) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    fn device(&self) -> CraneResult<crane_core::models::Device> {
+        Ok(match &self.config.device {
             DeviceConfig::Cpu => crane_core::models::Device::Cpu,
             DeviceConfig::Cuda(gpu_id) => crane_core::models::Device::cuda_if_available(*gpu_id as usize)
                 .map_err(|e| CraneError::ModelError(e.to_string()))?,
@@ -34,20 +80,148 @@ impl TtsClient {
                     return Err(CraneError::ConfigError("Metal device not available on this platform".to_string()));
                 }
             }
-        };
-        
-        // Placeholder implementation - in reality, we would use the appropriate TTS model
-        // For example, SparkTTS or other available TTS models in crane_core
-        println!("Converting text to speech: {}", text);
-        println!("Saving to: {}", output_file.as_ref().display());
-        
-        // This is a placeholder - the actual implementation would depend on the specific TTS model
-        // available in crane_core
-        Err(CraneError::Other("TTS functionality not fully implemented yet".to_string()))
-    }
-    
+        })
+    }
+
+    fn dtype(&self) -> crane_core::models::DType {
+        match self.config.dtype {
+            DataType::F16 => crane_core::models::DType::F16,
+            DataType::F32 => crane_core::models::DType::F32,
+            DataType::BF16 => crane_core::models::DType::BF16,
+        }
+    }
+
+    fn is_spark(&self) -> bool {
+        Path::new(&self.config.model_path).join("BiCodec").is_dir()
+    }
+
+    /// Convert text to speech and save to file
+    ///
+    /// Spark-TTS writes 16 kHz mono WAV. Orpheus decodes with the SNAC decoder found in (or
+    /// next to) the model directory and writes 24 kHz mono WAV.
+    pub fn text_to_speech<P: AsRef<Path>>(&self, text: &str, output_file: P) -> CraneResult<()> {
+        let output_file = output_file.as_ref().to_string_lossy();
+        if self.is_spark() {
+            self.spark_to_file(text, &output_file)
+        } else {
+            self.orpheus_to_file(text, &output_file)
+        }
+    }
+
+    /// Convert text to speech, calling `on_chunk` with mono PCM while it is generated
+    ///
+    /// Codec frames are decoded a few at a time and the chunks are cross-faded, so playback
+    /// can start long before the utterance is complete. Returns the sample rate.
+    pub fn text_to_speech_stream(
+        &self,
+        text: &str,
+        on_chunk: impl FnMut(AudioChunk) -> anyhow::Result<()>,
+    ) -> CraneResult<u32> {
+        if self.is_spark() {
+            let mut model = self.spark()?;
+            let voice = self.spark_voice(&model)?;
+            model
+                .synthesize_streaming(
+                    text,
+                    &voice,
+                    &SparkTTS::generation_config(3000),
+                    BiCodecFrameCodec::streaming_config(),
+                    on_chunk,
+                )
+                .map_err(|e| CraneError::ModelError(e.to_string()))?;
+            Ok(crane_core::models::sparktts::SAMPLE_RATE)
+        } else {
+            self.orpheus_stream(text, on_chunk)
+        }
+    }
+
+    fn spark(&self) -> CraneResult<SparkTTS> {
+        SparkTTS::from_pretrained(&self.config.model_path, &self.device()?, &self.dtype())
+            .map_err(|e| CraneError::ModelError(e.to_string()))
+    }
+
+    fn spark_voice(&self, model: &SparkTTS) -> CraneResult<SparkVoice> {
+        Ok(match &self.prompt_audio {
+            Some(path) => SparkVoice::Clone(
+                model
+                    .voice_prompt_from_file(path, self.prompt_text.as_deref())
+                    .map_err(|e| CraneError::ModelError(e.to_string()))?,
+            ),
+            None => SparkVoice::Control {
+                gender: self.gender,
+                pitch: self.pitch,
+                speed: self.speed,
+            },
+        })
+    }
+
+    fn spark_to_file(&self, text: &str, output_file: &str) -> CraneResult<()> {
+        let mut model = self.spark()?;
+        let voice = self.spark_voice(&model)?;
+        model
+            .text_to_speech(text, &voice, output_file)
+            .map_err(|e| CraneError::ModelError(e.to_string()))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "onnx")]
+    fn orpheus_to_file(&self, text: &str, output_file: &str) -> CraneResult<()> {
+        let mut model = crane_core::models::orpheus::OrpheusTTS::new(
+            &self.config.model_path,
+            None,
+            &self.device()?,
+            &self.dtype(),
+        ).map_err(|e| CraneError::ModelError(e.to_string()))?;
+
+        model.text_to_speech(
+            text,
+            Some(&self.voice),
+            output_file,
+        ).map_err(|e| CraneError::ModelError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "onnx")]
+    fn orpheus_stream(
+        &self,
+        text: &str,
+        on_chunk: impl FnMut(AudioChunk) -> anyhow::Result<()>,
+    ) -> CraneResult<u32> {
+        use crane_core::models::orpheus::{OrpheusLM, OrpheusTTS, SnacFrameCodec, SAMPLE_RATE};
+
+        let mut model = OrpheusTTS::new(&self.config.model_path, None, &self.device()?, &self.dtype())
+            .map_err(|e| CraneError::ModelError(e.to_string()))?;
+        model.synthesize_streaming(
+            text,
+            Some(&self.voice),
+            &OrpheusLM::generation_config(1200),
+            SnacFrameCodec::streaming_config(),
+            on_chunk,
+        ).map_err(|e| CraneError::ModelError(e.to_string()))?;
+        Ok(SAMPLE_RATE)
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn orpheus_stream(
+        &self,
+        _text: &str,
+        _on_chunk: impl FnMut(AudioChunk) -> anyhow::Result<()>,
+    ) -> CraneResult<u32> {
+        Err(CraneError::ConfigError(
+            "Orpheus TTS requires the 'onnx' feature for the SNAC decoder".to_string(),
+        ))
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn orpheus_to_file(&self, _text: &str, _output_file: &str) -> CraneResult<()> {
+        Err(CraneError::ConfigError(
+            "Orpheus TTS requires the 'onnx' feature for the SNAC decoder".to_string(),
+        ))
+    }
+
     /// Convert text to speech and return audio data (placeholder implementation)
     pub fn text_to_speech_data(&self, _text: &str) -> CraneResult<Vec<u8>> {
         Err(CraneError::Other("Audio data output not implemented yet".to_string()))
     }
-}
\ No newline at end of file
+}
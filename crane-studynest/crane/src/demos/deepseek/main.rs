@@ -39,6 +39,8 @@ fn main() {
         pad_token_id: tokenizer.get_token("<|end_of_text|>"),
         eos_token_id: tokenizer.get_token("<|im_end|>"),
         report_speed: true,
+        cancellation: None,
+        deadline: None,
     };
 
     let chats = [
@@ -46,6 +46,8 @@ impl ChatCLI {
             pad_token_id: tokenizer.get_token("<|end_of_text|>"),
             eos_token_id: tokenizer.get_token("<|im_end|>"),
             report_speed: true,
+            cancellation: None,
+            deadline: None,
         };
 
         Ok(Self {
@@ -1,3 +1,59 @@
-fn main() {
-    
-}
\ No newline at end of file
+use clap::Parser;
+use crane_core::models::{
+    DType, Device,
+    sparktts::{Gender, Level, SparkTTS, SparkVoice},
+};
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Args {
+    #[clap(short('m'), long, default_value = "checkpoints/Spark-TTS-0.5B")]
+    model_path: String,
+
+    #[clap(short('t'), long, default_value = "身临其境，换新体验。塑造开源语音合成新范式，让智能语音更自然。")]
+    text: String,
+
+    /// Reference recording to clone, e.g. data/prompt_audio.wav
+    #[clap(long)]
+    prompt_audio: Option<String>,
+
+    /// What is said in the reference recording
+    #[clap(long)]
+    prompt_text: Option<String>,
+
+    /// female or male, used without a reference
+    #[clap(long, default_value = "female")]
+    gender: Gender,
+
+    /// very_low, low, moderate, high or very_high, used without a reference
+    #[clap(long, default_value = "moderate")]
+    pitch: Level,
+
+    /// very_low, low, moderate, high or very_high, used without a reference
+    #[clap(long, default_value = "moderate")]
+    speed: Level,
+
+    #[clap(short('o'), long, default_value = "sparktts_out.wav")]
+    output: String,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let device = Device::cuda_if_available(0)?;
+    let dtype = if device.is_cpu() { DType::F32 } else { DType::BF16 };
+
+    let mut model = SparkTTS::from_pretrained(&args.model_path, &device, &dtype)?;
+    let voice = match &args.prompt_audio {
+        Some(path) => SparkVoice::Clone(model.voice_prompt_from_file(path, args.prompt_text.as_deref())?),
+        None => SparkVoice::Control {
+            gender: args.gender,
+            pitch: args.pitch,
+            speed: args.speed,
+        },
+    };
+
+    let start = std::time::Instant::now();
+    let file = model.text_to_speech(&args.text, &voice, &args.output)?;
+    println!("saved {file} in {:.2}s", start.elapsed().as_secs_f64());
+    Ok(())
+}
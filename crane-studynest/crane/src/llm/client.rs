@@ -70,6 +70,8 @@ impl LlmClient {
             pad_token_id: config.pad_token_id,
             eos_token_id: config.eos_token_id,
             report_speed: config.report_speed,
+            cancellation: None,
+            deadline: None,
         };
 
         let input_ids = model.prepare_inputs(prompt)
@@ -141,6 +143,8 @@ impl LlmClient {
             pad_token_id: config.pad_token_id,
             eos_token_id: config.eos_token_id,
             report_speed: config.report_speed,
+            cancellation: None,
+            deadline: None,
         };
 
         let input_ids = model.prepare_inputs(prompt)
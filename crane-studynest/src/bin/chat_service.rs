@@ -1,9 +1,24 @@
 //! Chat service binary for Electron integration
 //! Provides HTTP server for chat functionality
+//!
+//! Each request runs on its own thread so stdin keeps being read while a chat is
+//! generating (e.g. to `cancel` it). Responses echo the request `id` when one is given.
+//! Long requests (`summarize`, `lecture_notes`, `read_aloud`) write `{"method": "progress", "params": {...}}`
+//! notifications, without an `id`, before their response. `read_aloud_stream` writes
+//! `{"method": "audio", "params": {...}}` notifications with base64 PCM instead, the
+//! last one marked `"last": true`.
 
-use crane_studynest::service::{ChatService, ServiceConfig, ChatRequest, ChatResponse, ErrorResponse};
+use crane_studynest::service::{
+    AddCardsRequest, AskRequest, CardsDueRequest, ChatService, CreateSessionRequest, ClassifyImageRequest, ServiceConfig, ChatRequest,
+    ExportFlashcardsRequest, FlashcardsRequest, GradeQuizRequest, IngestRequest,
+    LectureNotesRequest, QuizRequest, ReadAloudRequest, ReviewCardRequest, ReviewSettingsRequest, SearchImagesRequest, SearchRequest, SummarizeRequest,
+    UpdateSessionRequest,
+};
+use crane_studynest::session::ExportFormat;
+use serde_json::Value;
 use std::io::{BufRead, BufReader, Write};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
 fn main() {
     if let Err(e) = run_service() {
@@ -16,7 +31,7 @@ fn run_service() -> Result<(), Box<dyn std::error::Error>> {
     eprintln!("[ChatService] Starting crane-studynest chat service...");
     
     let config = ServiceConfig::default();
-    let service = Arc::new(ChatService::new(config));
+    let service = Arc::new(ChatService::new(config)?);
     
     eprintln!("[ChatService] Service initialized. Waiting for commands via stdin...");
     eprintln!("[ChatService] Send JSON-RPC commands in the format:");
@@ -25,34 +40,66 @@ fn run_service() -> Result<(), Box<dyn std::error::Error>> {
     
     let stdin = std::io::stdin();
     let reader = BufReader::new(stdin);
+    let stdout = Arc::new(Mutex::new(std::io::stdout()));
+    let mut workers: Vec<JoinHandle<()>> = Vec::new();
     
     for line in reader.lines() {
         let line = line?;
         if line.trim().is_empty() {
             continue;
         }
-        
-        match handle_request(&service, &line) {
-            Ok(response) => {
-                println!("{}", response);
-                std::io::stdout().flush()?;
-            }
+
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
             Err(e) => {
-                let error_response = serde_json::json!({
-                    "error": e.to_string()
-                });
-                println!("{}", serde_json::to_string(&error_response)?);
-                std::io::stdout().flush()?;
+                write_response(&stdout, &serde_json::json!({ "error": e.to_string() }));
+                continue;
             }
-        }
+        };
+
+        let service = service.clone();
+        let stdout = stdout.clone();
+        workers.retain(|worker| !worker.is_finished());
+        workers.push(std::thread::spawn(move || {
+            let notify = |message: &Value| write_response(&stdout, message);
+            let mut response = match handle_request(&service, &request, &notify) {
+                Ok(response) => response,
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            };
+            if let Some(id) = request.get("id") {
+                response["id"] = id.clone();
+            }
+            write_response(&stdout, &response);
+        }));
+    }
+
+    // Let in-flight requests finish after stdin is closed
+    for worker in workers {
+        let _ = worker.join();
     }
     
     Ok(())
 }
 
-fn handle_request(service: &Arc<ChatService>, request_str: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let request: serde_json::Value = serde_json::from_str(request_str)?;
-    
+fn write_response(stdout: &Mutex<std::io::Stdout>, response: &Value) {
+    let mut out = stdout.lock().unwrap();
+    let _ = writeln!(out, "{}", response);
+    let _ = out.flush();
+}
+
+fn id_to_string(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Handle one request; `notify` writes notifications (e.g. progress) before the response
+fn handle_request(
+    service: &Arc<ChatService>,
+    request: &Value,
+    notify: &dyn Fn(&Value),
+) -> Result<Value, Box<dyn std::error::Error>> {
     let method = request["method"].as_str().ok_or("Missing method")?;
     let params = &request["params"];
     
@@ -68,32 +115,424 @@ fn handle_request(service: &Arc<ChatService>, request_str: &str) -> Result<Strin
             let response = serde_json::json!({
                 "result": "Model initialized successfully"
             });
-            Ok(serde_json::to_string(&response)?)
+            Ok(response)
         }
         
         "chat" => {
-            let chat_request: ChatRequest = serde_json::from_value(params.clone())?;
+            let mut chat_request: ChatRequest = serde_json::from_value(params.clone())?;
+            if chat_request.request_id.is_none() {
+                chat_request.request_id = request.get("id").map(id_to_string);
+            }
             
             eprintln!("[ChatService] Processing chat request with {} messages", 
                      chat_request.messages.len());
             
             let chat_response = service.chat(chat_request)?;
             
-            eprintln!("[ChatService] Generated response: {} chars", 
-                     chat_response.message.content.len());
+            eprintln!("[ChatService] Generated response: {} chars ({:?})", 
+                     chat_response.message.content.len(), chat_response.finish_reason);
             
             let response = serde_json::json!({
                 "result": chat_response
             });
-            Ok(serde_json::to_string(&response)?)
+            Ok(response)
         }
         
+        "cancel" => {
+            let request_id = params.get("request_id").map(id_to_string)
+                .ok_or("Missing request_id parameter")?;
+            let cancelled = service.cancel(&request_id);
+            eprintln!("[ChatService] Cancel request {}: {}", request_id, cancelled);
+            let response = serde_json::json!({
+                "result": cancelled
+            });
+            Ok(response)
+        }
+
+        "create_session" => {
+            let create_request: CreateSessionRequest = match params {
+                Value::Null => CreateSessionRequest::default(),
+                _ => serde_json::from_value(params.clone())?,
+            };
+            let session = service.create_session(create_request)?;
+            eprintln!("[ChatService] Created session {}", session.id);
+            let response = serde_json::json!({
+                "result": session
+            });
+            Ok(response)
+        }
+
+        "list_sessions" => {
+            let sessions = service.list_sessions()?;
+            let response = serde_json::json!({
+                "result": sessions
+            });
+            Ok(response)
+        }
+
+        "load_session" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            let session = service.load_session(id)?;
+            let response = serde_json::json!({
+                "result": session
+            });
+            Ok(response)
+        }
+
+        "rename_session" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            let name = params["name"].as_str().ok_or("Missing name parameter")?;
+            let info = service.rename_session(id, name)?;
+            let response = serde_json::json!({
+                "result": info
+            });
+            Ok(response)
+        }
+
+        "update_session" => {
+            let update_request: UpdateSessionRequest = serde_json::from_value(params.clone())?;
+            let info = service.update_session(update_request)?;
+            let response = serde_json::json!({
+                "result": info
+            });
+            Ok(response)
+        }
+
+        "delete_session" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            service.delete_session(id)?;
+            eprintln!("[ChatService] Deleted session {}", id);
+            let response = serde_json::json!({
+                "result": "Session deleted"
+            });
+            Ok(response)
+        }
+
+        "export_session" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            let format: ExportFormat = match params.get("format") {
+                Some(format) => serde_json::from_value(format.clone())?,
+                None => ExportFormat::Json,
+            };
+            let exported = service.export_session(id, format, params["path"].as_str())?;
+            let response = serde_json::json!({
+                "result": exported
+            });
+            Ok(response)
+        }
+
+        "close_session" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            let closed = service.close_session(id);
+            let response = serde_json::json!({
+                "result": closed
+            });
+            Ok(response)
+        }
+
+        "status" => {
+            let (open_sessions, running, queued) = service.status();
+            let response = serde_json::json!({
+                "result": {
+                    "open_sessions": open_sessions,
+                    "running": running,
+                    "queued": queued
+                }
+            });
+            Ok(response)
+        }
+
+        "embed" => {
+            let input: Vec<String> = match &params["input"] {
+                Value::String(text) => vec![text.clone()],
+                Value::Array(_) => serde_json::from_value(params["input"].clone())?,
+                _ => return Err("Missing input parameter".into()),
+            };
+            let embeddings = service.embed(&input, params["model"].as_str())?;
+            eprintln!("[ChatService] Embedded {} texts with {}", input.len(), embeddings.model);
+            let response = serde_json::json!({
+                "result": embeddings
+            });
+            Ok(response)
+        }
+
+        "ingest" => {
+            let ingest_request: IngestRequest = serde_json::from_value(params.clone())?;
+            let document = service.ingest(ingest_request)?;
+            eprintln!("[ChatService] Indexed {} ({} chunks)", document.title, document.chunks);
+            let response = serde_json::json!({
+                "result": document
+            });
+            Ok(response)
+        }
+
+        "list_documents" => {
+            let response = serde_json::json!({
+                "result": service.list_documents()
+            });
+            Ok(response)
+        }
+
+        "delete_document" => {
+            let id = params["id"].as_str().ok_or("Missing id parameter")?;
+            service.delete_document(id)?;
+            eprintln!("[ChatService] Deleted document {}", id);
+            let response = serde_json::json!({
+                "result": "Document deleted"
+            });
+            Ok(response)
+        }
+
+        "ask" => {
+            let mut ask_request: AskRequest = serde_json::from_value(params.clone())?;
+            if ask_request.request_id.is_none() {
+                ask_request.request_id = request.get("id").map(id_to_string);
+            }
+            let answer = service.ask(ask_request)?;
+            eprintln!("[ChatService] Answered from notes with {} of {} sources cited ({:?})",
+                     answer.citations.len(), answer.sources.len(), answer.finish_reason);
+            let response = serde_json::json!({
+                "result": answer
+            });
+            Ok(response)
+        }
+
+        "search" => {
+            let search_request: SearchRequest = serde_json::from_value(params.clone())?;
+            let results = service.search(search_request)?;
+            eprintln!("[ChatService] Found {} notes ({:?} search)", results.hits.len(), results.mode);
+            let response = serde_json::json!({
+                "result": results
+            });
+            Ok(response)
+        }
+
+        "search_images" => {
+            let search_request: SearchImagesRequest = serde_json::from_value(params.clone())?;
+            let results = service.search_images(search_request)?;
+            eprintln!("[ChatService] Found {} images with {}", results.hits.len(), results.model);
+            let response = serde_json::json!({
+                "result": results
+            });
+            Ok(response)
+        }
+
+        "classify_image" => {
+            let classify_request: ClassifyImageRequest = serde_json::from_value(params.clone())?;
+            let classified = service.classify_image(classify_request)?;
+            let response = serde_json::json!({
+                "result": classified
+            });
+            Ok(response)
+        }
+
+        "generate_flashcards" => {
+            let mut flashcards_request: FlashcardsRequest = serde_json::from_value(params.clone())?;
+            if flashcards_request.request_id.is_none() {
+                flashcards_request.request_id = request.get("id").map(id_to_string);
+            }
+            let set = service.generate_flashcards(flashcards_request)?;
+            eprintln!("[ChatService] Generated {} flashcards ({:?})", set.cards.len(), set.finish_reason);
+            let response = serde_json::json!({
+                "result": set
+            });
+            Ok(response)
+        }
+
+        "export_flashcards" => {
+            let export_request: ExportFlashcardsRequest = serde_json::from_value(params.clone())?;
+            let exported = service.export_flashcards(export_request)?;
+            let response = serde_json::json!({
+                "result": exported
+            });
+            Ok(response)
+        }
+
+        "generate_quiz" => {
+            let mut quiz_request: QuizRequest = serde_json::from_value(params.clone())?;
+            if quiz_request.request_id.is_none() {
+                quiz_request.request_id = request.get("id").map(id_to_string);
+            }
+            let quiz = service.generate_quiz(quiz_request)?;
+            eprintln!("[ChatService] Generated {} quiz questions ({:?})", quiz.questions.len(), quiz.finish_reason);
+            let response = serde_json::json!({
+                "result": quiz
+            });
+            Ok(response)
+        }
+
+        "grade_quiz" => {
+            let mut grade_request: GradeQuizRequest = serde_json::from_value(params.clone())?;
+            if grade_request.request_id.is_none() {
+                grade_request.request_id = request.get("id").map(id_to_string);
+            }
+            let result = service.grade_quiz(grade_request)?;
+            eprintln!("[ChatService] Graded quiz: {}/{} correct", result.correct, result.total);
+            let response = serde_json::json!({
+                "result": result
+            });
+            Ok(response)
+        }
+
+        "add_cards" => {
+            let add_request: AddCardsRequest = serde_json::from_value(params.clone())?;
+            let added = service.add_cards(add_request)?;
+            eprintln!("[ChatService] Scheduled {} new cards", added);
+            let response = serde_json::json!({
+                "result": { "added": added }
+            });
+            Ok(response)
+        }
+
+        "remove_cards" => {
+            let ids: Vec<String> = serde_json::from_value(params.get("ids").cloned().unwrap_or_default())?;
+            let removed = service.remove_cards(&ids)?;
+            let response = serde_json::json!({
+                "result": { "removed": removed }
+            });
+            Ok(response)
+        }
+
+        "cards_due" => {
+            // Params are optional: all cards due now
+            let due_request: CardsDueRequest = if params.is_null() {
+                CardsDueRequest::default()
+            } else {
+                serde_json::from_value(params.clone())?
+            };
+            let queue = service.cards_due(due_request);
+            let response = serde_json::json!({
+                "result": queue
+            });
+            Ok(response)
+        }
+
+        "review_card" => {
+            let review_request: ReviewCardRequest = serde_json::from_value(params.clone())?;
+            let card = service.review_card(review_request)?;
+            let response = serde_json::json!({
+                "result": card
+            });
+            Ok(response)
+        }
+
+        "review_settings" => {
+            // Without params the current settings are returned unchanged
+            let settings_request: ReviewSettingsRequest = if params.is_null() {
+                ReviewSettingsRequest::default()
+            } else {
+                serde_json::from_value(params.clone())?
+            };
+            let parameters = service.review_settings(settings_request)?;
+            let response = serde_json::json!({
+                "result": parameters
+            });
+            Ok(response)
+        }
+
+        "optimize_scheduler" => {
+            let report = service.optimize_scheduler()?;
+            let response = serde_json::json!({
+                "result": report
+            });
+            Ok(response)
+        }
+
+        "summarize" => {
+            let mut summarize_request: SummarizeRequest = serde_json::from_value(params.clone())?;
+            if summarize_request.request_id.is_none() {
+                summarize_request.request_id = request.get("id").map(id_to_string);
+            }
+            let request_id = summarize_request.request_id.clone();
+            let summary = service.summarize(summarize_request, |progress| {
+                let mut params = serde_json::to_value(progress).unwrap_or_default();
+                params["request_id"] = serde_json::json!(request_id);
+                notify(&serde_json::json!({ "method": "progress", "params": params }));
+            })?;
+            eprintln!(
+                "[ChatService] Summarized {} chunks in {} levels ({:?})",
+                summary.chunks, summary.levels, summary.finish_reason
+            );
+            let response = serde_json::json!({
+                "result": summary
+            });
+            Ok(response)
+        }
+
+        "lecture_notes" => {
+            let mut lecture_request: LectureNotesRequest = serde_json::from_value(params.clone())?;
+            if lecture_request.request_id.is_none() {
+                lecture_request.request_id = request.get("id").map(id_to_string);
+            }
+            let request_id = lecture_request.request_id.clone();
+            let notes = service.lecture_notes(lecture_request, |progress| {
+                let mut params = serde_json::to_value(progress).unwrap_or_default();
+                params["request_id"] = serde_json::json!(request_id);
+                notify(&serde_json::json!({ "method": "progress", "params": params }));
+            })?;
+            eprintln!(
+                "[ChatService] Took notes of {} topics ({:?})",
+                notes.topics.len(), notes.finish_reason
+            );
+            let response = serde_json::json!({
+                "result": notes
+            });
+            Ok(response)
+        }
+
+        "read_aloud" => {
+            let mut read_request: ReadAloudRequest = serde_json::from_value(params.clone())?;
+            if read_request.request_id.is_none() {
+                read_request.request_id = request.get("id").map(id_to_string);
+            }
+            let request_id = read_request.request_id.clone();
+            let audio = service.read_aloud(read_request, |progress| {
+                let mut params = serde_json::to_value(progress).unwrap_or_default();
+                params["request_id"] = serde_json::json!(request_id);
+                notify(&serde_json::json!({ "method": "progress", "params": params }));
+            })?;
+            eprintln!(
+                "[ChatService] Read aloud {}ms of audio to {} ({:?})",
+                audio.duration_ms, audio.path, audio.finish_reason
+            );
+            let response = serde_json::json!({
+                "result": audio
+            });
+            Ok(response)
+        }
+
+        "read_aloud_stream" => {
+            let mut read_request: ReadAloudRequest = serde_json::from_value(params.clone())?;
+            if read_request.request_id.is_none() {
+                read_request.request_id = request.get("id").map(id_to_string);
+            }
+            let request_id = read_request.request_id.clone();
+            let audio = service.read_aloud_stream(read_request, |chunk| {
+                let mut params = serde_json::to_value(chunk).unwrap_or_default();
+                params["request_id"] = serde_json::json!(request_id);
+                notify(&serde_json::json!({ "method": "audio", "params": params }));
+                Ok(())
+            })?;
+            eprintln!(
+                "[ChatService] Streamed {}ms of audio in {} chunks ({:?})",
+                audio.duration_ms, audio.audio_chunks, audio.finish_reason
+            );
+            let response = serde_json::json!({
+                "result": audio
+            });
+            Ok(response)
+        }
+
         "list_models" => {
-            let models = service.get_available_models();
+            let kind = match params.get("kind") {
+                Some(kind) if !kind.is_null() => Some(serde_json::from_value(kind.clone())?),
+                _ => None,
+            };
+            let models = service.list_models(kind)?;
             let response = serde_json::json!({
                 "result": models
             });
-            Ok(serde_json::to_string(&response)?)
+            Ok(response)
         }
         
         _ => {
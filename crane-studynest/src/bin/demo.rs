@@ -1,4 +1,4 @@
-//! StudyNest Demo - Interactive CLI for Chat, OCR, and STT
+//! StudyNest Demo - Interactive CLI for Chat, OCR, STT and voice conversation
 //!
 //! Run with: cargo run --bin studynest-demo --release
 
@@ -22,11 +22,12 @@ fn main() {
         println!("  1. Chat (Qwen2.5/Qwen3)");
         println!("  2. OCR (Image to Text)");
         println!("  3. Speech-to-Text (Audio to Text)");
-        println!("  4. List available models");
-        println!("  5. Exit");
+        println!("  4. Voice conversation (Audio to spoken reply)");
+        println!("  5. List available models");
+        println!("  6. Exit");
         println!();
         
-        print!("Enter choice (1-5): ");
+        print!("Enter choice (1-6): ");
         io::stdout().flush().unwrap();
         
         let mut choice = String::new();
@@ -36,12 +37,13 @@ fn main() {
             "1" => run_chat_demo(),
             "2" => run_ocr_demo(),
             "3" => run_stt_demo(),
-            "4" => list_models(),
-            "5" => {
+            "4" => run_voice_demo(),
+            "5" => list_models(),
+            "6" => {
                 println!("\nGoodbye! 👋");
                 break;
             }
-            _ => println!("Invalid choice. Please enter 1-5."),
+            _ => println!("Invalid choice. Please enter 1-6."),
         }
     }
 }
@@ -63,16 +65,9 @@ fn run_chat_demo() {
         model_path.to_string()
     };
     
-    // Detect model type from path
-    let model_type = if model_path.to_lowercase().contains("qwen3") {
-        crane_studynest::chat::ChatModelType::Qwen3
-    } else {
-        crane_studynest::chat::ChatModelType::Qwen25
-    };
-    
+    // The architecture is detected from the model's config.json
     let config = ChatConfig {
         model_path,
-        model_type,
         device: DeviceType::Auto,
         ..Default::default()
     };
@@ -255,12 +250,96 @@ fn run_stt_demo() {
     }
 }
 
+fn run_voice_demo() {
+    println!("\n=== Voice Conversation Demo ===");
+    
+    #[cfg(not(feature = "onnx"))]
+    {
+        println!("Voice conversation requires ONNX feature. Run with: cargo run --features onnx --bin studynest-demo");
+    }
+    
+    #[cfg(feature = "onnx")]
+    {
+        let stt = SttConfig::default().with_model_path("checkpoints/moonshine-tiny");
+        let chat = ChatConfig::default().with_model_path("checkpoints/Qwen2.5-0.5B-Instruct");
+        let tts = TtsConfig::default().with_model_path("checkpoints/orpheus-3b-0.1-ft");
+        
+        println!("\nLoading models...");
+        
+        let engines = SttEngine::new(stt).and_then(|stt| {
+            let chat = ChatEngine::new(chat)?;
+            let tts = TtsEngine::new(tts)?;
+            VoiceSession::new(stt, chat, tts, VoiceConfig::default())
+        });
+        let (mut session, events) = match engines {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Failed to start voice session: {}", e);
+                return;
+            }
+        };
+        
+        println!("\nEnter WAV files to say, one per line; an empty line ends the conversation.\n");
+        
+        loop {
+            print!("Audio file: ");
+            io::stdout().flush().unwrap();
+            
+            let mut path = String::new();
+            io::stdin().read_line(&mut path).unwrap();
+            let path = path.trim();
+            
+            if path.is_empty() {
+                break;
+            }
+            
+            if let Err(e) = session.push_wav(path) {
+                println!("Failed to read audio: {}", e);
+            }
+        }
+        
+        if let Err(e) = session.finish() {
+            println!("Failed to end the utterance: {}", e);
+        }
+        session.wait();
+        
+        for event in events.try_iter() {
+            match event {
+                VoiceEvent::Transcript { turn, text } => println!("[{}] You: {}", turn, text),
+                VoiceEvent::Sentence { turn, text } => println!("[{}] AI: {}", turn, text),
+                VoiceEvent::Audio { samples, sample_rate, .. } => {
+                    println!("    ({:.1}s of audio)", samples.len() as f32 / sample_rate as f32)
+                }
+                VoiceEvent::Interrupted { turn } => println!("[{}] (interrupted)", turn),
+                VoiceEvent::Error { turn, message } => println!("[{}] Error: {}", turn, message),
+                _ => {}
+            }
+        }
+    }
+}
+
 fn list_models() {
     println!("\n=== Available Models ===\n");
     
     println!("📝 Chat Models:");
-    for (name, desc) in crane_studynest::chat::list_available_models() {
-        println!("   • {} - {}", name, desc);
+    match crane_studynest::chat::list_available_models("checkpoints") {
+        Ok(models) if !models.is_empty() => {
+            for m in models {
+                let params = m
+                    .parameters
+                    .map(|p| format!("{:.1}B params", p as f64 / 1e9))
+                    .unwrap_or_else(|| "unknown size".to_string());
+                println!(
+                    "   • {} - {}, context {}, {:.1} MB on disk",
+                    m.name,
+                    params,
+                    m.context_length.unwrap_or_default(),
+                    m.size_bytes as f64 / 1e6
+                );
+            }
+        }
+        Ok(_) => println!("   (none installed in checkpoints/)"),
+        Err(e) => println!("   Failed to scan checkpoints/: {}", e),
     }
     
     println!("\n🖼️  OCR Models:");
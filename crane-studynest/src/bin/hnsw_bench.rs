@@ -0,0 +1,238 @@
+//! Recall and latency of the HNSW index against brute-force search
+//!
+//! Builds an index over clustered random vectors (like embeddings of notes on a few
+//! topics), then compares its top-k results with exact search, with and without
+//! metadata filters, after deleting vectors, and after a save/load round trip.
+//!
+//! Run with: cargo run --bin hnsw-bench --release -- --count 50000 --dim 384
+//! `--min-recall 0.95` exits with an error when recall@k falls below the threshold.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crane_studynest::hnsw::{HnswConfig, HnswIndex, Metric, SearchFilter, VectorMetadata};
+
+struct Options {
+    count: usize,
+    dim: usize,
+    queries: usize,
+    k: usize,
+    ef: usize,
+    m: usize,
+    metric: Metric,
+    min_recall: Option<f64>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        count: 20_000,
+        dim: 128,
+        queries: 200,
+        k: 10,
+        ef: 64,
+        m: 16,
+        metric: Metric::Cosine,
+        min_recall: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--count" => options.count = value()?.parse().map_err(|e| format!("--count: {}", e))?,
+            "--dim" => options.dim = value()?.parse().map_err(|e| format!("--dim: {}", e))?,
+            "--queries" => options.queries = value()?.parse().map_err(|e| format!("--queries: {}", e))?,
+            "--k" => options.k = value()?.parse().map_err(|e| format!("--k: {}", e))?,
+            "--ef" => options.ef = value()?.parse().map_err(|e| format!("--ef: {}", e))?,
+            "--m" => options.m = value()?.parse().map_err(|e| format!("--m: {}", e))?,
+            "--metric" => {
+                options.metric = match value()?.as_str() {
+                    "cosine" => Metric::Cosine,
+                    "dot" => Metric::Dot,
+                    other => return Err(format!("unknown metric {}", other)),
+                }
+            }
+            "--min-recall" => {
+                options.min_recall = Some(value()?.parse().map_err(|e| format!("--min-recall: {}", e))?)
+            }
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+/// splitmix64 with Box-Muller normals, so runs are reproducible without extra crates
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn normal(&mut self) -> f32 {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        ((-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()) as f32
+    }
+}
+
+/// Vectors scattered around `clusters` random centres
+fn clustered(rng: &mut Rng, count: usize, clusters: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    (0..count)
+        .map(|_| {
+            let centre = &clusters[(rng.next_u64() % clusters.len() as u64) as usize];
+            centre.iter().map(|c| c + 0.5 * rng.normal()).collect()
+        })
+        .collect()
+}
+
+fn recall(
+    index: &HnswIndex,
+    queries: &[Vec<f32>],
+    k: usize,
+    ef: usize,
+    filter: Option<&SearchFilter>,
+) -> (f64, Duration, Duration) {
+    let (mut hits, mut total) = (0usize, 0usize);
+    let (mut approx_time, mut exact_time) = (Duration::ZERO, Duration::ZERO);
+    for query in queries {
+        let start = Instant::now();
+        let approx = index.search_with_ef(query, k, ef, filter);
+        approx_time += start.elapsed();
+
+        let start = Instant::now();
+        let exact = index.brute_force(query, k, filter);
+        exact_time += start.elapsed();
+
+        let exact: HashSet<u64> = exact.iter().map(|r| r.id).collect();
+        hits += approx.iter().filter(|r| exact.contains(&r.id)).count();
+        total += exact.len();
+    }
+    let n = queries.len().max(1) as u32;
+    (hits as f64 / total.max(1) as f64, approx_time / n, exact_time / n)
+}
+
+fn report(label: &str, (recall, approx, exact): (f64, Duration, Duration)) -> f64 {
+    println!(
+        "{:<28} recall {:.4}  hnsw {:>9.3?}/query  brute force {:>9.3?}/query",
+        label, recall, approx, exact
+    );
+    recall
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut rng = Rng(42);
+    let clusters: Vec<Vec<f32>> = (0..32)
+        .map(|_| (0..options.dim).map(|_| rng.normal()).collect())
+        .collect();
+    let vectors = clustered(&mut rng, options.count, &clusters);
+    let queries = clustered(&mut rng, options.queries, &clusters);
+    let courses = ["BIO101", "CHEM201", "HIST110", "MATH220", "PHYS150"];
+
+    let config = HnswConfig::default()
+        .with_metric(options.metric)
+        .with_m(options.m)
+        .with_ef_search(options.ef);
+    let mut index = HnswIndex::new(options.dim, config);
+    let start = Instant::now();
+    for (id, vector) in vectors.iter().enumerate() {
+        let metadata = VectorMetadata {
+            document: Some(format!("doc-{}", id % 200)),
+            course: Some(courses[id % courses.len()].to_string()),
+            timestamp: Some(id as u64),
+        };
+        index.insert(id as u64, vector, metadata).expect("vector has the index dimension");
+    }
+    println!(
+        "Built index of {} x {} ({:?}, m={}) in {:.2?}",
+        options.count, options.dim, options.metric, options.m, start.elapsed()
+    );
+
+    let mut worst = report(
+        &format!("top-{} ef={}", options.k, options.ef),
+        recall(&index, &queries, options.k, options.ef, None),
+    );
+    for ef in [options.ef * 2, options.ef * 4] {
+        report(&format!("top-{} ef={}", options.k, ef), recall(&index, &queries, options.k, ef, None));
+    }
+
+    let course = SearchFilter {
+        course: Some(courses[0].to_string()),
+        ..Default::default()
+    };
+    worst = worst.min(report(
+        "course filter (20%)",
+        recall(&index, &queries, options.k, options.ef, Some(&course)),
+    ));
+    let recent = SearchFilter {
+        after: Some(options.count as u64 / 2),
+        ..Default::default()
+    };
+    worst = worst.min(report(
+        "date filter (50%)",
+        recall(&index, &queries, options.k, options.ef, Some(&recent)),
+    ));
+    let documents = SearchFilter {
+        documents: Some(vec!["doc-1".to_string(), "doc-2".to_string()]),
+        ..Default::default()
+    };
+    worst = worst.min(report(
+        "document filter (1%)",
+        recall(&index, &queries, options.k, options.ef, Some(&documents)),
+    ));
+
+    // Delete a third of the vectors and update another tenth in place
+    for id in (0..options.count as u64).step_by(3) {
+        index.remove(id);
+    }
+    for id in (1..options.count as u64).step_by(10) {
+        let vector: Vec<f32> = (0..options.dim).map(|_| rng.normal()).collect();
+        index
+            .insert(id, &vector, VectorMetadata::default())
+            .expect("vector has the index dimension");
+    }
+    worst = worst.min(report(
+        "after deletes and updates",
+        recall(&index, &queries, options.k, options.ef, None),
+    ));
+    let start = Instant::now();
+    index.compact();
+    println!("Compacted to {} vectors in {:.2?}", index.len(), start.elapsed());
+    worst = worst.min(report("after compaction", recall(&index, &queries, options.k, options.ef, None)));
+
+    let path = std::env::temp_dir().join(format!("hnsw-bench-{}.hnsw", std::process::id()));
+    let start = Instant::now();
+    index.save(&path).expect("save index");
+    let saved = start.elapsed();
+    let start = Instant::now();
+    let loaded = HnswIndex::load(&path).expect("load index");
+    println!("Saved in {:.2?}, loaded (memory-mapped) in {:.2?}", saved, start.elapsed());
+    let same = queries
+        .iter()
+        .all(|q| index.search(q, options.k, None) == loaded.search(q, options.k, None));
+    println!("Results after reload are {}", if same { "identical" } else { "DIFFERENT" });
+    worst = worst.min(report("after reload", recall(&loaded, &queries, options.k, options.ef, None)));
+    drop(loaded);
+    let _ = std::fs::remove_file(&path);
+
+    if let Some(min_recall) = options.min_recall {
+        if worst < min_recall || !same {
+            eprintln!("Recall {:.4} is below {:.4} or reload changed results", worst, min_recall);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Ranking quality and speed of a reranker on a small fixture set
+//!
+//! Each fixture line holds a query and passages labelled relevant or not. Passages are
+//! ranked by BM25 (and by embedding similarity when `--embedding-model` is given) and by
+//! the reranker, and MRR, precision@1 and nDCG@3 of the rankings are compared. The
+//! reranker is then timed at several batch sizes.
+//!
+//! Run with: cargo run --release --bin rerank-bench -- --model checkpoints/bge-reranker-v2-m3
+
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crane_studynest::bm25::Bm25Index;
+use crane_studynest::embed::{EmbeddingConfig, EmbeddingEngine, cosine_similarity};
+use crane_studynest::rerank::{Reranker, RerankerConfig};
+use crane_studynest::DeviceType;
+
+#[derive(Deserialize)]
+struct Passage {
+    text: String,
+    relevant: bool,
+}
+
+#[derive(Deserialize)]
+struct Case {
+    query: String,
+    passages: Vec<Passage>,
+}
+
+struct Options {
+    model: String,
+    fixture: String,
+    embedding_model: Option<String>,
+    batch_sizes: Vec<usize>,
+    device: DeviceType,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut model = None;
+    let mut options = Options {
+        model: String::new(),
+        fixture: "data/rerank_fixture.jsonl".to_string(),
+        embedding_model: None,
+        batch_sizes: vec![1, 4, 16],
+        device: DeviceType::Auto,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--model" => model = Some(value()?),
+            "--fixture" => options.fixture = value()?,
+            "--embedding-model" => options.embedding_model = Some(value()?),
+            "--batch-sizes" => {
+                options.batch_sizes = value()?
+                    .split(',')
+                    .map(|s| s.trim().parse().map_err(|e| format!("--batch-sizes: {}", e)))
+                    .collect::<Result<_, _>>()?
+            }
+            "--device" => {
+                options.device = match value()?.as_str() {
+                    "cpu" => DeviceType::Cpu,
+                    "metal" => DeviceType::Metal,
+                    "auto" => DeviceType::Auto,
+                    other => match other.strip_prefix("cuda:").map(str::parse) {
+                        Some(Ok(index)) => DeviceType::Cuda(index),
+                        _ => return Err(format!("unknown device {}", other)),
+                    },
+                }
+            }
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+    options.model = model.ok_or("--model is required")?;
+    Ok(options)
+}
+
+/// Mean reciprocal rank, precision@1 and nDCG@3 of rankings (passage indices, best first)
+fn metrics(cases: &[Case], rankings: &[Vec<usize>]) -> (f64, f64, f64) {
+    let (mut mrr, mut p1, mut ndcg) = (0.0, 0.0, 0.0);
+    for (case, ranking) in cases.iter().zip(rankings) {
+        let relevant = |i: usize| case.passages[i].relevant;
+        if let Some(rank) = ranking.iter().position(|&i| relevant(i)) {
+            mrr += 1.0 / (rank + 1) as f64;
+        }
+        if ranking.first().is_some_and(|&i| relevant(i)) {
+            p1 += 1.0;
+        }
+        let dcg: f64 = ranking
+            .iter()
+            .take(3)
+            .enumerate()
+            .filter(|(_, i)| relevant(**i))
+            .map(|(rank, _)| 1.0 / (rank as f64 + 2.0).log2())
+            .sum();
+        let ideal: f64 = (0..case.passages.iter().filter(|p| p.relevant).count().min(3))
+            .map(|rank| 1.0 / (rank as f64 + 2.0).log2())
+            .sum();
+        if ideal > 0.0 {
+            ndcg += dcg / ideal;
+        }
+    }
+    let n = cases.len().max(1) as f64;
+    (mrr / n, p1 / n, ndcg / n)
+}
+
+fn report(label: &str, cases: &[Case], rankings: &[Vec<usize>]) {
+    let (mrr, p1, ndcg) = metrics(cases, rankings);
+    println!("{:<12} MRR {:.3}  P@1 {:.3}  nDCG@3 {:.3}", label, mrr, p1, ndcg);
+}
+
+/// Passage indices sorted by score, best first
+fn ranking(scores: &[f32]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    order
+}
+
+fn run(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let cases: Vec<Case> = std::fs::read_to_string(&options.fixture)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let pairs: usize = cases.iter().map(|c| c.passages.len()).sum();
+    println!("{} queries, {} passages from {}", cases.len(), pairs, options.fixture);
+
+    let bm25: Vec<Vec<usize>> = cases
+        .iter()
+        .map(|case| {
+            let mut index = Bm25Index::new();
+            for (i, passage) in case.passages.iter().enumerate() {
+                index.insert(i as u64, &passage.text);
+            }
+            let mut scores = vec![0.0; case.passages.len()];
+            for (i, score) in index.search(&case.query, case.passages.len(), |_| true) {
+                scores[i as usize] = score;
+            }
+            ranking(&scores)
+        })
+        .collect();
+    report("BM25", &cases, &bm25);
+
+    if let Some(model) = &options.embedding_model {
+        let config = EmbeddingConfig::default()
+            .with_model_path(model.as_str())
+            .with_device(options.device);
+        let mut embedder = EmbeddingEngine::new(config)?;
+        let mut rankings = Vec::new();
+        for case in &cases {
+            let query = embedder.embed_query(&case.query)?;
+            let texts: Vec<&str> = case.passages.iter().map(|p| p.text.as_str()).collect();
+            let scores: Vec<f32> = embedder
+                .embed(&texts)?
+                .iter()
+                .map(|e| cosine_similarity(&query, e))
+                .collect();
+            rankings.push(ranking(&scores));
+        }
+        report("Embedding", &cases, &rankings);
+    }
+
+    let config = RerankerConfig::default()
+        .with_model_path(options.model.as_str())
+        .with_device(options.device);
+    let mut reranker = Reranker::new(config)?;
+    let mut rankings = Vec::new();
+    let (mut relevant, mut irrelevant) = (Vec::new(), Vec::new());
+    for case in &cases {
+        let texts: Vec<&str> = case.passages.iter().map(|p| p.text.as_str()).collect();
+        let scores = reranker.score(&case.query, &texts)?;
+        for (passage, score) in case.passages.iter().zip(&scores) {
+            if passage.relevant {
+                relevant.push(*score)
+            } else {
+                irrelevant.push(*score)
+            }
+        }
+        rankings.push(ranking(&scores));
+    }
+    report("Reranker", &cases, &rankings);
+    let mean = |scores: &[f32]| scores.iter().sum::<f32>() / scores.len().max(1) as f32;
+    println!(
+        "Mean relevance: {:.3} for relevant passages, {:.3} for the others",
+        mean(&relevant),
+        mean(&irrelevant)
+    );
+
+    for &batch_size in &options.batch_sizes {
+        let config = reranker.config().clone().with_batch_size(batch_size);
+        let mut timed = Reranker::new(config)?;
+        let start = Instant::now();
+        for case in &cases {
+            let texts: Vec<&str> = case.passages.iter().map(|p| p.text.as_str()).collect();
+            timed.score(&case.query, &texts)?;
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "Batch size {:>3}: {:.2?} for {} pairs ({:.1} pairs/s)",
+            batch_size,
+            elapsed,
+            pairs,
+            pairs as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("Benchmark failed: {}", e);
+        std::process::exit(1);
+    }
+}
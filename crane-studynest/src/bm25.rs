@@ -0,0 +1,339 @@
+//! Keyword search with BM25
+//!
+//! Embeddings are weak at exact lookups (formulas, names, course codes), so note chunks
+//! are also kept in an in-memory inverted index scored with Okapi BM25. Text is split
+//! into lowercase alphanumeric words; Chinese, Japanese and Korean, which are written
+//! without spaces, are indexed as single characters plus overlapping character pairs.
+//! `reciprocal_rank_fusion` merges the keyword ranking with the vector ranking.
+
+use std::collections::{HashMap, HashSet};
+
+/// Term frequency saturation
+const K1: f32 = 1.2;
+/// Document length normalization
+const B: f32 = 0.75;
+/// Rank offset of reciprocal rank fusion; 60 is the value from the original paper
+pub const RRF_K: f32 = 60.0;
+
+/// A term and the characters of the text it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    /// Character offset of the first character
+    pub start: usize,
+    /// Character offset after the last character
+    pub end: usize,
+}
+
+/// Han, kana and Hangul, which are written without spaces between words
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff      // Hiragana, Katakana
+        | 0x3400..=0x4dbf    // CJK Extension A
+        | 0x4e00..=0x9fff    // CJK Unified Ideographs
+        | 0xac00..=0xd7af    // Hangul syllables
+        | 0xf900..=0xfaff    // CJK Compatibility Ideographs
+        | 0x20000..=0x2ebef  // CJK Extensions B-F
+    )
+}
+
+/// Split text into search terms. Words are lowercased runs of letters and digits (a
+/// point between digits stays in the word, so `3.14` is one term); each CJK character
+/// is a term, and so is each pair of adjacent CJK characters.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if is_cjk(c) {
+            tokens.push(Token {
+                term: c.to_string(),
+                start: i,
+                end: i + 1,
+            });
+            if let Some(&next) = chars.get(i + 1).filter(|n| is_cjk(**n)) {
+                tokens.push(Token {
+                    term: [c, next].iter().collect(),
+                    start: i,
+                    end: i + 2,
+                });
+            }
+            i += 1;
+        } else if c.is_alphanumeric() {
+            let start = i;
+            while i < chars.len() {
+                let c = chars[i];
+                let decimal_point = c == '.'
+                    && i > start
+                    && chars[i - 1].is_ascii_digit()
+                    && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit());
+                let word_char = c.is_alphanumeric() && !is_cjk(c);
+                if !word_char && !decimal_point {
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token {
+                term: chars[start..i].iter().collect::<String>().to_lowercase(),
+                start,
+                end: i,
+            });
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Distinct terms of a query
+pub fn query_terms(query: &str) -> HashSet<String> {
+    tokenize(query).into_iter().map(|t| t.term).collect()
+}
+
+/// Inverted index over texts identified by `u64` ids
+#[derive(Debug, Default)]
+pub struct Bm25Index {
+    /// Term -> (id -> occurrences)
+    postings: HashMap<String, HashMap<u64, u32>>,
+    /// Id -> (number of terms, distinct terms)
+    documents: HashMap<u64, (u32, Vec<String>)>,
+    total_terms: u64,
+}
+
+impl Bm25Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Index `text` under `id`, replacing what was indexed under it before
+    pub fn insert(&mut self, id: u64, text: &str) {
+        self.remove(id);
+        let tokens = tokenize(text);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for token in &tokens {
+            *counts.entry(token.term.clone()).or_default() += 1;
+        }
+        for (term, count) in &counts {
+            self.postings.entry(term.clone()).or_default().insert(id, *count);
+        }
+        self.total_terms += tokens.len() as u64;
+        self.documents.insert(id, (tokens.len() as u32, counts.into_keys().collect()));
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        let Some((length, terms)) = self.documents.remove(&id) else {
+            return false;
+        };
+        for term in terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_terms -= length as u64;
+        true
+    }
+
+    /// The `k` best-scoring ids for `query` among those `accept` allows, best first
+    pub fn search(&self, query: &str, k: usize, accept: impl Fn(u64) -> bool) -> Vec<(u64, f32)> {
+        if k == 0 || self.documents.is_empty() {
+            return Vec::new();
+        }
+        let count = self.documents.len() as f32;
+        let average_length = (self.total_terms as f32 / count).max(1.0);
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        for term in query_terms(query) {
+            let Some(posting) = self.postings.get(&term) else { continue };
+            let frequency = posting.len() as f32;
+            let idf = (1.0 + (count - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (&id, &occurrences) in posting {
+                if !accept(id) {
+                    continue;
+                }
+                let tf = occurrences as f32;
+                let length = self.documents[&id].0 as f32;
+                let norm = K1 * (1.0 - B + B * length / average_length);
+                *scores.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(u64, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(k);
+        ranked
+    }
+}
+
+/// Merge rankings (best first) by summing `1 / (RRF_K + rank)` over the lists each id
+/// appears in. Returns ids with their fused score, best first.
+pub fn reciprocal_rank_fusion(rankings: &[Vec<u64>]) -> Vec<(u64, f32)> {
+    let mut scores: HashMap<u64, f32> = HashMap::new();
+    for ranking in rankings {
+        for (rank, id) in ranking.iter().enumerate() {
+            *scores.entry(*id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut fused: Vec<(u64, f32)> = scores.into_iter().collect();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    fused
+}
+
+/// The part of `text` (at most about `max_chars` characters) with the most query terms,
+/// with the character ranges of those terms in the snippet. Cut ends are marked with `…`.
+pub fn snippet(text: &str, terms: &HashSet<String>, max_chars: usize) -> (String, Vec<(usize, usize)>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut matches: Vec<(usize, usize)> = tokenize(text)
+        .into_iter()
+        .filter(|t| terms.contains(&t.term))
+        .map(|t| (t.start, t.end))
+        .collect();
+    matches.sort();
+
+    // Window start covering the most matches
+    let mut start = 0;
+    if chars.len() > max_chars {
+        let (mut best, mut last, mut anchor) = (0, 0, 0);
+        for (first, &(from, _)) in matches.iter().enumerate() {
+            while last < matches.len() && matches[last].1 <= from + max_chars {
+                last += 1;
+            }
+            if last - first > best {
+                best = last - first;
+                anchor = from;
+            }
+        }
+        // Some context before the first match, starting at a word
+        start = anchor.saturating_sub(max_chars / 4).min(chars.len() - max_chars);
+        while start > 0 && start < anchor && !chars[start - 1].is_whitespace() && !is_cjk(chars[start]) {
+            start += 1;
+        }
+    }
+    let mut end = (start + max_chars).min(chars.len());
+    if end < chars.len() && !chars[end].is_whitespace() && !is_cjk(chars[end]) {
+        if let Some(space) = (end - max_chars / 4..end).rev().find(|&i| chars[i].is_whitespace()) {
+            end = space;
+        }
+    }
+
+    let mut snippet = String::new();
+    let shift = if start > 0 {
+        snippet.push('…');
+        1
+    } else {
+        0
+    };
+    snippet.extend(&chars[start..end]);
+    if end < chars.len() {
+        snippet.push('…');
+    }
+
+    // Merge overlapping and touching ranges (CJK characters and pairs overlap)
+    let mut highlights: Vec<(usize, usize)> = Vec::new();
+    for (from, to) in matches {
+        if from < start || to > end {
+            continue;
+        }
+        let (from, to) = (from - start + shift, to - start + shift);
+        match highlights.last_mut() {
+            Some(last) if from <= last.1 => last.1 = last.1.max(to),
+            _ => highlights.push((from, to)),
+        }
+    }
+    (snippet, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().map(|t| t.term).collect()
+    }
+
+    #[test]
+    fn words_are_lowercased_and_keep_decimal_points() {
+        assert_eq!(terms("Newton's 2nd Law: F=ma"), ["newton", "s", "2nd", "law", "f", "ma"]);
+        assert_eq!(terms("pi is 3.14, not 3."), ["pi", "is", "3.14", "not", "3"]);
+        assert_eq!(terms("CS-101 ÉTÉ"), ["cs", "101", "été"]);
+        let tokens = tokenize("  Cell wall");
+        assert_eq!((tokens[1].start, tokens[1].end), (7, 11));
+    }
+
+    #[test]
+    fn cjk_text_gives_characters_and_pairs() {
+        assert_eq!(terms("光合作用"), ["光", "光合", "合", "合作", "作", "作用", "用"]);
+        // A pair never crosses into a Latin word
+        assert_eq!(terms("DNA复制"), ["dna", "复", "复制", "制"]);
+        let pair = &tokenize("细胞")[1];
+        assert_eq!((pair.start, pair.end), (0, 2));
+    }
+
+    #[test]
+    fn rarer_terms_and_shorter_texts_score_higher() {
+        let mut index = Bm25Index::new();
+        index.insert(1, "mitochondria produce energy for the cell");
+        index.insert(2, "the cell membrane controls what enters the cell");
+        index.insert(3, "the cell wall of plants is made of cellulose and the cell wall is rigid");
+
+        // "mitochondria" appears in one text, "cell" in all of them
+        let ranked = index.search("cell mitochondria", 3, |_| true);
+        assert_eq!(ranked[0].0, 1);
+        assert_eq!(ranked.len(), 3);
+        assert!(ranked.windows(2).all(|w| w[0].1 >= w[1].1));
+
+        // Text 3 says "wall" twice, but among many other words
+        index.insert(4, "wall");
+        let ranked = index.search("wall", 2, |_| true);
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(), [4, 3]);
+
+        assert_eq!(index.search("cell", 10, |id| id == 2).len(), 1);
+        assert!(index.search("photosynthesis", 10, |_| true).is_empty());
+        assert!(index.search("cell", 0, |_| true).is_empty());
+    }
+
+    #[test]
+    fn insert_replaces_and_remove_forgets() {
+        let mut index = Bm25Index::new();
+        index.insert(1, "osmosis");
+        index.insert(1, "diffusion");
+        assert_eq!(index.len(), 1);
+        assert!(index.search("osmosis", 5, |_| true).is_empty());
+        assert_eq!(index.search("diffusion", 5, |_| true)[0].0, 1);
+
+        assert!(index.remove(1));
+        assert!(!index.remove(1));
+        assert!(index.is_empty());
+        assert!(index.postings.is_empty());
+        assert_eq!(index.total_terms, 0);
+    }
+
+    #[test]
+    fn fusion_rewards_ids_ranked_by_both_lists() {
+        let fused = reciprocal_rank_fusion(&[vec![1, 2, 3], vec![3, 4]]);
+        assert_eq!(fused[0].0, 3);
+        assert!((fused[0].1 - (1.0 / (RRF_K + 3.0) + 1.0 / (RRF_K + 1.0))).abs() < 1e-6);
+        assert_eq!(fused.iter().map(|f| f.0).collect::<Vec<_>>(), [3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn snippet_centres_on_matches() {
+        let text = format!("{} the Krebs cycle releases energy {}", "intro ".repeat(30), "outro ".repeat(30));
+        let (snippet, highlights) = snippet(&text, &query_terms("krebs energy"), 40);
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'), "{snippet}");
+        let chars: Vec<char> = snippet.chars().collect();
+        let highlighted: Vec<String> = highlights.iter().map(|&(a, b)| chars[a..b].iter().collect()).collect();
+        assert_eq!(highlighted, ["Krebs", "energy"]);
+    }
+}
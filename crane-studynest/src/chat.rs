@@ -1,17 +1,25 @@
 //! Chat inference module using Qwen models
 
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
 use candle_core::DType;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use crate::device::{DeviceType, get_device};
 use crate::error::{Result, StudyNestError};
+use crate::registry::{ModelInfo, ModelKind, ModelRegistry};
 
 use crane_core::autotokenizer::AutoTokenizer;
 use crane_core::chat::{Message, Role as CoreRole};
-use crane_core::generation::{GenerationConfig, based::ModelForCausalLM, streamer::TextStreamer};
+use crane_core::generation::{GenerationConfig, streamer::{TextStreamer, TokenStreamer}};
+pub use crane_core::generation::{CancellationToken, FinishReason};
 use crane_core::models::qwen25::Model as Qwen25Model;
 use crane_core::models::qwen3::Model as Qwen3Model;
 
 /// Chat message role
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
     User,
@@ -57,21 +65,104 @@ impl ChatMessage {
 }
 
 /// Supported chat model types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChatModelType {
+    /// Detect from the model's `config.json`
+    #[default]
+    Auto,
     Qwen25,
     Qwen3,
 }
 
-impl Default for ChatModelType {
-    fn default() -> Self {
-        ChatModelType::Qwen25
+impl ChatModelType {
+    /// Loader for the given `architectures` entry or `model_type` of a `config.json`
+    pub fn from_architecture(architecture: Option<&str>, model_type: Option<&str>) -> Option<Self> {
+        match (architecture, model_type) {
+            (Some("Qwen3ForCausalLM"), _) | (_, Some("qwen3")) => Some(ChatModelType::Qwen3),
+            (Some("Qwen2ForCausalLM" | "Qwen2MoeForCausalLM"), _) | (_, Some("qwen2" | "qwen2_moe")) => {
+                Some(ChatModelType::Qwen25)
+            }
+            _ => None,
+        }
+    }
+
+    /// Detect the loader from the `config.json` in a model directory
+    pub fn detect(model_path: impl AsRef<Path>) -> Option<Self> {
+        let info = ModelInfo::inspect(model_path).ok()?;
+        Self::from_architecture(info.architecture.as_deref(), info.model_type.as_deref())
+    }
+}
+
+/// What to do with old turns once the prompt no longer fits the context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextPolicy {
+    /// Drop the oldest turns, always keeping the system prompt
+    #[default]
+    DropOldest,
+    /// Summarize evicted turns with the model into a running memory message
+    Summarize,
+}
+
+/// Action taken on the history while preparing a prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextAction {
+    /// The prompt fit as-is
+    None,
+    /// Oldest turns were dropped
+    Truncated,
+    /// Oldest turns were folded into the running memory
+    Summarized,
+}
+
+/// Report of how the history was fitted into the context window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextReport {
+    pub action: ContextAction,
+    pub policy: ContextPolicy,
+    /// Tokens in the final prompt
+    pub prompt_tokens: usize,
+    /// Maximum context length of the model
+    pub max_context: usize,
+    /// Number of messages removed from the history for this turn
+    pub evicted_messages: usize,
+}
+
+/// Response of a chat turn together with its context report
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    /// Generated text; partial when the request was cancelled or timed out
+    pub content: String,
+    pub finish_reason: FinishReason,
+    pub context: ContextReport,
+}
+
+/// Cancellation and wall-clock deadline for a single chat request
+#[derive(Debug, Clone, Default)]
+pub struct GenerationControl {
+    pub cancellation: Option<CancellationToken>,
+    pub deadline: Option<Instant>,
+}
+
+impl GenerationControl {
+    /// Why work on this request must stop, if it was cancelled or timed out
+    pub fn interruption(&self) -> Option<FinishReason> {
+        if self.cancellation.as_ref().is_some_and(|token| token.is_cancelled()) {
+            return Some(FinishReason::Cancelled);
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(FinishReason::Timeout);
+        }
+        None
     }
 }
 
 /// Chat configuration
 #[derive(Debug, Clone)]
 pub struct ChatConfig {
+    /// Checkpoint directory, or a quantized `.gguf` file
     pub model_path: String,
     pub model_type: ChatModelType,
     pub device: DeviceType,
@@ -83,13 +174,18 @@ pub struct ChatConfig {
     pub repeat_last_n: usize,
     pub do_sample: bool,
     pub report_speed: bool,
+    pub context_policy: ContextPolicy,
+    /// Overrides the context length read from the model files
+    pub max_context_tokens: Option<usize>,
+    /// Token budget for the running memory when summarizing
+    pub summary_max_tokens: usize,
 }
 
 impl Default for ChatConfig {
     fn default() -> Self {
         Self {
             model_path: "checkpoints/Qwen2.5-0.5B-Instruct".to_string(),
-            model_type: ChatModelType::Qwen25,
+            model_type: ChatModelType::Auto,
             device: DeviceType::Auto,
             dtype: DType::F16,
             max_new_tokens: 256,
@@ -99,6 +195,9 @@ impl Default for ChatConfig {
             repeat_last_n: 64,
             do_sample: true,
             report_speed: true,
+            context_policy: ContextPolicy::DropOldest,
+            max_context_tokens: None,
+            summary_max_tokens: 256,
         }
     }
 }
@@ -109,6 +208,11 @@ impl ChatConfig {
         self
     }
 
+    pub fn with_model_type(mut self, model_type: ChatModelType) -> Self {
+        self.model_type = model_type;
+        self
+    }
+
     pub fn with_device(mut self, device: DeviceType) -> Self {
         self.device = device;
         self
@@ -118,6 +222,41 @@ impl ChatConfig {
         self.max_new_tokens = max_tokens;
         self
     }
+
+    pub fn with_context_policy(mut self, policy: ContextPolicy) -> Self {
+        self.context_policy = policy;
+        self
+    }
+
+    pub fn with_max_context_tokens(mut self, max_context: usize) -> Self {
+        self.max_context_tokens = Some(max_context);
+        self
+    }
+}
+
+/// Fallback context length when neither the model nor the tokenizer declares one
+const DEFAULT_MAX_CONTEXT: usize = 4096;
+
+/// Prompt used to fold evicted turns into the running memory
+const SUMMARY_SYSTEM_PROMPT: &str = "You maintain a running memory of a study conversation. \
+Summarize the conversation so far in a few short sentences, keeping facts, definitions, \
+decisions and open questions. Reply with the summary only.";
+
+/// Read the maximum context length from `config.json` and the tokenizer config,
+/// taking the smaller one when both are present
+fn read_max_context(model_path: &str, tokenizer: &AutoTokenizer) -> usize {
+    let from_config = std::fs::read(Path::new(model_path).join("config.json"))
+        .ok()
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+        .and_then(|config| config["max_position_embeddings"].as_u64())
+        .map(|n| n as usize);
+    let from_tokenizer = Some(tokenizer.config.model_max_length).filter(|&n| n > 0);
+
+    match (from_config, from_tokenizer) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(n), None) | (None, Some(n)) => n,
+        (None, None) => DEFAULT_MAX_CONTEXT,
+    }
 }
 
 /// Internal model wrapper
@@ -126,125 +265,481 @@ enum ChatModel {
     Qwen3(Qwen3Model),
 }
 
+impl ChatModel {
+    fn fork(&self) -> Self {
+        match self {
+            ChatModel::Qwen25(m) => ChatModel::Qwen25(m.fork()),
+            ChatModel::Qwen3(m) => ChatModel::Qwen3(m.fork()),
+        }
+    }
+}
+
+/// Hands out the text of each generated token. Tokens are decoded together, so a
+/// character spread over several tokens comes out once it is complete. Like
+/// `TokenOutputStream`, only a window from the previous piece on is decoded, which
+/// keeps the leading space of a word without decoding the whole reply each time.
+struct DeltaStreamer<'a> {
+    tokenizer: AutoTokenizer,
+    ids: Vec<u32>,
+    /// Start of the window: the first token of the piece handed out last
+    prev: usize,
+    /// Tokens already handed out
+    sent: usize,
+    on_text: &'a mut dyn FnMut(&str),
+}
+
+impl<'a> DeltaStreamer<'a> {
+    fn new(tokenizer: AutoTokenizer, on_text: &'a mut dyn FnMut(&str)) -> Self {
+        Self { tokenizer, ids: Vec::new(), prev: 0, sent: 0, on_text }
+    }
+}
+
+impl TokenStreamer for DeltaStreamer<'_> {
+    fn append(&mut self, token_id: u32) -> anyhow::Result<()> {
+        self.ids.push(token_id);
+        let before = self.tokenizer.decode(&self.ids[self.prev..self.sent], true).map_err(anyhow::Error::msg)?;
+        let text = self.tokenizer.decode(&self.ids[self.prev..], true).map_err(anyhow::Error::msg)?;
+        if text.ends_with('\u{FFFD}') || text.len() <= before.len() || !text.is_char_boundary(before.len()) {
+            return Ok(());
+        }
+        (self.on_text)(&text[before.len()..]);
+        self.prev = self.sent;
+        self.sent = self.ids.len();
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
 /// Chat engine for conversational AI
 pub struct ChatEngine {
     model: ChatModel,
     tokenizer: AutoTokenizer,
     config: ChatConfig,
     history: Vec<ChatMessage>,
+    /// Running summary of turns evicted from the history
+    memory: Option<String>,
+    max_context: usize,
 }
 
 impl ChatEngine {
     /// Create a new chat engine with the given configuration
     pub fn new(config: ChatConfig) -> Result<Self> {
-        println!("[StudyNest] Loading chat model from: {}", config.model_path);
+        eprintln!("[StudyNest] Loading chat model from: {}", config.model_path);
         
         let device = get_device(config.device)?;
         
         let tokenizer = AutoTokenizer::from_pretrained(&config.model_path, None)
             .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
         
-        let model = match config.model_type {
+        let model_type = match config.model_type {
+            ChatModelType::Auto => ChatModelType::detect(&config.model_path).unwrap_or_else(|| {
+                eprintln!("[StudyNest] Could not detect the model architecture, assuming Qwen2.5");
+                ChatModelType::Qwen25
+            }),
+            model_type => model_type,
+        };
+
+        let model = match model_type {
+            ChatModelType::Auto => unreachable!("model type is resolved above"),
             ChatModelType::Qwen25 => {
                 let m = Qwen25Model::new(&config.model_path, &device, &config.dtype)
                     .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+                if m.is_moe() {
+                    eprintln!("[StudyNest] Detected a mixture-of-experts checkpoint");
+                }
+                if m.is_quantized() {
+                    eprintln!("[StudyNest] Loaded quantized GGUF weights");
+                }
                 ChatModel::Qwen25(m)
             }
             ChatModelType::Qwen3 => {
                 let m = Qwen3Model::new(&config.model_path, &device, &config.dtype)
                     .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+                if m.is_quantized() {
+                    eprintln!("[StudyNest] Loaded quantized GGUF weights");
+                }
                 ChatModel::Qwen3(m)
             }
         };
         
-        println!("[StudyNest] Chat model loaded successfully on {}", config.device);
+        let max_context = config
+            .max_context_tokens
+            .unwrap_or_else(|| read_max_context(&config.model_path, &tokenizer));
+
+        eprintln!("[StudyNest] Chat model loaded successfully on {} (context: {} tokens)", config.device, max_context);
         
         Ok(Self {
             model,
             tokenizer,
             config,
             history: Vec::new(),
+            memory: None,
+            max_context,
         })
     }
 
+    /// Create an engine with its own history and kv cache that shares this engine's weights
+    pub fn fork(&self) -> Self {
+        Self {
+            model: self.model.fork(),
+            tokenizer: self.tokenizer.clone(),
+            config: self.config.clone(),
+            history: Vec::new(),
+            memory: None,
+            max_context: self.max_context,
+        }
+    }
+
     /// Send a message and get a response
     pub fn chat(&mut self, message: &str) -> Result<String> {
+        Ok(self.chat_with_report(message)?.content)
+    }
+
+    /// Send a message and get a response along with the context report
+    pub fn chat_with_report(&mut self, message: &str) -> Result<ChatReply> {
+        self.chat_with_control(message, &GenerationControl::default())
+    }
+
+    /// Send a message that can be cancelled or time out; the partial response is
+    /// kept in the history and returned with the reason generation stopped
+    pub fn chat_with_control(&mut self, message: &str, control: &GenerationControl) -> Result<ChatReply> {
+        let mut streamer = TextStreamer {
+            tokenizer: self.tokenizer.clone(),
+            buffer: String::new(),
+        };
+        self.chat_turn(message, control, &mut streamer)
+    }
+
+    /// Like `chat_with_control`, calling `on_text` with each new piece of the response as
+    /// it is generated
+    pub fn chat_streaming_with_control(
+        &mut self,
+        message: &str,
+        control: &GenerationControl,
+        mut on_text: impl FnMut(&str),
+    ) -> Result<ChatReply> {
+        let mut streamer = DeltaStreamer::new(self.tokenizer.clone(), &mut on_text);
+        self.chat_turn(message, control, &mut streamer)
+    }
+
+    fn chat_turn(
+        &mut self,
+        message: &str,
+        control: &GenerationControl,
+        streamer: &mut dyn TokenStreamer,
+    ) -> Result<ChatReply> {
         self.history.push(ChatMessage::user(message));
-        
-        let prompt = self.build_prompt()?;
-        let response = self.generate(&prompt)?;
-        
-        self.history.push(ChatMessage::assistant(&response));
-        
-        Ok(response)
+
+        let reply = self.fit_context(control).and_then(|(prompt, context)| {
+            let (response, finish_reason) =
+                self.generate_with_streamer(&prompt, self.config.max_new_tokens, control, streamer)?;
+            Ok(ChatReply { content: response, finish_reason, context })
+        });
+        match reply {
+            Ok(reply) => {
+                self.history.push(ChatMessage::assistant(&reply.content));
+                Ok(reply)
+            }
+            Err(e) => {
+                // Leave no unanswered user turn behind for the next prompt
+                self.history.pop();
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `chat_with_control`, with `context` (e.g. excerpts from notes) put before the
+    /// message for this turn only; the history keeps the plain message
+    pub fn chat_with_context(
+        &mut self,
+        message: &str,
+        context: &str,
+        control: &GenerationControl,
+    ) -> Result<ChatReply> {
+        let reply = self.chat_with_control(&format!("{}\n\n{}", context, message), control)?;
+        // The turn just added is the last user/assistant pair
+        let user_turn = self.history.len() - 2;
+        self.history[user_turn] = ChatMessage::user(message);
+        Ok(reply)
+    }
+
+    /// Answer a single prompt under `system` without reading or changing the history
+    pub fn complete(
+        &mut self,
+        system: &str,
+        prompt: &str,
+        max_tokens: usize,
+        control: &GenerationControl,
+    ) -> Result<(String, FinishReason)> {
+        let messages = [ChatMessage::system(system), ChatMessage::user(prompt)];
+        self.complete_messages(&messages, max_tokens, control)
     }
 
-    /// Send a message with streaming output
-    pub fn chat_streaming<F>(&mut self, message: &str, callback: F) -> Result<String>
+    /// Answer a prompt with a JSON value of type `T`, without reading or changing the
+    /// history. A reply that does not parse is shown to the model with the error once
+    /// before giving up.
+    pub fn complete_json<T: DeserializeOwned>(
+        &mut self,
+        system: &str,
+        prompt: &str,
+        max_tokens: usize,
+        control: &GenerationControl,
+    ) -> Result<T> {
+        let mut messages = vec![ChatMessage::system(system), ChatMessage::user(prompt)];
+        let mut attempts = 0;
+        loop {
+            let (reply, finish_reason) = self.complete_messages(&messages, max_tokens, control)?;
+            let error = match parse_json(&reply) {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            attempts += 1;
+            match finish_reason {
+                FinishReason::Cancelled | FinishReason::Timeout => {
+                    return Err(StudyNestError::ModelError(format!(
+                        "generation stopped ({:?}) before the JSON reply was complete",
+                        finish_reason
+                    )))
+                }
+                // A truncated reply would only be truncated again
+                FinishReason::Length => {
+                    return Err(StudyNestError::ModelError(format!(
+                        "the JSON reply did not fit in {} tokens",
+                        max_tokens
+                    )))
+                }
+                FinishReason::Stop if attempts > 1 => {
+                    return Err(StudyNestError::ModelError(format!(
+                        "the model did not reply with valid JSON: {}",
+                        error
+                    )))
+                }
+                FinishReason::Stop => {}
+            }
+            messages.push(ChatMessage::assistant(reply));
+            messages.push(ChatMessage::user(format!(
+                "That is not valid JSON ({}). Reply again with the JSON only.",
+                error
+            )));
+        }
+    }
+
+    /// Generate a reply to `messages` in place of the history
+    fn complete_messages(
+        &mut self,
+        messages: &[ChatMessage],
+        max_tokens: usize,
+        control: &GenerationControl,
+    ) -> Result<(String, FinishReason)> {
+        let messages: Vec<Message> = messages
+            .iter()
+            .map(|m| Message {
+                role: m.role.into(),
+                content: m.content.clone(),
+            })
+            .collect();
+        let prompt = self.tokenizer.apply_chat_template(&messages, true)
+            .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
+        let prompt_tokens = self.count_tokens(&prompt)?;
+        if prompt_tokens + max_tokens > self.max_context {
+            return Err(StudyNestError::ContextLengthExceeded(format!(
+                "prompt needs {} tokens plus {} for the reply, but the context holds {}",
+                prompt_tokens, max_tokens, self.max_context
+            )));
+        }
+        self.generate_with_limit(&prompt, max_tokens, control)
+    }
+
+    /// Send a message with streaming output; the reply carries the context report like
+    /// `chat_with_report`
+    pub fn chat_streaming<F>(&mut self, message: &str, callback: F) -> Result<ChatReply>
     where
         F: Fn(&str),
     {
-        self.history.push(ChatMessage::user(message));
-        
-        let prompt = self.build_prompt()?;
-        let response = self.generate_streaming(&prompt, callback)?;
-        
-        self.history.push(ChatMessage::assistant(&response));
-        
-        Ok(response)
+        self.chat_streaming_with_control(message, &GenerationControl::default(), callback)
     }
 
-    /// Generate response from prompt
-    fn generate(&mut self, prompt: &str) -> Result<String> {
-        let gen_config = self.build_gen_config();
+    /// Fit the history into the context window according to the configured policy
+    /// and return the final prompt
+    fn fit_context(&mut self, control: &GenerationControl) -> Result<(String, ContextReport)> {
+        let budget = self.max_context.saturating_sub(self.config.max_new_tokens);
+        // Room kept for the memory message that will be (re)written after eviction
+        let reserve = match self.config.context_policy {
+            ContextPolicy::Summarize => self.config.summary_max_tokens,
+            ContextPolicy::DropOldest => match &self.memory {
+                Some(memory) => self.count_tokens(memory)?,
+                None => 0,
+            },
+        };
+
+        let mut evicted = Vec::new();
+        loop {
+            let prompt = self.build_prompt_with(None)?;
+            if self.count_tokens(&prompt)? + reserve <= budget {
+                break;
+            }
+            // The system prompt is pinned and the pending user message is never evicted
+            let Some(first) = self.history.iter().position(|m| m.role != Role::System) else {
+                break;
+            };
+            if first + 1 >= self.history.len() {
+                break;
+            }
+            evicted.push(self.history.remove(first));
+            // Keep user/assistant turns paired
+            if first + 1 < self.history.len() && self.history[first].role == Role::Assistant {
+                evicted.push(self.history.remove(first));
+            }
+        }
+
+        let action = if evicted.is_empty() {
+            ContextAction::None
+        } else if self.config.context_policy == ContextPolicy::Summarize {
+            // An interrupted summary is discarded and the turns are simply dropped
+            match self.summarize(&evicted, control)? {
+                Some(memory) => {
+                    self.memory = Some(memory);
+                    ContextAction::Summarized
+                }
+                None => ContextAction::Truncated,
+            }
+        } else {
+            ContextAction::Truncated
+        };
+
+        let prompt = self.build_prompt_with(self.memory.as_deref())?;
+        let prompt_tokens = self.count_tokens(&prompt)?;
+        if prompt_tokens > budget {
+            return Err(StudyNestError::ContextLengthExceeded(format!(
+                "prompt needs {} tokens but only {} of {} are available after reserving {} for generation",
+                prompt_tokens, budget, self.max_context, self.config.max_new_tokens
+            )));
+        }
+
+        Ok((
+            prompt,
+            ContextReport {
+                action,
+                policy: self.config.context_policy,
+                prompt_tokens,
+                max_context: self.max_context,
+                evicted_messages: evicted.len(),
+            },
+        ))
+    }
+
+    /// Fold evicted turns and the previous memory into a new memory with the model itself.
+    /// Returns `None` when the request was cancelled or timed out while summarizing.
+    fn summarize(&mut self, evicted: &[ChatMessage], control: &GenerationControl) -> Result<Option<String>> {
+        let mut transcript = String::new();
+        if let Some(memory) = &self.memory {
+            transcript.push_str(&format!("Earlier summary: {}\n\n", memory));
+        }
+        for m in evicted {
+            let speaker = match m.role {
+                Role::System => "System",
+                Role::User => "Student",
+                Role::Assistant => "Assistant",
+            };
+            transcript.push_str(&format!("{}: {}\n", speaker, m.content));
+        }
+
+        let messages = [
+            Message {
+                role: CoreRole::System,
+                content: SUMMARY_SYSTEM_PROMPT.to_string(),
+            },
+            Message {
+                role: CoreRole::User,
+                content: transcript,
+            },
+        ];
+        let prompt = self.tokenizer.apply_chat_template(&messages, true)
+            .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
+
+        let (summary, finish_reason) =
+            self.generate_with_limit(&prompt, self.config.summary_max_tokens, control)?;
+        match finish_reason {
+            FinishReason::Cancelled | FinishReason::Timeout => Ok(None),
+            FinishReason::Stop | FinishReason::Length => Ok(Some(summary.trim().to_string())),
+        }
+    }
+
+    /// Count the tokens of `text`, e.g. to fit source text into a prompt
+    pub fn count_tokens(&self, text: &str) -> Result<usize> {
+        self.tokenizer.encode(text, false)
+            .map(|ids| ids.len())
+            .map_err(|e| StudyNestError::TokenizationError(e.to_string()))
+    }
+
+    /// Generate response from prompt with an explicit token limit, stopping early
+    /// on cancellation or deadline
+    fn generate_with_limit(
+        &mut self,
+        prompt: &str,
+        max_new_tokens: usize,
+        control: &GenerationControl,
+    ) -> Result<(String, FinishReason)> {
+        let mut streamer = TextStreamer {
+            tokenizer: self.tokenizer.clone(),
+            buffer: String::new(),
+        };
+        self.generate_with_streamer(prompt, max_new_tokens, control, &mut streamer)
+    }
+
+    fn generate_with_streamer(
+        &mut self,
+        prompt: &str,
+        max_new_tokens: usize,
+        control: &GenerationControl,
+        streamer: &mut dyn TokenStreamer,
+    ) -> Result<(String, FinishReason)> {
+        let mut gen_config = self.build_gen_config();
+        gen_config.max_new_tokens = max_new_tokens;
+        gen_config.cancellation = control.cancellation.clone();
+        gen_config.deadline = control.deadline;
         
         let input_ids = match &mut self.model {
             ChatModel::Qwen25(m) => m.prepare_inputs(prompt),
             ChatModel::Qwen3(m) => m.prepare_inputs(prompt),
         }.map_err(|e| StudyNestError::ModelError(e.to_string()))?;
         
-        let mut streamer = TextStreamer {
-            tokenizer: self.tokenizer.clone(),
-            buffer: String::new(),
-        };
-        
-        let output_ids = match &mut self.model {
-            ChatModel::Qwen25(m) => m.generate(&input_ids, &gen_config, Some(&mut streamer)),
-            ChatModel::Qwen3(m) => m.generate(&input_ids, &gen_config, Some(&mut streamer)),
+        let (output_ids, finish_reason) = match &mut self.model {
+            ChatModel::Qwen25(m) => m.generate_with_reason(&input_ids, &gen_config, Some(&mut *streamer)),
+            ChatModel::Qwen3(m) => m.generate_with_reason(&input_ids, &gen_config, Some(&mut *streamer)),
         }.map_err(|e| StudyNestError::ModelError(e.to_string()))?;
         
         // Only decode the new tokens (skip the input prompt)
-        let input_len = input_ids.len();
-        let new_tokens = if output_ids.len() > input_len {
-            &output_ids[input_len..]
-        } else {
-            &output_ids[..]
-        };
+        let new_tokens = output_ids.get(input_ids.len()..).unwrap_or(&[]);
         
         let response = self.tokenizer.decode(new_tokens, true)
             .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
         
-        Ok(response)
+        Ok((response, finish_reason))
     }
 
-    /// Generate with streaming
-    fn generate_streaming<F>(&mut self, prompt: &str, _callback: F) -> Result<String>
-    where
-        F: Fn(&str),
-    {
-        // For now, use regular generation with TextStreamer which prints to stdout
-        self.generate(prompt)
-    }
-
-    /// Build prompt from chat history
-    fn build_prompt(&self) -> Result<String> {
-        let messages: Vec<Message> = self.history
+    /// Build prompt from chat history, folding the running memory into the system prompt
+    fn build_prompt_with(&self, memory: Option<&str>) -> Result<String> {
+        let mut messages: Vec<Message> = self.history
             .iter()
             .map(|m| Message {
                 role: m.role.into(),
                 content: m.content.clone(),
             })
             .collect();
+
+        if let Some(memory) = memory {
+            let note = format!("Summary of the earlier conversation: {}", memory);
+            match self.history.first() {
+                Some(first) if first.role == Role::System => {
+                    messages[0].content = format!("{}\n\n{}", first.content, note);
+                }
+                _ => messages.insert(0, Message { role: CoreRole::System, content: note }),
+            }
+        }
         
         self.tokenizer.apply_chat_template(&messages, true)
             .map_err(|e| StudyNestError::TokenizationError(e.to_string()))
@@ -262,12 +757,15 @@ impl ChatEngine {
             pad_token_id: self.tokenizer.get_token("<|end_of_text|>"),
             eos_token_id: self.tokenizer.get_token("<|im_end|>"),
             report_speed: self.config.report_speed,
+            cancellation: None,
+            deadline: None,
         }
     }
 
     /// Clear chat history
     pub fn clear_history(&mut self) {
         self.history.clear();
+        self.memory = None;
     }
 
     /// Get current chat history
@@ -275,6 +773,38 @@ impl ChatEngine {
         &self.history
     }
 
+    /// Append a message to the history without generating a reply
+    pub fn add_message(&mut self, message: ChatMessage) {
+        self.history.push(message);
+    }
+
+    /// Get the running summary of evicted turns, if any
+    pub fn memory(&self) -> Option<&str> {
+        self.memory.as_deref()
+    }
+
+    /// Maximum context length used for the prompt budget
+    pub fn max_context(&self) -> usize {
+        self.max_context
+    }
+
+    /// Checkpoint directory or GGUF file the model was loaded from
+    pub fn model_path(&self) -> &str {
+        &self.config.model_path
+    }
+
+    /// Configuration the engine was created with, with the current reply settings
+    pub fn config(&self) -> &ChatConfig {
+        &self.config
+    }
+
+    /// Change the length and sampling of later replies
+    pub fn set_sampling(&mut self, max_new_tokens: usize, temperature: Option<f64>, top_p: Option<f64>) {
+        self.config.max_new_tokens = max_new_tokens;
+        self.config.temperature = temperature;
+        self.config.top_p = top_p;
+    }
+
     /// Set system prompt
     pub fn set_system_prompt(&mut self, prompt: &str) {
         // Remove existing system message if any
@@ -285,24 +815,31 @@ impl ChatEngine {
 
     /// Warmup the model
     pub fn warmup(&mut self) {
-        println!("[StudyNest] Warming up chat model...");
+        eprintln!("[StudyNest] Warming up chat model...");
         match &mut self.model {
             ChatModel::Qwen25(m) => m.warmup(),
             ChatModel::Qwen3(m) => m.warmup(),
         }
-        println!("[StudyNest] Warmup complete");
+        eprintln!("[StudyNest] Warmup complete");
+    }
+}
+
+/// Parse the first JSON object or array in a model reply, skipping a `<think>` block,
+/// code fences and any text around the value
+pub fn parse_json<T: DeserializeOwned>(reply: &str) -> std::result::Result<T, serde_json::Error> {
+    let reply = match reply.find("</think>") {
+        Some(end) => &reply[end + "</think>".len()..],
+        None => reply,
+    };
+    let start = reply.find(['{', '[']).unwrap_or(0);
+    let mut values = serde_json::Deserializer::from_str(&reply[start..]).into_iter::<T>();
+    match values.next() {
+        Some(value) => value,
+        None => serde_json::from_str(""),
     }
 }
 
-/// List available chat models
-pub fn list_available_models() -> Vec<(&'static str, &'static str)> {
-    vec![
-        ("Qwen2.5-0.5B-Instruct", "Small, fast model for basic chat"),
-        ("Qwen2.5-1.5B-Instruct", "Medium model with better quality"),
-        ("Qwen2.5-3B-Instruct", "Larger model for complex tasks"),
-        ("Qwen2.5-7B-Instruct", "High quality, requires more memory"),
-        ("Qwen3-0.6B", "Latest Qwen3 small model"),
-        ("Qwen3-1.7B", "Latest Qwen3 medium model"),
-        ("Qwen3-4B", "Latest Qwen3 larger model"),
-    ]
+/// List the chat models installed under a checkpoints directory
+pub fn list_available_models(checkpoints_dir: impl Into<PathBuf>) -> Result<Vec<ModelInfo>> {
+    ModelRegistry::new(checkpoints_dir).list(ModelKind::Chat)
 }
@@ -76,14 +76,14 @@ pub fn get_device(device_type: DeviceType) -> Result<Device> {
             {
                 if let Ok(device) = Device::cuda_if_available(0) {
                     if !matches!(device, Device::Cpu) {
-                        println!("[StudyNest] Using CUDA GPU");
+                        eprintln!("[StudyNest] Using CUDA GPU");
                         return Ok(device);
                     }
                 }
             }
             
             // Use CPU for stability (Metal has rms-norm issues)
-            println!("[StudyNest] Using CPU (Metal GPU disabled due to compatibility issues)");
+            eprintln!("[StudyNest] Using CPU (Metal GPU disabled due to compatibility issues)");
             Ok(Device::Cpu)
         }
     }
@@ -0,0 +1,165 @@
+//! Text embedding module for semantic search over notes
+//!
+//! Supports BERT-style models (BGE, MiniLM, ...) and decoder embedders such as
+//! Qwen3-Embedding, as checkpoint directories or `.gguf` files.
+
+use crate::device::{DeviceType, get_device};
+use crate::error::{Result, StudyNestError};
+
+use crane_core::models::embedding::EmbeddingModel;
+pub use crane_core::models::embedding::Pooling;
+
+/// Embedding configuration
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    pub model_path: String,
+    pub device: DeviceType,
+    /// Texts run through the model at once
+    pub batch_size: usize,
+    /// Truncate inputs to this many tokens (the model's context length when `None`)
+    pub max_length: Option<usize>,
+    /// Overrides the pooling declared by the checkpoint
+    pub pooling: Option<Pooling>,
+    /// L2-normalize the embeddings
+    pub normalize: bool,
+    /// Task description prepended to queries by `embed_query`, as Qwen3-Embedding expects
+    pub query_instruction: Option<String>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "checkpoints/Qwen3-Embedding-0.6B".to_string(),
+            device: DeviceType::Auto,
+            batch_size: 16,
+            max_length: Some(8192),
+            pooling: None,
+            normalize: true,
+            query_instruction: None,
+        }
+    }
+}
+
+impl EmbeddingConfig {
+    pub fn with_model_path(mut self, path: impl Into<String>) -> Self {
+        self.model_path = path.into();
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceType) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn with_pooling(mut self, pooling: Pooling) -> Self {
+        self.pooling = Some(pooling);
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn with_query_instruction(mut self, instruction: impl Into<String>) -> Self {
+        self.query_instruction = Some(instruction.into());
+        self
+    }
+}
+
+/// Text embedding engine
+pub struct EmbeddingEngine {
+    config: EmbeddingConfig,
+    model: EmbeddingModel,
+}
+
+impl EmbeddingEngine {
+    /// Create a new embedding engine
+    pub fn new(config: EmbeddingConfig) -> Result<Self> {
+        eprintln!("[StudyNest] Initializing embedding engine with model: {}", config.model_path);
+
+        let device = get_device(config.device)?;
+        let mut model = EmbeddingModel::new(&config.model_path, &device)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        model.normalize = config.normalize;
+        if let Some(pooling) = config.pooling {
+            model.pooling = pooling;
+        }
+        if let Some(max_length) = config.max_length {
+            model.max_length = model.max_length.min(max_length);
+        }
+
+        eprintln!(
+            "[StudyNest] Embedding engine initialized ({} dimensions, {:?} pooling)",
+            model.dimension, model.pooling
+        );
+        Ok(Self { config, model })
+    }
+
+    pub fn config(&self) -> &EmbeddingConfig {
+        &self.config
+    }
+
+    /// Size of the embedding vectors
+    pub fn dimension(&self) -> usize {
+        self.model.dimension
+    }
+
+    /// Embed documents, in batches of `batch_size`
+    pub fn embed(&mut self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.model
+            .embed(texts, self.config.batch_size)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+
+    pub fn embed_one(&mut self, text: &str) -> Result<Vec<f32>> {
+        Ok(self.embed(&[text])?.pop().unwrap_or_default())
+    }
+
+    /// Embed a search query, prefixed with `query_instruction` when configured
+    pub fn embed_query(&mut self, query: &str) -> Result<Vec<f32>> {
+        match &self.config.query_instruction {
+            Some(instruction) => {
+                let query = format!("Instruct: {instruction}\nQuery:{query}");
+                self.embed_one(&query)
+            }
+            None => self.embed_one(query),
+        }
+    }
+
+    /// Number of tokens each text is encoded to, after truncation
+    pub fn count_tokens(&self, texts: &[&str]) -> Result<Vec<usize>> {
+        texts
+            .iter()
+            .map(|text| {
+                self.model
+                    .tokenizer
+                    .encode(*text, true)
+                    .map(|e| e.get_ids().len().min(self.model.max_length))
+                    .map_err(|e| StudyNestError::TokenizationError(e.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Cosine similarity of two vectors (the dot product when both are normalized)
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
@@ -11,9 +11,18 @@ pub enum StudyNestError {
     #[error("Model error: {0}")]
     ModelError(String),
 
+    #[error("Model not found: {0}")]
+    ModelNotFound(String),
+
     #[error("Tokenization error: {0}")]
     TokenizationError(String),
 
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+
+    #[error("Session not found: {0}")]
+    SessionNotFound(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
@@ -26,6 +35,12 @@ pub enum StudyNestError {
     #[error("OCR error: {0}")]
     OcrError(String),
 
+    #[error("Image error: {0}")]
+    ImageError(String),
+
+    #[error("Index error: {0}")]
+    IndexError(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
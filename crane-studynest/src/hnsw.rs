@@ -0,0 +1,1030 @@
+//! Approximate nearest-neighbour search with HNSW (hierarchical navigable small worlds)
+//!
+//! Vectors are addressed by caller-chosen `u64` ids and carry metadata (document,
+//! course, date) that searches can filter on. Deleted and updated vectors are
+//! tombstoned and dropped by `compact`.
+//!
+//! Indexes are saved to a single file whose vector block is memory-mapped on load, so
+//! opening a large index does not read the vectors into memory. Saves write a new file
+//! and rename it over the old one, so a crash leaves the previous index intact.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, StudyNestError};
+
+const MAGIC: &[u8; 8] = b"SNHNSW\0\x01";
+const FORMAT_VERSION: u32 = 1;
+/// Highest layer a node can be placed on
+const MAX_LEVEL: usize = 16;
+/// Filters matching less than this fraction of the index are searched exhaustively
+const EXACT_FILTER_FRACTION: f64 = 0.1;
+
+/// Similarity measure; higher scores are closer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Vectors are normalized on insertion and scored by cosine similarity
+    #[default]
+    Cosine,
+    /// Raw inner product
+    Dot,
+}
+
+/// Graph construction and search parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswConfig {
+    pub metric: Metric,
+    /// Links per node on the upper layers (twice as many on the bottom layer)
+    pub m: usize,
+    /// Candidate list size while inserting
+    pub ef_construction: usize,
+    /// Candidate list size while searching (at least `k`)
+    pub ef_search: usize,
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            metric: Metric::Cosine,
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 0x5eed,
+        }
+    }
+}
+
+impl HnswConfig {
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn with_m(mut self, m: usize) -> Self {
+        self.m = m.max(2);
+        self
+    }
+
+    pub fn with_ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction.max(1);
+        self
+    }
+
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search.max(1);
+        self
+    }
+}
+
+/// Attributes searches can filter on
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VectorMetadata {
+    #[serde(default)]
+    pub document: Option<String>,
+    #[serde(default)]
+    pub course: Option<String>,
+    /// Unix timestamp in milliseconds
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+/// Restricts a search to vectors whose metadata matches every given field
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFilter {
+    #[serde(default)]
+    pub documents: Option<Vec<String>>,
+    #[serde(default)]
+    pub course: Option<String>,
+    /// Only vectors with a timestamp at or after this one
+    #[serde(default)]
+    pub after: Option<u64>,
+    /// Only vectors with a timestamp before this one
+    #[serde(default)]
+    pub before: Option<u64>,
+}
+
+impl SearchFilter {
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_none() && self.course.is_none() && self.after.is_none() && self.before.is_none()
+    }
+
+    pub fn matches(&self, metadata: &VectorMetadata) -> bool {
+        let document_ok = match (&self.documents, &metadata.document) {
+            (None, _) => true,
+            (Some(documents), Some(document)) => documents.contains(document),
+            (Some(_), None) => false,
+        };
+        let course_ok = match (&self.course, &metadata.course) {
+            (None, _) => true,
+            (Some(course), Some(c)) => course == c,
+            (Some(_), None) => false,
+        };
+        let after_ok = self.after.is_none_or(|after| metadata.timestamp.is_some_and(|t| t >= after));
+        let before_ok = self.before.is_none_or(|before| metadata.timestamp.is_some_and(|t| t < before));
+        document_ok && course_ok && after_ok && before_ok
+    }
+}
+
+/// A search hit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: u64,
+    /// Cosine similarity or inner product, depending on the metric
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    score: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Vector storage: a memory-mapped block from the last load followed by vectors
+/// added since
+struct VectorStore {
+    dim: usize,
+    mapped: Option<Mmap>,
+    mapped_offset: usize,
+    mapped_count: usize,
+    owned: Vec<f32>,
+}
+
+impl VectorStore {
+    fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            mapped: None,
+            mapped_offset: 0,
+            mapped_count: 0,
+            owned: Vec::new(),
+        }
+    }
+
+    fn get(&self, i: usize) -> &[f32] {
+        match &self.mapped {
+            Some(mmap) if i < self.mapped_count => {
+                let start = self.mapped_offset + i * self.dim * 4;
+                let bytes = &mmap[start..start + self.dim * 4];
+                // SAFETY: `load` only maps the block on little-endian targets when it is
+                // 4-byte aligned, and the index never writes to a mapped file
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, self.dim) }
+            }
+            _ => {
+                let j = i - self.mapped_count;
+                &self.owned[j * self.dim..(j + 1) * self.dim]
+            }
+        }
+    }
+
+    fn push(&mut self, vector: &[f32]) {
+        self.owned.extend_from_slice(vector);
+    }
+
+    /// Copy the mapped vectors into memory and release the mapping
+    fn detach(&mut self) {
+        if self.mapped.is_none() {
+            return;
+        }
+        let mut owned = Vec::with_capacity((self.mapped_count * self.dim) + self.owned.len());
+        for i in 0..self.mapped_count {
+            owned.extend_from_slice(self.get(i));
+        }
+        owned.append(&mut self.owned);
+        self.owned = owned;
+        self.mapped = None;
+        self.mapped_offset = 0;
+        self.mapped_count = 0;
+    }
+}
+
+/// Fixed part of the index file, stored as JSON after the magic bytes
+#[derive(Debug, Serialize, Deserialize)]
+struct FileHeader {
+    version: u32,
+    config: HnswConfig,
+    dim: usize,
+    count: usize,
+    entry: Option<u32>,
+    max_level: usize,
+    rng: u64,
+    graph_len: usize,
+    metadata_len: usize,
+}
+
+/// HNSW index over fixed-size `f32` vectors
+pub struct HnswIndex {
+    config: HnswConfig,
+    dim: usize,
+    vectors: VectorStore,
+    ids: Vec<u64>,
+    metadata: Vec<VectorMetadata>,
+    deleted: Vec<bool>,
+    /// Links of each node, one list per layer it is on
+    links: Vec<Vec<Vec<u32>>>,
+    by_id: HashMap<u64, u32>,
+    entry: Option<u32>,
+    max_level: usize,
+    rng: u64,
+}
+
+impl HnswIndex {
+    pub fn new(dim: usize, config: HnswConfig) -> Self {
+        Self {
+            rng: config.seed,
+            config,
+            dim,
+            vectors: VectorStore::new(dim),
+            ids: Vec::new(),
+            metadata: Vec::new(),
+            deleted: Vec::new(),
+            links: Vec::new(),
+            by_id: HashMap::new(),
+            entry: None,
+            max_level: 0,
+        }
+    }
+
+    pub fn config(&self) -> &HnswConfig {
+        &self.config
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.config.metric
+    }
+
+    /// Number of live vectors
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// Number of tombstoned nodes still in the graph
+    pub fn deleted_count(&self) -> usize {
+        self.ids.len() - self.by_id.len()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    /// Ids of the live vectors
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.by_id.keys().copied()
+    }
+
+    /// Stored vector of an id (normalized for the cosine metric)
+    pub fn get(&self, id: u64) -> Option<&[f32]> {
+        self.by_id.get(&id).map(|&node| self.vectors.get(node as usize))
+    }
+
+    pub fn metadata(&self, id: u64) -> Option<&VectorMetadata> {
+        self.by_id.get(&id).map(|&node| &self.metadata[node as usize])
+    }
+
+    /// Add a vector, replacing the previous one stored under `id`
+    pub fn insert(&mut self, id: u64, vector: &[f32], metadata: VectorMetadata) -> Result<()> {
+        if vector.len() != self.dim {
+            return Err(StudyNestError::IndexError(format!(
+                "expected a vector of {} dimensions, got {}",
+                self.dim,
+                vector.len()
+            )));
+        }
+        self.remove(id);
+        let vector = self.prepare(vector);
+
+        let node = self.ids.len() as u32;
+        let level = self.random_level();
+        self.vectors.push(&vector);
+        self.ids.push(id);
+        self.metadata.push(metadata);
+        self.deleted.push(false);
+        self.links.push(vec![Vec::new(); level + 1]);
+        self.by_id.insert(id, node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            self.max_level = level;
+            return Ok(());
+        };
+
+        let mut nearest = Candidate {
+            score: self.score(&vector, entry),
+            node: entry,
+        };
+        for layer in (level + 1..=self.max_level).rev() {
+            nearest = self.greedy(&vector, nearest, layer);
+        }
+
+        let mut entries = vec![nearest];
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let found = self.search_layer(&vector, &entries, self.config.ef_construction, layer, |_| true);
+            let neighbours = self.select_neighbours(&found, self.config.m);
+            for &neighbour in &neighbours {
+                self.link(neighbour, node, layer);
+            }
+            self.links[node as usize][layer] = neighbours;
+            entries = found;
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(node);
+        }
+        Ok(())
+    }
+
+    /// Tombstone a vector; returns false if the id is unknown
+    pub fn remove(&mut self, id: u64) -> bool {
+        match self.by_id.remove(&id) {
+            Some(node) => {
+                self.deleted[node as usize] = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether tombstones outnumber live vectors enough to be worth a `compact`
+    pub fn needs_compaction(&self) -> bool {
+        self.deleted_count() > 64 && self.deleted_count() > self.len()
+    }
+
+    /// Rebuild the graph from the live vectors only
+    pub fn compact(&mut self) {
+        let mut rebuilt = HnswIndex::new(self.dim, self.config.clone());
+        rebuilt.rng = self.rng;
+        for node in 0..self.ids.len() {
+            if self.deleted[node] {
+                continue;
+            }
+            let vector = self.vectors.get(node).to_vec();
+            let metadata = self.metadata[node].clone();
+            rebuilt
+                .insert(self.ids[node], &vector, metadata)
+                .expect("vectors of the index have its dimension");
+        }
+        *self = rebuilt;
+    }
+
+    /// The `k` closest vectors to `query`
+    pub fn search(&self, query: &[f32], k: usize, filter: Option<&SearchFilter>) -> Vec<SearchResult> {
+        self.search_with_ef(query, k, self.config.ef_search, filter)
+    }
+
+    /// `search` with an explicit candidate list size; larger is slower and more accurate
+    pub fn search_with_ef(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        filter: Option<&SearchFilter>,
+    ) -> Vec<SearchResult> {
+        let filter = filter.filter(|f| !f.is_empty());
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if k == 0 || query.len() != self.dim || self.is_empty() {
+            return Vec::new();
+        }
+
+        let matching = match filter {
+            Some(filter) => self.live_nodes().filter(|&n| filter.matches(&self.metadata[n])).count(),
+            None => self.len(),
+        };
+        if (matching as f64) < EXACT_FILTER_FRACTION * self.len() as f64 {
+            return self.brute_force(query, k, filter);
+        }
+
+        let query = self.prepare(query);
+        let mut nearest = Candidate {
+            score: self.score(&query, entry),
+            node: entry,
+        };
+        for layer in (1..=self.max_level).rev() {
+            nearest = self.greedy(&query, nearest, layer);
+        }
+        let accept = |node: u32| {
+            !self.deleted[node as usize] && filter.is_none_or(|f| f.matches(&self.metadata[node as usize]))
+        };
+        let found = self.search_layer(&query, &[nearest], ef.max(k), 0, accept);
+        if found.len() < k.min(matching) {
+            // Tombstones can cut off parts of the graph
+            return self.brute_force(&query, k, filter);
+        }
+
+        found
+            .into_iter()
+            .take(k)
+            .map(|c| SearchResult {
+                id: self.ids[c.node as usize],
+                score: c.score,
+            })
+            .collect()
+    }
+
+    /// Exact search by scoring every live vector
+    pub fn brute_force(&self, query: &[f32], k: usize, filter: Option<&SearchFilter>) -> Vec<SearchResult> {
+        if query.len() != self.dim {
+            return Vec::new();
+        }
+        let query = self.prepare(query);
+        let mut best: BinaryHeap<Reverse<Candidate>> = BinaryHeap::with_capacity(k + 1);
+        for node in self.live_nodes() {
+            if filter.is_some_and(|f| !f.matches(&self.metadata[node])) {
+                continue;
+            }
+            best.push(Reverse(Candidate {
+                score: self.score(&query, node as u32),
+                node: node as u32,
+            }));
+            if best.len() > k {
+                best.pop();
+            }
+        }
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(c)| SearchResult {
+                id: self.ids[c.node as usize],
+                score: c.score,
+            })
+            .collect()
+    }
+
+    fn live_nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.ids.len()).filter(|&n| !self.deleted[n])
+    }
+
+    fn prepare(&self, vector: &[f32]) -> Vec<f32> {
+        match self.config.metric {
+            Metric::Dot => vector.to_vec(),
+            Metric::Cosine => {
+                let norm = dot(vector, vector).sqrt();
+                if norm > 0.0 {
+                    vector.iter().map(|x| x / norm).collect()
+                } else {
+                    vector.to_vec()
+                }
+            }
+        }
+    }
+
+    fn score(&self, query: &[f32], node: u32) -> f32 {
+        dot(query, self.vectors.get(node as usize))
+    }
+
+    fn pair_score(&self, a: u32, b: u32) -> f32 {
+        dot(self.vectors.get(a as usize), self.vectors.get(b as usize))
+    }
+
+    fn random_level(&mut self) -> usize {
+        // splitmix64
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let uniform = 1.0 - (z >> 11) as f64 / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.config.m as f64).ln();
+        ((-uniform.ln() * ml) as usize).min(MAX_LEVEL)
+    }
+
+    /// Walk to the closest node of a layer, one best neighbour at a time
+    fn greedy(&self, query: &[f32], mut nearest: Candidate, layer: usize) -> Candidate {
+        loop {
+            let mut improved = false;
+            for &neighbour in &self.links[nearest.node as usize][layer] {
+                let score = self.score(query, neighbour);
+                if score > nearest.score {
+                    nearest = Candidate { score, node: neighbour };
+                    improved = true;
+                }
+            }
+            if !improved {
+                return nearest;
+            }
+        }
+    }
+
+    /// Best-first search of one layer. Every node is explored, but only nodes passing
+    /// `accept` are returned. Results are sorted closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+        accept: impl Fn(u32) -> bool,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut candidates: BinaryHeap<Candidate> = BinaryHeap::new();
+        let mut results: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+
+        for &entry in entries {
+            if visited.insert(entry.node) {
+                candidates.push(entry);
+                if accept(entry.node) {
+                    results.push(Reverse(entry));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        while let Some(current) = candidates.pop() {
+            if let Some(Reverse(worst)) = results.peek() {
+                if results.len() >= ef && current.score < worst.score {
+                    break;
+                }
+            }
+            for &neighbour in &self.links[current.node as usize][layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let score = self.score(query, neighbour);
+                let worst = results.peek().map(|Reverse(w)| w.score).unwrap_or(f32::NEG_INFINITY);
+                if results.len() < ef || score > worst {
+                    let candidate = Candidate { score, node: neighbour };
+                    candidates.push(candidate);
+                    if accept(neighbour) {
+                        results.push(Reverse(candidate));
+                        if results.len() > ef {
+                            results.pop();
+                        }
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec().into_iter().map(|Reverse(c)| c).collect()
+    }
+
+    /// Keep candidates that are closer to the base than to any neighbour already kept,
+    /// then fill up with the closest pruned ones. `candidates` is sorted closest first.
+    fn select_neighbours(&self, candidates: &[Candidate], m: usize) -> Vec<u32> {
+        let mut selected: Vec<Candidate> = Vec::with_capacity(m);
+        let mut pruned = Vec::new();
+        for &candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            if selected
+                .iter()
+                .all(|s| self.pair_score(candidate.node, s.node) < candidate.score)
+            {
+                selected.push(candidate);
+            } else {
+                pruned.push(candidate);
+            }
+        }
+        for candidate in pruned {
+            if selected.len() >= m {
+                break;
+            }
+            selected.push(candidate);
+        }
+        selected.into_iter().map(|c| c.node).collect()
+    }
+
+    /// Add a link from `from` to `to`, pruning `from`'s links when over capacity
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        let max_links = if layer == 0 { 2 * self.config.m } else { self.config.m };
+        self.links[from as usize][layer].push(to);
+        if self.links[from as usize][layer].len() <= max_links {
+            return;
+        }
+        let mut candidates: Vec<Candidate> = self.links[from as usize][layer]
+            .iter()
+            .map(|&node| Candidate {
+                score: self.pair_score(from, node),
+                node,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.cmp(a));
+        self.links[from as usize][layer] = self.select_neighbours(&candidates, max_links);
+    }
+
+    /// Write the index to `path`. The file is written next to it and renamed into place.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = tmp_path(path);
+        self.write_file(&tmp)?;
+        if fs::rename(&tmp, path).is_err() {
+            // Windows refuses to replace a file that is still mapped
+            self.vectors.detach();
+            fs::rename(&tmp, path)?;
+        }
+        sync_parent(path);
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path) -> Result<()> {
+        let mut graph = Vec::new();
+        for node in 0..self.ids.len() {
+            graph.extend_from_slice(&self.ids[node].to_le_bytes());
+            graph.push(self.deleted[node] as u8);
+            graph.push((self.links[node].len() - 1) as u8);
+            for layer in &self.links[node] {
+                graph.extend_from_slice(&(layer.len() as u32).to_le_bytes());
+                for &neighbour in layer {
+                    graph.extend_from_slice(&neighbour.to_le_bytes());
+                }
+            }
+        }
+        let metadata = serde_json::to_vec(&self.metadata)?;
+        let header = serde_json::to_vec(&FileHeader {
+            version: FORMAT_VERSION,
+            config: self.config.clone(),
+            dim: self.dim,
+            count: self.ids.len(),
+            entry: self.entry,
+            max_level: self.max_level,
+            rng: self.rng,
+            graph_len: graph.len(),
+            metadata_len: metadata.len(),
+        })?;
+
+        let file = File::create(path)?;
+        let mut out = BufWriter::new(&file);
+        out.write_all(MAGIC)?;
+        out.write_all(&(header.len() as u64).to_le_bytes())?;
+        out.write_all(&header)?;
+        let header_end = MAGIC.len() + 8 + header.len();
+        out.write_all(&vec![0u8; vectors_offset(header.len()).expect("header fits in memory") - header_end])?;
+        for node in 0..self.ids.len() {
+            for value in self.vectors.get(node) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        out.write_all(&graph)?;
+        out.write_all(&metadata)?;
+        out.flush()?;
+        drop(out);
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Open an index saved with `save`, mapping its vectors into memory
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let corrupt = |what: &str| StudyNestError::IndexError(format!("{}: {}", path.display(), what));
+        let file = File::open(path)?;
+        // SAFETY: the index replaces its files by renaming and never modifies them in place
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < 16 || &mmap[..8] != MAGIC {
+            return Err(corrupt("not an HNSW index file"));
+        }
+        let header_len = u64::from_le_bytes(mmap[8..16].try_into().expect("8 bytes")) as usize;
+        let header_bytes = 16usize
+            .checked_add(header_len)
+            .and_then(|end| mmap.get(16..end))
+            .ok_or_else(|| corrupt("truncated header"))?;
+        let header: FileHeader =
+            serde_json::from_slice(header_bytes).map_err(|e| corrupt(&format!("bad header: {}", e)))?;
+        if header.version != FORMAT_VERSION {
+            return Err(corrupt(&format!("unsupported version {}", header.version)));
+        }
+
+        // Sizes come from the file, so a corrupt header must not overflow the offsets
+        let offsets = vectors_offset(header_len).and_then(|vectors_start| {
+            let graph_start = header
+                .count
+                .checked_mul(header.dim)?
+                .checked_mul(4)?
+                .checked_add(vectors_start)?;
+            let metadata_start = graph_start.checked_add(header.graph_len)?;
+            let end = metadata_start.checked_add(header.metadata_len)?;
+            Some((vectors_start, graph_start, metadata_start, end))
+        });
+        let (vectors_start, graph_start, metadata_start) = match offsets {
+            Some((vectors_start, graph_start, metadata_start, end)) if end == mmap.len() => {
+                (vectors_start, graph_start, metadata_start)
+            }
+            _ => return Err(corrupt("unexpected file size")),
+        };
+        if header.entry.is_some_and(|entry| entry as usize >= header.count) {
+            return Err(corrupt("entry point out of range"));
+        }
+
+        let mut index = HnswIndex::new(header.dim, header.config);
+        index.entry = header.entry;
+        index.max_level = header.max_level;
+        index.rng = header.rng;
+
+        let mut graph = &mmap[graph_start..metadata_start];
+        let mut take = |n: usize| -> Result<&[u8]> {
+            if graph.len() < n {
+                return Err(corrupt("truncated graph"));
+            }
+            let (head, rest) = graph.split_at(n);
+            graph = rest;
+            Ok(head)
+        };
+        for node in 0..header.count {
+            let id = u64::from_le_bytes(take(8)?.try_into().expect("8 bytes"));
+            let flags = take(2)?;
+            let (deleted, top_layer) = (flags[0] != 0, flags[1] as usize);
+            let mut layers = Vec::with_capacity(top_layer + 1);
+            for _ in 0..=top_layer {
+                let n = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes")) as usize;
+                let bytes = take(n * 4)?;
+                let links: Vec<u32> = bytes
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes")))
+                    .collect();
+                if links.iter().any(|&l| l as usize >= header.count) {
+                    return Err(corrupt("link out of range"));
+                }
+                layers.push(links);
+            }
+            index.ids.push(id);
+            index.deleted.push(deleted);
+            index.links.push(layers);
+            if !deleted {
+                index.by_id.insert(id, node as u32);
+            }
+        }
+        // Searches follow a link on a layer of the node it points to, and start from
+        // the entry point on the top layer
+        for layers in &index.links {
+            for (layer, links) in layers.iter().enumerate() {
+                if links.iter().any(|&l| index.links[l as usize].len() <= layer) {
+                    return Err(corrupt("link to a missing layer"));
+                }
+            }
+        }
+        if let Some(entry) = index.entry {
+            if index.links[entry as usize].len() <= index.max_level {
+                return Err(corrupt("entry point below the top layer"));
+            }
+        }
+        index.metadata = serde_json::from_slice(&mmap[metadata_start..])
+            .map_err(|e| corrupt(&format!("bad metadata: {}", e)))?;
+        if index.metadata.len() != header.count {
+            return Err(corrupt("metadata count mismatch"));
+        }
+
+        let aligned = (mmap.as_ptr() as usize + vectors_start).is_multiple_of(std::mem::align_of::<f32>());
+        if cfg!(target_endian = "little") && aligned {
+            index.vectors.mapped = Some(mmap);
+            index.vectors.mapped_offset = vectors_start;
+            index.vectors.mapped_count = header.count;
+        } else {
+            index.vectors.owned = mmap[vectors_start..graph_start]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().expect("4 bytes")))
+                .collect();
+        }
+        Ok(index)
+    }
+}
+
+/// Vectors start on a 64-byte boundary after the header
+fn vectors_offset(header_len: usize) -> Option<usize> {
+    (MAGIC.len() + 8)
+        .checked_add(header_len)?
+        .checked_next_multiple_of(64)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Persist the rename itself (a no-op where directories cannot be opened)
+fn sync_parent(path: &Path) {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+/// Inner product with independent accumulators so it vectorizes
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = [0.0f32; 8];
+    let chunks_a = a.chunks_exact(8);
+    let chunks_b = b.chunks_exact(8);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (ca, cb) in chunks_a.zip(chunks_b) {
+        for i in 0..8 {
+            acc[i] += ca[i] * cb[i];
+        }
+    }
+    acc.iter().sum::<f32>() + tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIM: usize = 16;
+
+    /// Deterministic pseudo-random vectors
+    fn vectors(count: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut state = seed;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        };
+        (0..count).map(|_| (0..DIM).map(|_| next()).collect()).collect()
+    }
+
+    fn build(data: &[Vec<f32>]) -> HnswIndex {
+        let mut index = HnswIndex::new(DIM, HnswConfig::default());
+        for (id, vector) in data.iter().enumerate() {
+            let metadata = VectorMetadata {
+                course: Some(if id % 2 == 0 { "even" } else { "odd" }.to_string()),
+                ..VectorMetadata::default()
+            };
+            index.insert(id as u64, vector, metadata).unwrap();
+        }
+        index
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<u64> {
+        results.iter().map(|r| r.id).collect()
+    }
+
+    /// Fraction of the exact top `k` found by the graph search, over several queries
+    fn recall(index: &HnswIndex, queries: &[Vec<f32>], k: usize) -> f64 {
+        let mut found = 0;
+        for query in queries {
+            let exact: HashSet<u64> = ids(&index.brute_force(query, k, None)).into_iter().collect();
+            found += index.search(query, k, None).iter().filter(|r| exact.contains(&r.id)).count();
+        }
+        found as f64 / (queries.len() * k) as f64
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("studynest-hnsw-{name}-{}.hnsw", std::process::id()))
+    }
+
+    #[test]
+    fn search_finds_the_nearest_vectors() {
+        let data = vectors(500, 1);
+        let mut index = build(&data);
+        assert_eq!(index.len(), 500);
+
+        // A stored vector is its own nearest neighbour, with a cosine of one
+        let hit = index.search(&data[42], 1, None)[0];
+        assert_eq!(hit.id, 42);
+        assert!((hit.score - 1.0).abs() < 1e-5);
+
+        assert!(recall(&index, &vectors(20, 2), 10) >= 0.95);
+
+        let results = index.search(&data[0], 10, None);
+        assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+        assert_eq!(index.search(&data[0], 0, None), []);
+        assert_eq!(index.search(&[1.0; DIM - 1], 5, None), []);
+        assert!(index.insert(1000, &[1.0; DIM + 1], VectorMetadata::default()).is_err());
+    }
+
+    #[test]
+    fn filters_restrict_results() {
+        let data = vectors(300, 3);
+        let index = build(&data);
+        let filter = SearchFilter {
+            course: Some("odd".to_string()),
+            ..SearchFilter::default()
+        };
+        let results = index.search(&data[10], 10, Some(&filter));
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|r| r.id % 2 == 1));
+    }
+
+    #[test]
+    fn removed_vectors_are_not_found_and_compact_drops_them() {
+        let data = vectors(300, 4);
+        let mut index = build(&data);
+        for id in 0..200 {
+            assert!(index.remove(id));
+        }
+        assert!(!index.remove(0));
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.deleted_count(), 200);
+        assert!(index.needs_compaction());
+
+        let results = index.search(&data[5], 10, None);
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|r| r.id >= 200));
+        assert!(index.get(5).is_none());
+
+        index.compact();
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.deleted_count(), 0);
+        assert_eq!(index.search(&data[250], 1, None)[0].id, 250);
+        assert_eq!(index.metadata(251).unwrap().course.as_deref(), Some("odd"));
+
+        // Inserting under an existing id replaces the vector
+        index.insert(250, &data[0], VectorMetadata::default()).unwrap();
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.search(&data[0], 1, None)[0].id, 250);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let data = vectors(200, 5);
+        let mut index = build(&data);
+        index.remove(7);
+        let path = temp_file("round-trip");
+        index.save(&path).unwrap();
+
+        let mut loaded = HnswIndex::load(&path).unwrap();
+        assert!(loaded.vectors.mapped.is_some() || cfg!(target_endian = "big"));
+        assert_eq!(loaded.len(), index.len());
+        assert_eq!(loaded.deleted_count(), 1);
+        assert!(!loaded.contains(7));
+        assert_eq!(loaded.get(3), index.get(3));
+        assert_eq!(loaded.metadata(4), index.metadata(4));
+        for query in vectors(5, 6) {
+            assert_eq!(loaded.search(&query, 10, None), index.search(&query, 10, None));
+        }
+
+        // Vectors added after loading live next to the mapped ones, and saving over the
+        // mapped file keeps both
+        loaded.insert(1000, &data[7], VectorMetadata::default()).unwrap();
+        loaded.save(&path).unwrap();
+        let reloaded = HnswIndex::load(&path).unwrap();
+        assert_eq!(reloaded.len(), 200);
+        assert_eq!(reloaded.search(&data[7], 1, None)[0].id, 1000);
+        assert_eq!(reloaded.get(3), index.get(3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let mut index = build(&vectors(20, 7));
+        let path = temp_file("corrupt");
+        index.save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let header_len = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        let mut bad_header = bytes.clone();
+        bad_header[16] = b'!';
+        let mut huge_header = bytes.clone();
+        huge_header[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        let header = String::from_utf8(bytes[16..16 + header_len].to_vec()).unwrap();
+        let mut bad_version = bytes.clone();
+        bad_version[16..16 + header_len]
+            .copy_from_slice(header.replacen("\"version\":1", "\"version\":9", 1).as_bytes());
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+
+        for (case, contents) in [
+            ("magic", bad_magic),
+            ("header", bad_header),
+            ("header length", huge_header),
+            ("version", bad_version),
+            ("truncated", truncated),
+            ("empty", Vec::new()),
+        ] {
+            fs::write(&path, contents).unwrap();
+            assert!(
+                matches!(HnswIndex::load(&path), Err(StudyNestError::IndexError(_))),
+                "{case} was accepted"
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! Image search module for finding photographed slides and diagrams by description
+//!
+//! Siglip2 embeds images and texts in one space, so a description can be compared with
+//! photos directly, and a photo can be labelled by the description that fits it best
+//! (zero-shot classification). Image embeddings are kept per file and computed again
+//! only when the file changes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::{DeviceType, get_device};
+use crate::error::{Result, StudyNestError};
+
+use crane_core::models::siglip2::{self, Siglip2};
+use crane_core::models::DType;
+
+/// Extensions of the image files `find_images` picks up
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// Image search configuration
+#[derive(Debug, Clone)]
+pub struct ImageSearchConfig {
+    pub model_path: String,
+    pub device: DeviceType,
+    /// Images run through the model at once
+    pub batch_size: usize,
+    /// Images matching a query with a lower probability (0 to 1) are dropped
+    pub threshold: Option<f32>,
+}
+
+impl Default for ImageSearchConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "checkpoints/siglip2-base-patch16-naflex".to_string(),
+            device: DeviceType::Auto,
+            batch_size: 8,
+            threshold: None,
+        }
+    }
+}
+
+impl ImageSearchConfig {
+    pub fn with_model_path(mut self, path: impl Into<String>) -> Self {
+        self.model_path = path.into();
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceType) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+}
+
+/// Image found by a search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageHit {
+    pub path: String,
+    /// Probability that the query describes the image
+    pub score: f32,
+}
+
+/// Label of a zero-shot classification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageLabel {
+    pub label: String,
+    /// Probability that the label fits the image, independent of the other labels
+    pub probability: f32,
+}
+
+/// Image-text search engine
+pub struct ImageSearchEngine {
+    config: ImageSearchConfig,
+    model: Siglip2,
+    /// Embeddings of image files with the modification time they were computed at
+    cache: HashMap<PathBuf, (Option<SystemTime>, Vec<f32>)>,
+}
+
+impl ImageSearchEngine {
+    /// Create a new image search engine
+    pub fn new(config: ImageSearchConfig) -> Result<Self> {
+        eprintln!("[StudyNest] Initializing image search with model: {}", config.model_path);
+
+        let device = get_device(config.device)?;
+        let model = Siglip2::from_pretrained(&config.model_path, &device, &DType::F32)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+
+        eprintln!(
+            "[StudyNest] Image search initialized ({} dimensions, up to {} patches per image)",
+            model.dimension(),
+            model.processor.max_num_patches
+        );
+        Ok(Self {
+            config,
+            model,
+            cache: HashMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &ImageSearchConfig {
+        &self.config
+    }
+
+    /// Size of the embedding vectors
+    pub fn dimension(&self) -> usize {
+        self.model.dimension()
+    }
+
+    /// Embeddings of image files, in batches of `batch_size`
+    pub fn embed_images<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<Vec<Vec<f32>>> {
+        let paths: Vec<&Path> = paths.iter().map(|p| p.as_ref()).collect();
+        self.update_cache(&paths, false)?;
+        Ok(paths
+            .iter()
+            .map(|path| self.cache[*path].1.clone())
+            .collect())
+    }
+
+    /// Embeddings of descriptions, in the space of the image embeddings
+    pub fn embed_texts(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.model
+            .embed_texts(texts)
+            .and_then(|embeddings| embeddings.to_vec2::<f32>())
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+
+    /// The `top_k` images described best by `query`. Files that cannot be read as images
+    /// are skipped.
+    pub fn search<P: AsRef<Path>>(
+        &mut self,
+        query: &str,
+        paths: &[P],
+        top_k: usize,
+    ) -> Result<Vec<ImageHit>> {
+        let paths: Vec<&Path> = paths.iter().map(|p| p.as_ref()).collect();
+        self.update_cache(&paths, true)?;
+        let found: Vec<(&Path, &Vec<f32>)> = paths
+            .iter()
+            .filter_map(|path| self.cache.get(*path).map(|(_, embedding)| (*path, embedding)))
+            .collect();
+        if found.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<f32> = found.iter().flat_map(|(_, e)| e.iter().copied()).collect();
+        let ranked = candle_core::Tensor::from_vec(rows, (found.len(), self.dimension()), &self.model.device)
+            .and_then(|image_embeds| self.model.search(query, &image_embeds, top_k))
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+
+        Ok(ranked
+            .into_iter()
+            .filter(|(_, score)| self.config.threshold.is_none_or(|t| *score >= t))
+            .map(|(i, score)| ImageHit {
+                path: found[i].0.to_string_lossy().to_string(),
+                score,
+            })
+            .collect())
+    }
+
+    /// Zero-shot classification: how well each label describes the image, most likely
+    /// first
+    pub fn classify<P: AsRef<Path>>(&self, path: P, labels: &[&str]) -> Result<Vec<ImageLabel>> {
+        if labels.is_empty() {
+            return Err(StudyNestError::ConfigError("No labels given".to_string()));
+        }
+        let image = siglip2::load_image(path.as_ref())
+            .map_err(|e| StudyNestError::ImageError(e.to_string()))?;
+        let scored = self
+            .model
+            .classify(&image, labels)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        Ok(scored
+            .into_iter()
+            .map(|(label, probability)| ImageLabel { label, probability })
+            .collect())
+    }
+
+    /// Embed the images that are new or changed since they were last embedded. With
+    /// `skip_unreadable`, files that cannot be decoded are left out instead of failing.
+    fn update_cache(&mut self, paths: &[&Path], skip_unreadable: bool) -> Result<()> {
+        let mut stale = Vec::new();
+        for path in paths {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            match self.cache.get(*path) {
+                Some((cached, _)) if *cached == modified => {}
+                _ => stale.push((*path, modified)),
+            }
+        }
+        stale.dedup_by(|a, b| a.0 == b.0);
+
+        let mut embedded = 0;
+        for batch in stale.chunks(self.config.batch_size) {
+            let mut images = Vec::with_capacity(batch.len());
+            let mut loaded = Vec::with_capacity(batch.len());
+            for (path, modified) in batch {
+                match siglip2::load_image(path) {
+                    Ok(image) => {
+                        images.push(image);
+                        loaded.push((path.to_path_buf(), *modified));
+                    }
+                    Err(e) if skip_unreadable => {
+                        eprintln!("[StudyNest] Skipping image {}", e);
+                        self.cache.remove(*path);
+                    }
+                    Err(e) => return Err(StudyNestError::ImageError(e.to_string())),
+                }
+            }
+            let embeddings = self
+                .model
+                .embed_images(&images)
+                .and_then(|embeddings| embeddings.to_vec2::<f32>())
+                .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+            for ((path, modified), embedding) in loaded.into_iter().zip(embeddings) {
+                self.cache.insert(path, (modified, embedding));
+                embedded += 1;
+            }
+        }
+        if embedded > 0 {
+            eprintln!("[StudyNest] Embedded {} images", embedded);
+        }
+        Ok(())
+    }
+}
+
+/// Image files in `dir` and its subdirectories, sorted by path
+pub fn find_images<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| IMAGE_EXTENSIONS.contains(&x.to_lowercase().as_str()))
+            {
+                images.push(path);
+            }
+        }
+    }
+    images.sort();
+    Ok(images)
+}
@@ -1,14 +1,27 @@
 //! # Crane StudyNest
 //!
-//! A unified library for Chat, OCR, and Speech-to-Text inference.
+//! A unified library for Chat, OCR, Speech-to-Text and Text-to-Speech inference, spoken
+//! conversation combining them, and image search by description.
 //! Supports CPU, CUDA, and Metal (Apple Silicon) backends.
 
 pub mod device;
 pub mod chat;
 pub mod ocr;
 pub mod stt;
+pub mod tts;
+pub mod voice;
+pub mod embed;
+pub mod rerank;
+pub mod image_search;
+pub mod rag;
+pub mod hnsw;
+pub mod bm25;
+pub mod study;
 pub mod error;
 pub mod service;
+pub mod scheduler;
+pub mod session;
+pub mod registry;
 
 pub use device::{DeviceType, get_device};
 pub use error::{StudyNestError, Result};
@@ -16,8 +29,22 @@ pub use error::{StudyNestError, Result};
 /// Prelude module for convenient imports
 pub mod prelude {
     pub use crate::device::{DeviceType, get_device};
-    pub use crate::chat::{ChatEngine, ChatConfig, ChatMessage, Role};
+    pub use crate::chat::{ChatEngine, ChatConfig, ChatMessage, ContextPolicy, Role};
     pub use crate::ocr::{OcrEngine, OcrConfig};
     pub use crate::stt::{SttEngine, SttConfig};
+    pub use crate::tts::{TtsEngine, TtsConfig};
+    pub use crate::voice::{VoiceConfig, VoiceEvent, VoiceSession};
+    pub use crate::embed::{EmbeddingEngine, EmbeddingConfig, cosine_similarity};
+    pub use crate::rerank::{Reranker, RerankerConfig};
+    pub use crate::image_search::{ImageHit, ImageLabel, ImageSearchConfig, ImageSearchEngine};
+    pub use crate::rag::{RagIndex, RagConfig, Citation, RagAnswer, SearchHit, SearchMode};
+    pub use crate::study::flashcards::{CardKind, Flashcard, FlashcardConfig, FlashcardGenerator};
+    pub use crate::study::lecture::{LectureConfig, LectureNotes, NoteTaker, Transcript};
+    pub use crate::study::quiz::{QuestionKind, Quiz, QuizConfig, QuizGenerator, QuizGrader, QuizQuestion};
+    pub use crate::study::review::{Rating, ReviewStore, SchedulerParameters};
+    pub use crate::study::summary::{Summarizer, SummaryConfig, SummaryStyle};
+    pub use crate::hnsw::{HnswIndex, HnswConfig, Metric, SearchFilter};
+    pub use crate::session::{Session, SessionStore};
+    pub use crate::registry::{ModelInfo, ModelKind, ModelRegistry};
     pub use crate::error::{StudyNestError, Result};
 }
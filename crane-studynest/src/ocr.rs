@@ -71,7 +71,7 @@ pub struct OcrEngine {
 impl OcrEngine {
     /// Create a new OCR engine
     pub fn new(config: OcrConfig) -> Result<Self> {
-        println!("[StudyNest] Initializing OCR engine with model: {}", config.model_path);
+        eprintln!("[StudyNest] Initializing OCR engine with model: {}", config.model_path);
         
         let device = get_device(config.device)?;
         
@@ -83,7 +83,7 @@ impl OcrEngine {
             )));
         }
         
-        println!("[StudyNest] OCR engine initialized on {}", config.device);
+        eprintln!("[StudyNest] OCR engine initialized on {}", config.device);
         
         Ok(Self { config, device })
     }
@@ -100,7 +100,7 @@ impl OcrEngine {
             )));
         }
         
-        println!("[StudyNest] Processing image: {}", image_path.display());
+        eprintln!("[StudyNest] Processing image: {}", image_path.display());
         
         // Load and process image based on model type
         let text = match self.config.model_type {
@@ -138,7 +138,7 @@ impl OcrEngine {
         // Note: Qwen3-VL implementation requires the vision model to be loaded
         // This is a placeholder that shows the expected interface
         
-        println!("[StudyNest] Using Qwen3-VL for OCR...");
+        eprintln!("[StudyNest] Using Qwen3-VL for OCR...");
         
         // For now, return a message indicating the model needs to be implemented
         // In a full implementation, this would:
@@ -154,7 +154,7 @@ impl OcrEngine {
 
     /// Process image with Namo2 model
     fn process_with_namo2(&self, image_path: &Path) -> Result<String> {
-        println!("[StudyNest] Using Namo2 for document parsing...");
+        eprintln!("[StudyNest] Using Namo2 for document parsing...");
         
         Err(StudyNestError::OcrError(
             "Namo2 OCR implementation pending.".to_string()
@@ -174,7 +174,7 @@ impl OcrEngine {
             )));
         }
         
-        println!("[StudyNest] Extracting text from PDF: {}", pdf_path.display());
+        eprintln!("[StudyNest] Extracting text from PDF: {}", pdf_path.display());
         
         let text = pdf_extract::extract_text(pdf_path)
             .map_err(|e| StudyNestError::OcrError(format!("PDF extraction failed: {}", e)))?;
@@ -0,0 +1,989 @@
+//! Retrieval-augmented generation over the student's notes
+//!
+//! Documents (plain text, PDFs and images through `OcrEngine`) are split into
+//! overlapping chunks and embedded. Like sessions, documents and chunks are kept in an
+//! append-only JSONL log (`<root>/index.jsonl`) that is replayed on open; the embeddings
+//! live in an HNSW index (`<root>/vectors.hnsw`). Vectors are saved before the log line
+//! that refers to them, so a crash at worst leaves vectors without chunks, which are
+//! dropped on the next open. Questions are answered by retrieving the closest chunks
+//! and giving them to the `ChatEngine` as numbered excerpts, which the model cites as
+//! `[1]`, `[2]`, ...
+//!
+//! Chunk texts are also indexed for BM25 keyword search, which `search` fuses with the
+//! vector ranking so exact terms (formulas, names, course codes) are found as well.
+//! Both can pass a deeper candidate list through a cross-encoder `Reranker`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bm25::{self, Bm25Index};
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::embed::EmbeddingEngine;
+use crate::error::{Result, StudyNestError};
+use crate::hnsw::{HnswConfig, HnswIndex, SearchFilter, VectorMetadata};
+use crate::ocr::OcrEngine;
+use crate::rerank::Reranker;
+use crate::session::{SessionStore, now_millis};
+
+/// Form feed, which PDF text extraction puts between pages
+const PAGE_BREAK: char = '\u{c}';
+/// Length of search result snippets in characters
+const SNIPPET_CHARS: usize = 240;
+/// Fewest candidates given to a reranker; otherwise four per result kept
+const RERANK_CANDIDATES: usize = 20;
+
+/// Retrieval configuration
+#[derive(Debug, Clone)]
+pub struct RagConfig {
+    /// Directory holding the index
+    pub index_dir: String,
+    /// Target chunk length in characters
+    pub chunk_size: usize,
+    /// Characters shared by consecutive chunks
+    pub chunk_overlap: usize,
+    /// Chunks retrieved per question
+    pub top_k: usize,
+    /// Chunks scoring below this cosine similarity are not used
+    pub min_score: f32,
+}
+
+impl Default for RagConfig {
+    fn default() -> Self {
+        Self {
+            index_dir: "notes_index".to_string(),
+            chunk_size: 1000,
+            chunk_overlap: 200,
+            top_k: 4,
+            min_score: 0.0,
+        }
+    }
+}
+
+impl RagConfig {
+    pub fn with_index_dir(mut self, dir: impl Into<String>) -> Self {
+        self.index_dir = dir.into();
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize, chunk_overlap: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self.chunk_overlap = chunk_overlap.min(self.chunk_size / 2);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+}
+
+/// An indexed document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentInfo {
+    pub id: String,
+    pub title: String,
+    /// File the document was ingested from
+    pub source: Option<String>,
+    /// Course the notes belong to, for filtering questions
+    #[serde(default)]
+    pub course: Option<String>,
+    pub pages: usize,
+    pub chunks: usize,
+    /// Embedding model the chunks were embedded with
+    pub embedding_model: String,
+    pub created_at: u64,
+}
+
+/// A piece of a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Id of the chunk's embedding in the vector index
+    #[serde(default)]
+    pub id: u64,
+    pub document_id: String,
+    /// 1-based page number
+    pub page: usize,
+    /// Character offset of the chunk within its page
+    pub offset: usize,
+    pub text: String,
+}
+
+/// A retrieved chunk, numbered as it is cited in the answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    /// Number of the `[n]` marker
+    pub marker: usize,
+    pub document_id: String,
+    pub title: String,
+    pub source: Option<String>,
+    pub page: usize,
+    pub offset: usize,
+    /// Length of the chunk in characters
+    pub length: usize,
+    pub score: f32,
+    pub text: String,
+}
+
+/// How `search` ranks chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Keyword and vector rankings fused by reciprocal rank
+    #[default]
+    Hybrid,
+    /// BM25 only; needs no embedding model
+    Keyword,
+    /// Vector similarity only
+    Semantic,
+}
+
+/// A chunk found by `search`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub document_id: String,
+    pub title: String,
+    pub source: Option<String>,
+    pub page: usize,
+    /// Character offset of the chunk within its page
+    pub offset: usize,
+    /// Length of the chunk in characters
+    pub length: usize,
+    /// Reranker relevance when reranked, otherwise the fused score in hybrid mode or
+    /// the BM25 score or cosine similarity
+    pub score: f32,
+    /// 1-based position in the keyword ranking
+    pub keyword_rank: Option<usize>,
+    /// 1-based position in the vector ranking
+    pub semantic_rank: Option<usize>,
+    /// Part of the chunk around the query terms
+    pub snippet: String,
+    /// Character ranges of query terms in `snippet`
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// Answer generated from the notes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RagAnswer {
+    pub content: String,
+    pub finish_reason: FinishReason,
+    /// Retrieved chunks the answer cites
+    pub citations: Vec<Citation>,
+    /// All chunks given to the model
+    pub sources: Vec<Citation>,
+}
+
+/// A chunk as written to the index file. Indexes from before the vector index stored
+/// each embedding with its chunk; they are moved into the vector index on open.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkRecord {
+    #[serde(flatten)]
+    chunk: Chunk,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    embedding: Vec<f32>,
+}
+
+/// One line of the index file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum IndexEvent {
+    Document { info: DocumentInfo, chunks: Vec<ChunkRecord> },
+    Removed { id: String },
+}
+
+/// Persistent index of embedded document chunks
+pub struct RagIndex {
+    config: RagConfig,
+    path: PathBuf,
+    vectors_path: PathBuf,
+    documents: Vec<DocumentInfo>,
+    /// Chunks by id; ids grow in reading order within a document
+    chunks: BTreeMap<u64, Chunk>,
+    vectors: Option<HnswIndex>,
+    keywords: Bm25Index,
+    next_chunk_id: u64,
+}
+
+impl RagIndex {
+    /// Open (and create if needed) the index in `config.index_dir`
+    pub fn open(config: RagConfig) -> Result<Self> {
+        fs::create_dir_all(&config.index_dir)?;
+        let dir = Path::new(&config.index_dir);
+        let mut index = Self {
+            path: dir.join("index.jsonl"),
+            vectors_path: dir.join("vectors.hnsw"),
+            config,
+            documents: Vec::new(),
+            chunks: BTreeMap::new(),
+            vectors: None,
+            keywords: Bm25Index::new(),
+            next_chunk_id: 1,
+        };
+        let legacy = if index.path.exists() { index.replay()? } else { Vec::new() };
+        if index.vectors_path.exists() {
+            match HnswIndex::load(&index.vectors_path) {
+                Ok(vectors) => index.vectors = Some(vectors),
+                Err(e) => eprintln!("[StudyNest] Ignoring unreadable vector index: {}", e),
+            }
+        }
+        index.reconcile(legacy)?;
+        Ok(index)
+    }
+
+    /// Replay the log, returning embeddings stored in it by older versions
+    fn replay(&mut self) -> Result<Vec<(u64, Vec<f32>)>> {
+        let mut legacy = Vec::new();
+        let reader = BufReader::new(File::open(&self.path)?);
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A torn trailing line from an interrupted write is skipped
+            match serde_json::from_str::<IndexEvent>(&line) {
+                Ok(IndexEvent::Document { info, chunks }) => {
+                    self.documents.push(info);
+                    for ChunkRecord { mut chunk, embedding } in chunks {
+                        if chunk.id == 0 {
+                            chunk.id = self.next_chunk_id;
+                        }
+                        self.next_chunk_id = self.next_chunk_id.max(chunk.id + 1);
+                        if !embedding.is_empty() {
+                            legacy.push((chunk.id, embedding));
+                        }
+                        self.keywords.insert(chunk.id, &chunk.text);
+                        self.chunks.insert(chunk.id, chunk);
+                    }
+                }
+                Ok(IndexEvent::Removed { id }) => self.forget(&id),
+                Err(_) => continue,
+            }
+        }
+        Ok(legacy)
+    }
+
+    /// Bring the vector index in line with the log: drop vectors of chunks that were
+    /// never logged or were removed, and move embeddings out of old logs
+    fn reconcile(&mut self, legacy: Vec<(u64, Vec<f32>)>) -> Result<()> {
+        let mut changed = false;
+        if let Some(vectors) = &mut self.vectors {
+            let orphans: Vec<u64> = vectors.ids().filter(|id| !self.chunks.contains_key(id)).collect();
+            for id in &orphans {
+                vectors.remove(*id);
+            }
+            changed = !orphans.is_empty();
+        }
+
+        let migrate = !legacy.is_empty();
+        for (id, embedding) in legacy {
+            let Some(chunk) = self.chunks.get(&id) else { continue };
+            let vectors = self
+                .vectors
+                .get_or_insert_with(|| HnswIndex::new(embedding.len(), HnswConfig::default()));
+            if !vectors.contains(id) {
+                let document = self.documents.iter().find(|d| d.id == chunk.document_id);
+                vectors.insert(id, &embedding, vector_metadata(&chunk.document_id, document))?;
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_vectors()?;
+        }
+        if migrate {
+            eprintln!("[StudyNest] Moved note embeddings into the vector index");
+            self.compact_log()?;
+        }
+
+        let missing = self
+            .chunks
+            .keys()
+            .filter(|&&id| !self.vectors.as_ref().is_some_and(|v| v.contains(id)))
+            .count();
+        if missing > 0 {
+            eprintln!(
+                "[StudyNest] {} note chunks have no vectors and cannot be retrieved; ingest their documents again",
+                missing
+            );
+        }
+        Ok(())
+    }
+
+    fn save_vectors(&mut self) -> Result<()> {
+        if let Some(vectors) = &mut self.vectors {
+            if vectors.needs_compaction() {
+                vectors.compact();
+            }
+            vectors.save(&self.vectors_path)?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, event: &IndexEvent) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn forget(&mut self, id: &str) {
+        self.documents.retain(|d| d.id != id);
+        let (vectors, keywords) = (&mut self.vectors, &mut self.keywords);
+        self.chunks.retain(|&chunk_id, c| {
+            if c.document_id != id {
+                return true;
+            }
+            if let Some(vectors) = vectors {
+                vectors.remove(chunk_id);
+            }
+            keywords.remove(chunk_id);
+            false
+        });
+    }
+
+    pub fn config(&self) -> &RagConfig {
+        &self.config
+    }
+
+    pub fn documents(&self) -> &[DocumentInfo] {
+        &self.documents
+    }
+
+    pub fn document(&self, id: &str) -> Option<&DocumentInfo> {
+        self.documents.iter().find(|d| d.id == id)
+    }
+
+    /// Chunks of a document, in reading order
+    pub fn chunks<'a>(&'a self, document_id: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks.values().filter(move |c| c.document_id == document_id)
+    }
+
+    /// Text of each page of a document as `(page, text)`, pieced together from its
+    /// overlapping chunks. Whitespace between chunks is collapsed to a single space.
+    pub fn pages(&self, document_id: &str) -> Vec<(usize, String)> {
+        let mut pages: Vec<(usize, String)> = Vec::new();
+        // Character offset up to which the current page has been rebuilt
+        let mut end: usize = 0;
+        for chunk in self.chunks(document_id) {
+            if pages.last().is_none_or(|(page, _)| *page != chunk.page) {
+                pages.push((chunk.page, String::new()));
+                end = 0;
+            }
+            let (_, text) = pages.last_mut().expect("page pushed above");
+            let skip = end.saturating_sub(chunk.offset);
+            if chunk.offset > end && !text.is_empty() {
+                text.push(' ');
+            }
+            text.extend(chunk.text.chars().skip(skip));
+            end = end.max(chunk.offset + chunk.text.chars().count());
+        }
+        pages
+    }
+
+    /// Embedding model of the indexed chunks; queries must use the same model
+    pub fn embedding_model(&self) -> Option<&str> {
+        self.documents.first().map(|d| d.embedding_model.as_str())
+    }
+
+    /// Chunk, embed and store a document given as page texts
+    pub fn add_pages(
+        &mut self,
+        embedder: &mut EmbeddingEngine,
+        title: &str,
+        pages: &[String],
+        source: Option<&str>,
+        course: Option<&str>,
+    ) -> Result<DocumentInfo> {
+        let model = embedding_model_name(embedder);
+        if let Some(indexed) = self.embedding_model().filter(|m| *m != model) {
+            return Err(StudyNestError::ConfigError(format!(
+                "The index was built with {}, not {}",
+                indexed, model
+            )));
+        }
+
+        let dimension = embedder.dimension();
+        if let Some(vectors) = self.vectors.as_ref().filter(|v| !v.is_empty() && v.dim() != dimension) {
+            return Err(StudyNestError::IndexError(format!(
+                "The vector index holds {}-dimensional vectors, {} produces {}",
+                vectors.dim(),
+                model,
+                dimension
+            )));
+        }
+
+        let id = SessionStore::new_id();
+        let mut chunks = Vec::new();
+        for (page, text) in pages.iter().enumerate() {
+            for (offset, piece) in chunk_text(text, self.config.chunk_size, self.config.chunk_overlap) {
+                chunks.push(Chunk {
+                    id: self.next_chunk_id + chunks.len() as u64,
+                    document_id: id.clone(),
+                    page: page + 1,
+                    offset,
+                    text: piece,
+                });
+            }
+        }
+        if chunks.is_empty() {
+            return Err(StudyNestError::ConfigError(format!("{} contains no text", title)));
+        }
+
+        eprintln!("[StudyNest] Indexing {} ({} chunks)", title, chunks.len());
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        let embeddings = embedder.embed(&texts)?;
+
+        let info = DocumentInfo {
+            id,
+            title: title.to_string(),
+            source: source.map(str::to_string),
+            course: course.map(str::to_string),
+            pages: pages.len(),
+            chunks: chunks.len(),
+            embedding_model: model,
+            created_at: now_millis(),
+        };
+        if self.vectors.as_ref().is_none_or(|v| v.dim() != dimension) {
+            self.vectors = Some(HnswIndex::new(dimension, HnswConfig::default()));
+        }
+        let vectors = self.vectors.as_mut().expect("vector index created above");
+        for (chunk, embedding) in chunks.iter().zip(&embeddings) {
+            vectors.insert(chunk.id, embedding, vector_metadata(&info.id, Some(&info)))?;
+        }
+
+        // Vectors first: if the log line is lost, the next open drops them again
+        let event = IndexEvent::Document {
+            info: info.clone(),
+            chunks: chunks
+                .iter()
+                .map(|chunk| ChunkRecord {
+                    chunk: chunk.clone(),
+                    embedding: Vec::new(),
+                })
+                .collect(),
+        };
+        if let Err(e) = self.save_vectors().and_then(|_| self.append(&event)) {
+            if let Some(vectors) = &mut self.vectors {
+                for chunk in &chunks {
+                    vectors.remove(chunk.id);
+                }
+            }
+            return Err(e);
+        }
+        self.next_chunk_id += chunks.len() as u64;
+        self.documents.push(info.clone());
+        for chunk in &chunks {
+            self.keywords.insert(chunk.id, &chunk.text);
+        }
+        self.chunks.extend(chunks.into_iter().map(|c| (c.id, c)));
+        Ok(info)
+    }
+
+    /// Index a plain text document; form feeds separate pages
+    pub fn add_text(
+        &mut self,
+        embedder: &mut EmbeddingEngine,
+        title: &str,
+        text: &str,
+        source: Option<&str>,
+        course: Option<&str>,
+    ) -> Result<DocumentInfo> {
+        let pages: Vec<String> = text.split(PAGE_BREAK).map(str::to_string).collect();
+        self.add_pages(embedder, title, &pages, source, course)
+    }
+
+    /// Index a text, Markdown, PDF or image file, titled after the file name unless
+    /// `title` is given. PDFs and images are read through `ocr`; an image is one page.
+    pub fn add_file(
+        &mut self,
+        embedder: &mut EmbeddingEngine,
+        path: impl AsRef<Path>,
+        title: Option<&str>,
+        course: Option<&str>,
+        ocr: Option<&OcrEngine>,
+    ) -> Result<DocumentInfo> {
+        let path = path.as_ref();
+        let title = match title {
+            Some(title) => title.to_string(),
+            None => path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        };
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        let ocr = || {
+            ocr.ok_or_else(|| {
+                StudyNestError::ConfigError(format!("An OCR engine is needed to read {}", path.display()))
+            })
+        };
+
+        let text = match extension.as_str() {
+            "pdf" => ocr()?.extract_from_pdf(path)?.text,
+            "png" | "jpg" | "jpeg" | "webp" | "bmp" | "tif" | "tiff" => {
+                ocr()?.extract_from_image(path)?.text
+            }
+            _ => fs::read_to_string(path)?,
+        };
+        let source = path.to_string_lossy();
+        self.add_text(embedder, &title, &text, Some(&source), course)
+    }
+
+    /// Remove a document and its chunks
+    pub fn remove(&mut self, id: &str) -> Result<()> {
+        if self.document(id).is_none() {
+            return Err(StudyNestError::ConfigError(format!("Unknown document: {}", id)));
+        }
+        self.append(&IndexEvent::Removed { id: id.to_string() })?;
+        self.forget(id);
+        self.save_vectors()
+    }
+
+    /// Rewrite the index without removed documents. The new files replace the old ones
+    /// atomically, so a crash leaves either of them intact.
+    pub fn compact(&mut self) -> Result<()> {
+        if let Some(vectors) = &mut self.vectors {
+            vectors.compact();
+        }
+        self.save_vectors()?;
+        self.compact_log()
+    }
+
+    fn compact_log(&self) -> Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for info in &self.documents {
+                let event = IndexEvent::Document {
+                    info: info.clone(),
+                    chunks: self
+                        .chunks(&info.id)
+                        .map(|chunk| ChunkRecord {
+                            chunk: chunk.clone(),
+                            embedding: Vec::new(),
+                        })
+                        .collect(),
+                };
+                let mut line = serde_json::to_string(&event)?;
+                line.push('\n');
+                file.write_all(line.as_bytes())?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Closest chunks to a question among those matching `filter` (documents, course,
+    /// ingestion date). With a reranker, more candidates are retrieved and the best
+    /// `top_k` by reranker relevance are kept.
+    pub fn retrieve(
+        &self,
+        embedder: &mut EmbeddingEngine,
+        reranker: Option<&mut Reranker>,
+        question: &str,
+        top_k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<Citation>> {
+        let depth = if reranker.is_some() { rerank_depth(top_k) } else { top_k };
+        let candidates = self.nearest(embedder, question, depth, filter)?;
+        Ok(self
+            .rerank(reranker, question, candidates, top_k)?
+            .into_iter()
+            .filter_map(|(id, score)| self.chunks.get(&id).map(|chunk| (score, chunk)))
+            .enumerate()
+            .map(|(i, (score, chunk))| {
+                let document = self.document(&chunk.document_id);
+                Citation {
+                    marker: i + 1,
+                    document_id: chunk.document_id.clone(),
+                    title: document.map(|d| d.title.clone()).unwrap_or_default(),
+                    source: document.and_then(|d| d.source.clone()),
+                    page: chunk.page,
+                    offset: chunk.offset,
+                    length: chunk.text.chars().count(),
+                    score,
+                    text: chunk.text.clone(),
+                }
+            })
+            .collect())
+    }
+
+    /// Ids and cosine similarities of the chunks closest to `text`
+    fn nearest(
+        &self,
+        embedder: &mut EmbeddingEngine,
+        text: &str,
+        top_k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<(u64, f32)>> {
+        let Some(vectors) = self.vectors.as_ref().filter(|v| !v.is_empty()) else {
+            return Ok(Vec::new());
+        };
+        if top_k == 0 {
+            return Ok(Vec::new());
+        }
+        let model = embedding_model_name(embedder);
+        if let Some(indexed) = self.embedding_model().filter(|m| *m != model) {
+            return Err(StudyNestError::ConfigError(format!(
+                "The index was built with {}, not {}",
+                indexed, model
+            )));
+        }
+
+        let query = embedder.embed_query(text)?;
+        if query.len() != vectors.dim() {
+            return Err(StudyNestError::IndexError(format!(
+                "The vector index holds {}-dimensional vectors, {} produces {}",
+                vectors.dim(),
+                model,
+                query.len()
+            )));
+        }
+        Ok(vectors
+            .search(&query, top_k, Some(filter))
+            .into_iter()
+            .filter(|hit| hit.score >= self.config.min_score)
+            .map(|hit| (hit.id, hit.score))
+            .collect())
+    }
+
+    /// Replace retrieval scores by reranker relevance and keep the best `top_k`
+    fn rerank(
+        &self,
+        reranker: Option<&mut Reranker>,
+        query: &str,
+        mut candidates: Vec<(u64, f32)>,
+        top_k: usize,
+    ) -> Result<Vec<(u64, f32)>> {
+        let Some(reranker) = reranker else {
+            candidates.truncate(top_k);
+            return Ok(candidates);
+        };
+        let candidates: Vec<(u64, &str)> = candidates
+            .into_iter()
+            .filter_map(|(id, _)| self.chunks.get(&id).map(|c| (id, c.text.as_str())))
+            .collect();
+        Ok(reranker
+            .rerank(query, candidates, |(_, text)| text, top_k)?
+            .into_iter()
+            .map(|((id, _), score)| (id, score))
+            .collect())
+    }
+
+    /// Rank chunks for a search query with BM25, vector similarity, or both fused by
+    /// reciprocal rank, optionally reranked. The embedder is only used by the semantic
+    /// and hybrid modes; hybrid search without one falls back to keywords alone.
+    pub fn search(
+        &self,
+        embedder: Option<&mut EmbeddingEngine>,
+        reranker: Option<&mut Reranker>,
+        query: &str,
+        top_k: usize,
+        filter: &SearchFilter,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchHit>> {
+        if top_k == 0 || self.chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Fusion works on deeper lists than the results shown
+        let depth = (top_k * 4).max(50);
+        let mode = match (mode, &embedder) {
+            (SearchMode::Hybrid, None) => SearchMode::Keyword,
+            _ => mode,
+        };
+
+        let keyword = match mode {
+            SearchMode::Semantic => Vec::new(),
+            _ => {
+                let allowed: HashSet<&str> = self
+                    .documents
+                    .iter()
+                    .filter(|d| filter.matches(&vector_metadata(&d.id, Some(d))))
+                    .map(|d| d.id.as_str())
+                    .collect();
+                self.keywords.search(query, depth, |id| {
+                    self.chunks.get(&id).is_some_and(|c| allowed.contains(c.document_id.as_str()))
+                })
+            }
+        };
+        let semantic = match (mode, embedder) {
+            (SearchMode::Keyword, _) => Vec::new(),
+            (_, Some(embedder)) => self.nearest(embedder, query, depth, filter)?,
+            (_, None) => {
+                return Err(StudyNestError::ConfigError(format!(
+                    "{:?} search needs an embedding model",
+                    mode
+                )));
+            }
+        };
+
+        let mut ranked = match mode {
+            SearchMode::Keyword => keyword.clone(),
+            SearchMode::Semantic => semantic.clone(),
+            SearchMode::Hybrid => bm25::reciprocal_rank_fusion(&[
+                keyword.iter().map(|(id, _)| *id).collect(),
+                semantic.iter().map(|(id, _)| *id).collect(),
+            ]),
+        };
+        if reranker.is_some() {
+            ranked.truncate(rerank_depth(top_k));
+        }
+        let ranked = self.rerank(reranker, query, ranked, top_k)?;
+        let rank = |list: &[(u64, f32)], id: u64| list.iter().position(|(i, _)| *i == id).map(|p| p + 1);
+        let terms = bm25::query_terms(query);
+
+        Ok(ranked
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let chunk = self.chunks.get(&id)?;
+                let document = self.document(&chunk.document_id);
+                let (snippet, highlights) = bm25::snippet(&chunk.text, &terms, SNIPPET_CHARS);
+                Some(SearchHit {
+                    document_id: chunk.document_id.clone(),
+                    title: document.map(|d| d.title.clone()).unwrap_or_default(),
+                    source: document.and_then(|d| d.source.clone()),
+                    page: chunk.page,
+                    offset: chunk.offset,
+                    length: chunk.text.chars().count(),
+                    score,
+                    keyword_rank: rank(&keyword, id),
+                    semantic_rank: rank(&semantic, id),
+                    snippet,
+                    highlights,
+                })
+            })
+            .collect())
+    }
+
+    /// Answer a question from the notes. The excerpts are only part of this turn; the
+    /// chat history keeps the plain question.
+    pub fn answer(
+        &self,
+        embedder: &mut EmbeddingEngine,
+        reranker: Option<&mut Reranker>,
+        chat: &mut ChatEngine,
+        question: &str,
+        filter: &SearchFilter,
+        control: &GenerationControl,
+    ) -> Result<RagAnswer> {
+        let sources = self.retrieve(embedder, reranker, question, self.config.top_k, filter)?;
+        let reply = chat.chat_with_context(question, &build_context(&sources), control)?;
+        Ok(RagAnswer {
+            citations: cited(&reply.content, &sources),
+            content: reply.content,
+            finish_reason: reply.finish_reason,
+            sources,
+        })
+    }
+}
+
+/// Candidates retrieved for a reranker to keep `top_k`
+fn rerank_depth(top_k: usize) -> usize {
+    (top_k * 4).max(RERANK_CANDIDATES)
+}
+
+/// Filterable attributes of a chunk's vector
+fn vector_metadata(document_id: &str, document: Option<&DocumentInfo>) -> VectorMetadata {
+    VectorMetadata {
+        document: Some(document_id.to_string()),
+        course: document.and_then(|d| d.course.clone()),
+        timestamp: document.map(|d| d.created_at),
+    }
+}
+
+/// Name the index records for an embedding model
+fn embedding_model_name(embedder: &EmbeddingEngine) -> String {
+    let path = Path::new(&embedder.config().model_path);
+    let name = if path.is_file() { path.file_stem() } else { path.file_name() };
+    name.map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| embedder.config().model_path.clone())
+}
+
+/// Numbered excerpts placed before the question
+pub fn build_context(sources: &[Citation]) -> String {
+    if sources.is_empty() {
+        return "No excerpts from the student's notes match this question. Say so if you \
+                cannot answer from general knowledge."
+            .to_string();
+    }
+    let mut context = String::from(
+        "Answer using the following excerpts from the student's notes. Cite the excerpts \
+         you use with their numbers in square brackets, e.g. [1]. If the excerpts do not \
+         contain the answer, say so.\n",
+    );
+    for source in sources {
+        context.push_str(&format!(
+            "\n[{}] {}, page {}:\n{}\n",
+            source.marker, source.title, source.page, source.text
+        ));
+    }
+    context.push_str("\nQuestion:");
+    context
+}
+
+/// Sources referenced by `[n]` markers (also `[1, 2]`) in an answer, in marker order
+pub fn cited(answer: &str, sources: &[Citation]) -> Vec<Citation> {
+    let mut markers = Vec::new();
+    let mut rest = answer;
+    while let Some(open) = rest.find('[') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find(']') else { break };
+        let inside = &rest[..close];
+        if inside.chars().all(|c| c.is_ascii_digit() || c == ',' || c.is_whitespace()) {
+            markers.extend(inside.split(',').filter_map(|n| n.trim().parse::<usize>().ok()));
+        }
+    }
+    sources
+        .iter()
+        .filter(|s| markers.contains(&s.marker))
+        .cloned()
+        .collect()
+}
+
+/// Split text into chunks of about `chunk_size` characters that overlap by about
+/// `overlap` characters, preferring to cut after a sentence or at a space. Returns
+/// each chunk with its character offset.
+pub fn chunk_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<(usize, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let chunk_size = chunk_size.max(1);
+    let overlap = overlap.min(chunk_size / 2);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    loop {
+        while start < chars.len() && chars[start].is_whitespace() {
+            start += 1;
+        }
+        if start >= chars.len() {
+            break;
+        }
+
+        let mut end = (start + chunk_size).min(chars.len());
+        if end < chars.len() {
+            // At least one character past `start`, so `chars[i - 1]` exists and the chunk is not empty
+            let earliest = start + (chunk_size / 2).max(1);
+            let sentence_end = (earliest..end).rev().find(|&i| {
+                matches!(chars[i - 1], '.' | '!' | '?' | '\n' | '。' | '！' | '？')
+                    && (chars[i].is_whitespace() || !chars[i].is_ascii())
+            });
+            let space = || (earliest..end).rev().find(|&i| chars[i].is_whitespace());
+            if let Some(cut) = sentence_end.or_else(space) {
+                end = cut;
+            }
+        }
+
+        let piece: String = chars[start..end].iter().collect();
+        chunks.push((start, piece.trim_end().to_string()));
+        if end >= chars.len() {
+            break;
+        }
+
+        // Step back for the overlap, then forward to the start of a word
+        let mut next = end.saturating_sub(overlap).max(start + 1);
+        if next < end && !chars[next - 1].is_whitespace() {
+            while next < end && !chars[next].is_whitespace() {
+                next += 1;
+            }
+        }
+        start = next;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index whose log holds the given documents, written directly since adding them
+    /// through `add_pages` needs an embedding model
+    fn keyword_only_index(name: &str, documents: &[(&str, Option<&str>, &[&str])]) -> RagIndex {
+        let dir = std::env::temp_dir().join(format!("studynest-rag-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut log = String::new();
+        let mut next_id = 1;
+        for (title, course, texts) in documents {
+            let info = DocumentInfo {
+                id: title.to_lowercase(),
+                title: title.to_string(),
+                source: None,
+                course: course.map(str::to_string),
+                pages: 1,
+                chunks: texts.len(),
+                embedding_model: "minilm".to_string(),
+                created_at: 0,
+            };
+            let chunks = texts
+                .iter()
+                .map(|text| {
+                    next_id += 1;
+                    ChunkRecord {
+                        chunk: Chunk {
+                            id: next_id - 1,
+                            document_id: info.id.clone(),
+                            page: 1,
+                            offset: 0,
+                            text: text.to_string(),
+                        },
+                        embedding: Vec::new(),
+                    }
+                })
+                .collect();
+            log.push_str(&serde_json::to_string(&IndexEvent::Document { info, chunks }).unwrap());
+            log.push('\n');
+        }
+        fs::write(dir.join("index.jsonl"), log).unwrap();
+        RagIndex::open(RagConfig::default().with_index_dir(dir.to_string_lossy())).unwrap()
+    }
+
+    #[test]
+    fn hybrid_search_without_an_embedder_returns_keyword_results() {
+        let index = keyword_only_index(
+            "hybrid",
+            &[
+                (
+                    "Biology",
+                    Some("BIO101"),
+                    &["Osmosis moves water across a membrane.", "Cells divide by mitosis."],
+                ),
+                ("Chemistry", Some("CHEM110"), &["Water is a polar molecule."]),
+            ],
+        );
+        let filter = SearchFilter::default();
+
+        let hits = index.search(None, None, "water membrane", 5, &filter, SearchMode::Hybrid).unwrap();
+        let keyword = index.search(None, None, "water membrane", 5, &filter, SearchMode::Keyword).unwrap();
+        let titles: Vec<&str> = hits.iter().map(|h| h.title.as_str()).collect();
+        assert_eq!(titles, ["Biology", "Chemistry"]);
+        let scores = |hits: &[SearchHit]| hits.iter().map(|h| h.score).collect::<Vec<_>>();
+        assert_eq!(scores(&hits), scores(&keyword));
+        assert!(hits.iter().all(|h| h.semantic_rank.is_none()));
+        assert_eq!(hits[0].keyword_rank, Some(1));
+        assert_eq!(hits[0].snippet, "Osmosis moves water across a membrane.");
+        assert_eq!(hits[0].highlights, [(14, 19), (29, 37)]);
+
+        let course = SearchFilter {
+            course: Some("CHEM110".to_string()),
+            ..SearchFilter::default()
+        };
+        let hits = index.search(None, None, "water", 5, &course, SearchMode::Hybrid).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].title, "Chemistry");
+
+        assert!(index.search(None, None, "water", 5, &filter, SearchMode::Semantic).is_err());
+        fs::remove_dir_all(&index.config().index_dir).unwrap();
+    }
+}
@@ -0,0 +1,486 @@
+//! Model registry
+//!
+//! Scans a checkpoints directory for installed models, reads their `config.json`
+//! and tokenizer files, and classifies them so the right engine and loader can be
+//! picked without the caller naming the architecture. Quantized `.gguf` files are
+//! listed as models of their own, described by their GGUF metadata; a directory holding
+//! a single `.gguf` file is listed once, as the directory.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crane_core::utils::gguf;
+
+use crate::chat::ChatModelType;
+use crate::error::{Result, StudyNestError};
+use crate::stt::SttModelType;
+
+/// Files that make up a tokenizer, in order of preference
+const TOKENIZER_FILES: &[&str] = &[
+    "tokenizer.json",
+    "tokenizer_config.json",
+    "vocab.json",
+    "merges.txt",
+    "tokenizer.model",
+];
+
+/// What a model is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelKind {
+    Chat,
+    Vision,
+    Asr,
+    Tts,
+    Embedding,
+    Reranker,
+    Unknown,
+}
+
+/// Metadata of an installed model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Directory name of the model
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: ModelKind,
+    /// First entry of `architectures` in `config.json`
+    pub architecture: Option<String>,
+    pub model_type: Option<String>,
+    /// Loader used for chat models
+    pub chat_model_type: Option<ChatModelType>,
+    /// Variant of speech-to-text models
+    pub stt_model_type: Option<SttModelType>,
+    /// Read from the safetensors headers, or estimated from `config.json`
+    pub parameters: Option<u64>,
+    pub context_length: Option<usize>,
+    pub hidden_size: Option<usize>,
+    pub num_layers: Option<usize>,
+    pub vocab_size: Option<usize>,
+    pub torch_dtype: Option<String>,
+    /// Quantization of GGUF files, e.g. `Q4_K_M`
+    pub quantization: Option<String>,
+    /// Tokenizer files found next to the weights
+    pub tokenizer_files: Vec<String>,
+    /// Total size of the model directory in bytes
+    pub size_bytes: u64,
+}
+
+impl ModelInfo {
+    /// Read the metadata of a model directory or GGUF file
+    pub fn inspect(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if gguf::is_gguf(path) {
+            return Self::inspect_gguf(path);
+        }
+        if !path.is_dir() {
+            return Err(StudyNestError::ModelNotFound(path.display().to_string()));
+        }
+
+        let config = read_config(path);
+        let architecture = config
+            .as_ref()
+            .and_then(|c| c["architectures"].as_array())
+            .and_then(|a| a.first())
+            .and_then(|a| a.as_str())
+            .map(str::to_string);
+        let model_type = config
+            .as_ref()
+            .and_then(|c| c["model_type"].as_str())
+            .map(str::to_string);
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let kind = classify(&name, architecture.as_deref(), model_type.as_deref(), path);
+
+        // A quantized checkpoint directory is described by its GGUF file
+        if let Some(file) = gguf::single_gguf_in_dir(path) {
+            let info = Self::inspect_gguf(&file)?;
+            return Ok(Self {
+                name,
+                path: path.to_path_buf(),
+                kind: if config.is_some() { kind } else { info.kind },
+                architecture,
+                size_bytes: dir_size(path),
+                ..info
+            });
+        }
+
+        let chat_model_type = match kind {
+            ModelKind::Chat => {
+                ChatModelType::from_architecture(architecture.as_deref(), model_type.as_deref())
+            }
+            _ => None,
+        };
+
+        let text_usize = |key: &str| {
+            config
+                .as_ref()
+                .and_then(|c| text_field(c, key))
+                .and_then(|v| v.as_u64())
+                .map(|n| n as usize)
+        };
+        let stt_model_type = match kind {
+            ModelKind::Asr => SttModelType::from_config(model_type.as_deref(), text_usize("hidden_size")),
+            _ => None,
+        };
+        let parameters = count_safetensors_parameters(path)
+            .or_else(|| config.as_ref().and_then(estimate_parameters));
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            kind,
+            architecture,
+            model_type,
+            chat_model_type,
+            stt_model_type,
+            parameters,
+            context_length: text_usize("max_position_embeddings"),
+            hidden_size: text_usize("hidden_size"),
+            num_layers: text_usize("num_hidden_layers"),
+            vocab_size: text_usize("vocab_size"),
+            torch_dtype: config
+                .as_ref()
+                .and_then(|c| text_field(c, "torch_dtype"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            quantization: None,
+            tokenizer_files: TOKENIZER_FILES
+                .iter()
+                .filter(|f| path.join(f).is_file())
+                .map(|f| f.to_string())
+                .collect(),
+            size_bytes: dir_size(path),
+        })
+    }
+
+    fn inspect_gguf(path: &Path) -> Result<Self> {
+        let content = gguf::read_content(path)?;
+        let model_type = gguf::architecture(&content);
+        let chat_model_type = ChatModelType::from_architecture(None, model_type.as_deref());
+        let arch_usize = |key: &str| gguf::arch_u32(&content, key).map(|n| n as usize);
+        let vocab_size = content
+            .metadata
+            .get("tokenizer.ggml.tokens")
+            .and_then(|t| t.to_vec().ok())
+            .map(|t| t.len());
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        Ok(Self {
+            name: path
+                .file_stem()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path: path.to_path_buf(),
+            kind: ModelKind::Chat,
+            architecture: None,
+            model_type,
+            chat_model_type,
+            stt_model_type: None,
+            parameters: Some(gguf::parameter_count(&content)),
+            context_length: arch_usize("context_length"),
+            hidden_size: arch_usize("embedding_length"),
+            num_layers: arch_usize("block_count"),
+            vocab_size,
+            torch_dtype: None,
+            quantization: gguf::file_type_name(&content).map(str::to_string),
+            tokenizer_files: TOKENIZER_FILES
+                .iter()
+                .filter(|f| dir.join(f).is_file())
+                .map(|f| f.to_string())
+                .collect(),
+            size_bytes: fs::metadata(path)?.len(),
+        })
+    }
+}
+
+/// Registry of the models installed under a checkpoints directory
+pub struct ModelRegistry {
+    root: PathBuf,
+}
+
+impl ModelRegistry {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Scan the checkpoints directory. Models may be nested one level deep
+    /// (e.g. `checkpoints/Qwen/Qwen3-0.6B` or `checkpoints/Qwen3-0.6B-GGUF/*.gguf`).
+    pub fn scan(&self) -> Result<Vec<ModelInfo>> {
+        let mut models = Vec::new();
+        if !self.root.is_dir() {
+            return Ok(models);
+        }
+        Self::scan_dir(&self.root, 2, &mut models)?;
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(models)
+    }
+
+    fn scan_dir(dir: &Path, depth: usize, models: &mut Vec<ModelInfo>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_dir = path.is_dir();
+            if is_dir && is_sentence_transformers_module(&path) {
+                continue;
+            }
+            if gguf::is_gguf(&path) || (is_dir && is_model_dir(&path)) {
+                match ModelInfo::inspect(&path) {
+                    Ok(info) => models.push(info),
+                    Err(e) => eprintln!("[StudyNest] Skipping model {}: {}", path.display(), e),
+                }
+            }
+            // The file of a single-GGUF model directory is already listed as the directory
+            if is_dir && depth > 0 && gguf::single_gguf_in_dir(&path).is_none() {
+                Self::scan_dir(&path, depth - 1, models)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Installed models of one kind
+    pub fn list(&self, kind: ModelKind) -> Result<Vec<ModelInfo>> {
+        Ok(self.scan()?.into_iter().filter(|m| m.kind == kind).collect())
+    }
+
+    /// Find a model by name (directory name or GGUF file stem) or path
+    pub fn find(&self, name_or_path: &str) -> Result<ModelInfo> {
+        let path = Path::new(name_or_path);
+        if gguf::is_gguf(path) || is_model_dir(path) {
+            return ModelInfo::inspect(path);
+        }
+        self.scan()?
+            .into_iter()
+            .find(|m| m.name == name_or_path)
+            .ok_or_else(|| StudyNestError::ModelNotFound(name_or_path.to_string()))
+    }
+}
+
+fn read_config(path: &Path) -> Option<Value> {
+    let data = fs::read(path.join("config.json")).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Look a field up at the top level, then in `text_config` (vision-language configs)
+fn text_field<'a>(config: &'a Value, key: &str) -> Option<&'a Value> {
+    config
+        .get(key)
+        .filter(|v| !v.is_null())
+        .or_else(|| config.get("text_config").and_then(|t| t.get(key)))
+        .filter(|v| !v.is_null())
+}
+
+fn is_model_dir(path: &Path) -> bool {
+    path.join("config.json").is_file()
+        || gguf::single_gguf_in_dir(path).is_some()
+        || fs::read_dir(path)
+            .map(|entries| {
+                entries.flatten().any(|e| {
+                    matches!(
+                        e.path().extension().and_then(|x| x.to_str()),
+                        Some("safetensors" | "onnx")
+                    )
+                })
+            })
+            .unwrap_or(false)
+}
+
+/// Pooling/Dense/Normalize stages of a sentence-transformers model (`1_Pooling`, `2_Dense`, ...)
+fn is_sentence_transformers_module(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.split_once('_'))
+        .is_some_and(|(index, _)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Classify by `config.json` first; the directory name is only consulted when the config
+/// does not settle it (e.g. a fine-tune of a generic causal LM such as Orpheus)
+fn classify(name: &str, architecture: Option<&str>, model_type: Option<&str>, path: &Path) -> ModelKind {
+    classify_config(architecture, model_type, path)
+        .or_else(|| classify_name(name))
+        .unwrap_or(if architecture.is_some_and(|a| a.ends_with("ForCausalLM")) {
+            ModelKind::Chat
+        } else {
+            ModelKind::Unknown
+        })
+}
+
+fn classify_config(architecture: Option<&str>, model_type: Option<&str>, path: &Path) -> Option<ModelKind> {
+    let architecture = architecture.unwrap_or_default();
+    let model_type = model_type.unwrap_or_default();
+
+    if matches!(model_type, "whisper" | "moonshine" | "paraformer" | "wav2vec2" | "hubert")
+        || architecture.ends_with("ForSpeechSeq2Seq")
+        || architecture.ends_with("ForCTC")
+    {
+        Some(ModelKind::Asr)
+    } else if matches!(model_type, "snac" | "bicodec") {
+        Some(ModelKind::Tts)
+    } else if architecture.ends_with("ForSequenceClassification") {
+        Some(ModelKind::Reranker)
+    } else if path.join("1_Pooling").is_dir()
+        || path.join("modules.json").is_file()
+        || matches!(architecture, "BertModel" | "XLMRobertaModel" | "NewModel")
+    {
+        Some(ModelKind::Embedding)
+    } else if matches!(
+        model_type,
+        "siglip" | "siglip2" | "clip" | "qwen2_vl" | "qwen2_5_vl" | "namo"
+    ) || (path.join("preprocessor_config.json").is_file()
+        && architecture.ends_with("ForConditionalGeneration"))
+    {
+        Some(ModelKind::Vision)
+    } else if ChatModelType::from_architecture(Some(architecture), Some(model_type)).is_some() {
+        Some(ModelKind::Chat)
+    } else {
+        None
+    }
+}
+
+/// Match whole tokens of the directory name, so e.g. "vl" does not match "marvel"
+fn classify_name(name: &str) -> Option<ModelKind> {
+    let name = name.to_lowercase();
+    let tokens: Vec<&str> = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let has = |hints: &[&str]| tokens.iter().any(|t| hints.contains(t));
+
+    if has(&["whisper", "moonshine", "paraformer", "asr"]) {
+        Some(ModelKind::Asr)
+    } else if has(&["orpheus", "snac", "spark", "tts", "bicodec"]) {
+        Some(ModelKind::Tts)
+    } else if has(&["rerank", "reranker", "marco"]) || name.contains("cross-encoder") {
+        Some(ModelKind::Reranker)
+    } else if has(&["embedding", "embeddings", "bge", "gte", "minilm", "e5", "sentence"]) {
+        Some(ModelKind::Embedding)
+    } else if has(&["vl", "vision", "siglip", "siglip2", "clip", "namo"]) {
+        Some(ModelKind::Vision)
+    } else {
+        None
+    }
+}
+
+/// Sum the tensor sizes declared in the safetensors headers
+fn count_safetensors_parameters(path: &Path) -> Option<u64> {
+    let mut total = 0u64;
+    let mut found = false;
+    for entry in fs::read_dir(path).ok()?.flatten() {
+        let file = entry.path();
+        if file.extension().and_then(|x| x.to_str()) != Some("safetensors") {
+            continue;
+        }
+        total += safetensors_header_parameters(&file)?;
+        found = true;
+    }
+    found.then_some(total)
+}
+
+fn safetensors_header_parameters(file: &Path) -> Option<u64> {
+    let file_len = fs::metadata(file).ok()?.len();
+    let mut f = File::open(file).ok()?;
+    let mut len_bytes = [0u8; 8];
+    f.read_exact(&mut len_bytes).ok()?;
+    let header_len = u64::from_le_bytes(len_bytes);
+    // Git LFS pointers and truncated downloads have no valid header
+    if header_len == 0 || header_len + 8 > file_len {
+        return None;
+    }
+    let mut header = vec![0u8; header_len as usize];
+    f.read_exact(&mut header).ok()?;
+    let header: serde_json::Map<String, Value> = serde_json::from_slice(&header).ok()?;
+
+    Some(
+        header
+            .iter()
+            .filter(|(name, _)| name.as_str() != "__metadata__")
+            .filter_map(|(_, tensor)| tensor["shape"].as_array())
+            .map(|shape| shape.iter().map(|d| d.as_u64().unwrap_or(0)).product::<u64>())
+            .sum(),
+    )
+}
+
+/// Rough parameter count of a decoder-only transformer from its config
+fn estimate_parameters(config: &Value) -> Option<u64> {
+    let get = |key: &str| text_field(config, key).and_then(|v| v.as_u64());
+    let hidden = get("hidden_size")?;
+    let layers = get("num_hidden_layers")?;
+    let vocab = get("vocab_size")?;
+    let intermediate = get("intermediate_size").unwrap_or(4 * hidden);
+    let heads = get("num_attention_heads").unwrap_or(1).max(1);
+    let kv_heads = get("num_key_value_heads").unwrap_or(heads);
+    let head_dim = get("head_dim").unwrap_or(hidden / heads);
+    let tied = text_field(config, "tie_word_embeddings")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let attention = 2 * hidden * heads * head_dim + 2 * hidden * kv_heads * head_dim;
+    let mlp = match get("num_experts") {
+        Some(experts) => {
+            let expert_size = get("moe_intermediate_size").unwrap_or(intermediate);
+            let shared = get("shared_expert_intermediate_size").unwrap_or(0);
+            experts * 3 * hidden * expert_size + 3 * hidden * shared + hidden * experts
+        }
+        None => 3 * hidden * intermediate,
+    };
+    let embeddings = if tied { vocab * hidden } else { 2 * vocab * hidden };
+
+    Some(embeddings + layers * (attention + mlp))
+}
+
+fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| {
+                    let p = e.path();
+                    if p.is_dir() {
+                        dir_size(&p)
+                    } else {
+                        e.metadata().map(|m| m.len()).unwrap_or(0)
+                    }
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moonshine_variant_is_read_from_the_config() {
+        let root = std::env::temp_dir().join(format!("studynest-registry-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        // Directory names say nothing about the variant
+        for (name, hidden_size) in [("lecture-asr", 416), ("asr-small", 288), ("asr-other", 320)] {
+            let dir = root.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            let config = serde_json::json!({"model_type": "moonshine", "hidden_size": hidden_size});
+            fs::write(dir.join("config.json"), config.to_string()).unwrap();
+        }
+
+        let registry = ModelRegistry::new(&root);
+        let variant = |name: &str| {
+            let info = registry.find(name).unwrap();
+            assert_eq!(info.kind, ModelKind::Asr);
+            info.stt_model_type
+        };
+        assert_eq!(variant("lecture-asr"), Some(SttModelType::MoonshineBase));
+        assert_eq!(variant("asr-small"), Some(SttModelType::MoonshineTiny));
+        assert_eq!(variant("asr-other"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+//! Cross-encoder reranking of retrieved passages
+//!
+//! Retrieval compares separately computed embeddings (and keywords), which is fast but
+//! misorders close candidates. A reranker reads the query together with each candidate
+//! and rescores it, at the cost of one model pass per candidate, so it is used as an
+//! optional second stage over a few dozen retrieved chunks. Supports BGE-reranker,
+//! MS MARCO cross-encoders and Qwen3-Reranker.
+
+use crate::device::{DeviceType, get_device};
+use crate::error::{Result, StudyNestError};
+
+use crane_core::models::reranker::RerankerModel;
+
+/// Reranker configuration
+#[derive(Debug, Clone)]
+pub struct RerankerConfig {
+    pub model_path: String,
+    pub device: DeviceType,
+    /// Query-passage pairs run through the model at once
+    pub batch_size: usize,
+    /// Truncate pairs to this many tokens (the model's limit when `None`)
+    pub max_length: Option<usize>,
+    /// Passages scoring below this relevance (0 to 1) are dropped
+    pub threshold: Option<f32>,
+    /// Task description for instruction-following rerankers such as Qwen3-Reranker
+    pub instruction: Option<String>,
+}
+
+impl Default for RerankerConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "checkpoints/bge-reranker-v2-m3".to_string(),
+            device: DeviceType::Auto,
+            batch_size: 16,
+            max_length: Some(1024),
+            threshold: None,
+            instruction: None,
+        }
+    }
+}
+
+impl RerankerConfig {
+    pub fn with_model_path(mut self, path: impl Into<String>) -> Self {
+        self.model_path = path.into();
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceType) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn with_instruction(mut self, instruction: impl Into<String>) -> Self {
+        self.instruction = Some(instruction.into());
+        self
+    }
+}
+
+/// Cross-encoder reranker
+pub struct Reranker {
+    config: RerankerConfig,
+    model: RerankerModel,
+}
+
+impl Reranker {
+    /// Create a new reranker
+    pub fn new(config: RerankerConfig) -> Result<Self> {
+        eprintln!("[StudyNest] Initializing reranker with model: {}", config.model_path);
+
+        let device = get_device(config.device)?;
+        let mut model = RerankerModel::new(&config.model_path, &device)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        if let Some(max_length) = config.max_length {
+            model.max_length = model.max_length.min(max_length);
+        }
+        if let Some(instruction) = &config.instruction {
+            model.instruction = instruction.clone();
+        }
+
+        eprintln!("[StudyNest] Reranker initialized (up to {} tokens per pair)", model.max_length);
+        Ok(Self { config, model })
+    }
+
+    pub fn config(&self) -> &RerankerConfig {
+        &self.config
+    }
+
+    /// Relevance of each passage to `query`, between 0 and 1
+    pub fn score(&mut self, query: &str, passages: &[&str]) -> Result<Vec<f32>> {
+        self.model
+            .score(query, passages, self.config.batch_size)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+
+    /// Reorder `items` by the relevance of their text to `query`, best first, keeping at
+    /// most `top_n` of those at or above the threshold. Returns each item with its score.
+    pub fn rerank<T>(
+        &mut self,
+        query: &str,
+        items: Vec<T>,
+        text: impl Fn(&T) -> &str,
+        top_n: usize,
+    ) -> Result<Vec<(T, f32)>> {
+        let passages: Vec<&str> = items.iter().map(&text).collect();
+        let scores = self.score(query, &passages)?;
+        let threshold = self.config.threshold.unwrap_or(f32::NEG_INFINITY);
+        let mut ranked: Vec<(T, f32)> = items
+            .into_iter()
+            .zip(scores)
+            .filter(|(_, score)| *score >= threshold)
+            .collect();
+        // Stable, so ties keep the retrieval order
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top_n);
+        Ok(ranked)
+    }
+}
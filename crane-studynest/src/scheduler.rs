@@ -0,0 +1,76 @@
+//! Scheduling of generation work across chat sessions
+//!
+//! Sessions share one set of model weights, so running every request at once
+//! would only fight over the same cores or GPU. The scheduler admits requests
+//! in arrival order and caps how many generate at the same time.
+
+use std::sync::{Condvar, Mutex};
+
+struct SchedulerState {
+    next_ticket: u64,
+    now_serving: u64,
+    running: usize,
+}
+
+/// First-come, first-served admission of generation requests
+pub struct GenerationScheduler {
+    state: Mutex<SchedulerState>,
+    admitted: Condvar,
+    max_concurrent: usize,
+}
+
+/// Held while a request is generating; releases its slot when dropped
+pub struct GenerationPermit<'a> {
+    scheduler: &'a GenerationScheduler,
+}
+
+impl GenerationScheduler {
+    /// Create a scheduler; `max_concurrent` of 1 fully serializes generation
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            state: Mutex::new(SchedulerState {
+                next_ticket: 0,
+                now_serving: 0,
+                running: 0,
+            }),
+            admitted: Condvar::new(),
+            max_concurrent: max_concurrent.max(1),
+        }
+    }
+
+    /// Wait for this request's turn and a free generation slot
+    pub fn acquire(&self) -> GenerationPermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+
+        while ticket != state.now_serving || state.running >= self.max_concurrent {
+            state = self.admitted.wait(state).unwrap();
+        }
+        state.now_serving += 1;
+        state.running += 1;
+        // The next ticket may also fit if there are free slots
+        self.admitted.notify_all();
+
+        GenerationPermit { scheduler: self }
+    }
+
+    /// Number of requests waiting for a slot
+    pub fn queued(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        (state.next_ticket - state.now_serving) as usize
+    }
+
+    /// Number of requests currently generating
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+}
+
+impl Drop for GenerationPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.running -= 1;
+        self.scheduler.admitted.notify_all();
+    }
+}
@@ -1,11 +1,42 @@
 //! Service module for Electron integration
 //! Provides JSON-RPC interface for chat functionality
 
-use crate::chat::{ChatEngine, ChatConfig, ChatMessage, Role};
+use crate::chat::{
+    CancellationToken, ChatConfig, ChatEngine, ChatMessage, ContextPolicy, ContextReport,
+    FinishReason, GenerationControl, Role,
+};
 use crate::device::DeviceType;
+use crate::embed::{EmbeddingConfig, EmbeddingEngine};
+use crate::rerank::{Reranker, RerankerConfig};
+use crate::image_search::{self, ImageHit, ImageLabel, ImageSearchConfig, ImageSearchEngine};
+use crate::ocr::{OcrConfig, OcrEngine};
+use crate::hnsw::SearchFilter;
+use crate::rag::{self, Citation, DocumentInfo, RagConfig, RagIndex, SearchHit, SearchMode};
 use crate::error::{Result, StudyNestError};
+use crate::registry::{ModelInfo, ModelKind, ModelRegistry};
+use crate::scheduler::GenerationScheduler;
+use crate::stt::{SttConfig, SttEngine};
+use crate::tts::{self, AudioChunk, Level, TtsConfig, TtsEngine, TtsProgress};
+use crate::session::{ExportFormat, Session, SessionInfo, SessionSettings, SessionStore, now_millis};
+use crate::study::SourceText;
+use crate::study::lecture::{LectureConfig, LectureNotes, LectureProgress, NoteTaker};
+use crate::study::flashcards::{
+    self, CardKind, DeckFormat, Flashcard, FlashcardConfig, FlashcardGenerator, FlashcardSet,
+};
+use crate::study::review::{
+    self as review, DueQuery, DueQueue, OptimizationReport, Rating, ReviewCard, ReviewStore,
+    SchedulerParameters,
+};
+use crate::study::summary::{Summarizer, Summary, SummaryConfig, SummaryProgress, SummaryStyle};
+use crate::study::quiz::{
+    GradingConfig, QuestionKind, Quiz, QuizConfig, QuizGenerator, QuizGrader, QuizQuestion,
+    QuizResult,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServiceConfig {
@@ -14,6 +45,44 @@ pub struct ServiceConfig {
     pub max_tokens: usize,
     pub temperature: f64,
     pub top_p: f64,
+    pub context_policy: String,
+    pub sessions_dir: String,
+    /// Directory scanned for installed models
+    pub checkpoints_dir: String,
+    /// How many sessions may generate at the same time (1 serializes generation)
+    pub max_concurrent_generations: usize,
+    /// Default wall-clock limit for a chat request, including time spent queued
+    pub request_timeout_ms: Option<u64>,
+    /// Embedding model used when an `embed` request names none
+    pub embedding_model: Option<String>,
+    /// Directory of the notes index used by `ask`
+    pub notes_index_dir: String,
+    /// Directory of the flashcard review store
+    pub reviews_dir: String,
+    /// Directory of checkpoints of summaries in progress
+    pub summaries_dir: String,
+    /// Directory of checkpoints of lecture notes in progress
+    pub lectures_dir: String,
+    /// Speech-to-text model used for lecture recordings
+    pub stt_model: Option<String>,
+    /// Silero VAD model file; downloaded from the Hugging Face Hub when not given
+    pub vad_model: Option<String>,
+    /// Directory read-aloud audio is written to
+    pub audio_dir: String,
+    /// Text-to-speech model used to read notes aloud
+    pub tts_model: Option<String>,
+    /// Voice of the text-to-speech model
+    pub tts_voice: Option<String>,
+    /// SNAC decoder file; looked for in and next to the text-to-speech model when not given
+    pub snac_model: Option<String>,
+    /// Vision model used to read PDFs and images into the notes index
+    pub ocr_model: Option<String>,
+    /// Cross-encoder used to rerank `ask` and `search` candidates
+    pub reranker_model: Option<String>,
+    /// Reranked passages scoring below this relevance (0 to 1) are dropped
+    pub rerank_threshold: Option<f32>,
+    /// Siglip2 checkpoint used to search images by description
+    pub image_model: Option<String>,
 }
 
 impl Default for ServiceConfig {
@@ -24,6 +93,26 @@ impl Default for ServiceConfig {
             max_tokens: 2048,
             temperature: 0.7,
             top_p: 0.9,
+            context_policy: "drop_oldest".to_string(),
+            sessions_dir: "sessions".to_string(),
+            checkpoints_dir: "checkpoints".to_string(),
+            max_concurrent_generations: 1,
+            request_timeout_ms: Some(120_000),
+            embedding_model: None,
+            notes_index_dir: "notes_index".to_string(),
+            reviews_dir: "reviews".to_string(),
+            summaries_dir: "summaries".to_string(),
+            lectures_dir: "lectures".to_string(),
+            stt_model: None,
+            vad_model: None,
+            audio_dir: "audio".to_string(),
+            tts_model: None,
+            tts_voice: None,
+            snac_model: None,
+            ocr_model: None,
+            reranker_model: None,
+            rerank_threshold: None,
+            image_model: None,
         }
     }
 }
@@ -34,6 +123,15 @@ pub struct ChatRequest {
     pub messages: Vec<MessageRequest>,
     pub temperature: Option<f64>,
     pub max_tokens: Option<usize>,
+    /// Persist the turn into this session and use its stored history
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Id used to cancel this request; the JSON-RPC request id when not given
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Overrides the service's default request timeout
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,6 +144,9 @@ pub struct MessageRequest {
 pub struct ChatResponse {
     pub message: MessageResponse,
     pub done: bool,
+    pub finish_reason: FinishReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextReport>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,21 +155,512 @@ pub struct MessageResponse {
     pub content: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    /// Name of the embedding model
+    pub model: String,
+    pub dimension: usize,
+    pub embeddings: Vec<Vec<f32>>,
+    /// Tokens of the inputs after truncation
+    pub prompt_tokens: usize,
+}
+
+/// Document to add to the notes index, either a file or inline text
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IngestRequest {
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Course the notes belong to, so questions can be limited to it
+    #[serde(default)]
+    pub course: Option<String>,
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+}
+
+/// Session to create; settings not given are the service defaults
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CreateSessionRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Chat model, by directory name under `checkpoints_dir` or path; the loaded model
+    /// when not given
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+}
+
+/// Model and generation settings of a session to change; the others are kept
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateSessionRequest {
+    pub id: String,
+    /// Chat model, by directory name under `checkpoints_dir` or path; empty for the
+    /// loaded model
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+}
+
+/// Question answered from the notes index
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AskRequest {
+    pub question: String,
+    /// Persist the turn into this session and use its stored history
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Only search these documents
+    #[serde(default)]
+    pub document_ids: Option<Vec<String>>,
+    /// Only search documents of this course
+    #[serde(default)]
+    pub course: Option<String>,
+    /// Only search documents ingested at or after this time (Unix milliseconds)
+    #[serde(default)]
+    pub after: Option<u64>,
+    /// Only search documents ingested before this time (Unix milliseconds)
+    #[serde(default)]
+    pub before: Option<u64>,
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Rerank the retrieved chunks; on by default when a reranker is configured
+    #[serde(default)]
+    pub rerank: Option<bool>,
+    /// Overrides the configured reranker
+    #[serde(default)]
+    pub reranker_model: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AskResponse {
+    pub message: MessageResponse,
+    pub done: bool,
+    pub finish_reason: FinishReason,
+    /// Chunks cited in the answer
+    pub citations: Vec<Citation>,
+    /// All chunks given to the model
+    pub sources: Vec<Citation>,
+}
+
+/// Keyword and/or semantic search of the notes index
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// Number of hits, 10 by default
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub document_ids: Option<Vec<String>>,
+    #[serde(default)]
+    pub course: Option<String>,
+    #[serde(default)]
+    pub after: Option<u64>,
+    #[serde(default)]
+    pub before: Option<u64>,
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Rerank the hits; on by default when a reranker is configured
+    #[serde(default)]
+    pub rerank: Option<bool>,
+    /// Overrides the configured reranker
+    #[serde(default)]
+    pub reranker_model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub mode: SearchMode,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Search of image files by description
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchImagesRequest {
+    pub query: String,
+    /// Image files to search
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Directory whose images, including those in subdirectories, are searched too
+    #[serde(default)]
+    pub dir: Option<String>,
+    /// Number of hits, 10 by default
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// Overrides the configured image model
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchImagesResponse {
+    /// Name of the image model
+    pub model: String,
+    pub hits: Vec<ImageHit>,
+}
+
+/// Zero-shot classification of an image file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClassifyImageRequest {
+    pub path: String,
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClassifyImageResponse {
+    pub model: String,
+    /// Labels with their probability, most likely first
+    pub labels: Vec<ImageLabel>,
+}
+
+/// Flashcards to write from a document in the notes index or from inline text
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlashcardsRequest {
+    #[serde(default)]
+    pub document_id: Option<String>,
+    /// Only these pages of the document
+    #[serde(default)]
+    pub pages: Option<Vec<usize>>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Both kinds by default
+    #[serde(default)]
+    pub kinds: Option<Vec<CardKind>>,
+    #[serde(default)]
+    pub max_cards: Option<usize>,
+    /// Added to every card, besides the document's course
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Cards to write to a CSV file or an Anki package
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportFlashcardsRequest {
+    pub cards: Vec<Flashcard>,
+    pub format: DeckFormat,
+    /// Deck name in the Anki package, `StudyNest` by default
+    #[serde(default)]
+    pub deck: Option<String>,
+    /// Required for packages; CSV is returned as text without one
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Quiz to write on a document in the notes index or on inline text
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuizRequest {
+    #[serde(default)]
+    pub document_id: Option<String>,
+    /// Only these pages of the document
+    #[serde(default)]
+    pub pages: Option<Vec<usize>>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// All kinds by default
+    #[serde(default)]
+    pub kinds: Option<Vec<QuestionKind>>,
+    #[serde(default)]
+    pub max_questions: Option<usize>,
+    /// Options per multiple-choice question, 4 by default
+    #[serde(default)]
+    pub options: Option<usize>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Answers to grade, keyed by question id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GradeQuizRequest {
+    pub questions: Vec<QuizQuestion>,
+    pub answers: HashMap<String, String>,
+    /// Score from which an answer counts as correct, 0.6 by default
+    #[serde(default)]
+    pub pass_score: Option<f32>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Cards to schedule for review
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddCardsRequest {
+    pub cards: Vec<Flashcard>,
+    /// `StudyNest` by default; `::` separates subdecks
+    #[serde(default)]
+    pub deck: Option<String>,
+}
+
+/// Cards due for review now
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CardsDueRequest {
+    /// Only this deck and its subdecks
+    #[serde(default)]
+    pub deck: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// A review of a card
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewCardRequest {
+    pub card_id: String,
+    pub rating: Rating,
+    /// Time spent answering
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+/// Scheduler settings to change; the others are kept
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReviewSettingsRequest {
+    #[serde(default)]
+    pub desired_retention: Option<f64>,
+    #[serde(default)]
+    pub maximum_interval_days: Option<u32>,
+    #[serde(default)]
+    pub learning_steps: Option<Vec<u32>>,
+    #[serde(default)]
+    pub relearning_steps: Option<Vec<u32>>,
+    #[serde(default)]
+    pub new_per_day: Option<usize>,
+}
+
+/// Lecture recording to take notes from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LectureNotesRequest {
+    /// WAV file of the recording
+    pub path: String,
+    /// Title of the notes; the file name when not given
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Overrides the service's speech-to-text model
+    #[serde(default)]
+    pub stt_model: Option<String>,
+    /// Whether to add a glossary, true by default
+    #[serde(default)]
+    pub glossary: Option<bool>,
+    /// Where times in the notes link to; a `file://` URL of the recording by default
+    #[serde(default)]
+    pub audio_link: Option<String>,
+    /// Token budget of the notes of each topic
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Text to read aloud
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadAloudRequest {
+    /// Plain text or markdown
+    pub text: String,
+    /// WAV file to write; a file in `audio_dir` named after the text and voice by default
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Overrides the service's voice
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Pitch of a Spark-TTS voice, `very_low` to `very_high`
+    #[serde(default)]
+    pub pitch: Option<Level>,
+    /// Speaking rate of a Spark-TTS voice, `very_low` to `very_high`
+    #[serde(default)]
+    pub speed: Option<Level>,
+    /// WAV recording whose voice Spark-TTS clones; pitch and speed then follow the recording
+    #[serde(default)]
+    pub prompt_audio: Option<String>,
+    /// What is said in `prompt_audio`, for a closer copy of the voice
+    #[serde(default)]
+    pub prompt_text: Option<String>,
+    /// Overrides the service's text-to-speech model
+    #[serde(default)]
+    pub tts_model: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadAloudResponse {
+    /// The WAV file written, mono at the model's sample rate
+    pub path: String,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    /// Pieces the text was read in
+    pub chunks: usize,
+    /// The file was already there from an earlier request
+    pub cached: bool,
+    pub finish_reason: FinishReason,
+}
+
+/// Audio of a streamed reading, sent as soon as it is decoded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PcmChunk {
+    pub index: usize,
+    /// Base64 of mono 16-bit little-endian samples
+    pub pcm: String,
+    pub sample_rate: u32,
+    /// Nothing follows; carries no samples
+    pub last: bool,
+}
+
+impl PcmChunk {
+    pub fn new(chunk: &AudioChunk, sample_rate: u32) -> Self {
+        use base64::Engine as _;
+        Self {
+            index: chunk.index,
+            pcm: base64::engine::general_purpose::STANDARD.encode(tts::pcm_s16le(&chunk.samples)),
+            sample_rate,
+            last: chunk.last,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadAloudStreamResponse {
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    /// Audio chunks sent, including the last, empty one
+    pub audio_chunks: usize,
+    /// Pieces the text was read in
+    pub chunks: usize,
+    pub finish_reason: FinishReason,
+}
+
+/// Document in the notes index, or inline text, to summarize
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SummarizeRequest {
+    #[serde(default)]
+    pub document_id: Option<String>,
+    /// Only these pages of the document
+    #[serde(default)]
+    pub pages: Option<Vec<usize>>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub style: SummaryStyle,
+    /// Token budget of the summary and of each partial one
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
+/// Key of the conversation used by requests without a session
+const DEFAULT_CONVERSATION: &str = "";
+
+/// Gradient steps when fitting the scheduler to the review history
+const OPTIMIZE_ITERATIONS: usize = 200;
+
+/// Checkpoint file of a summary, named after what is summarized, how, and by which
+/// model, so replies of another model are never resumed. The request id and timeout
+/// are left out, as a retry is a new request.
+fn summary_checkpoint(summaries_dir: &str, model_path: &str, request: &SummarizeRequest) -> PathBuf {
+    let key = serde_json::json!([
+        model_path,
+        request.document_id,
+        request.pages,
+        request.text,
+        request.style,
+        request.max_tokens
+    ]);
+    Path::new(summaries_dir).join(format!("{}.json", sha1_smol::Sha1::from(key.to_string()).digest()))
+}
+
 pub struct ChatService {
+    /// Engine holding the loaded weights; conversations are forked from it
     engine: Arc<Mutex<Option<ChatEngine>>>,
+    /// Per-session engines with their own history and kv cache
+    conversations: Mutex<HashMap<String, Arc<Mutex<ChatEngine>>>>,
+    scheduler: GenerationScheduler,
+    /// Loaded embedding model with its registry name
+    embedder: Mutex<Option<(String, EmbeddingEngine)>>,
+    /// Loaded reranker with its registry name
+    reranker: Mutex<Option<(String, Reranker)>>,
+    /// Loaded text-to-speech model with its registry name
+    tts: Mutex<Option<(String, TtsEngine)>>,
+    /// Loaded image model with its registry name, keeping the embeddings of searched images
+    images: Mutex<Option<(String, ImageSearchEngine)>>,
+    notes: Mutex<RagIndex>,
+    reviews: Mutex<ReviewStore>,
+    /// Cancellation tokens of requests currently queued or generating
+    in_flight: Mutex<HashMap<String, CancellationToken>>,
+    sessions: SessionStore,
+    registry: ModelRegistry,
     config: ServiceConfig,
 }
 
 impl ChatService {
-    pub fn new(config: ServiceConfig) -> Self {
-        Self {
+    pub fn new(config: ServiceConfig) -> Result<Self> {
+        let sessions = SessionStore::open(&config.sessions_dir)?;
+        let notes = RagIndex::open(RagConfig::default().with_index_dir(&config.notes_index_dir))?;
+        let reviews = ReviewStore::open(&config.reviews_dir)?;
+        Ok(Self {
             engine: Arc::new(Mutex::new(None)),
+            conversations: Mutex::new(HashMap::new()),
+            scheduler: GenerationScheduler::new(config.max_concurrent_generations),
+            embedder: Mutex::new(None),
+            reranker: Mutex::new(None),
+            tts: Mutex::new(None),
+            images: Mutex::new(None),
+            notes: Mutex::new(notes),
+            reviews: Mutex::new(reviews),
+            in_flight: Mutex::new(HashMap::new()),
+            sessions,
+            registry: ModelRegistry::new(&config.checkpoints_dir),
             config,
+        })
+    }
+
+    fn session_settings(&self) -> SessionSettings {
+        SessionSettings {
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+            top_p: self.config.top_p,
         }
     }
 
@@ -85,6 +677,13 @@ impl ChatService {
         }
     }
 
+    fn parse_context_policy(policy_str: &str) -> ContextPolicy {
+        match policy_str.to_lowercase().as_str() {
+            "summarize" => ContextPolicy::Summarize,
+            _ => ContextPolicy::DropOldest,
+        }
+    }
+
     fn parse_role(role_str: &str) -> Role {
         match role_str.to_lowercase().as_str() {
             "system" => Role::System,
@@ -94,59 +693,1086 @@ impl ChatService {
         }
     }
 
-    pub fn initialize_model(&self, model_path: &str) -> Result<()> {
-        let device = Self::parse_device(&self.config.device);
-        
-        let chat_config = ChatConfig::default()
-            .with_model_path(model_path)
-            .with_device(device)
-            .with_max_tokens(self.config.max_tokens);
+    /// Find a chat model given its path or its directory name under `checkpoints_dir`
+    fn find_chat_model(&self, model: &str) -> Result<ModelInfo> {
+        let info = self.registry.find(model)?;
+        if info.kind != ModelKind::Chat {
+            return Err(StudyNestError::ConfigError(format!(
+                "{} is not a chat model ({:?})",
+                info.name, info.kind
+            )));
+        }
+        Ok(info)
+    }
 
-        let mut engine = ChatEngine::new(chat_config)?;
+    fn chat_config(&self, info: &ModelInfo) -> ChatConfig {
+        ChatConfig::default()
+            .with_model_path(info.path.to_string_lossy())
+            .with_model_type(info.chat_model_type.unwrap_or_default())
+            .with_device(Self::parse_device(&self.config.device))
+            .with_max_tokens(self.config.max_tokens)
+            .with_context_policy(Self::parse_context_policy(&self.config.context_policy))
+    }
+
+    /// Load a chat model given its path or its directory name under `checkpoints_dir`
+    pub fn initialize_model(&self, model: &str) -> Result<()> {
+        let info = self.find_chat_model(model)?;
+        let mut engine = ChatEngine::new(self.chat_config(&info))?;
         engine.warmup();
 
-        let mut engine_lock = self.engine.lock().unwrap();
-        *engine_lock = Some(engine);
+        // Lock order is conversations, then engine (same as `conversation`)
+        let mut conversations = self.conversations.lock().unwrap();
+        *self.engine.lock().unwrap() = Some(engine);
+        // Conversations forked from the previous model are no longer valid
+        conversations.clear();
 
         Ok(())
     }
 
+    /// Engine for `model`, forked from the loaded model when it is the same one or when
+    /// no model is named, and loaded with weights of its own otherwise
+    fn model_engine(&self, model: &str) -> Result<ChatEngine> {
+        let info = (!model.is_empty()).then(|| self.find_chat_model(model)).transpose()?;
+        {
+            let engine_lock = self.engine.lock().unwrap();
+            match (engine_lock.as_ref(), &info) {
+                (Some(base), None) => return Ok(base.fork()),
+                (Some(base), Some(info)) if Path::new(base.model_path()) == info.path => {
+                    return Ok(base.fork());
+                }
+                (None, None) => {
+                    return Err(StudyNestError::ConfigError("Model not initialized".to_string()));
+                }
+                _ => {}
+            }
+        }
+        let info = info.expect("an unnamed model is the loaded one");
+        eprintln!("[StudyNest] Loading {} for a session", info.name);
+        ChatEngine::new(self.chat_config(&info))
+    }
+
+    /// Use generation settings for the replies of `engine`
+    fn apply_settings(engine: &mut ChatEngine, settings: &SessionSettings) {
+        engine.set_sampling(settings.max_tokens, Some(settings.temperature), Some(settings.top_p));
+    }
+
+    /// Get the engine of a conversation on first use: forked from the loaded model, or
+    /// for a session, on the session's model with its settings and stored history
+    fn conversation(&self, session_id: Option<&str>) -> Result<Arc<Mutex<ChatEngine>>> {
+        let key = session_id.unwrap_or(DEFAULT_CONVERSATION);
+        let mut conversations = self.conversations.lock().unwrap();
+        if let Some(engine) = conversations.get(key) {
+            return Ok(engine.clone());
+        }
+
+        let engine = match session_id {
+            Some(id) => {
+                let session = self.sessions.load(id)?;
+                let mut engine = self.model_engine(&session.model)?;
+                Self::apply_settings(&mut engine, &session.settings);
+                for msg in session.chat_messages() {
+                    engine.add_message(msg);
+                }
+                engine
+            }
+            None => {
+                let mut engine = self.model_engine("")?;
+                Self::apply_settings(&mut engine, &self.session_settings());
+                engine
+            }
+        };
+
+        let engine = Arc::new(Mutex::new(engine));
+        conversations.insert(key.to_string(), engine.clone());
+        Ok(engine)
+    }
+
     pub fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
-        let mut engine_lock = self.engine.lock().unwrap();
-        
-        let engine = engine_lock.as_mut().ok_or_else(|| {
-            StudyNestError::ConfigError("Model not initialized".to_string())
-        })?;
+        let request_id = request.request_id.clone();
+        let timeout_ms = request.timeout_ms;
+        self.with_control(request_id, timeout_ms, |control| self.run_chat(request, control))
+    }
 
-        engine.clear_history();
+    /// Run a generation that can be cancelled by `request_id` and stops at the timeout
+    fn with_control<T>(
+        &self,
+        request_id: Option<String>,
+        timeout_ms: Option<u64>,
+        run: impl FnOnce(&GenerationControl) -> Result<T>,
+    ) -> Result<T> {
+        let timeout_ms = timeout_ms.or(self.config.request_timeout_ms);
+        let control = GenerationControl {
+            cancellation: Some(CancellationToken::new()),
+            deadline: timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+        };
 
-        for msg in &request.messages[..request.messages.len().saturating_sub(1)] {
-            let role = Self::parse_role(&msg.role);
-            engine.get_history();
+        if let (Some(id), Some(token)) = (&request_id, &control.cancellation) {
+            self.in_flight.lock().unwrap().insert(id.clone(), token.clone());
+        }
+        let result = run(&control);
+        if let Some(id) = &request_id {
+            self.in_flight.lock().unwrap().remove(id);
         }
+        result
+    }
 
+    /// Cancel an in-flight chat request; returns false if it is unknown or already finished
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.in_flight.lock().unwrap().get(request_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn run_chat(&self, request: ChatRequest, control: &GenerationControl) -> Result<ChatResponse> {
         let last_message = request.messages.last().ok_or_else(|| {
             StudyNestError::ConfigError("No messages provided".to_string())
         })?;
 
-        let response = engine.chat(&last_message.content)?;
+        let session_id = request.session_id.as_deref();
+        let conversation = self.conversation(session_id)?;
+        let mut engine = conversation.lock().unwrap();
+
+        // Without a session the request carries the whole history
+        if session_id.is_none() {
+            engine.clear_history();
+            for msg in &request.messages[..request.messages.len().saturating_sub(1)] {
+                let role = Self::parse_role(&msg.role);
+                engine.add_message(ChatMessage::new(role, msg.content.clone()));
+            }
+        }
+
+        // Settings given with the request apply to this reply only
+        let (max_tokens, temperature, top_p) = {
+            let config = engine.config();
+            (config.max_new_tokens, config.temperature, config.top_p)
+        };
+        engine.set_sampling(
+            request.max_tokens.unwrap_or(max_tokens),
+            request.temperature.or(temperature),
+            top_p,
+        );
+        let reply = {
+            let _permit = self.scheduler.acquire();
+            engine.chat_with_control(&last_message.content, control)
+        };
+        engine.set_sampling(max_tokens, temperature, top_p);
+        let reply = reply?;
+
+        if let Some(id) = session_id {
+            self.sessions.append_message(id, Role::User, &last_message.content)?;
+            self.sessions.append_message(id, Role::Assistant, &reply.content)?;
+        }
 
         Ok(ChatResponse {
             message: MessageResponse {
                 role: "assistant".to_string(),
-                content: response,
+                content: reply.content,
             },
             done: true,
+            finish_reason: reply.finish_reason,
+            context: Some(reply.context),
+        })
+    }
+
+    pub fn create_session(&self, request: CreateSessionRequest) -> Result<Session> {
+        let model = request.model.unwrap_or_default();
+        if !model.is_empty() {
+            self.find_chat_model(&model)?;
+        }
+        let defaults = self.session_settings();
+        let settings = SessionSettings {
+            max_tokens: request.max_tokens.unwrap_or(defaults.max_tokens),
+            temperature: request.temperature.unwrap_or(defaults.temperature),
+            top_p: request.top_p.unwrap_or(defaults.top_p),
+        };
+        let name = request.name.as_deref().unwrap_or("New chat");
+        self.sessions.create(name, &model, settings)
+    }
+
+    /// Change the model or generation settings of a session. An open conversation takes
+    /// new settings from its next reply on, and is reopened on a new model.
+    pub fn update_session(&self, request: UpdateSessionRequest) -> Result<SessionInfo> {
+        let session = self.sessions.load(&request.id)?;
+        let model = request.model.unwrap_or_else(|| session.model.clone());
+        if !model.is_empty() {
+            self.find_chat_model(&model)?;
+        }
+        let settings = SessionSettings {
+            max_tokens: request.max_tokens.unwrap_or(session.settings.max_tokens),
+            temperature: request.temperature.unwrap_or(session.settings.temperature),
+            top_p: request.top_p.unwrap_or(session.settings.top_p),
+        };
+        self.sessions.update_settings(&request.id, &model, settings.clone())?;
+
+        if model != session.model {
+            self.close_session(&request.id);
+        } else {
+            let open = self.conversations.lock().unwrap().get(&request.id).cloned();
+            if let Some(engine) = open {
+                Self::apply_settings(&mut engine.lock().unwrap(), &settings);
+            }
+        }
+        Ok(self.sessions.load(&request.id)?.info())
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.sessions.list()
+    }
+
+    pub fn load_session(&self, id: &str) -> Result<Session> {
+        self.sessions.load(id)
+    }
+
+    pub fn rename_session(&self, id: &str, name: &str) -> Result<SessionInfo> {
+        self.sessions.rename(id, name)?;
+        Ok(self.sessions.load(id)?.info())
+    }
+
+    pub fn delete_session(&self, id: &str) -> Result<()> {
+        self.close_session(id);
+        self.sessions.delete(id)
+    }
+
+    /// Drop the in-memory history and kv cache of a session; it stays on disk
+    pub fn close_session(&self, id: &str) -> bool {
+        self.conversations.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Number of sessions with a live engine, and requests running and queued for generation
+    pub fn status(&self) -> (usize, usize, usize) {
+        let open = self.conversations.lock().unwrap().len();
+        (open, self.scheduler.running(), self.scheduler.queued())
+    }
+
+    /// Export a session, writing it to `path` when given and returning the content otherwise
+    pub fn export_session(&self, id: &str, format: ExportFormat, path: Option<&str>) -> Result<String> {
+        let content = self.sessions.export(id, format)?;
+        match path {
+            Some(path) => {
+                std::fs::write(path, &content)?;
+                Ok(path.to_string())
+            }
+            None => Ok(content),
+        }
+    }
+
+    /// Embed `input` with an embedding model, by default `embedding_model` of the config.
+    /// The model is loaded on first use and kept until another one is requested.
+    pub fn embed(&self, input: &[String], model: Option<&str>) -> Result<EmbeddingResponse> {
+        let model = model
+            .or(self.config.embedding_model.as_deref())
+            .ok_or_else(|| StudyNestError::ConfigError("No embedding model given".to_string()))?;
+
+        self.with_embedder(model, |name, engine| {
+            let texts: Vec<&str> = input.iter().map(String::as_str).collect();
+            let prompt_tokens = engine.count_tokens(&texts)?.iter().sum();
+            let embeddings = engine.embed(&texts)?;
+            Ok(EmbeddingResponse {
+                model: name.to_string(),
+                dimension: engine.dimension(),
+                embeddings,
+                prompt_tokens,
+            })
+        })
+    }
+
+    /// Run `f` with the embedding model `model`, loading it if another one is loaded
+    fn with_embedder<T>(
+        &self,
+        model: &str,
+        f: impl FnOnce(&str, &mut EmbeddingEngine) -> Result<T>,
+    ) -> Result<T> {
+        let mut embedder = self.embedder.lock().unwrap();
+        if !matches!(&*embedder, Some((name, _)) if name == model) {
+            let info = self.registry.find(model)?;
+            if info.kind != ModelKind::Embedding {
+                return Err(StudyNestError::ConfigError(format!(
+                    "{} is not an embedding model ({:?})",
+                    info.name, info.kind
+                )));
+            }
+            // Free the previous model before loading the next one
+            *embedder = None;
+            let config = EmbeddingConfig::default()
+                .with_model_path(info.path.to_string_lossy())
+                .with_device(Self::parse_device(&self.config.device));
+            *embedder = Some((model.to_string(), EmbeddingEngine::new(config)?));
+        }
+        let (name, engine) = embedder.as_mut().expect("embedding model loaded above");
+        f(name, engine)
+    }
+
+    /// Reranker for a request: the one it names, else the configured one unless the
+    /// request turned reranking off
+    fn reranker_model(&self, rerank: Option<bool>, requested: Option<&str>) -> Result<Option<String>> {
+        if rerank == Some(false) {
+            return Ok(None);
+        }
+        let model = requested.or(self.config.reranker_model.as_deref()).map(str::to_string);
+        if rerank == Some(true) && model.is_none() {
+            return Err(StudyNestError::ConfigError("No reranker model given".to_string()));
+        }
+        Ok(model)
+    }
+
+    /// Run `f` with the reranker `model`, loading it if another one is loaded, or with
+    /// no reranker
+    fn with_reranker<T>(
+        &self,
+        model: Option<&str>,
+        f: impl FnOnce(Option<&mut Reranker>) -> Result<T>,
+    ) -> Result<T> {
+        let Some(model) = model else {
+            return f(None);
+        };
+        let mut reranker = self.reranker.lock().unwrap();
+        if !matches!(&*reranker, Some((name, _)) if name == model) {
+            let info = self.registry.find(model)?;
+            if info.kind != ModelKind::Reranker {
+                return Err(StudyNestError::ConfigError(format!(
+                    "{} is not a reranker ({:?})",
+                    info.name, info.kind
+                )));
+            }
+            *reranker = None;
+            let mut config = RerankerConfig::default()
+                .with_model_path(info.path.to_string_lossy())
+                .with_device(Self::parse_device(&self.config.device));
+            if let Some(threshold) = self.config.rerank_threshold {
+                config = config.with_threshold(threshold);
+            }
+            *reranker = Some((model.to_string(), Reranker::new(config)?));
+        }
+        let (_, engine) = reranker.as_mut().expect("reranker loaded above");
+        f(Some(engine))
+    }
+
+    /// Run `f` with the text-to-speech model `model`, loading it if another one is loaded
+    fn with_tts<T>(&self, model: &str, f: impl FnOnce(&mut TtsEngine) -> Result<T>) -> Result<T> {
+        let mut tts = self.tts.lock().unwrap();
+        if !matches!(&*tts, Some((name, _)) if name == model) {
+            let info = self.registry.find(model)?;
+            if info.kind != ModelKind::Tts {
+                return Err(StudyNestError::ConfigError(format!(
+                    "{} is not a text-to-speech model ({:?})",
+                    info.name, info.kind
+                )));
+            }
+            *tts = None;
+            let mut config = TtsConfig::default()
+                .with_model_path(info.path.to_string_lossy())
+                .with_device(Self::parse_device(&self.config.device));
+            if let Some(snac_model) = &self.config.snac_model {
+                config = config.with_snac_model_path(snac_model);
+            }
+            *tts = Some((model.to_string(), TtsEngine::new(config)?));
+        }
+        let (_, engine) = tts.as_mut().expect("text-to-speech model loaded above");
+        f(engine)
+    }
+
+    /// Embedding model for the notes index: the requested one, the one the index was
+    /// built with, or the configured default
+    fn notes_embedding_model(&self, requested: Option<&str>) -> Result<String> {
+        let notes = self.notes.lock().unwrap();
+        if let (Some(requested), Some(indexed)) = (requested, notes.embedding_model()) {
+            if requested != indexed {
+                return Err(StudyNestError::ConfigError(format!(
+                    "The notes index was built with {}, not {}",
+                    indexed, requested
+                )));
+            }
+        }
+        requested
+            .or(notes.embedding_model())
+            .or(self.config.embedding_model.as_deref())
+            .map(str::to_string)
+            .ok_or_else(|| StudyNestError::ConfigError("No embedding model given".to_string()))
+    }
+
+    /// Add a file or text to the notes index. PDFs and images need `ocr_model`.
+    pub fn ingest(&self, request: IngestRequest) -> Result<DocumentInfo> {
+        let model = self.notes_embedding_model(request.embedding_model.as_deref())?;
+        let ocr = match (&request.path, &self.config.ocr_model) {
+            (Some(_), Some(ocr_model)) => {
+                let info = self.registry.find(ocr_model)?;
+                let config = OcrConfig::default()
+                    .with_model_path(info.path.to_string_lossy())
+                    .with_device(Self::parse_device(&self.config.device));
+                Some(OcrEngine::new(config)?)
+            }
+            _ => None,
+        };
+
+        self.with_embedder(&model, |_, engine| {
+            let mut notes = self.notes.lock().unwrap();
+            match (&request.path, &request.text) {
+                (Some(path), _) => {
+                    notes.add_file(
+                        engine,
+                        path,
+                        request.title.as_deref(),
+                        request.course.as_deref(),
+                        ocr.as_ref(),
+                    )
+                }
+                (None, Some(text)) => {
+                    let title = request.title.as_deref().unwrap_or("Untitled notes");
+                    notes.add_text(engine, title, text, None, request.course.as_deref())
+                }
+                (None, None) => Err(StudyNestError::ConfigError(
+                    "Either path or text is required".to_string(),
+                )),
+            }
+        })
+    }
+
+    pub fn list_documents(&self) -> Vec<DocumentInfo> {
+        self.notes.lock().unwrap().documents().to_vec()
+    }
+
+    pub fn delete_document(&self, id: &str) -> Result<()> {
+        self.notes.lock().unwrap().remove(id)
+    }
+
+    /// Answer a question from the notes index, returning the chunks the answer cites
+    pub fn ask(&self, request: AskRequest) -> Result<AskResponse> {
+        let model = self.notes_embedding_model(request.embedding_model.as_deref())?;
+        let top_k = request.top_k.unwrap_or_else(|| self.notes.lock().unwrap().config().top_k);
+        let filter = SearchFilter {
+            documents: request.document_ids.clone(),
+            course: request.course.clone(),
+            after: request.after,
+            before: request.before,
+        };
+        let reranker = self.reranker_model(request.rerank, request.reranker_model.as_deref())?;
+        let sources = self.with_embedder(&model, |_, engine| {
+            self.with_reranker(reranker.as_deref(), |reranker| {
+                self.notes
+                    .lock()
+                    .unwrap()
+                    .retrieve(engine, reranker, &request.question, top_k, &filter)
+            })
+        })?;
+
+        let request_id = request.request_id.clone();
+        self.with_control(request_id, request.timeout_ms, |control| {
+            let session_id = request.session_id.as_deref();
+            let conversation = self.conversation(session_id)?;
+            let mut engine = conversation.lock().unwrap();
+            if session_id.is_none() {
+                engine.clear_history();
+            }
+
+            let reply = {
+                let _permit = self.scheduler.acquire();
+                engine.chat_with_context(&request.question, &rag::build_context(&sources), control)?
+            };
+
+            if let Some(id) = session_id {
+                self.sessions.append_message(id, Role::User, &request.question)?;
+                self.sessions.append_message(id, Role::Assistant, &reply.content)?;
+            }
+
+            Ok(AskResponse {
+                citations: rag::cited(&reply.content, &sources),
+                message: MessageResponse {
+                    role: "assistant".to_string(),
+                    content: reply.content,
+                },
+                done: true,
+                finish_reason: reply.finish_reason,
+                sources,
+            })
+        })
+    }
+
+    /// Search the notes index, returning ranked snippets with the query terms highlighted.
+    /// Keyword search works without an embedding model.
+    pub fn search(&self, request: SearchRequest) -> Result<SearchResponse> {
+        let top_k = request.top_k.unwrap_or(10);
+        let filter = SearchFilter {
+            documents: request.document_ids.clone(),
+            course: request.course.clone(),
+            after: request.after,
+            before: request.before,
+        };
+        let reranker = self.reranker_model(request.rerank, request.reranker_model.as_deref())?;
+        let empty = self.notes.lock().unwrap().documents().is_empty();
+        let hits = if request.mode == SearchMode::Keyword || empty {
+            self.with_reranker(reranker.as_deref(), |reranker| {
+                self.notes
+                    .lock()
+                    .unwrap()
+                    .search(None, reranker, &request.query, top_k, &filter, request.mode)
+            })?
+        } else {
+            let model = self.notes_embedding_model(request.embedding_model.as_deref())?;
+            self.with_embedder(&model, |_, engine| {
+                self.with_reranker(reranker.as_deref(), |reranker| {
+                    self.notes
+                        .lock()
+                        .unwrap()
+                        .search(Some(engine), reranker, &request.query, top_k, &filter, request.mode)
+                })
+            })?
+        };
+        Ok(SearchResponse {
+            mode: request.mode,
+            hits,
+        })
+    }
+
+    /// Image files described best by the query, from `paths` and the images under `dir`.
+    /// Image embeddings are kept while the model stays loaded, so searching the same
+    /// photos again only embeds the new and changed ones.
+    pub fn search_images(&self, request: SearchImagesRequest) -> Result<SearchImagesResponse> {
+        let model = self.image_model(request.model.as_deref())?;
+        let mut paths: Vec<PathBuf> = request.paths.iter().map(PathBuf::from).collect();
+        if let Some(dir) = &request.dir {
+            paths.extend(image_search::find_images(dir)?);
+        }
+        let top_k = request.top_k.unwrap_or(10);
+        self.with_image_model(&model, |engine| {
+            Ok(SearchImagesResponse {
+                model: model.clone(),
+                hits: engine.search(&request.query, &paths, top_k)?,
+            })
+        })
+    }
+
+    /// How well each of `labels` describes the image
+    pub fn classify_image(&self, request: ClassifyImageRequest) -> Result<ClassifyImageResponse> {
+        let model = self.image_model(request.model.as_deref())?;
+        let labels: Vec<&str> = request.labels.iter().map(String::as_str).collect();
+        self.with_image_model(&model, |engine| {
+            Ok(ClassifyImageResponse {
+                model: model.clone(),
+                labels: engine.classify(&request.path, &labels)?,
+            })
+        })
+    }
+
+    fn image_model(&self, requested: Option<&str>) -> Result<String> {
+        requested
+            .or(self.config.image_model.as_deref())
+            .map(str::to_string)
+            .ok_or_else(|| StudyNestError::ConfigError("No image model given".to_string()))
+    }
+
+    /// Run `f` with the image model `model`, loading it if another one is loaded
+    fn with_image_model<T>(
+        &self,
+        model: &str,
+        f: impl FnOnce(&mut ImageSearchEngine) -> Result<T>,
+    ) -> Result<T> {
+        let mut images = self.images.lock().unwrap();
+        if !matches!(&*images, Some((name, _)) if name == model) {
+            let info = self.registry.find(model)?;
+            let siglip = format!("{:?} {:?}", info.architecture, info.model_type)
+                .to_lowercase()
+                .contains("siglip");
+            if info.kind != ModelKind::Vision || !siglip {
+                return Err(StudyNestError::ConfigError(format!(
+                    "{} is not a Siglip2 image-text model ({:?})",
+                    info.name, info.kind
+                )));
+            }
+            *images = None;
+            let config = ImageSearchConfig::default()
+                .with_model_path(info.path.to_string_lossy())
+                .with_device(Self::parse_device(&self.config.device));
+            *images = Some((model.to_string(), ImageSearchEngine::new(config)?));
+        }
+        let (_, engine) = images.as_mut().expect("image model loaded above");
+        f(engine)
+    }
+
+    /// Pages of a document in the notes index, or inline text, to write study material
+    /// from, with the document's course
+    fn study_sources(
+        &self,
+        document_id: Option<&str>,
+        pages: Option<&[usize]>,
+        text: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(Vec<SourceText>, Option<String>)> {
+        match (document_id, text) {
+            (Some(id), _) => {
+                let notes = self.notes.lock().unwrap();
+                let document = notes
+                    .document(id)
+                    .ok_or_else(|| StudyNestError::ConfigError(format!("Unknown document: {}", id)))?;
+                let sources = notes
+                    .pages(id)
+                    .into_iter()
+                    .filter(|(page, _)| pages.is_none_or(|pages| pages.contains(page)))
+                    .map(|(page, text)| {
+                        SourceText::new(text)
+                            .with_document(id)
+                            .with_title(document.title.as_str())
+                            .with_page(page)
+                    })
+                    .collect();
+                Ok((sources, document.course.clone()))
+            }
+            (None, Some(text)) => {
+                let source = SourceText::new(text);
+                let source = match title {
+                    Some(title) => source.with_title(title),
+                    None => source,
+                };
+                Ok((vec![source], None))
+            }
+            (None, None) => Err(StudyNestError::ConfigError(
+                "Either document_id or text is required".to_string(),
+            )),
+        }
+    }
+
+    /// Write flashcards from a document in the notes index or from inline text with the
+    /// loaded chat model. Cards from a document are tagged with its course.
+    pub fn generate_flashcards(&self, request: FlashcardsRequest) -> Result<FlashcardSet> {
+        let (sources, course) = self.study_sources(
+            request.document_id.as_deref(),
+            request.pages.as_deref(),
+            request.text.as_deref(),
+            request.title.as_deref(),
+        )?;
+        let mut tags = request.tags.clone().unwrap_or_default();
+        tags.extend(course);
+
+        let mut config = FlashcardConfig::default().with_tags(tags);
+        if let Some(kinds) = &request.kinds {
+            config = config.with_kinds(kinds.clone());
+        }
+        if let Some(max_cards) = request.max_cards {
+            config = config.with_max_cards(max_cards);
+        }
+        let generator = FlashcardGenerator::new(config);
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            let conversation = self.conversation(None)?;
+            let mut engine = conversation.lock().unwrap();
+            let _permit = self.scheduler.acquire();
+            generator.generate(&mut engine, &sources, control)
+        })
+    }
+
+    /// Write a quiz on a document in the notes index or on inline text with the loaded
+    /// chat model
+    pub fn generate_quiz(&self, request: QuizRequest) -> Result<Quiz> {
+        let (sources, _) = self.study_sources(
+            request.document_id.as_deref(),
+            request.pages.as_deref(),
+            request.text.as_deref(),
+            request.title.as_deref(),
+        )?;
+        let title = request
+            .title
+            .clone()
+            .or_else(|| sources.first().and_then(|s| s.source.title.clone()));
+
+        let mut config = QuizConfig::default();
+        if let Some(kinds) = &request.kinds {
+            config = config.with_kinds(kinds.clone());
+        }
+        if let Some(max_questions) = request.max_questions {
+            config = config.with_max_questions(max_questions);
+        }
+        if let Some(options) = request.options {
+            config = config.with_options(options);
+        }
+        let generator = QuizGenerator::new(config);
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            let conversation = self.conversation(None)?;
+            let mut engine = conversation.lock().unwrap();
+            let _permit = self.scheduler.acquire();
+            generator.generate(&mut engine, &sources, title.clone(), control)
+        })
+    }
+
+    /// Grade answers to quiz questions; short answers are graded by the loaded chat model
+    pub fn grade_quiz(&self, request: GradeQuizRequest) -> Result<QuizResult> {
+        let mut config = GradingConfig::default();
+        if let Some(pass_score) = request.pass_score {
+            config = config.with_pass_score(pass_score);
+        }
+        let grader = QuizGrader::new(config);
+        // Choice questions are graded without the model, which need not be loaded
+        let needs_model = request
+            .questions
+            .iter()
+            .any(|q| q.kind == QuestionKind::ShortAnswer && request.answers.contains_key(&q.id));
+        if !needs_model {
+            let control = GenerationControl::default();
+            return grader.grade_quiz(None, &request.questions, &request.answers, &control);
+        }
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            let conversation = self.conversation(None)?;
+            let mut engine = conversation.lock().unwrap();
+            let _permit = self.scheduler.acquire();
+            grader.grade_quiz(Some(&mut engine), &request.questions, &request.answers, control)
+        })
+    }
+
+    /// Summarize a document in the notes index or inline text with the loaded chat
+    /// model, calling `progress` after each step. An interrupted summary continues where
+    /// it stopped when the same request is sent again.
+    pub fn summarize(
+        &self,
+        request: SummarizeRequest,
+        mut progress: impl FnMut(&SummaryProgress),
+    ) -> Result<Summary> {
+        let (sources, _) = self.study_sources(
+            request.document_id.as_deref(),
+            request.pages.as_deref(),
+            request.text.as_deref(),
+            request.title.as_deref(),
+        )?;
+        let title = request
+            .title
+            .clone()
+            .or_else(|| sources.first().and_then(|s| s.source.title.clone()));
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            let conversation = self.conversation(None)?;
+            let mut engine = conversation.lock().unwrap();
+
+            let checkpoint = summary_checkpoint(&self.config.summaries_dir, engine.model_path(), &request);
+            let mut config = SummaryConfig::default()
+                .with_style(request.style)
+                .with_checkpoint(checkpoint);
+            if let Some(max_tokens) = request.max_tokens {
+                config = config.with_max_tokens(max_tokens);
+            }
+            let summarizer = Summarizer::new(config);
+
+            let _permit = self.scheduler.acquire();
+            summarizer.summarize(&mut engine, &sources, title.as_deref(), control, &mut progress)
+        })
+    }
+
+    /// Transcribe a lecture recording and take notes of it, calling `progress` after each
+    /// step. Like summaries, notes that stop early are resumed by the same request.
+    pub fn lecture_notes(
+        &self,
+        request: LectureNotesRequest,
+        mut progress: impl FnMut(&LectureProgress),
+    ) -> Result<LectureNotes> {
+        let path = std::path::Path::new(&request.path);
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| StudyNestError::AudioError(format!("Cannot read {}: {}", request.path, e)))?;
+        let model = request
+            .stt_model
+            .as_deref()
+            .or(self.config.stt_model.as_deref())
+            .ok_or_else(|| StudyNestError::ConfigError("No speech-to-text model given".to_string()))?;
+        let info = self.registry.find(model)?;
+        let model_type = info.stt_model_type.ok_or_else(|| {
+            StudyNestError::ConfigError(format!("{} is not a Moonshine speech-to-text model", info.name))
+        })?;
+        let mut stt_config = SttConfig::default()
+            .with_model_path(info.path.to_string_lossy())
+            .with_device(Self::parse_device(&self.config.device))
+            .with_model_type(model_type);
+        if let Some(vad_model) = &self.config.vad_model {
+            stt_config = stt_config.with_vad_model_path(vad_model);
+        }
+        let stt = SttEngine::new(stt_config)?;
+
+        let title = request.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| "Lecture".to_string())
+        });
+        let absolute = std::fs::canonicalize(path)?;
+        // Checkpoints are named after the recording; replies within are keyed by prompt
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        let key = serde_json::json!([absolute, metadata.len(), modified, info.path]);
+        let checkpoint = std::path::Path::new(&self.config.lectures_dir)
+            .join(format!("{}.json", sha1_smol::Sha1::from(key.to_string()).digest()));
+        let audio_link = request
+            .audio_link
+            .clone()
+            .unwrap_or_else(|| format!("file://{}", absolute.to_string_lossy().replace('\\', "/")));
+        let mut config = LectureConfig::default()
+            .with_glossary(request.glossary.unwrap_or(true))
+            .with_audio_link(audio_link)
+            .with_checkpoint(checkpoint);
+        if let Some(max_tokens) = request.max_tokens {
+            config = config.with_max_tokens(max_tokens);
+        }
+        let note_taker = NoteTaker::new(config);
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            // Fail before transcribing when no chat model is loaded, then leave it free
+            // for other requests while transcribing
+            let conversation = self.conversation(None)?;
+            let transcript = note_taker.transcribe(&stt, path, control, &mut progress)?;
+            let mut engine = conversation.lock().unwrap();
+            let _permit = self.scheduler.acquire();
+            note_taker.notes(&mut engine, &transcript, &title, control, &mut progress)
+        })
+    }
+
+    /// Read text or notes aloud into a WAV file, calling `progress` after each piece.
+    /// Without a `path`, the same text and voice are only synthesized once. A recording
+    /// given as `prompt_audio` is identified by its path, so a file changed in place under
+    /// the same name is not read again.
+    pub fn read_aloud(
+        &self,
+        request: ReadAloudRequest,
+        mut progress: impl FnMut(&TtsProgress),
+    ) -> Result<ReadAloudResponse> {
+        let model = request
+            .tts_model
+            .as_deref()
+            .or(self.config.tts_model.as_deref())
+            .ok_or_else(|| StudyNestError::ConfigError("No text-to-speech model given".to_string()))?;
+        let voice = request.voice.as_deref().or(self.config.tts_voice.as_deref());
+        let pitch = request.pitch.unwrap_or_default();
+        let speed = request.speed.unwrap_or_default();
+        let cache_path = request.path.is_none();
+        let path = match &request.path {
+            Some(path) => std::path::PathBuf::from(path),
+            None => {
+                let key = serde_json::json!([
+                    request.text,
+                    voice,
+                    model,
+                    pitch,
+                    speed,
+                    request.prompt_audio,
+                    request.prompt_text
+                ]);
+                std::path::Path::new(&self.config.audio_dir)
+                    .join(format!("{}.wav", sha1_smol::Sha1::from(key.to_string()).digest()))
+            }
+        };
+        if cache_path {
+            if let Ok(reader) = hound::WavReader::open(&path) {
+                let spec = reader.spec();
+                return Ok(ReadAloudResponse {
+                    path: path.to_string_lossy().to_string(),
+                    duration_ms: reader.duration() as u64 * 1000 / spec.sample_rate.max(1) as u64,
+                    sample_rate: spec.sample_rate,
+                    chunks: 0,
+                    cached: true,
+                    finish_reason: FinishReason::Stop,
+                });
+            }
+        }
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            self.with_tts(model, |tts| {
+                tts.set_voice(voice.unwrap_or(tts.default_voice()))?;
+                tts.set_style(pitch, speed);
+                tts.set_prompt_audio(request.prompt_audio.as_deref(), request.prompt_text.as_deref())?;
+                let result = {
+                    let _permit = self.scheduler.acquire();
+                    tts.synthesize(&request.text, control, &mut progress)?
+                };
+                // Partial audio is returned but not kept under the name of the whole text
+                let path = if cache_path && result.finish_reason != FinishReason::Stop {
+                    path.with_extension("partial.wav")
+                } else {
+                    path.clone()
+                };
+                tts.save_wav(&result, &path)?;
+                Ok(ReadAloudResponse {
+                    path: path.to_string_lossy().to_string(),
+                    duration_ms: result.duration_ms,
+                    sample_rate: result.sample_rate,
+                    chunks: result.chunks,
+                    cached: false,
+                    finish_reason: result.finish_reason,
+                })
+            })
+        })
+    }
+
+    /// Read text aloud without writing a file, calling `on_chunk` with audio as soon as it
+    /// is decoded so that playback can start right away. `path` is ignored and nothing is
+    /// cached. Stops sending when `on_chunk` fails.
+    pub fn read_aloud_stream(
+        &self,
+        request: ReadAloudRequest,
+        mut on_chunk: impl FnMut(&PcmChunk) -> Result<()>,
+    ) -> Result<ReadAloudStreamResponse> {
+        let model = request
+            .tts_model
+            .as_deref()
+            .or(self.config.tts_model.as_deref())
+            .ok_or_else(|| StudyNestError::ConfigError("No text-to-speech model given".to_string()))?;
+        let voice = request.voice.as_deref().or(self.config.tts_voice.as_deref());
+
+        self.with_control(request.request_id.clone(), request.timeout_ms, |control| {
+            self.with_tts(model, |tts| {
+                tts.set_voice(voice.unwrap_or(tts.default_voice()))?;
+                tts.set_style(request.pitch.unwrap_or_default(), request.speed.unwrap_or_default());
+                tts.set_prompt_audio(request.prompt_audio.as_deref(), request.prompt_text.as_deref())?;
+                let sample_rate = tts.sample_rate();
+                let mut audio_chunks = 0;
+                let result = {
+                    let _permit = self.scheduler.acquire();
+                    tts.synthesize_streaming(&request.text, control, |chunk| {
+                        audio_chunks += 1;
+                        on_chunk(&PcmChunk::new(chunk, sample_rate))
+                    })?
+                };
+                Ok(ReadAloudStreamResponse {
+                    duration_ms: result.duration_ms,
+                    sample_rate,
+                    audio_chunks,
+                    chunks: result.chunks,
+                    finish_reason: result.finish_reason,
+                })
+            })
         })
     }
 
-    pub fn get_available_models(&self) -> Vec<String> {
-        vec![
-            "Qwen2.5-0.5B-Instruct".to_string(),
-            "Qwen2.5-1.5B-Instruct".to_string(),
-            "Qwen2.5-3B-Instruct".to_string(),
-            "Qwen3-0.6B".to_string(),
-            "Qwen3-1.7B".to_string(),
-        ]
+    /// Export cards, writing them to `path` when given and returning the path; CSV is
+    /// returned as text when there is no path
+    pub fn export_flashcards(&self, request: ExportFlashcardsRequest) -> Result<String> {
+        let deck = request.deck.as_deref().unwrap_or("StudyNest");
+        match (&request.path, request.format) {
+            (Some(path), format) => {
+                flashcards::export(&request.cards, format, deck, path)?;
+                Ok(path.clone())
+            }
+            (None, DeckFormat::Csv) => Ok(flashcards::to_csv(&request.cards)),
+            (None, DeckFormat::Apkg) => Err(StudyNestError::ConfigError(
+                "A path is required to export an Anki package".to_string(),
+            )),
+        }
+    }
+
+    /// Schedule cards for review, returning how many were new. Cards already scheduled
+    /// keep their schedule.
+    pub fn add_cards(&self, request: AddCardsRequest) -> Result<usize> {
+        let deck = request.deck.as_deref().unwrap_or("StudyNest");
+        self.reviews.lock().unwrap().add_cards(request.cards, deck, now_millis())
+    }
+
+    /// Stop scheduling cards and drop their reviews, returning how many were found
+    pub fn remove_cards(&self, ids: &[String]) -> Result<usize> {
+        self.reviews.lock().unwrap().remove_cards(ids)
+    }
+
+    /// Cards to review now, with when each would be due again after each rating
+    pub fn cards_due(&self, request: CardsDueRequest) -> DueQueue {
+        let query = DueQuery {
+            deck: request.deck,
+            limit: request.limit,
+        };
+        self.reviews.lock().unwrap().due(&query, now_millis())
+    }
+
+    /// Record a review and return the card with its next due date
+    pub fn review_card(&self, request: ReviewCardRequest) -> Result<ReviewCard> {
+        self.reviews
+            .lock()
+            .unwrap()
+            .review(&request.card_id, request.rating, now_millis(), request.duration_ms)
+    }
+
+    /// Change scheduler settings and return them all
+    pub fn review_settings(&self, request: ReviewSettingsRequest) -> Result<SchedulerParameters> {
+        let mut reviews = self.reviews.lock().unwrap();
+        let current = reviews.parameters().clone();
+        let mut parameters = current.clone();
+        if let Some(retention) = request.desired_retention {
+            parameters = parameters.with_desired_retention(retention);
+        }
+        if let Some(days) = request.maximum_interval_days {
+            parameters = parameters.with_maximum_interval_days(days);
+        }
+        if let Some(steps) = request.learning_steps {
+            parameters = parameters.with_learning_steps(steps);
+        }
+        if let Some(steps) = request.relearning_steps {
+            parameters = parameters.with_relearning_steps(steps);
+        }
+        if let Some(cards) = request.new_per_day {
+            parameters = parameters.with_new_per_day(cards);
+        }
+        if parameters != current {
+            reviews.set_parameters(parameters.clone())?;
+        }
+        Ok(parameters)
+    }
+
+    /// Fit the scheduler to the review history. The fit runs on a copy of the history so
+    /// reviews are not blocked meanwhile.
+    pub fn optimize_scheduler(&self) -> Result<OptimizationReport> {
+        let (logs, weights) = {
+            let reviews = self.reviews.lock().unwrap();
+            (reviews.logs().to_vec(), reviews.parameters().weights.clone())
+        };
+        let report = review::optimize_weights(&logs, &weights, OPTIMIZE_ITERATIONS)?;
+        if report.updated {
+            self.reviews.lock().unwrap().set_weights(report.weights.clone())?;
+        }
+        eprintln!(
+            "[StudyNest] Fitted scheduler to {} reviews: log loss {:.4} -> {:.4}{}",
+            report.reviews,
+            report.loss_before,
+            report.loss_after,
+            if report.updated { "" } else { " (kept previous weights)" }
+        );
+        Ok(report)
+    }
+
+    /// Models installed under `checkpoints_dir`, optionally of one kind only
+    pub fn list_models(&self, kind: Option<ModelKind>) -> Result<Vec<ModelInfo>> {
+        match kind {
+            Some(kind) => self.registry.list(kind),
+            None => self.registry.scan(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summarize_request(text: &str) -> SummarizeRequest {
+        serde_json::from_value(serde_json::json!({"text": text})).unwrap()
+    }
+
+    #[test]
+    fn summary_checkpoints_are_keyed_by_content_style_and_model() {
+        let checkpoint =
+            |model: &str, request: &SummarizeRequest| summary_checkpoint("summaries", model, request);
+        let request = summarize_request("Photosynthesis turns light into sugar.");
+        let path = checkpoint("checkpoints/Qwen3-0.6B", &request);
+        // Changing the key orphans the checkpoints of interrupted summaries
+        assert_eq!(path, Path::new("summaries/bf25e991a0cfeeda548ce6499cadc7536bf64a94.json"));
+
+        // A retry of the same request resumes, whatever its id and timeout
+        let mut retry = summarize_request("Photosynthesis turns light into sugar.");
+        retry.request_id = Some("retry-1".to_string());
+        retry.timeout_ms = Some(1000);
+        assert_eq!(checkpoint("checkpoints/Qwen3-0.6B", &retry), path);
+
+        assert_ne!(checkpoint("checkpoints/Qwen2.5-0.5B-Instruct", &request), path);
+        let mut other_style = summarize_request("Photosynthesis turns light into sugar.");
+        other_style.style = SummaryStyle::Outline;
+        assert_ne!(checkpoint("checkpoints/Qwen3-0.6B", &other_style), path);
+        let mut shorter = summarize_request("Photosynthesis turns light into sugar.");
+        shorter.max_tokens = Some(128);
+        assert_ne!(checkpoint("checkpoints/Qwen3-0.6B", &shorter), path);
+        assert_ne!(checkpoint("checkpoints/Qwen3-0.6B", &summarize_request("Respiration")), path);
     }
 }
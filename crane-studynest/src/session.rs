@@ -0,0 +1,449 @@
+//! Persistent chat sessions stored as append-only JSONL files
+//!
+//! Every session lives in `<root>/<id>.jsonl`. The first line holds the session
+//! metadata and each following line is an event (message, rename, settings change).
+//! Loading a session replays its events, so a write interrupted by a crash only
+//! loses the last incomplete line.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{ChatMessage, Role};
+use crate::error::{Result, StudyNestError};
+
+/// Generation settings recorded with a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    pub max_tokens: usize,
+    pub temperature: f64,
+    pub top_p: f64,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            max_tokens: 2048,
+            temperature: 0.7,
+            top_p: 0.9,
+        }
+    }
+}
+
+/// A message stored in a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub role: Role,
+    pub content: String,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+}
+
+impl From<&StoredMessage> for ChatMessage {
+    fn from(message: &StoredMessage) -> Self {
+        ChatMessage::new(message.role, message.content.clone())
+    }
+}
+
+/// Session summary returned by listings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub name: String,
+    pub model: String,
+    pub settings: SessionSettings,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub message_count: usize,
+}
+
+/// A full chat session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    pub model: String,
+    pub settings: SessionSettings,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<StoredMessage>,
+}
+
+impl Session {
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            model: self.model.clone(),
+            settings: self.settings.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            message_count: self.messages.len(),
+        }
+    }
+
+    /// Messages converted for a `ChatEngine` history
+    pub fn chat_messages(&self) -> Vec<ChatMessage> {
+        self.messages.iter().map(ChatMessage::from).collect()
+    }
+
+    /// Render the session as Markdown
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n\n_Model: {}_\n\n", self.name, self.model);
+        for m in &self.messages {
+            let speaker = match m.role {
+                Role::System => "System",
+                Role::User => "You",
+                Role::Assistant => "Assistant",
+            };
+            out.push_str(&format!("**{}:**\n\n{}\n\n", speaker, m.content));
+        }
+        out
+    }
+}
+
+/// Supported export formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Markdown,
+}
+
+/// One line of a session file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionEvent {
+    Created {
+        id: String,
+        name: String,
+        model: String,
+        settings: SessionSettings,
+        timestamp: u64,
+    },
+    Message(StoredMessage),
+    Renamed { name: String, timestamp: u64 },
+    Settings { model: String, settings: SessionSettings, timestamp: u64 },
+}
+
+/// Open an append-only JSON lines file. If an interrupted write left the last line
+/// without its newline, one is written first, so the next record starts on a line of its
+/// own instead of being merged into the torn one.
+pub(crate) fn open_for_append(path: &Path) -> std::io::Result<File> {
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+    if file.metadata()?.len() > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+        }
+    }
+    Ok(file)
+}
+
+/// Current Unix time in milliseconds
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Append-only JSONL session store
+pub struct SessionStore {
+    root: PathBuf,
+}
+
+impl SessionStore {
+    /// Open (and create if needed) a session store in the given directory
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path_for(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(StudyNestError::ConfigError(format!("Invalid session id: {}", id)));
+        }
+        Ok(self.root.join(format!("{}.jsonl", id)))
+    }
+
+    fn existing_path(&self, id: &str) -> Result<PathBuf> {
+        let path = self.path_for(id)?;
+        if !path.exists() {
+            return Err(StudyNestError::SessionNotFound(id.to_string()));
+        }
+        Ok(path)
+    }
+
+    pub(crate) fn new_id() -> String {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+        format!("{:x}-{:x}-{:04x}", nanos, std::process::id(), seq & 0xffff)
+    }
+
+    fn append(path: &Path, event: &SessionEvent) -> Result<()> {
+        let mut file = open_for_append(path)?;
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Create a new empty session
+    pub fn create(&self, name: &str, model: &str, settings: SessionSettings) -> Result<Session> {
+        let id = Self::new_id();
+        let timestamp = now_millis();
+        let event = SessionEvent::Created {
+            id: id.clone(),
+            name: name.to_string(),
+            model: model.to_string(),
+            settings: settings.clone(),
+            timestamp,
+        };
+        Self::append(&self.path_for(&id)?, &event)?;
+
+        Ok(Session {
+            id,
+            name: name.to_string(),
+            model: model.to_string(),
+            settings,
+            created_at: timestamp,
+            updated_at: timestamp,
+            messages: Vec::new(),
+        })
+    }
+
+    /// Load a session by replaying its events
+    pub fn load(&self, id: &str) -> Result<Session> {
+        Self::read_file(&self.existing_path(id)?)
+    }
+
+    fn read_file(path: &Path) -> Result<Session> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut session: Option<Session> = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A torn trailing line from an interrupted write is skipped
+            let Ok(event) = serde_json::from_str::<SessionEvent>(&line) else {
+                continue;
+            };
+            match (event, session.as_mut()) {
+                (SessionEvent::Created { id, name, model, settings, timestamp }, None) => {
+                    session = Some(Session {
+                        id,
+                        name,
+                        model,
+                        settings,
+                        created_at: timestamp,
+                        updated_at: timestamp,
+                        messages: Vec::new(),
+                    });
+                }
+                (SessionEvent::Message(message), Some(s)) => {
+                    s.updated_at = message.timestamp;
+                    s.messages.push(message);
+                }
+                (SessionEvent::Renamed { name, timestamp }, Some(s)) => {
+                    s.name = name;
+                    s.updated_at = timestamp;
+                }
+                (SessionEvent::Settings { model, settings, timestamp }, Some(s)) => {
+                    s.model = model;
+                    s.settings = settings;
+                    s.updated_at = timestamp;
+                }
+                _ => {}
+            }
+        }
+
+        session.ok_or_else(|| {
+            StudyNestError::ConfigError(format!("Corrupt session file: {}", path.display()))
+        })
+    }
+
+    /// List all sessions, most recently updated first
+    pub fn list(&self) -> Result<Vec<SessionInfo>> {
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+                continue;
+            }
+            match Self::read_file(&path) {
+                Ok(session) => sessions.push(session.info()),
+                Err(e) => eprintln!("[StudyNest] Skipping session {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        Ok(sessions)
+    }
+
+    /// Append a message to a session
+    pub fn append_message(&self, id: &str, role: Role, content: &str) -> Result<StoredMessage> {
+        let message = StoredMessage {
+            role,
+            content: content.to_string(),
+            timestamp: now_millis(),
+        };
+        Self::append(&self.existing_path(id)?, &SessionEvent::Message(message.clone()))?;
+        Ok(message)
+    }
+
+    /// Rename a session
+    pub fn rename(&self, id: &str, name: &str) -> Result<()> {
+        let event = SessionEvent::Renamed {
+            name: name.to_string(),
+            timestamp: now_millis(),
+        };
+        Self::append(&self.existing_path(id)?, &event)
+    }
+
+    /// Update the model and generation settings of a session
+    pub fn update_settings(&self, id: &str, model: &str, settings: SessionSettings) -> Result<()> {
+        let event = SessionEvent::Settings {
+            model: model.to_string(),
+            settings,
+            timestamp: now_millis(),
+        };
+        Self::append(&self.existing_path(id)?, &event)
+    }
+
+    /// Delete a session
+    pub fn delete(&self, id: &str) -> Result<()> {
+        fs::remove_file(self.existing_path(id)?)?;
+        Ok(())
+    }
+
+    /// Export a session as a string in the given format
+    pub fn export(&self, id: &str, format: ExportFormat) -> Result<String> {
+        let session = self.load(id)?;
+        match format {
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(&session)?),
+            ExportFormat::Markdown => Ok(session.to_markdown()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store in an empty directory of its own under the system temp dir
+    fn temp_store(name: &str) -> SessionStore {
+        let dir = std::env::temp_dir().join(format!("studynest-sessions-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SessionStore::open(dir).unwrap()
+    }
+
+    fn settings(max_tokens: usize) -> SessionSettings {
+        SessionSettings { max_tokens, ..SessionSettings::default() }
+    }
+
+    #[test]
+    fn load_replays_messages() {
+        let store = temp_store("replay");
+        let id = store.create("Biology", "qwen", settings(512)).unwrap().id;
+        store.append_message(&id, Role::User, "What is osmosis?").unwrap();
+        store.append_message(&id, Role::Assistant, "Water crossing a membrane.").unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.name, "Biology");
+        assert_eq!(session.model, "qwen");
+        assert_eq!(session.settings, settings(512));
+        let contents: Vec<_> = session.messages.iter().map(|m| (m.role, m.content.as_str())).collect();
+        assert_eq!(
+            contents,
+            [(Role::User, "What is osmosis?"), (Role::Assistant, "Water crossing a membrane.")]
+        );
+        assert_eq!(session.updated_at, session.messages[1].timestamp);
+        assert_eq!(store.list().unwrap()[0].message_count, 2);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn torn_trailing_line_is_skipped_and_repaired() {
+        let store = temp_store("torn");
+        let id = store.create("Chemistry", "", SessionSettings::default()).unwrap().id;
+        store.append_message(&id, Role::User, "first").unwrap();
+        let path = store.path_for(&id).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"message","role":"user","cont"#).unwrap();
+        drop(file);
+
+        assert_eq!(store.load(&id).unwrap().messages.len(), 1);
+
+        // The next event starts on a line of its own instead of extending the torn one
+        store.append_message(&id, Role::User, "second").unwrap();
+        let session = store.load(&id).unwrap();
+        let contents: Vec<_> = session.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["first", "second"]);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn open_for_append_ends_the_last_line() {
+        let store = temp_store("newline");
+        let path = store.root().join("log.jsonl");
+        fs::write(&path, "{\"a\":1}").unwrap();
+        open_for_append(&path).unwrap().write_all(b"{\"b\":2}\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"a\":1}\n{\"b\":2}\n");
+
+        // Nothing is added to an empty file or after a complete line
+        open_for_append(&path).unwrap();
+        let empty = store.root().join("empty.jsonl");
+        open_for_append(&empty).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"a\":1}\n{\"b\":2}\n");
+        assert_eq!(fs::read_to_string(&empty).unwrap(), "");
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn rename_and_settings_events_are_replayed() {
+        let store = temp_store("events");
+        let id = store.create("Draft", "qwen", settings(256)).unwrap().id;
+        store.rename(&id, "Physics").unwrap();
+        store.update_settings(&id, "qwen3", settings(1024)).unwrap();
+        store.rename(&id, "Physics 2").unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.name, "Physics 2");
+        assert_eq!(session.model, "qwen3");
+        assert_eq!(session.settings, settings(1024));
+        assert!(session.messages.is_empty());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        let store = temp_store("ids");
+        for id in ["", "../secrets", "a/b", "a.b", "x y"] {
+            assert!(
+                matches!(store.load(id), Err(StudyNestError::ConfigError(_))),
+                "{id:?} was accepted"
+            );
+            assert!(store.rename(id, "name").is_err());
+        }
+        assert!(matches!(store.load("abc-123"), Err(StudyNestError::SessionNotFound(_))));
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+}
@@ -1,14 +1,29 @@
 //! Speech-to-Text (STT) module using Moonshine ASR
+//!
+//! Moonshine transcribes up to a few tens of seconds at a time, so longer recordings are
+//! first split into stretches of speech with the Silero voice activity detector.
 
+use std::ops::Range;
 use std::path::Path;
 use crate::device::DeviceType;
+#[cfg(feature = "onnx")]
+use crate::device::get_device;
 use crate::error::{Result, StudyNestError};
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "onnx")]
 use crane_core::models::moonshine_asr::MoonshineASR;
+#[cfg(feature = "onnx")]
+use crane_core::models::silero_vad::{Vad, VadConfig};
+#[cfg(feature = "onnx")]
+use tokenizers::Tokenizer;
+
+/// Sample rate Moonshine and the voice activity detector work at
+const SAMPLE_RATE: u32 = 16000;
 
 /// Supported STT model types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SttModelType {
     /// Moonshine Tiny - fastest, lower accuracy
     MoonshineTiny,
@@ -23,6 +38,15 @@ impl Default for SttModelType {
 }
 
 impl SttModelType {
+    /// Variant of a Moonshine checkpoint, told apart by the `hidden_size` in its `config.json`
+    pub fn from_config(model_type: Option<&str>, hidden_size: Option<usize>) -> Option<Self> {
+        match (model_type, hidden_size) {
+            (Some("moonshine"), Some(288)) => Some(SttModelType::MoonshineTiny),
+            (Some("moonshine"), Some(416)) => Some(SttModelType::MoonshineBase),
+            _ => None,
+        }
+    }
+
     fn model_name(&self) -> &'static str {
         match self {
             SttModelType::MoonshineTiny => "tiny",
@@ -39,6 +63,10 @@ pub struct SttConfig {
     pub device: DeviceType,
     pub sample_rate: u32,
     pub token_rate: Option<usize>,
+    /// Silero VAD model file; downloaded from the Hugging Face Hub when not given
+    pub vad_model_path: Option<String>,
+    /// Longer stretches of speech are split for transcription
+    pub max_segment_ms: usize,
 }
 
 impl Default for SttConfig {
@@ -47,8 +75,10 @@ impl Default for SttConfig {
             model_path: "checkpoints/moonshine-tiny".to_string(),
             model_type: SttModelType::MoonshineTiny,
             device: DeviceType::Auto,
-            sample_rate: 16000,
+            sample_rate: SAMPLE_RATE,
             token_rate: None,
+            vad_model_path: None,
+            max_segment_ms: 20_000,
         }
     }
 }
@@ -68,6 +98,11 @@ impl SttConfig {
         self.model_type = model_type;
         self
     }
+
+    pub fn with_vad_model_path(mut self, path: impl Into<String>) -> Self {
+        self.vad_model_path = Some(path.into());
+        self
+    }
 }
 
 /// STT result containing transcribed text and metadata
@@ -84,6 +119,8 @@ pub struct SttEngine {
     config: SttConfig,
     #[cfg(feature = "onnx")]
     model: MoonshineASR,
+    #[cfg(feature = "onnx")]
+    tokenizer: Tokenizer,
     #[cfg(not(feature = "onnx"))]
     _phantom: std::marker::PhantomData<()>,
 }
@@ -92,28 +129,46 @@ impl SttEngine {
     /// Create a new STT engine
     #[cfg(feature = "onnx")]
     pub fn new(config: SttConfig) -> Result<Self> {
-        println!("[StudyNest] Initializing STT engine with model: {}", config.model_path);
+        eprintln!("[StudyNest] Initializing STT engine with model: {}", config.model_path);
         
         let device = get_device(config.device)?;
         
         // Verify model path exists
-        if !Path::new(&config.model_path).exists() {
+        let model_path = Path::new(&config.model_path);
+        if !model_path.exists() {
             return Err(StudyNestError::ConfigError(format!(
                 "Model path does not exist: {}. Please download the model first.",
                 config.model_path
             )));
         }
-        
+
+        // Hugging Face ONNX exports keep the graphs in `onnx/` and the tokenizer at the top
+        let onnx_dir = if model_path.join("encoder_model.onnx").exists() {
+            model_path.to_path_buf()
+        } else {
+            model_path.join("onnx")
+        };
+        let tokenizer_file = [model_path.join("tokenizer.json"), onnx_dir.join("tokenizer.json")]
+            .into_iter()
+            .chain(model_path.parent().map(|parent| parent.join("tokenizer.json")))
+            .find(|path| path.exists())
+            .ok_or_else(|| StudyNestError::ConfigError(format!(
+                "No tokenizer.json found with the model in {}",
+                config.model_path
+            )))?;
+        let tokenizer = Tokenizer::from_file(&tokenizer_file)
+            .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
+
         let model = MoonshineASR::new(
-            &config.model_path,
+            &onnx_dir.to_string_lossy(),
             config.model_type.model_name(),
             config.token_rate,
             &device,
         ).map_err(|e| StudyNestError::ModelError(e.to_string()))?;
         
-        println!("[StudyNest] STT engine initialized on {}", config.device);
+        eprintln!("[StudyNest] STT engine initialized on {}", config.device);
         
-        Ok(Self { config, model })
+        Ok(Self { config, model, tokenizer })
     }
 
     #[cfg(not(feature = "onnx"))]
@@ -136,10 +191,10 @@ impl SttEngine {
             )));
         }
         
-        println!("[StudyNest] Transcribing audio: {}", audio_path.display());
+        eprintln!("[StudyNest] Transcribing audio: {}", audio_path.display());
         
         // Load audio file
-        let audio = Self::load_wav_file(audio_path)?;
+        let audio = Self::load_audio(audio_path)?;
         let duration_ms = (audio.len() as f64 / self.config.sample_rate as f64 * 1000.0) as u64;
         
         // Run inference
@@ -151,7 +206,7 @@ impl SttEngine {
         
         let processing_time_ms = start.elapsed().as_millis() as u64;
         
-        println!("[StudyNest] Transcription complete in {}ms", processing_time_ms);
+        eprintln!("[StudyNest] Transcription complete in {}ms", processing_time_ms);
         
         Ok(SttResult {
             text,
@@ -174,7 +229,7 @@ impl SttEngine {
         let start = std::time::Instant::now();
         let duration_ms = (audio_samples.len() as f64 / self.config.sample_rate as f64 * 1000.0) as u64;
         
-        println!("[StudyNest] Transcribing {} samples ({:.2}s)", 
+        eprintln!("[StudyNest] Transcribing {} samples ({:.2}s)", 
             audio_samples.len(), 
             duration_ms as f64 / 1000.0
         );
@@ -200,65 +255,122 @@ impl SttEngine {
         ))
     }
 
-    /// Load WAV file and return audio samples
-    fn load_wav_file(path: &Path) -> Result<Vec<f32>> {
+    /// Split audio into stretches of speech, as sample ranges, leaving out silence.
+    /// Stretches longer than `max_segment_ms` are split at the longest pause within.
+    #[cfg(feature = "onnx")]
+    pub fn speech_segments(&self, audio_samples: &[f32]) -> Result<Vec<Range<usize>>> {
+        let mut config = VadConfig::default();
+        config.use_cpu = true;
+        config.max_speech = self.config.max_segment_ms;
+        let mut vad = Vad::new(config);
+        vad.load(self.config.vad_model_path.as_deref().unwrap_or_default())
+            .map_err(|e| StudyNestError::ModelError(format!("Failed to load VAD model: {}", e)))?;
+        vad.segment_audio(audio_samples)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        let segments = vad.flush().map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        Ok(segments
+            .iter()
+            .map(|&(start, end)| start.min(audio_samples.len())..end.min(audio_samples.len()))
+            .filter(|range| !range.is_empty())
+            .collect())
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    pub fn speech_segments(&self, _audio_samples: &[f32]) -> Result<Vec<Range<usize>>> {
+        Err(StudyNestError::FeatureNotEnabled(
+            "ONNX feature not enabled. Compile with --features onnx for STT support.".to_string()
+        ))
+    }
+
+    /// Load a WAV file as 16 kHz mono samples, mixing down channels and resampling
+    pub fn load_audio<P: AsRef<Path>>(path: P) -> Result<Vec<f32>> {
+        let path = path.as_ref();
         let mut reader = hound::WavReader::open(path)
             .map_err(|e| StudyNestError::AudioError(format!("Failed to open WAV file: {}", e)))?;
         
         let spec = reader.spec();
         
-        // Validate format
-        if spec.sample_rate != 16000 {
-            return Err(StudyNestError::AudioError(format!(
-                "Unsupported sample rate: {}. Expected 16000 Hz.",
-                spec.sample_rate
-            )));
-        }
-        
-        if spec.channels != 1 {
-            return Err(StudyNestError::AudioError(format!(
-                "Unsupported channels: {}. Expected mono (1 channel).",
-                spec.channels
-            )));
-        }
-        
         // Read samples
-        let samples: Vec<f32> = match spec.bits_per_sample {
-            16 => {
+        let samples: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Int, 16) => {
                 reader.samples::<i16>()
-                    .map(|s| s.unwrap() as f32 / 32768.0)
-                    .collect()
+                    .map(|s| s.map(|s| s as f32 / 32768.0))
+                    .collect::<std::result::Result<_, _>>()
             }
-            32 => {
+            (hound::SampleFormat::Int, 24) => {
                 reader.samples::<i32>()
-                    .map(|s| s.unwrap() as f32 / 2147483648.0)
-                    .collect()
+                    .map(|s| s.map(|s| s as f32 / 8388608.0))
+                    .collect::<std::result::Result<_, _>>()
+            }
+            (hound::SampleFormat::Int, 32) => {
+                reader.samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / 2147483648.0))
+                    .collect::<std::result::Result<_, _>>()
+            }
+            (hound::SampleFormat::Float, 32) => {
+                reader.samples::<f32>().collect::<std::result::Result<_, _>>()
             }
             _ => {
                 return Err(StudyNestError::AudioError(format!(
-                    "Unsupported bit depth: {}. Expected 16 or 32 bits.",
+                    "Unsupported bit depth: {}. Expected 16, 24 or 32 bits.",
                     spec.bits_per_sample
                 )));
             }
+        }
+        .map_err(|e| StudyNestError::AudioError(format!("Failed to read WAV file: {}", e)))?;
+
+        let channels = spec.channels.max(1) as usize;
+        let mono: Vec<f32> = if channels == 1 {
+            samples
+        } else {
+            samples
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+                .collect()
         };
-        
-        Ok(samples)
+
+        Ok(resample(&mono, spec.sample_rate, SAMPLE_RATE))
     }
 
-    /// Decode token IDs to text
+    /// Decode token IDs to text, leaving out the start and end tokens
+    #[cfg(feature = "onnx")]
     fn decode_tokens(&self, tokens: &[i64]) -> Result<String> {
-        // Basic token decoding - in a full implementation, 
-        // this would use the model's tokenizer
-        // For now, return token IDs as a debug string
-        Ok(format!("[Tokens: {:?}]", tokens))
+        let ids: Vec<u32> = tokens
+            .iter()
+            .filter(|&&t| t != self.model.decoder_start_token_id && t != self.model.eos_token_id)
+            .map(|&t| t as u32)
+            .collect();
+        let text = self.tokenizer
+            .decode(&ids, true)
+            .map_err(|e| StudyNestError::TokenizationError(e.to_string()))?;
+        Ok(text.trim().to_string())
     }
 
     /// Get expected audio format info
     pub fn expected_format() -> &'static str {
-        "WAV format: 16kHz sample rate, mono channel, 16-bit PCM"
+        "WAV format: 16, 24 or 32-bit PCM or 32-bit float, any sample rate and channels (converted to 16kHz mono)"
     }
 }
 
+/// Linear interpolation between sample rates; enough for speech recognition
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() || from == 0 {
+        return samples.to_vec();
+    }
+    let step = from as f64 / to as f64;
+    let len = (samples.len() as f64 / step).floor() as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).copied().unwrap_or(current);
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
 /// List available STT models
 pub fn list_available_models() -> Vec<(&'static str, &'static str)> {
     vec![
@@ -0,0 +1,233 @@
+//! Anki package (`.apkg`) export
+//!
+//! A package is a zip archive holding a SQLite collection, `collection.anki2` in the
+//! schema 11 layout that every Anki version imports, and a `media` map. Cards are added
+//! as new notes of two note types, "StudyNest Basic" and "StudyNest Cloze", each with a
+//! Source field. Note type and deck ids are derived from their names and note GUIDs from
+//! the card ids, so importing an updated export again updates the same notes.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+use serde_json::json;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::error::{Result, StudyNestError};
+use crate::session::now_millis;
+use crate::study::SourceRef;
+use crate::study::flashcards::{CardKind, Flashcard, cloze_numbers};
+
+const BASIC_MODEL: &str = "StudyNest Basic";
+const CLOZE_MODEL: &str = "StudyNest Cloze";
+
+const SCHEMA: &str = "
+CREATE TABLE col (
+    id integer primary key, crt integer not null, mod integer not null,
+    scm integer not null, ver integer not null, dty integer not null,
+    usn integer not null, ls integer not null, conf text not null,
+    models text not null, decks text not null, dconf text not null, tags text not null
+);
+CREATE TABLE notes (
+    id integer primary key, guid text not null, mid integer not null,
+    mod integer not null, usn integer not null, tags text not null,
+    flds text not null, sfld integer not null, csum integer not null,
+    flags integer not null, data text not null
+);
+CREATE TABLE cards (
+    id integer primary key, nid integer not null, did integer not null,
+    ord integer not null, mod integer not null, usn integer not null,
+    type integer not null, queue integer not null, due integer not null,
+    ivl integer not null, factor integer not null, reps integer not null,
+    lapses integer not null, left integer not null, odue integer not null,
+    odid integer not null, flags integer not null, data text not null
+);
+CREATE TABLE revlog (
+    id integer primary key, cid integer not null, usn integer not null,
+    ease integer not null, ivl integer not null, lastIvl integer not null,
+    factor integer not null, time integer not null, type integer not null
+);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+";
+
+const CSS: &str = ".card { font-family: arial; font-size: 20px; text-align: center; \
+color: black; background-color: white; }\n.cloze { font-weight: bold; color: blue; }\n\
+.source { margin-top: 1em; font-size: 12px; color: gray; }";
+
+const LATEX_PRE: &str = "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\
+\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\
+\\setlength{\\parindent}{0in}\n\\begin{document}\n";
+
+fn anki_error(e: impl std::fmt::Display) -> StudyNestError {
+    StudyNestError::ConfigError(format!("Could not write the Anki package: {}", e))
+}
+
+fn sha1_hex(text: &str) -> String {
+    sha1_smol::Sha1::from(text).digest().to_string()
+}
+
+/// Positive id derived from a name, stable across exports
+fn stable_id(name: &str) -> i64 {
+    let hex = sha1_hex(name);
+    // 40 bits keeps it clear of the default ids and below Anki's millisecond ids
+    i64::from_str_radix(&hex[..10], 16).unwrap_or(0) + 2
+}
+
+/// Field text as HTML
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>")
+}
+
+fn field(name: &str, ord: usize) -> serde_json::Value {
+    json!({"name": name, "ord": ord, "sticky": false, "rtl": false, "font": "Arial", "size": 20, "media": []})
+}
+
+fn template(name: &str, qfmt: &str, afmt: &str) -> serde_json::Value {
+    json!({"name": name, "ord": 0, "qfmt": qfmt, "afmt": afmt, "bqfmt": "", "bafmt": "", "did": null, "bfont": "", "bsize": 0})
+}
+
+fn note_type(name: &str, kind: CardKind, deck_id: i64, now: i64) -> serde_json::Value {
+    let source = "{{#Source}}<div class=source>{{Source}}</div>{{/Source}}";
+    let (type_id, fields, templates, req) = match kind {
+        CardKind::Basic => (
+            0,
+            vec![field("Front", 0), field("Back", 1), field("Source", 2)],
+            template("Card 1", "{{Front}}", &format!("{{{{FrontSide}}}}\n\n<hr id=answer>\n\n{{{{Back}}}}{}", source)),
+            json!([[0, "any", [0]]]),
+        ),
+        CardKind::Cloze => (
+            1,
+            vec![field("Text", 0), field("Back Extra", 1), field("Source", 2)],
+            template("Cloze", "{{cloze:Text}}", &format!("{{{{cloze:Text}}}}<br>\n{{{{Back Extra}}}}{}", source)),
+            json!([]),
+        ),
+    };
+    json!({
+        "id": stable_id(name), "name": name, "type": type_id, "mod": now, "usn": -1,
+        "sortf": 0, "did": deck_id, "tmpls": [templates], "flds": fields, "css": CSS,
+        "latexPre": LATEX_PRE, "latexPost": "\\end{document}", "latexsvg": false,
+        "req": req, "tags": [], "vers": []
+    })
+}
+
+fn deck(id: i64, name: &str, now: i64) -> serde_json::Value {
+    json!({
+        "id": id, "name": name, "desc": "", "mod": now, "usn": -1, "dyn": 0, "conf": 1,
+        "collapsed": false, "browserCollapsed": false, "extendNew": 0, "extendRev": 0,
+        "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0], "timeToday": [0, 0]
+    })
+}
+
+fn deck_options() -> serde_json::Value {
+    json!({"1": {
+        "id": 1, "name": "Default", "mod": 0, "usn": 0, "maxTaken": 60, "autoplay": true,
+        "timer": 0, "replayq": true, "dyn": false,
+        "new": {"bury": false, "delays": [1, 10], "initialFactor": 2500, "ints": [1, 4, 0], "order": 1, "perDay": 20},
+        "rev": {"bury": false, "ease4": 1.3, "ivlFct": 1, "maxIvl": 36500, "perDay": 200, "hardFactor": 1.2},
+        "lapse": {"delays": [10], "leechAction": 1, "leechFails": 8, "minInt": 1, "mult": 0}
+    }})
+}
+
+/// Write the collection database for `cards` to `path`
+fn write_collection(cards: &[Flashcard], deck_name: &str, path: &Path) -> rusqlite::Result<()> {
+    let now_ms = now_millis() as i64;
+    let now = now_ms / 1000;
+    let deck_id = stable_id(&format!("deck:{}", deck_name));
+    let basic_id = stable_id(BASIC_MODEL);
+    let cloze_id = stable_id(CLOZE_MODEL);
+
+    let mut models = serde_json::Map::new();
+    models.insert(basic_id.to_string(), note_type(BASIC_MODEL, CardKind::Basic, deck_id, now));
+    models.insert(cloze_id.to_string(), note_type(CLOZE_MODEL, CardKind::Cloze, deck_id, now));
+    let decks = json!({
+        "1": deck(1, "Default", now),
+        deck_id.to_string(): deck(deck_id, deck_name, now),
+    });
+    let conf = json!({
+        "activeDecks": [deck_id], "curDeck": deck_id, "newSpread": 0, "collapseTime": 1200,
+        "timeLim": 0, "estTimes": true, "dueCounts": true, "curModel": basic_id,
+        "nextPos": cards.len() + 1, "sortType": "noteFld", "sortBackwards": false, "addToCur": true
+    });
+
+    let mut db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![
+            now - now % 86_400,
+            now_ms,
+            conf.to_string(),
+            serde_json::Value::Object(models).to_string(),
+            decks.to_string(),
+            deck_options().to_string()
+        ],
+    )?;
+
+    let mut card_id = now_ms;
+    for (position, card) in cards.iter().enumerate() {
+        let note_id = now_ms + position as i64;
+        let (model_id, ords): (i64, BTreeSet<u32>) = match card.kind {
+            CardKind::Basic => (basic_id, BTreeSet::from([1])),
+            CardKind::Cloze => (cloze_id, cloze_numbers(&card.front)),
+        };
+        let source = card.source.as_ref().map(SourceRef::label).unwrap_or_default();
+        let fields = [html(&card.front), html(&card.back), html(&source)].join("\u{1f}");
+        // Anki sorts and checks for duplicates on the first field without HTML
+        let sort_field = card.front.as_str();
+        let checksum = i64::from_str_radix(&sha1_hex(sort_field)[..8], 16).unwrap_or(0);
+        let tags = if card.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" {} ", card.tags.join(" "))
+        };
+        tx.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+            params![note_id, card.id, model_id, now, tags, fields, sort_field, checksum],
+        )?;
+        for ord in ords {
+            tx.execute(
+                "INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, 0, 0, ?6, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                params![card_id, note_id, deck_id, ord - 1, now, position as i64 + 1],
+            )?;
+            card_id += 1;
+        }
+    }
+    tx.commit()
+}
+
+/// Write `cards` as an Anki package with one deck named `deck`
+pub fn write_apkg(cards: &[Flashcard], deck: &str, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let collection = path.with_file_name(format!(".{}.anki2", name));
+    let _ = std::fs::remove_file(&collection);
+
+    let written = write_collection(cards, deck, &collection)
+        .map_err(anki_error)
+        .and_then(|()| Ok(std::fs::read(&collection)?));
+    let _ = std::fs::remove_file(&collection);
+    let database = written?;
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("collection.anki2", options).map_err(anki_error)?;
+    zip.write_all(&database)?;
+    zip.start_file("media", options).map_err(anki_error)?;
+    zip.write_all(b"{}")?;
+    zip.finish().map_err(anki_error)?;
+    Ok(())
+}
@@ -0,0 +1,387 @@
+//! Flashcards generated from notes
+//!
+//! Source text is split into pieces that fit the chat model's context and the model is
+//! asked for question/answer and cloze cards on each piece as JSON. Cards keep a
+//! reference to the page they came from, near-identical cards are dropped, and the
+//! result can be exported as CSV (with Anki's import headers) or as an Anki package.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bm25::query_terms;
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::error::{Result, StudyNestError};
+use crate::session::SessionStore;
+use crate::study::{SourceRef, SourceText, anki, generate_per_piece, near_duplicate};
+
+/// Instructions for the card writer, including the JSON shape of the reply
+const SYSTEM_PROMPT: &str = "You write flashcards for a student from their study notes. \
+Reply with JSON only, in this form:
+{\"cards\": [
+  {\"type\": \"basic\", \"front\": \"A question\", \"back\": \"Its short answer\", \"tags\": [\"topic\"]},
+  {\"type\": \"cloze\", \"front\": \"A sentence where the {{c1::key term}} is hidden.\", \"back\": \"\", \"tags\": [\"topic\"]}
+]}
+Each card tests one fact stated in the notes and makes sense without them. Cloze cards \
+hide one or a few words with {{c1::...}}, {{c2::...}}. Write the cards in the language \
+of the notes and tag them with one or two lowercase topics.";
+
+/// Kind of flashcard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CardKind {
+    /// Question on the front, answer on the back
+    Basic,
+    /// Text with `{{c1::...}}` deletions to recall
+    Cloze,
+}
+
+/// A generated flashcard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flashcard {
+    pub id: String,
+    pub kind: CardKind,
+    /// Question, or for cloze cards the text with its deletions
+    pub front: String,
+    /// Answer, or extra information shown with the answer of a cloze card
+    #[serde(default)]
+    pub back: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceRef>,
+}
+
+impl Flashcard {
+    /// Front with cloze deletions filled in
+    pub fn plain_front(&self) -> String {
+        match self.kind {
+            CardKind::Basic => self.front.clone(),
+            CardKind::Cloze => cloze_answer_text(&self.front),
+        }
+    }
+}
+
+/// Flashcard generation settings
+#[derive(Debug, Clone)]
+pub struct FlashcardConfig {
+    /// Kinds of cards to write
+    pub kinds: Vec<CardKind>,
+    /// Cards asked for per piece of text
+    pub cards_per_piece: usize,
+    /// Characters of source text given to the model at once
+    pub piece_chars: usize,
+    /// Token budget of each reply
+    pub max_tokens: usize,
+    /// Cards whose fronts share at least this fraction of their terms are duplicates
+    pub dedup_threshold: f32,
+    /// Tags added to every card
+    pub tags: Vec<String>,
+    /// Stop once this many cards are written
+    pub max_cards: Option<usize>,
+}
+
+impl Default for FlashcardConfig {
+    fn default() -> Self {
+        Self {
+            kinds: vec![CardKind::Basic, CardKind::Cloze],
+            cards_per_piece: 8,
+            piece_chars: 3000,
+            max_tokens: 1024,
+            dedup_threshold: 0.8,
+            tags: Vec::new(),
+            max_cards: None,
+        }
+    }
+}
+
+impl FlashcardConfig {
+    pub fn with_kinds(mut self, kinds: Vec<CardKind>) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    pub fn with_cards_per_piece(mut self, cards: usize) -> Self {
+        self.cards_per_piece = cards.max(1);
+        self
+    }
+
+    pub fn with_piece_chars(mut self, chars: usize) -> Self {
+        self.piece_chars = chars.max(1);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_dedup_threshold(mut self, threshold: f32) -> Self {
+        self.dedup_threshold = threshold;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn with_max_cards(mut self, max_cards: usize) -> Self {
+        self.max_cards = Some(max_cards);
+        self
+    }
+}
+
+/// Cards written from a set of sources
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashcardSet {
+    pub cards: Vec<Flashcard>,
+    /// `cancelled` or `timeout` when generation stopped before all sources were read
+    pub finish_reason: FinishReason,
+}
+
+/// File formats cards can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeckFormat {
+    /// Comma-separated values with Anki's import headers
+    Csv,
+    /// Anki package
+    Apkg,
+}
+
+/// A card as the model writes it
+#[derive(Debug, Deserialize)]
+struct DraftCard {
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(alias = "question", alias = "text", default)]
+    front: String,
+    #[serde(alias = "answer", alias = "extra", default)]
+    back: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// The model's reply: an object with a `cards` list, or the bare list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DraftReply {
+    Cards { cards: Vec<DraftCard> },
+    List(Vec<DraftCard>),
+}
+
+/// Writes flashcards with a chat model
+pub struct FlashcardGenerator {
+    config: FlashcardConfig,
+}
+
+impl FlashcardGenerator {
+    pub fn new(config: FlashcardConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &FlashcardConfig {
+        &self.config
+    }
+
+    /// Write cards for `sources`, without near-duplicates. Stops early, keeping the
+    /// cards written so far, when `control` cancels the request or its time runs out.
+    /// Pieces the model fails to answer in JSON are skipped unless all of them fail.
+    pub fn generate(
+        &self,
+        chat: &mut ChatEngine,
+        sources: &[SourceText],
+        control: &GenerationControl,
+    ) -> Result<FlashcardSet> {
+        if self.config.kinds.is_empty() {
+            return Err(StudyNestError::ConfigError("No card kinds requested".to_string()));
+        }
+        let pieces: Vec<SourceText> = sources
+            .iter()
+            .flat_map(|source| source.pieces(self.config.piece_chars))
+            .collect();
+        let limit = self.config.max_cards.unwrap_or(usize::MAX);
+
+        let mut cards: Vec<Flashcard> = Vec::new();
+        let finish_reason = generate_per_piece(
+            chat,
+            SYSTEM_PROMPT,
+            &pieces,
+            self.config.max_tokens,
+            control,
+            |piece| self.prompt(piece),
+            |reply: DraftReply, piece| {
+                let drafts = match reply {
+                    DraftReply::Cards { cards } | DraftReply::List(cards) => cards,
+                };
+                let written = drafts
+                    .into_iter()
+                    .filter_map(|draft| self.accept(draft, &piece.source))
+                    .take(self.config.cards_per_piece);
+                cards = deduplicate(cards.drain(..).chain(written).collect(), self.config.dedup_threshold);
+                cards.len() < limit
+            },
+        )?;
+        cards.truncate(limit);
+        Ok(FlashcardSet { cards, finish_reason })
+    }
+
+    fn prompt(&self, piece: &SourceText) -> String {
+        let kinds = match (
+            self.config.kinds.contains(&CardKind::Basic),
+            self.config.kinds.contains(&CardKind::Cloze),
+        ) {
+            (true, true) => "question/answer and cloze cards",
+            (false, true) => "cloze cards only",
+            _ => "question/answer cards only",
+        };
+        let from = match &piece.source.title {
+            Some(title) => format!(" from \"{}\"", title),
+            None => String::new(),
+        };
+        format!(
+            "Write up to {} flashcards ({}) from these notes{}:\n\n{}",
+            self.config.cards_per_piece, kinds, from, piece.text
+        )
+    }
+
+    /// Check and tidy a card the model wrote; `None` if it is unusable or of an
+    /// unwanted kind
+    fn accept(&self, draft: DraftCard, source: &SourceRef) -> Option<Flashcard> {
+        let front = draft.front.trim().to_string();
+        let back = draft.back.trim().to_string();
+        let kind = if !cloze_numbers(&front).is_empty() {
+            CardKind::Cloze
+        } else if draft.kind.as_deref() == Some("cloze") || back.is_empty() {
+            // A cloze card without deletions, or a question without an answer
+            return None;
+        } else {
+            CardKind::Basic
+        };
+        if front.is_empty() || !self.config.kinds.contains(&kind) {
+            return None;
+        }
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.config.tags.iter().chain(&draft.tags).map(|t| normalize_tag(t)) {
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Some(Flashcard {
+            id: SessionStore::new_id(),
+            kind,
+            front,
+            back,
+            tags,
+            source: Some(source.clone()),
+        })
+    }
+}
+
+/// Anki tags cannot contain spaces; they become underscores
+fn normalize_tag(tag: &str) -> String {
+    tag.trim()
+        .trim_start_matches('#')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase()
+}
+
+/// Positions of the `{{cN::...}}` deletions in `text` as `(number, start, end)`, with
+/// `start..end` spanning the whole marker
+fn cloze_markers(text: &str) -> Vec<(u32, usize, usize)> {
+    let mut markers = Vec::new();
+    let mut from = 0;
+    while let Some(found) = text[from..].find("{{c") {
+        let start = from + found;
+        let rest = &text[start + 3..];
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let number = rest[..digits].parse::<u32>().ok().filter(|n| *n > 0);
+        match (number, rest[digits..].starts_with("::"), rest.find("}}")) {
+            (Some(number), true, Some(close)) => {
+                let end = start + 3 + close + 2;
+                markers.push((number, start, end));
+                from = end;
+            }
+            _ => from = start + 3,
+        }
+    }
+    markers
+}
+
+/// Numbers of the cloze deletions in `text`; Anki makes one card per number
+pub fn cloze_numbers(text: &str) -> BTreeSet<u32> {
+    cloze_markers(text).into_iter().map(|(number, _, _)| number).collect()
+}
+
+/// Cloze text with every deletion replaced by its answer (hints dropped)
+pub fn cloze_answer_text(text: &str) -> String {
+    let mut plain = String::new();
+    let mut last = 0;
+    for (_, start, end) in cloze_markers(text) {
+        plain.push_str(&text[last..start]);
+        let inner = &text[start + 2..end - 2];
+        let answer = inner.split_once("::").map_or(inner, |(_, rest)| rest);
+        plain.push_str(answer.split_once("::").map_or(answer, |(answer, _hint)| answer));
+        last = end;
+    }
+    plain.push_str(&text[last..]);
+    plain
+}
+
+/// Drop cards whose front is near-identical to that of an earlier card: the same text
+/// ignoring case and punctuation, or sharing at least `threshold` of their terms
+/// (Jaccard similarity). The first of each group of duplicates is kept.
+pub fn deduplicate(cards: Vec<Flashcard>, threshold: f32) -> Vec<Flashcard> {
+    let mut kept: Vec<(HashSet<String>, Flashcard)> = Vec::new();
+    for card in cards {
+        let terms = query_terms(&card.plain_front());
+        if !kept.iter().any(|(other, _)| near_duplicate(&terms, other, threshold)) {
+            kept.push((terms, card));
+        }
+    }
+    kept.into_iter().map(|(_, card)| card).collect()
+}
+
+/// Cards as CSV with the header lines Anki (2.1.55 and later) reads on import, so the
+/// note type and tags columns are recognized. Columns: note type, front, back, tags,
+/// source.
+pub fn to_csv(cards: &[Flashcard]) -> String {
+    let mut csv = String::from(
+        "#separator:comma\n#html:false\n#notetype column:1\n#tags column:4\n\
+         #columns:Note type,Front,Back,Tags,Source\n",
+    );
+    for card in cards {
+        let notetype = match card.kind {
+            CardKind::Basic => "Basic",
+            CardKind::Cloze => "Cloze",
+        };
+        let source = card.source.as_ref().map(SourceRef::label).unwrap_or_default();
+        let fields = [notetype, &card.front, &card.back, &card.tags.join(" "), &source];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write cards to `path` as CSV or as an Anki package with the deck name `deck`
+pub fn export(cards: &[Flashcard], format: DeckFormat, deck: &str, path: impl AsRef<Path>) -> Result<()> {
+    match format {
+        DeckFormat::Csv => Ok(std::fs::write(path, to_csv(cards))?),
+        DeckFormat::Apkg => anki::write_apkg(cards, deck, path),
+    }
+}
@@ -0,0 +1,816 @@
+//! Notes from lecture recordings
+//!
+//! A recording is split into stretches of speech by the voice activity detector and
+//! each stretch is transcribed. The transcript is cleaned of fillers, stutters and
+//! repeated phrases, then cut into topics where the lecture's vocabulary shifts
+//! (TextTiling: a dip in word overlap between the minute before and the minute after a
+//! pause). The chat model gives each topic a heading and key points, each pointing back
+//! to the moment of the recording it comes from, and the glossary is collected like the
+//! key terms of a summary.
+//!
+//! Transcribed stretches and model replies are kept in a checkpoint, as for summaries, so
+//! notes that were cancelled or timed out continue where they stopped.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bm25;
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::error::{Result, StudyNestError};
+use crate::stt::SttEngine;
+use crate::study::summary::{Checkpoint, Run, Step, Summarizer, SummaryConfig, SummaryStyle};
+use crate::study::SourceText;
+
+/// Tokens of the notes prompt besides the transcript: instructions and chat template
+const PROMPT_OVERHEAD: usize = 320;
+
+/// Transcript on each side of a pause compared to find topic shifts
+const WINDOW_MS: u64 = 60_000;
+
+/// Checkpoint entry of the stretches of speech found in the recording
+const SPEECH_KEY: &str = "speech";
+
+const SAMPLE_RATE: u64 = 16_000;
+
+const NOTES_PROMPT: &str = "You write lecture notes. The user gives part of a lecture \
+transcript, one line per stretch of speech, each starting with its time as [mm:ss]. Reply \
+with JSON only, in this form:
+{\"title\": \"Short heading of the topic\", \"points\": [{\"point\": \"One key point in a sentence.\", \"at\": \"12:34\"}]}
+Give the key points in the order they come up: definitions, claims, steps, formulas, \
+examples and what the lecturer stresses, leaving out asides and class logistics. \"at\" is \
+the time of the line the point comes from. Write in the language of the transcript.";
+
+/// Hesitations dropped from transcripts
+const FILLERS: &[&str] = &["um", "umm", "uh", "uhh", "uhm", "erm", "hmm", "mm", "mhm"];
+
+/// Frequent English words that say nothing about the topic
+const STOPWORDS: &[&str] = &[
+    "about", "after", "again", "all", "also", "and", "any", "are", "because", "been", "before",
+    "being", "but", "can", "could", "did", "does", "doing", "don", "down", "each", "even",
+    "for", "from", "get", "going", "gonna", "got", "had", "has", "have", "her", "here", "him",
+    "his", "how", "its", "just", "know", "let", "like", "look", "make", "mean", "more", "most",
+    "much", "not", "now", "okay", "one", "only", "other", "our", "out", "over", "really",
+    "right", "said", "same", "say", "see", "she", "should", "some", "something", "such",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "thing",
+    "things", "think", "this", "those", "through", "too", "two", "very", "want", "was", "way",
+    "well", "were", "what", "when", "where", "which", "while", "who", "why", "will", "with",
+    "would", "yeah", "yes", "you", "your",
+];
+
+/// A stretch of speech and what was said
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
+/// What was said in a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcript {
+    pub segments: Vec<TranscriptSegment>,
+    /// Length of the recording
+    pub duration_ms: u64,
+    /// Stretches of speech taken from the checkpoint of an earlier, interrupted run
+    pub resumed: usize,
+    /// `Cancelled` or `Timeout` when only the start of the recording was transcribed
+    pub finish_reason: FinishReason,
+}
+
+impl Transcript {
+    /// A transcript made elsewhere, e.g. from subtitles
+    pub fn new(segments: Vec<TranscriptSegment>) -> Self {
+        Self {
+            duration_ms: segments.iter().map(|s| s.end_ms).max().unwrap_or(0),
+            segments,
+            resumed: 0,
+            finish_reason: FinishReason::Stop,
+        }
+    }
+}
+
+/// A key point and where in the recording it is made
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyPoint {
+    pub text: String,
+    pub at_ms: u64,
+}
+
+/// A stretch of the lecture about one topic
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LectureTopic {
+    pub title: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub points: Vec<KeyPoint>,
+}
+
+/// A term of the glossary and where it first comes up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub definition: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_ms: Option<u64>,
+}
+
+/// Notes taken from a lecture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LectureNotes {
+    pub title: String,
+    /// The notes as Markdown, times linking to the recording
+    pub markdown: String,
+    pub topics: Vec<LectureTopic>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glossary: Vec<GlossaryEntry>,
+    /// The cleaned transcript
+    pub transcript: Vec<TranscriptSegment>,
+    pub duration_ms: u64,
+    /// Steps taken from the checkpoint of an earlier, interrupted run
+    pub resumed: usize,
+    /// `Cancelled` or `Timeout` when the notes stop early
+    pub finish_reason: FinishReason,
+}
+
+/// Step of the pipeline being reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LectureStage {
+    /// Transcribing stretches of speech
+    Transcribe,
+    /// Writing the notes of each topic
+    Notes,
+    /// Collecting the glossary
+    Glossary,
+}
+
+/// Reported after each step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LectureProgress {
+    pub stage: LectureStage,
+    pub done: usize,
+    pub total: usize,
+    /// The step was taken from the checkpoint
+    pub resumed: bool,
+}
+
+/// How lecture notes are taken
+#[derive(Debug, Clone)]
+pub struct LectureConfig {
+    /// Topics are at least this long, except when the transcript must be split to fit
+    /// the model's context
+    pub min_topic_ms: u64,
+    /// Tokens of transcript given to the model for one topic
+    pub topic_tokens: usize,
+    /// Token budget of the notes of one topic
+    pub max_tokens: usize,
+    /// Whether to collect a glossary
+    pub glossary: bool,
+    /// Recording that times link to, as a path or URL; `#t=<seconds>` is appended
+    pub audio_link: Option<String>,
+    /// File keeping finished steps so interrupted notes can be resumed
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for LectureConfig {
+    fn default() -> Self {
+        Self {
+            min_topic_ms: 180_000,
+            topic_tokens: 2048,
+            max_tokens: 768,
+            glossary: true,
+            audio_link: None,
+            checkpoint: None,
+        }
+    }
+}
+
+impl LectureConfig {
+    pub fn with_min_topic_ms(mut self, ms: u64) -> Self {
+        self.min_topic_ms = ms;
+        self
+    }
+
+    pub fn with_topic_tokens(mut self, tokens: usize) -> Self {
+        self.topic_tokens = tokens;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_glossary(mut self, glossary: bool) -> Self {
+        self.glossary = glossary;
+        self
+    }
+
+    pub fn with_audio_link(mut self, link: impl Into<String>) -> Self {
+        self.audio_link = Some(link.into());
+        self
+    }
+
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+}
+
+/// Notes of one topic as the model writes them
+#[derive(Debug, Deserialize)]
+struct TopicReply {
+    #[serde(default, alias = "heading")]
+    title: String,
+    #[serde(default, alias = "key_points")]
+    points: Vec<PointReply>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PointReply {
+    Timed {
+        #[serde(alias = "text")]
+        point: String,
+        #[serde(default, alias = "time")]
+        at: Option<String>,
+    },
+    Plain(String),
+}
+
+/// Takes notes from lecture recordings with a speech-to-text engine and a chat model
+pub struct NoteTaker {
+    config: LectureConfig,
+}
+
+impl NoteTaker {
+    pub fn new(config: LectureConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &LectureConfig {
+        &self.config
+    }
+
+    /// Transcribe the WAV recording at `audio` stretch by stretch, calling `progress`
+    /// after each. Stops early when `control` cancels the request or its time runs out;
+    /// the stretches transcribed by then are kept in the checkpoint.
+    pub fn transcribe(
+        &self,
+        stt: &SttEngine,
+        audio: &Path,
+        control: &GenerationControl,
+        progress: &mut dyn FnMut(&LectureProgress),
+    ) -> Result<Transcript> {
+        let checkpoint_path = self.config.checkpoint.as_deref();
+        let mut checkpoint = Checkpoint::load(checkpoint_path);
+        let samples = SttEngine::load_audio(audio)?;
+        let duration_ms = to_ms(samples.len());
+
+        let spans: Vec<Range<usize>> = match checkpoint
+            .replies
+            .get(SPEECH_KEY)
+            .and_then(|spans| serde_json::from_str(spans).ok())
+        {
+            Some(spans) => spans,
+            None => {
+                eprintln!("[StudyNest] Looking for speech in {}", audio.display());
+                let spans = stt.speech_segments(&samples)?;
+                checkpoint.replies.insert(SPEECH_KEY.to_string(), serde_json::to_string(&spans)?);
+                checkpoint.save(checkpoint_path)?;
+                spans
+            }
+        };
+
+        let mut segments = Vec::with_capacity(spans.len());
+        let mut resumed = 0;
+        let mut finish_reason = FinishReason::Stop;
+        for (index, span) in spans.iter().enumerate() {
+            let key = format!("transcript {}-{}", span.start, span.end);
+            let (text, from_checkpoint) = match checkpoint.replies.get(&key) {
+                Some(text) => {
+                    resumed += 1;
+                    (text.clone(), true)
+                }
+                None => {
+                    if let Some(reason) = control.interruption() {
+                        finish_reason = reason;
+                        break;
+                    }
+                    let text = stt.transcribe_audio(&samples[span.clone()])?.text;
+                    checkpoint.replies.insert(key, text.clone());
+                    checkpoint.save(checkpoint_path)?;
+                    (text, false)
+                }
+            };
+            progress(&LectureProgress {
+                stage: LectureStage::Transcribe,
+                done: index + 1,
+                total: spans.len(),
+                resumed: from_checkpoint,
+            });
+            if !text.trim().is_empty() {
+                segments.push(TranscriptSegment {
+                    start_ms: to_ms(span.start),
+                    end_ms: to_ms(span.end),
+                    text: text.trim().to_string(),
+                });
+            }
+        }
+
+        Ok(Transcript {
+            segments,
+            duration_ms,
+            resumed,
+            finish_reason,
+        })
+    }
+
+    /// Clean `transcript`, cut it into topics and write their notes and the glossary,
+    /// calling `progress` after each step. A transcript that stopped early is returned
+    /// as it is, without notes.
+    pub fn notes(
+        &self,
+        chat: &mut ChatEngine,
+        transcript: &Transcript,
+        title: &str,
+        control: &GenerationControl,
+        progress: &mut dyn FnMut(&LectureProgress),
+    ) -> Result<LectureNotes> {
+        let segments = clean_transcript(&transcript.segments);
+        let mut notes = LectureNotes {
+            title: title.to_string(),
+            markdown: String::new(),
+            topics: Vec::new(),
+            glossary: Vec::new(),
+            transcript: segments,
+            duration_ms: transcript.duration_ms,
+            resumed: transcript.resumed,
+            finish_reason: transcript.finish_reason,
+        };
+        if notes.finish_reason != FinishReason::Stop {
+            notes.markdown = self.markdown(&notes);
+            return Ok(notes);
+        }
+        if notes.transcript.is_empty() {
+            return Err(StudyNestError::AudioError("No speech found in the recording".to_string()));
+        }
+
+        let max_tokens = self.config.max_tokens.max(16);
+        let budget = self
+            .config
+            .topic_tokens
+            .min(chat.max_context().saturating_sub(max_tokens + PROMPT_OVERHEAD));
+        if budget < max_tokens {
+            return Err(StudyNestError::ContextLengthExceeded(format!(
+                "{} tokens of transcript per topic leave no room for notes of {} tokens",
+                budget, max_tokens
+            )));
+        }
+
+        let lines: Vec<String> = notes
+            .transcript
+            .iter()
+            .map(|s| format!("[{}] {}", timestamp(s.start_ms), s.text))
+            .collect();
+        let tokens = lines
+            .iter()
+            .map(|line| chat.count_tokens(line).map(|t| t + 1))
+            .collect::<Result<Vec<_>>>()?;
+        let ranges = topics(&notes.transcript, &tokens, budget, self.config.min_topic_ms);
+
+        let checkpoint_path = self.config.checkpoint.as_deref();
+        let mut checkpoint = Checkpoint::load(checkpoint_path);
+        let mut run = Run {
+            chat: &mut *chat,
+            control,
+            checkpoint: &mut checkpoint,
+            checkpoint_path,
+            max_tokens,
+            resumed: 0,
+        };
+        let mut skipped = 0;
+        for (index, range) in ranges.iter().enumerate() {
+            let start_ms = notes.transcript[range.start].start_ms;
+            let end_ms = notes.transcript[range.end - 1].end_ms;
+            let prompt = format!(
+                "Part {} of {} of the lecture \"{}\":\n\n{}",
+                index + 1,
+                ranges.len(),
+                title,
+                lines[range.clone()].join("\n")
+            );
+            let reply = match run.json::<TopicReply>(NOTES_PROMPT, &prompt)? {
+                Step::Done(reply, resumed) => {
+                    progress(&LectureProgress {
+                        stage: LectureStage::Notes,
+                        done: index + 1,
+                        total: ranges.len(),
+                        resumed,
+                    });
+                    Some(reply)
+                }
+                Step::Interrupted(reason) => {
+                    notes.finish_reason = reason;
+                    break;
+                }
+                Step::Skipped => {
+                    skipped += 1;
+                    None
+                }
+            };
+            // A topic the model could not write notes for keeps its place and times
+            let (heading, points) = match reply {
+                Some(reply) => (reply.title.trim().to_string(), reply.points),
+                None => (String::new(), Vec::new()),
+            };
+            let points = points
+                .into_iter()
+                .filter_map(|point| {
+                    let (text, at) = match point {
+                        PointReply::Timed { point, at } => (point, at),
+                        PointReply::Plain(point) => (point, None),
+                    };
+                    let text = text.trim().trim_start_matches(['-', '*', ' ']).to_string();
+                    let at_ms = at
+                        .as_deref()
+                        .and_then(parse_timestamp)
+                        .filter(|at| (start_ms..=end_ms).contains(at))
+                        .unwrap_or(start_ms);
+                    (!text.is_empty()).then_some(KeyPoint { text, at_ms })
+                })
+                .collect();
+            notes.topics.push(LectureTopic {
+                title: if heading.is_empty() { format!("Part {}", index + 1) } else { heading },
+                start_ms,
+                end_ms,
+                points,
+            });
+        }
+        if skipped == ranges.len() {
+            return Err(StudyNestError::ModelError(
+                "the model did not write notes as JSON".to_string(),
+            ));
+        }
+        notes.resumed += run.resumed;
+
+        if notes.finish_reason == FinishReason::Stop && self.config.glossary {
+            self.glossary(chat, &mut notes, title, control, progress);
+        } else if notes.finish_reason == FinishReason::Stop {
+            Checkpoint::remove(checkpoint_path);
+        }
+        notes.markdown = self.markdown(&notes);
+        Ok(notes)
+    }
+
+    /// Collect the key terms of the topics, like a summary in the key terms style; notes
+    /// go without a glossary when the model cannot list them
+    fn glossary(
+        &self,
+        chat: &mut ChatEngine,
+        notes: &mut LectureNotes,
+        title: &str,
+        control: &GenerationControl,
+        progress: &mut dyn FnMut(&LectureProgress),
+    ) {
+        let sources: Vec<SourceText> = notes
+            .topics
+            .iter()
+            .map(|topic| {
+                let text = notes
+                    .transcript
+                    .iter()
+                    .filter(|s| s.start_ms >= topic.start_ms && s.end_ms <= topic.end_ms)
+                    .map(|s| s.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                SourceText::new(format!("## {}\n\n{}", topic.title, text))
+            })
+            .collect();
+        let mut config = SummaryConfig::default()
+            .with_style(SummaryStyle::KeyTerms)
+            .with_chunk_tokens(self.config.topic_tokens);
+        if let Some(path) = &self.config.checkpoint {
+            config = config.with_checkpoint(path);
+        }
+        let summary = Summarizer::new(config).summarize(chat, &sources, Some(title), control, &mut |p| {
+            progress(&LectureProgress {
+                stage: LectureStage::Glossary,
+                done: p.done,
+                total: p.total,
+                resumed: p.resumed,
+            })
+        });
+        match summary {
+            Ok(summary) => {
+                notes.resumed += summary.resumed;
+                notes.finish_reason = summary.finish_reason;
+                notes.glossary = summary
+                    .terms
+                    .into_iter()
+                    .map(|term| {
+                        let needle = term.term.to_lowercase();
+                        let at_ms = notes
+                            .transcript
+                            .iter()
+                            .find(|s| s.text.to_lowercase().contains(&needle))
+                            .map(|s| s.start_ms);
+                        GlossaryEntry {
+                            term: term.term,
+                            definition: term.definition,
+                            at_ms,
+                        }
+                    })
+                    .collect();
+            }
+            Err(e) => {
+                eprintln!("[StudyNest] Leaving out the glossary: {}", e);
+                Checkpoint::remove(self.config.checkpoint.as_deref());
+            }
+        }
+    }
+
+    fn markdown(&self, notes: &LectureNotes) -> String {
+        let link = |ms: u64| match &self.config.audio_link {
+            Some(audio) => format!("[{}](<{}#t={}>)", timestamp(ms), audio, ms / 1000),
+            None => timestamp(ms),
+        };
+        let mut markdown = format!("# {}\n\n", notes.title);
+        match &self.config.audio_link {
+            Some(audio) => markdown.push_str(&format!("[Recording](<{}>), {}\n", audio, timestamp(notes.duration_ms))),
+            None => markdown.push_str(&format!("Recording: {}\n", timestamp(notes.duration_ms))),
+        }
+        for topic in &notes.topics {
+            markdown.push_str(&format!("\n## {} ({})\n", topic.title, link(topic.start_ms)));
+            if !topic.points.is_empty() {
+                markdown.push('\n');
+            }
+            for point in &topic.points {
+                markdown.push_str(&format!("- {} ({})\n", point.text, link(point.at_ms)));
+            }
+        }
+        if !notes.glossary.is_empty() {
+            markdown.push_str("\n## Glossary\n\n");
+            for entry in &notes.glossary {
+                match entry.at_ms {
+                    Some(at_ms) => markdown.push_str(&format!(
+                        "- **{}**: {} ({})\n",
+                        entry.term,
+                        entry.definition,
+                        link(at_ms)
+                    )),
+                    None => markdown.push_str(&format!("- **{}**: {}\n", entry.term, entry.definition)),
+                }
+            }
+        }
+        markdown
+    }
+}
+
+/// Drop hesitations, stutters, repeated phrases and sound annotations such as `[MUSIC]`,
+/// and stretches that only repeat the one before, as speech recognition produces over
+/// noise
+pub fn clean_transcript(segments: &[TranscriptSegment]) -> Vec<TranscriptSegment> {
+    let mut cleaned: Vec<TranscriptSegment> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let text = clean_text(&segment.text);
+        if !text.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+        if cleaned.last().is_some_and(|last| normalize(&last.text) == normalize(&text)) {
+            continue;
+        }
+        cleaned.push(TranscriptSegment {
+            text,
+            ..segment.clone()
+        });
+    }
+    cleaned
+}
+
+fn clean_text(text: &str) -> String {
+    // Annotations: anything in square brackets, and one or two words in parentheses
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find(['[', '(']) {
+        let close = if rest[open..].starts_with('[') { ']' } else { ')' };
+        match rest[open..].find(close) {
+            Some(len) if close == ']' || rest[open + 1..open + len].split_whitespace().count() <= 2 => {
+                plain.push_str(&rest[..open]);
+                rest = &rest[open + len + 1..];
+            }
+            _ => {
+                plain.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    plain.push_str(rest);
+
+    let mut words: Vec<String> = Vec::new();
+    for word in plain.split_whitespace() {
+        if FILLERS.contains(&normalize(word).as_str()) {
+            // Keep the end of a sentence that ended on a filler
+            if let (Some(end @ ('.' | '?' | '!')), Some(last)) = (word.chars().last(), words.last_mut()) {
+                if !last.ends_with(['.', '?', '!', ',']) {
+                    last.push(end);
+                }
+            }
+            continue;
+        }
+        words.push(word.to_string());
+    }
+
+    // Stutters and phrases said twice in a row, up to four words long
+    for n in (1..=4).rev() {
+        let mut i = 0;
+        while i + 2 * n <= words.len() {
+            let repeated = (0..n).all(|k| {
+                let word = normalize(&words[i + k]);
+                !word.is_empty() && word == normalize(&words[i + n + k])
+            });
+            if repeated {
+                words.drain(i..i + n);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    let text = words.join(" ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+/// Lowercased word without surrounding punctuation
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+/// Words of a stretch of speech that can tell topics apart, with their counts
+fn topic_terms(text: &str) -> HashMap<String, f32> {
+    let mut terms = HashMap::new();
+    for token in bm25::tokenize(text) {
+        let cjk = token.term.chars().any(bm25::is_cjk);
+        let word = token.term.chars().count() >= 3
+            && !token.term.chars().all(|c| c.is_ascii_digit())
+            && !STOPWORDS.contains(&token.term.as_str());
+        if cjk || word {
+            *terms.entry(token.term).or_insert(0.0) += 1.0;
+        }
+    }
+    terms
+}
+
+fn cosine(a: &HashMap<String, f32>, b: &HashMap<String, f32>) -> f32 {
+    let dot: f32 = a.iter().filter_map(|(term, x)| b.get(term).map(|y| x * y)).sum();
+    let norm = |v: &HashMap<String, f32>| v.values().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// TextTiling depth of the gap before each segment: how far the word overlap across it
+/// dips below the overlap across the gaps around it. The first entry is unused.
+fn gap_depths(segments: &[TranscriptSegment]) -> Vec<f32> {
+    let terms: Vec<HashMap<String, f32>> = segments.iter().map(|s| topic_terms(&s.text)).collect();
+    let window = |indices: &mut dyn Iterator<Item = usize>, within: &dyn Fn(&TranscriptSegment) -> bool| {
+        let mut sum: HashMap<String, f32> = HashMap::new();
+        for (n, i) in indices.enumerate() {
+            if n > 0 && !within(&segments[i]) {
+                break;
+            }
+            for (term, count) in &terms[i] {
+                *sum.entry(term.clone()).or_insert(0.0) += count;
+            }
+        }
+        sum
+    };
+
+    let n = segments.len();
+    let mut similarity = vec![0.0f32; n];
+    for (i, score) in similarity.iter_mut().enumerate().skip(1) {
+        let at = segments[i].start_ms;
+        let before = window(&mut (0..i).rev(), &|s| at.saturating_sub(s.start_ms) <= WINDOW_MS);
+        let after = window(&mut (i..n), &|s| s.end_ms.saturating_sub(at) <= WINDOW_MS);
+        *score = cosine(&before, &after);
+    }
+
+    let mut depths = vec![0.0f32; n];
+    for i in 1..n {
+        let mut left = similarity[i];
+        let mut j = i;
+        while j > 1 && similarity[j - 1] >= left {
+            left = similarity[j - 1];
+            j -= 1;
+        }
+        let mut right = similarity[i];
+        let mut k = i;
+        while k + 1 < n && similarity[k + 1] >= right {
+            right = similarity[k + 1];
+            k += 1;
+        }
+        depths[i] = (left - similarity[i]) + (right - similarity[i]);
+    }
+    depths
+}
+
+/// Consecutive segments making up each topic: cut at the deepest gaps, at least
+/// `min_topic_ms` apart, then further where a topic has more than `budget` tokens
+fn topics(segments: &[TranscriptSegment], tokens: &[usize], budget: usize, min_topic_ms: u64) -> Vec<Range<usize>> {
+    let n = segments.len();
+    let depths = gap_depths(segments);
+
+    let mut cuts: Vec<usize> = Vec::new();
+    let scores: Vec<f32> = depths.iter().skip(1).copied().filter(|d| *d > 0.0).collect();
+    if !scores.is_empty() {
+        let mean = scores.iter().sum::<f32>() / scores.len() as f32;
+        let deviation = (scores.iter().map(|d| (d - mean).powi(2)).sum::<f32>() / scores.len() as f32).sqrt();
+        let threshold = mean - deviation / 2.0;
+        let mut candidates: Vec<usize> = (1..n).filter(|&i| depths[i] > 0.0 && depths[i] >= threshold).collect();
+        candidates.sort_by(|a, b| depths[*b].total_cmp(&depths[*a]));
+        let (first, last) = (segments[0].start_ms, segments[n - 1].end_ms);
+        for gap in candidates {
+            let at = segments[gap].start_ms;
+            let apart = |other: u64| at.abs_diff(other) >= min_topic_ms;
+            if apart(first) && apart(last) && cuts.iter().all(|&cut| apart(segments[cut].start_ms)) {
+                cuts.push(gap);
+            }
+        }
+        cuts.sort_unstable();
+    }
+
+    let mut pending: Vec<Range<usize>> = Vec::new();
+    let mut end = n;
+    for &cut in cuts.iter().rev() {
+        pending.push(cut..end);
+        end = cut;
+    }
+    pending.push(0..end);
+
+    // Split topics too long for the model at their deepest gap within the middle half of
+    // their tokens, so neither side is a sliver
+    let mut ranges = Vec::new();
+    while let Some(range) = pending.pop() {
+        let total: usize = tokens[range.clone()].iter().sum();
+        if range.len() < 2 || total <= budget {
+            ranges.push(range);
+            continue;
+        }
+        let middle = (range.start + range.end) / 2;
+        let mut before = 0;
+        let cut = (range.start + 1..range.end)
+            .filter(|&gap| {
+                before += tokens[gap - 1];
+                before >= total / 4 && total - before >= total / 4
+            })
+            .max_by(|a, b| {
+                depths[*a]
+                    .total_cmp(&depths[*b])
+                    .then_with(|| middle.abs_diff(*b).cmp(&middle.abs_diff(*a)))
+            })
+            .unwrap_or(middle);
+        pending.push(cut..range.end);
+        pending.push(range.start..cut);
+    }
+    ranges
+}
+
+fn to_ms(samples: usize) -> u64 {
+    samples as u64 * 1000 / SAMPLE_RATE
+}
+
+/// `mm:ss`, or `h:mm:ss` from an hour on
+fn timestamp(ms: u64) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// Milliseconds of a `mm:ss` or `h:mm:ss` time, also in brackets
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let text = text.trim().trim_matches(['[', ']']);
+    let parts = text
+        .split(':')
+        .map(|part| part.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let seconds = match parts.as_slice() {
+        [minutes, seconds] => minutes * 60 + seconds,
+        [hours, minutes, seconds] => hours * 3600 + minutes * 60 + seconds,
+        _ => return None,
+    };
+    Some(seconds * 1000)
+}
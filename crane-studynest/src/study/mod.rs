@@ -0,0 +1,144 @@
+//! Study material generated from notes with the chat model
+
+pub mod anki;
+pub mod flashcards;
+pub mod lecture;
+pub mod quiz;
+pub mod review;
+pub mod summary;
+
+use std::collections::HashSet;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::error::Result;
+
+/// Where a piece of generated study material came from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceRef {
+    /// Document in the notes index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// 1-based page number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    /// Character offset within the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+}
+
+impl SourceRef {
+    /// Short human-readable form, e.g. `Lecture 4, page 2`
+    pub fn label(&self) -> String {
+        let title = self.title.as_deref().or(self.document_id.as_deref()).unwrap_or("");
+        match self.page {
+            Some(page) if title.is_empty() => format!("page {}", page),
+            Some(page) => format!("{}, page {}", title, page),
+            None => title.to_string(),
+        }
+    }
+}
+
+/// Text to generate study material from: notes, OCR output or a transcript
+#[derive(Debug, Clone)]
+pub struct SourceText {
+    pub text: String,
+    pub source: SourceRef,
+}
+
+impl SourceText {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            source: SourceRef::default(),
+        }
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.source.title = Some(title.into());
+        self
+    }
+
+    pub fn with_document(mut self, document_id: impl Into<String>) -> Self {
+        self.source.document_id = Some(document_id.into());
+        self
+    }
+
+    pub fn with_page(mut self, page: usize) -> Self {
+        self.source.page = Some(page);
+        self
+    }
+
+    /// Split into pieces of at most about `max_chars` characters, each referring to its
+    /// offset in the page
+    pub(crate) fn pieces(&self, max_chars: usize) -> Vec<SourceText> {
+        crate::rag::chunk_text(&self.text, max_chars, 0)
+            .into_iter()
+            .map(|(offset, text)| SourceText {
+                text,
+                source: SourceRef {
+                    offset: Some(self.source.offset.unwrap_or(0) + offset),
+                    ..self.source.clone()
+                },
+            })
+            .collect()
+    }
+}
+
+/// Ask the model for a JSON reply of type `T` on each of `pieces` and hand the replies
+/// to `accept` until it returns false. Pieces the model fails to answer in JSON are
+/// skipped, unless none is answered. Returns `Stop`, or `Cancelled`/`Timeout` when
+/// `control` ended generation early.
+pub(crate) fn generate_per_piece<T: DeserializeOwned>(
+    chat: &mut ChatEngine,
+    system: &str,
+    pieces: &[SourceText],
+    max_tokens: usize,
+    control: &GenerationControl,
+    prompt: impl Fn(&SourceText) -> String,
+    mut accept: impl FnMut(T, &SourceText) -> bool,
+) -> Result<FinishReason> {
+    let mut answered = false;
+    let mut last_error = None;
+    for piece in pieces {
+        if let Some(reason) = control.interruption() {
+            return Ok(reason);
+        }
+        match chat.complete_json(system, &prompt(piece), max_tokens, control) {
+            Ok(reply) => {
+                answered = true;
+                if !accept(reply, piece) {
+                    break;
+                }
+            }
+            Err(e) => {
+                if let Some(reason) = control.interruption() {
+                    return Ok(reason);
+                }
+                let label = piece.source.label();
+                let label = if label.is_empty() { "a piece of text".to_string() } else { label };
+                eprintln!("[StudyNest] Skipping {}: {}", label, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if !answered => Err(e),
+        _ => Ok(FinishReason::Stop),
+    }
+}
+
+/// Whether two texts, given as their search terms, share at least `threshold` of their
+/// terms (Jaccard similarity). Texts without terms only match each other.
+pub(crate) fn near_duplicate(a: &HashSet<String>, b: &HashSet<String>, threshold: f32) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return a.is_empty() && b.is_empty();
+    }
+    let shared = a.intersection(b).count();
+    let union = a.len() + b.len() - shared;
+    shared as f32 / union as f32 >= threshold
+}
@@ -0,0 +1,603 @@
+//! Quizzes generated from notes, and grading of the answers
+//!
+//! Questions are written per piece of source text like flashcards: multiple choice with
+//! plausible distractors, true/false statements and short-answer questions with the key
+//! points a full answer covers, each with an explanation. Choice questions are graded by
+//! comparing the picked option; short answers are graded by the chat model against the
+//! reference answer and its key points, with a score and feedback for the student.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bm25::query_terms;
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::error::{Result, StudyNestError};
+use crate::session::SessionStore;
+use crate::study::{SourceRef, SourceText, generate_per_piece, near_duplicate};
+
+/// Instructions for the question writer, including the JSON shape of the reply
+const SYSTEM_PROMPT: &str = "You write quiz questions for a student from their study notes. \
+Reply with JSON only, in this form:
+{\"questions\": [
+  {\"type\": \"multiple_choice\", \"question\": \"...\", \"options\": [\"correct answer\", \"wrong 1\", \"wrong 2\", \"wrong 3\"], \"answer\": \"correct answer\", \"explanation\": \"Why it is correct\"},
+  {\"type\": \"true_false\", \"question\": \"A statement\", \"answer\": true, \"explanation\": \"...\"},
+  {\"type\": \"short_answer\", \"question\": \"...\", \"answer\": \"A model answer\", \"key_points\": [\"point a full answer makes\"], \"explanation\": \"...\"}
+]}
+Each question is answered by the notes and makes sense without them. Wrong options are \
+plausible but clearly wrong according to the notes. About half of the true/false \
+statements are false. Write in the language of the notes.";
+
+/// Instructions for grading a short answer
+const GRADING_PROMPT: &str = "You grade a student's answer to a quiz question against the \
+reference answer. Judge the meaning, not the wording, spelling or length. Rubric: 1.0 when \
+the answer is correct and makes every key point; partial credit for each key point made \
+when some are missing; 0.0 when it is wrong, contradicts the reference or does not answer \
+the question. Reply with JSON only, in this form:
+{\"score\": 0.5, \"feedback\": \"One or two sentences to the student on what was right and \
+what was missing or wrong\", \"missing\": [\"key point not made\"]}
+Write the feedback in the language of the question.";
+
+/// Kind of quiz question
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    MultipleChoice,
+    TrueFalse,
+    ShortAnswer,
+}
+
+/// A generated quiz question
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuizQuestion {
+    pub id: String,
+    pub kind: QuestionKind,
+    /// Question, or for true/false questions the statement to judge
+    pub prompt: String,
+    /// Choices in the order to show them; `True` and `False` for true/false questions,
+    /// empty for short answers
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// Index of the correct option
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correct_option: Option<usize>,
+    /// Correct option, or the model answer of a short-answer question
+    pub answer: String,
+    /// Points a complete short answer makes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_points: Vec<String>,
+    /// Why the answer is correct, shown after answering
+    #[serde(default)]
+    pub explanation: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceRef>,
+}
+
+/// Questions written from a set of sources
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quiz {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub questions: Vec<QuizQuestion>,
+    /// `cancelled` or `timeout` when generation stopped before all sources were read
+    pub finish_reason: FinishReason,
+}
+
+/// Quiz generation settings
+#[derive(Debug, Clone)]
+pub struct QuizConfig {
+    /// Kinds of questions to write
+    pub kinds: Vec<QuestionKind>,
+    /// Questions asked for per piece of text
+    pub questions_per_piece: usize,
+    /// Options of a multiple-choice question, the answer included
+    pub options: usize,
+    /// Characters of source text given to the model at once
+    pub piece_chars: usize,
+    /// Token budget of each reply
+    pub max_tokens: usize,
+    /// Questions sharing at least this fraction of their terms are duplicates
+    pub dedup_threshold: f32,
+    /// Stop once this many questions are written
+    pub max_questions: Option<usize>,
+}
+
+impl Default for QuizConfig {
+    fn default() -> Self {
+        Self {
+            kinds: vec![QuestionKind::MultipleChoice, QuestionKind::TrueFalse, QuestionKind::ShortAnswer],
+            questions_per_piece: 5,
+            options: 4,
+            piece_chars: 3000,
+            max_tokens: 1536,
+            dedup_threshold: 0.8,
+            max_questions: None,
+        }
+    }
+}
+
+impl QuizConfig {
+    pub fn with_kinds(mut self, kinds: Vec<QuestionKind>) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    pub fn with_questions_per_piece(mut self, questions: usize) -> Self {
+        self.questions_per_piece = questions.max(1);
+        self
+    }
+
+    pub fn with_options(mut self, options: usize) -> Self {
+        self.options = options.max(2);
+        self
+    }
+
+    pub fn with_piece_chars(mut self, chars: usize) -> Self {
+        self.piece_chars = chars.max(1);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_dedup_threshold(mut self, threshold: f32) -> Self {
+        self.dedup_threshold = threshold;
+        self
+    }
+
+    pub fn with_max_questions(mut self, max_questions: usize) -> Self {
+        self.max_questions = Some(max_questions);
+        self
+    }
+}
+
+/// A question as the model writes it
+#[derive(Debug, Deserialize)]
+struct DraftQuestion {
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(alias = "prompt", alias = "statement", default)]
+    question: String,
+    #[serde(alias = "choices", default)]
+    options: Vec<String>,
+    /// Option text, option letter or index, boolean, or model answer
+    #[serde(alias = "correct", default)]
+    answer: serde_json::Value,
+    #[serde(default)]
+    key_points: Vec<String>,
+    #[serde(default)]
+    explanation: String,
+}
+
+/// The model's reply: an object with a `questions` list, or the bare list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DraftReply {
+    Questions { questions: Vec<DraftQuestion> },
+    List(Vec<DraftQuestion>),
+}
+
+/// Writes quizzes with a chat model
+pub struct QuizGenerator {
+    config: QuizConfig,
+}
+
+impl QuizGenerator {
+    pub fn new(config: QuizConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &QuizConfig {
+        &self.config
+    }
+
+    /// Write a quiz on `sources`, without near-duplicate questions. Stops early, keeping
+    /// the questions written so far, when `control` cancels the request or its time runs
+    /// out.
+    pub fn generate(
+        &self,
+        chat: &mut ChatEngine,
+        sources: &[SourceText],
+        title: Option<String>,
+        control: &GenerationControl,
+    ) -> Result<Quiz> {
+        if self.config.kinds.is_empty() {
+            return Err(StudyNestError::ConfigError("No question kinds requested".to_string()));
+        }
+        let pieces: Vec<SourceText> = sources
+            .iter()
+            .flat_map(|source| source.pieces(self.config.piece_chars))
+            .collect();
+        let limit = self.config.max_questions.unwrap_or(usize::MAX);
+
+        let mut questions: Vec<(HashSet<String>, QuizQuestion)> = Vec::new();
+        let finish_reason = generate_per_piece(
+            chat,
+            SYSTEM_PROMPT,
+            &pieces,
+            self.config.max_tokens,
+            control,
+            |piece| self.prompt(piece),
+            |reply: DraftReply, piece| {
+                let drafts = match reply {
+                    DraftReply::Questions { questions } | DraftReply::List(questions) => questions,
+                };
+                let written = drafts
+                    .into_iter()
+                    .filter_map(|draft| self.accept(draft, &piece.source))
+                    .take(self.config.questions_per_piece);
+                for question in written {
+                    let terms = query_terms(&question.prompt);
+                    if !questions
+                        .iter()
+                        .any(|(other, _)| near_duplicate(&terms, other, self.config.dedup_threshold))
+                    {
+                        questions.push((terms, question));
+                    }
+                }
+                questions.len() < limit
+            },
+        )?;
+        questions.truncate(limit);
+        Ok(Quiz {
+            id: SessionStore::new_id(),
+            title,
+            questions: questions.into_iter().map(|(_, question)| question).collect(),
+            finish_reason,
+        })
+    }
+
+    fn prompt(&self, piece: &SourceText) -> String {
+        let kinds: Vec<&str> = self
+            .config
+            .kinds
+            .iter()
+            .map(|kind| match kind {
+                QuestionKind::MultipleChoice => "multiple_choice",
+                QuestionKind::TrueFalse => "true_false",
+                QuestionKind::ShortAnswer => "short_answer",
+            })
+            .collect();
+        let from = match &piece.source.title {
+            Some(title) => format!(" from \"{}\"", title),
+            None => String::new(),
+        };
+        format!(
+            "Write up to {} questions of type {} from these notes{}. Multiple-choice \
+             questions have {} options.\n\n{}",
+            self.config.questions_per_piece,
+            kinds.join(", "),
+            from,
+            self.config.options,
+            piece.text
+        )
+    }
+
+    /// Check and tidy a question the model wrote; `None` if it is unusable or of an
+    /// unwanted kind
+    fn accept(&self, draft: DraftQuestion, source: &SourceRef) -> Option<QuizQuestion> {
+        let prompt = draft.question.trim().to_string();
+        let mut options: Vec<String> = Vec::new();
+        for option in draft.options.iter().map(|o| o.trim()) {
+            if !option.is_empty() && !options.iter().any(|o| same_answer(o, option)) {
+                options.push(option.to_string());
+            }
+        }
+        let kind = match draft.kind.as_deref().map(|k| k.to_lowercase().replace(['-', ' '], "_")) {
+            Some(k) if k.starts_with("true") || k == "boolean" => QuestionKind::TrueFalse,
+            Some(k) if k.starts_with("short") || k == "open" => QuestionKind::ShortAnswer,
+            Some(k) if k.starts_with("multiple") || k == "mcq" => QuestionKind::MultipleChoice,
+            _ if draft.answer.is_boolean() => QuestionKind::TrueFalse,
+            _ if options.len() >= 2 => QuestionKind::MultipleChoice,
+            _ => QuestionKind::ShortAnswer,
+        };
+        if prompt.is_empty() || !self.config.kinds.contains(&kind) {
+            return None;
+        }
+
+        let id = SessionStore::new_id();
+        let (options, correct_option, answer) = match kind {
+            QuestionKind::MultipleChoice => {
+                let correct = choice_index(&draft.answer, &options)?;
+                let answer = options[correct].clone();
+                // Keep the answer and the first distractors, then shuffle so the answer
+                // is not always where the model put it
+                let mut kept: Vec<String> = options
+                    .into_iter()
+                    .filter(|o| *o != answer)
+                    .take(self.config.options.saturating_sub(1))
+                    .collect();
+                if kept.is_empty() {
+                    return None;
+                }
+                kept.push(answer.clone());
+                kept.sort_by_cached_key(|o| sha1_smol::Sha1::from(format!("{}{}", id, o)).digest().to_string());
+                let correct = kept.iter().position(|o| *o == answer)?;
+                (kept, Some(correct), answer)
+            }
+            QuestionKind::TrueFalse => {
+                let truth = match &draft.answer {
+                    serde_json::Value::Bool(truth) => *truth,
+                    serde_json::Value::String(text) => parse_truth(text)?,
+                    _ => return None,
+                };
+                let answer = if truth { "True" } else { "False" };
+                (
+                    vec!["True".to_string(), "False".to_string()],
+                    Some(if truth { 0 } else { 1 }),
+                    answer.to_string(),
+                )
+            }
+            QuestionKind::ShortAnswer => {
+                let answer = match &draft.answer {
+                    serde_json::Value::String(text) => text.trim().to_string(),
+                    _ => String::new(),
+                };
+                if answer.is_empty() {
+                    return None;
+                }
+                (Vec::new(), None, answer)
+            }
+        };
+        let key_points = match kind {
+            QuestionKind::ShortAnswer => draft
+                .key_points
+                .into_iter()
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(QuizQuestion {
+            id,
+            kind,
+            prompt,
+            options,
+            correct_option,
+            answer,
+            key_points,
+            explanation: draft.explanation.trim().to_string(),
+            source: Some(source.clone()),
+        })
+    }
+}
+
+/// Index of the option `answer` names, by text, letter (`B`) or 0-based index
+fn choice_index(answer: &serde_json::Value, options: &[String]) -> Option<usize> {
+    match answer {
+        serde_json::Value::Number(n) => n.as_u64().map(|i| i as usize).filter(|&i| i < options.len()),
+        serde_json::Value::String(text) => pick_option(text, options),
+        _ => None,
+    }
+}
+
+/// Index of the option a response picks: its text, or its letter (`b`, `B)`, `(b)`)
+fn pick_option(response: &str, options: &[String]) -> Option<usize> {
+    let response = response.trim();
+    if let Some(index) = options.iter().position(|o| same_answer(o, response)) {
+        return Some(index);
+    }
+    let letter: String = response
+        .trim_matches(|c: char| c == '(' || c == ')' || c == '.' || c == ':')
+        .to_lowercase();
+    let mut chars = letter.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ 'a'..='z'), None) => Some((c as u8 - b'a') as usize).filter(|&i| i < options.len()),
+        _ => None,
+    }
+}
+
+fn parse_truth(text: &str) -> Option<bool> {
+    match text.trim().trim_end_matches('.').to_lowercase().as_str() {
+        "true" | "t" | "yes" | "correct" => Some(true),
+        "false" | "f" | "no" | "incorrect" => Some(false),
+        _ => None,
+    }
+}
+
+/// Answers that differ only in case, spacing or trailing punctuation
+fn same_answer(a: &str, b: &str) -> bool {
+    let normalize = |text: &str| {
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_end_matches(['.', '!', '?', '。'])
+            .to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Grade of one answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerGrade {
+    pub question_id: String,
+    /// 0.0 to 1.0
+    pub score: f32,
+    /// Whether the score reaches the pass mark
+    pub correct: bool,
+    pub feedback: String,
+    /// Key points the answer did not make
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+}
+
+/// Grades of a quiz attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuizResult {
+    pub grades: Vec<AnswerGrade>,
+    /// Mean score over all questions, unanswered ones counting as 0
+    pub score: f32,
+    pub correct: usize,
+    pub total: usize,
+}
+
+/// Grading settings
+#[derive(Debug, Clone)]
+pub struct GradingConfig {
+    /// Score from which an answer counts as correct
+    pub pass_score: f32,
+    /// Token budget of each grading reply
+    pub max_tokens: usize,
+}
+
+impl Default for GradingConfig {
+    fn default() -> Self {
+        Self {
+            pass_score: 0.6,
+            max_tokens: 384,
+        }
+    }
+}
+
+impl GradingConfig {
+    pub fn with_pass_score(mut self, pass_score: f32) -> Self {
+        self.pass_score = pass_score.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+/// The grading model's reply
+#[derive(Debug, Deserialize)]
+struct DraftGrade {
+    #[serde(alias = "grade")]
+    score: f32,
+    #[serde(default)]
+    feedback: String,
+    #[serde(default)]
+    missing: Vec<String>,
+}
+
+/// Grades answers to quiz questions
+pub struct QuizGrader {
+    config: GradingConfig,
+}
+
+impl QuizGrader {
+    pub fn new(config: GradingConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &GradingConfig {
+        &self.config
+    }
+
+    /// Grade `response` to `question`. Choice questions are graded without the model;
+    /// `response` may be the option text, its letter or, for true/false, `true`/`false`.
+    /// Short answers other than the reference itself need `chat`.
+    pub fn grade(
+        &self,
+        chat: Option<&mut ChatEngine>,
+        question: &QuizQuestion,
+        response: &str,
+        control: &GenerationControl,
+    ) -> Result<AnswerGrade> {
+        let response = response.trim();
+        if response.is_empty() {
+            return Ok(self.verdict(question, 0.0, "No answer given.".to_string(), question.key_points.clone()));
+        }
+        match question.kind {
+            QuestionKind::MultipleChoice | QuestionKind::TrueFalse => {
+                let picked = pick_option(response, &question.options).or_else(|| {
+                    let truth = parse_truth(response).filter(|_| question.kind == QuestionKind::TrueFalse)?;
+                    Some(if truth { 0 } else { 1 })
+                });
+                let right = picked.is_some() && picked == question.correct_option;
+                let feedback = match (right, question.explanation.is_empty()) {
+                    (true, true) => "Correct.".to_string(),
+                    (true, false) => format!("Correct. {}", question.explanation),
+                    (false, true) => format!("Incorrect. The answer is: {}", question.answer),
+                    (false, false) => format!("Incorrect. The answer is: {}. {}", question.answer, question.explanation),
+                };
+                Ok(self.verdict(question, if right { 1.0 } else { 0.0 }, feedback, Vec::new()))
+            }
+            QuestionKind::ShortAnswer if same_answer(response, &question.answer) => {
+                Ok(self.verdict(question, 1.0, "Correct.".to_string(), Vec::new()))
+            }
+            QuestionKind::ShortAnswer => {
+                let mut prompt = format!(
+                    "Question: {}\nReference answer: {}\n",
+                    question.prompt, question.answer
+                );
+                if !question.key_points.is_empty() {
+                    prompt.push_str("Key points:\n");
+                    for point in &question.key_points {
+                        prompt.push_str(&format!("- {}\n", point));
+                    }
+                }
+                prompt.push_str(&format!("Student answer: {}", response));
+                let chat = chat.ok_or_else(|| {
+                    StudyNestError::ConfigError("Grading short answers needs a chat model".to_string())
+                })?;
+                let draft: DraftGrade =
+                    chat.complete_json(GRADING_PROMPT, &prompt, self.config.max_tokens, control)?;
+                let score = if draft.score.is_finite() {
+                    // Some models grade out of 10 or 100 despite the instructions
+                    match draft.score {
+                        s if s > 10.0 => s / 100.0,
+                        s if s > 1.0 => s / 10.0,
+                        s => s,
+                    }
+                    .clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let missing = draft
+                    .missing
+                    .into_iter()
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect();
+                Ok(self.verdict(question, score, draft.feedback.trim().to_string(), missing))
+            }
+        }
+    }
+
+    /// Grade an attempt at `questions` with `answers` keyed by question id. Stops when
+    /// `control` cancels the request or its time runs out.
+    pub fn grade_quiz(
+        &self,
+        mut chat: Option<&mut ChatEngine>,
+        questions: &[QuizQuestion],
+        answers: &HashMap<String, String>,
+        control: &GenerationControl,
+    ) -> Result<QuizResult> {
+        let mut grades = Vec::with_capacity(questions.len());
+        for question in questions {
+            if let Some(reason) = control.interruption() {
+                return Err(StudyNestError::ModelError(format!("grading stopped ({:?})", reason)));
+            }
+            let response = answers.get(&question.id).map(String::as_str).unwrap_or("");
+            grades.push(self.grade(chat.as_deref_mut(), question, response, control)?);
+        }
+        let total = grades.len();
+        let score = if total == 0 {
+            0.0
+        } else {
+            grades.iter().map(|g| g.score).sum::<f32>() / total as f32
+        };
+        Ok(QuizResult {
+            correct: grades.iter().filter(|g| g.correct).count(),
+            grades,
+            score,
+            total,
+        })
+    }
+
+    fn verdict(&self, question: &QuizQuestion, score: f32, feedback: String, missing: Vec<String>) -> AnswerGrade {
+        AnswerGrade {
+            question_id: question.id.clone(),
+            score,
+            correct: score >= self.config.pass_score,
+            feedback,
+            missing,
+        }
+    }
+}
@@ -0,0 +1,734 @@
+//! Spaced-repetition scheduling of flashcards
+//!
+//! Cards are scheduled with FSRS-5: each card has a memory stability (days until recall
+//! probability drops to 90%) and a difficulty, updated after every review from the
+//! rating and the time since the last one. New and lapsed cards go through short
+//! learning steps (minutes) before being scheduled in days, at the interval where recall
+//! is predicted to fall to the desired retention.
+//!
+//! Like the notes index, cards and reviews are kept in an append-only JSONL log
+//! (`<root>/reviews.jsonl`) that is replayed on open. Every review is logged with the
+//! state it produced, so changing the parameters does not move existing due dates. The
+//! review history is also what `optimize` fits the 19 FSRS weights to.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, StudyNestError};
+use crate::session::open_for_append;
+use crate::study::flashcards::Flashcard;
+
+const MINUTE: u64 = 60_000;
+const DAY: u64 = 86_400_000;
+
+/// Forgetting curve: recall probability after `t` days is `(1 + FACTOR * t / S)^DECAY`
+const DECAY: f64 = -0.5;
+const FACTOR: f64 = 19.0 / 81.0;
+
+/// FSRS-5 weights fitted on a large collection of Anki reviews
+pub const DEFAULT_WEIGHTS: [f64; 19] = [
+    0.40255, 1.18385, 3.173, 15.69105, 7.1949, 0.5345, 1.4604, 0.0046, 1.54575, 0.1192, 1.01925,
+    1.9395, 0.11, 0.29605, 2.2698, 0.2315, 2.9898, 0.51655, 0.6621,
+];
+
+/// Range each weight is kept in while optimizing
+const WEIGHT_BOUNDS: [(f64, f64); 19] = [
+    (0.001, 100.0),
+    (0.001, 100.0),
+    (0.001, 100.0),
+    (0.001, 100.0),
+    (1.0, 10.0),
+    (0.001, 4.0),
+    (0.001, 4.0),
+    (0.001, 0.75),
+    (0.0, 4.5),
+    (0.0, 0.8),
+    (0.001, 3.5),
+    (0.001, 5.0),
+    (0.001, 0.25),
+    (0.001, 0.9),
+    (0.0, 4.0),
+    (0.0, 1.0),
+    (1.0, 6.0),
+    (0.0, 2.0),
+    (0.0, 2.0),
+];
+
+/// Reviews a day or more after the previous one needed before the weights are fitted
+pub const MIN_OPTIMIZE_REVIEWS: usize = 100;
+
+/// How well a card was recalled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    /// Forgotten
+    Again,
+    /// Recalled with serious difficulty
+    Hard,
+    Good,
+    /// Recalled without effort
+    Easy,
+}
+
+impl Rating {
+    pub const ALL: [Rating; 4] = [Rating::Again, Rating::Hard, Rating::Good, Rating::Easy];
+
+    /// 1 (again) to 4 (easy), as in the FSRS formulas
+    pub fn grade(self) -> usize {
+        self as usize + 1
+    }
+}
+
+/// Where a card is in the scheduler
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Never reviewed
+    #[default]
+    New,
+    /// Going through the learning steps
+    Learning,
+    /// Scheduled in days
+    Review,
+    /// Forgotten and going through the relearning steps
+    Relearning,
+}
+
+/// Scheduling state of a card
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CardState {
+    pub phase: Phase,
+    /// When the card is next due (Unix milliseconds)
+    pub due: u64,
+    /// Days until recall probability falls to 90%; 0 for new cards
+    pub stability: f64,
+    /// 1 (easy) to 10 (hard); 0 for new cards
+    pub difficulty: f64,
+    /// Index into the (re)learning steps
+    #[serde(default)]
+    pub step: usize,
+    pub reps: u32,
+    /// Times the card was forgotten after graduating
+    pub lapses: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_review: Option<u64>,
+}
+
+impl CardState {
+    /// Days since the last review at `now`
+    fn elapsed_days(&self, now: u64) -> f64 {
+        self.last_review.map_or(0.0, |last| now.saturating_sub(last) as f64 / DAY as f64)
+    }
+
+    /// Probability of recalling the card at `now`; `None` for new cards
+    pub fn retrievability(&self, now: u64) -> Option<f64> {
+        (self.phase != Phase::New).then(|| forgetting_curve(self.elapsed_days(now), self.stability))
+    }
+}
+
+/// A flashcard in the review store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewCard {
+    pub card: Flashcard,
+    pub deck: String,
+    pub added_at: u64,
+    pub state: CardState,
+}
+
+/// One review of a card
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewLog {
+    pub card_id: String,
+    pub rating: Rating,
+    /// Unix milliseconds
+    pub reviewed_at: u64,
+    /// Time spent answering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Phase before the review
+    pub phase: Phase,
+    /// Days since the previous review
+    pub elapsed_days: f64,
+    /// Days the card was scheduled for by the previous review
+    pub scheduled_days: f64,
+}
+
+/// Scheduler settings, persisted with the store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulerParameters {
+    /// FSRS-5 weights
+    pub weights: Vec<f64>,
+    /// Recall probability at which reviews are scheduled
+    pub desired_retention: f64,
+    pub maximum_interval_days: u32,
+    /// Learning steps of new cards, in minutes
+    pub learning_steps: Vec<u32>,
+    /// Relearning steps of forgotten cards, in minutes
+    pub relearning_steps: Vec<u32>,
+    /// New cards introduced per (rolling) day
+    pub new_per_day: usize,
+}
+
+impl Default for SchedulerParameters {
+    fn default() -> Self {
+        Self {
+            weights: DEFAULT_WEIGHTS.to_vec(),
+            desired_retention: 0.9,
+            maximum_interval_days: 36_500,
+            learning_steps: vec![1, 10],
+            relearning_steps: vec![10],
+            new_per_day: 20,
+        }
+    }
+}
+
+impl SchedulerParameters {
+    pub fn with_desired_retention(mut self, retention: f64) -> Self {
+        self.desired_retention = retention.clamp(0.7, 0.99);
+        self
+    }
+
+    pub fn with_maximum_interval_days(mut self, days: u32) -> Self {
+        self.maximum_interval_days = days.max(1);
+        self
+    }
+
+    pub fn with_learning_steps(mut self, minutes: Vec<u32>) -> Self {
+        self.learning_steps = minutes;
+        self
+    }
+
+    pub fn with_relearning_steps(mut self, minutes: Vec<u32>) -> Self {
+        self.relearning_steps = minutes;
+        self
+    }
+
+    pub fn with_new_per_day(mut self, cards: usize) -> Self {
+        self.new_per_day = cards;
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.weights.len() != DEFAULT_WEIGHTS.len() {
+            return Err(StudyNestError::ConfigError(format!(
+                "Expected {} FSRS weights, got {}",
+                DEFAULT_WEIGHTS.len(),
+                self.weights.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Interval in days at which recall falls to the desired retention
+    fn interval_days(&self, stability: f64) -> f64 {
+        let days = stability / FACTOR * (self.desired_retention.powf(1.0 / DECAY) - 1.0);
+        days.round().clamp(1.0, self.maximum_interval_days as f64)
+    }
+
+    /// State of `state` after a review rated `rating` at `now`
+    pub fn schedule(&self, state: &CardState, rating: Rating, now: u64) -> CardState {
+        self.preview(state, now)[rating as usize].clone()
+    }
+
+    /// States after each rating, again to easy, for showing the next intervals
+    pub fn preview(&self, state: &CardState, now: u64) -> [CardState; 4] {
+        let mut next = Rating::ALL.map(|rating| self.next_state(state, rating, now));
+        // Keep the review intervals in the order of the ratings
+        if state.phase == Phase::Review {
+            let maximum = self.maximum_interval_days as u64;
+            let days = |s: &CardState| (s.due - now) / DAY;
+            let good = days(&next[2]).max(days(&next[1]) + 1).min(maximum);
+            let easy = days(&next[3]).max(good + 1).min(maximum);
+            next[2].due = now + good * DAY;
+            next[3].due = now + easy * DAY;
+        }
+        next
+    }
+
+    fn next_state(&self, state: &CardState, rating: Rating, now: u64) -> CardState {
+        let elapsed = state.elapsed_days(now);
+        let memory = (state.phase != Phase::New).then_some((state.stability, state.difficulty));
+        let (stability, difficulty) = next_memory(&self.weights, memory, elapsed, rating);
+        let mut next = CardState {
+            stability,
+            difficulty,
+            reps: state.reps + 1,
+            last_review: Some(now),
+            ..state.clone()
+        };
+        let review = |mut next: CardState| {
+            next.phase = Phase::Review;
+            next.step = 0;
+            next.due = now + self.interval_days(next.stability) as u64 * DAY;
+            next
+        };
+        let step = |mut next: CardState, phase: Phase, steps: &[u32], index: usize, minutes: u32| {
+            next.phase = phase;
+            next.step = index.min(steps.len().saturating_sub(1));
+            next.due = now + minutes as u64 * MINUTE;
+            next
+        };
+
+        match state.phase {
+            Phase::New | Phase::Learning | Phase::Relearning => {
+                let (phase, steps) = match state.phase {
+                    Phase::Relearning => (Phase::Relearning, &self.relearning_steps),
+                    _ => (Phase::Learning, &self.learning_steps),
+                };
+                let current = if state.phase == Phase::New { 0 } else { state.step };
+                match rating {
+                    _ if steps.is_empty() => review(next),
+                    Rating::Easy => review(next),
+                    Rating::Again => step(next, phase, steps, 0, steps[0]),
+                    Rating::Hard => {
+                        // Between this step and the next, or half again as long on the last one
+                        let minutes = match (steps.get(current), steps.get(current + 1)) {
+                            (Some(&now_step), Some(&next_step)) if current == 0 => (now_step + next_step) / 2,
+                            (Some(&now_step), _) => now_step * 3 / 2,
+                            _ => steps[steps.len() - 1],
+                        };
+                        step(next, phase, steps, current, minutes)
+                    }
+                    Rating::Good if state.phase == Phase::New && steps.len() > 1 => {
+                        step(next, phase, steps, 1, steps[1])
+                    }
+                    Rating::Good if state.phase == Phase::New => review(next),
+                    Rating::Good => match steps.get(current + 1) {
+                        Some(&minutes) => step(next, phase, steps, current + 1, minutes),
+                        None => review(next),
+                    },
+                }
+            }
+            Phase::Review => match rating {
+                Rating::Again => {
+                    next.lapses += 1;
+                    match self.relearning_steps.first() {
+                        Some(&minutes) => step(next, Phase::Relearning, &self.relearning_steps, 0, minutes),
+                        None => review(next),
+                    }
+                }
+                _ => review(next),
+            },
+        }
+    }
+}
+
+fn forgetting_curve(elapsed_days: f64, stability: f64) -> f64 {
+    (1.0 + FACTOR * elapsed_days / stability.max(0.01)).powf(DECAY)
+}
+
+fn initial_difficulty(w: &[f64], grade: f64) -> f64 {
+    w[4] - (w[5] * (grade - 1.0)).exp() + 1.0
+}
+
+/// Stability and difficulty after a review rated `rating`, `elapsed_days` after the
+/// previous one; `memory` is `None` for a card's first review
+fn next_memory(w: &[f64], memory: Option<(f64, f64)>, elapsed_days: f64, rating: Rating) -> (f64, f64) {
+    let grade = rating.grade() as f64;
+    let Some((stability, difficulty)) = memory else {
+        return (w[rating as usize].max(0.01), initial_difficulty(w, grade).clamp(1.0, 10.0));
+    };
+
+    let delta = -w[6] * (grade - 3.0);
+    let damped = difficulty + delta * (10.0 - difficulty) / 9.0;
+    let difficulty = (w[7] * initial_difficulty(w, 4.0) + (1.0 - w[7]) * damped).clamp(1.0, 10.0);
+
+    let stability = if elapsed_days < 1.0 {
+        // Reviewed again the same day
+        let next = stability * (w[17] * (grade - 3.0 + w[18])).exp();
+        if rating >= Rating::Good { next.max(stability) } else { next }
+    } else {
+        let r = forgetting_curve(elapsed_days, stability);
+        if rating == Rating::Again {
+            let forgotten = w[11]
+                * difficulty.powf(-w[12])
+                * ((stability + 1.0).powf(w[13]) - 1.0)
+                * (w[14] * (1.0 - r)).exp();
+            forgotten.min(stability / (w[17] * w[18]).exp())
+        } else {
+            let hard_penalty = if rating == Rating::Hard { w[15] } else { 1.0 };
+            let easy_bonus = if rating == Rating::Easy { w[16] } else { 1.0 };
+            stability
+                * (w[8].exp()
+                    * (11.0 - difficulty)
+                    * stability.powf(-w[9])
+                    * ((w[10] * (1.0 - r)).exp() - 1.0)
+                    * hard_penalty
+                    * easy_bonus
+                    + 1.0)
+        }
+    };
+    (stability.clamp(0.01, 36_500.0), difficulty)
+}
+
+/// Review histories as `(elapsed_days, rating)` sequences starting at a card's first review
+fn histories(logs: &[ReviewLog]) -> Vec<Vec<(f64, Rating)>> {
+    let mut by_card: HashMap<&str, Vec<&ReviewLog>> = HashMap::new();
+    for log in logs {
+        by_card.entry(log.card_id.as_str()).or_default().push(log);
+    }
+    by_card
+        .into_values()
+        .filter_map(|mut logs| {
+            logs.sort_by_key(|log| log.reviewed_at);
+            // Cards whose first review is missing (e.g. added with a state) are left out
+            (logs[0].phase == Phase::New).then(|| logs.iter().map(|log| (log.elapsed_days, log.rating)).collect())
+        })
+        .collect()
+}
+
+/// Mean log loss of the recall predictions over reviews a day or more after the
+/// previous one, and how many there are
+fn log_loss(w: &[f64], histories: &[Vec<(f64, Rating)>]) -> (f64, usize) {
+    let mut loss = 0.0;
+    let mut count = 0;
+    for history in histories {
+        let mut memory = None;
+        for &(elapsed, rating) in history {
+            if let Some((stability, _)) = memory {
+                if elapsed >= 1.0 {
+                    let p = forgetting_curve(elapsed, stability).clamp(1e-4, 1.0 - 1e-4);
+                    loss -= if rating == Rating::Again { (1.0 - p).ln() } else { p.ln() };
+                    count += 1;
+                }
+            }
+            memory = Some(next_memory(w, memory, elapsed, rating));
+        }
+    }
+    (if count == 0 { 0.0 } else { loss / count as f64 }, count)
+}
+
+/// Result of fitting the weights to the review history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizationReport {
+    /// Reviews the weights were fitted on
+    pub reviews: usize,
+    /// Log loss of the recall predictions with the previous weights
+    pub loss_before: f64,
+    pub loss_after: f64,
+    /// Whether the fitted weights were kept, which they are when they predict better
+    pub updated: bool,
+    pub weights: Vec<f64>,
+}
+
+/// Fit FSRS weights to `logs` from `start` by gradient descent (Adam) on the log loss,
+/// with finite-difference gradients
+pub fn optimize_weights(logs: &[ReviewLog], start: &[f64], iterations: usize) -> Result<OptimizationReport> {
+    let histories = histories(logs);
+    let (loss_before, reviews) = log_loss(start, &histories);
+    if reviews < MIN_OPTIMIZE_REVIEWS {
+        return Err(StudyNestError::ConfigError(format!(
+            "Optimizing needs at least {} reviews a day or more apart, there are {}",
+            MIN_OPTIMIZE_REVIEWS, reviews
+        )));
+    }
+
+    let (rate, beta1, beta2) = (0.04, 0.9, 0.999);
+    let mut w = start.to_vec();
+    let mut m = vec![0.0; w.len()];
+    let mut v = vec![0.0; w.len()];
+    let mut best = (loss_before, w.clone());
+    for t in 1..=iterations {
+        for i in 0..w.len() {
+            let h = 1e-4 * w[i].abs().max(0.1);
+            let mut probe = w.clone();
+            probe[i] = w[i] + h;
+            let up = log_loss(&probe, &histories).0;
+            probe[i] = w[i] - h;
+            let down = log_loss(&probe, &histories).0;
+            let gradient = (up - down) / (2.0 * h);
+            m[i] = beta1 * m[i] + (1.0 - beta1) * gradient;
+            v[i] = beta2 * v[i] + (1.0 - beta2) * gradient * gradient;
+            let m_hat = m[i] / (1.0 - beta1.powi(t as i32));
+            let v_hat = v[i] / (1.0 - beta2.powi(t as i32));
+            let (lo, hi) = WEIGHT_BOUNDS[i];
+            w[i] = (w[i] - rate * m_hat / (v_hat.sqrt() + 1e-8)).clamp(lo, hi);
+        }
+        let loss = log_loss(&w, &histories).0;
+        if loss < best.0 {
+            best = (loss, w.clone());
+        }
+    }
+
+    let (loss_after, weights) = best;
+    Ok(OptimizationReport {
+        reviews,
+        loss_before,
+        loss_after,
+        updated: loss_after < loss_before,
+        weights,
+    })
+}
+
+/// Which cards to review now
+#[derive(Debug, Clone, Default)]
+pub struct DueQuery {
+    /// Only cards of this deck or its subdecks (`Biology` includes `Biology::Cells`)
+    pub deck: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Cards due for review with the states each rating would lead to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DueCard {
+    #[serde(flatten)]
+    pub card: ReviewCard,
+    /// When the card would be due again after rating it again, hard, good or easy
+    pub next_due: [u64; 4],
+}
+
+/// Cards to review now: (re)learning cards first, then reviews, then new cards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DueQueue {
+    pub cards: Vec<DueCard>,
+    /// Due counts per phase, before `limit`
+    pub learning: usize,
+    pub review: usize,
+    pub new: usize,
+}
+
+/// One line of the review log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReviewEvent {
+    Card { card: ReviewCard },
+    Review { log: ReviewLog, state: CardState },
+    Removed { id: String },
+    Parameters { parameters: SchedulerParameters },
+}
+
+/// Persistent store of scheduled cards and their review history
+pub struct ReviewStore {
+    path: PathBuf,
+    parameters: SchedulerParameters,
+    /// Cards by id, in the order they were added
+    cards: BTreeMap<(u64, String), ReviewCard>,
+    order: HashMap<String, (u64, String)>,
+    logs: Vec<ReviewLog>,
+}
+
+impl ReviewStore {
+    /// Open (and create if needed) the store in `root`
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(root.as_ref())?;
+        let mut store = Self {
+            path: root.as_ref().join("reviews.jsonl"),
+            parameters: SchedulerParameters::default(),
+            cards: BTreeMap::new(),
+            order: HashMap::new(),
+            logs: Vec::new(),
+        };
+        if store.path.exists() {
+            store.replay()?;
+        }
+        Ok(store)
+    }
+
+    fn replay(&mut self) -> Result<()> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A torn trailing line from an interrupted write is skipped
+            match serde_json::from_str::<ReviewEvent>(&line) {
+                Ok(event) => self.apply(event),
+                Err(_) => continue,
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, event: ReviewEvent) {
+        match event {
+            ReviewEvent::Card { card } => {
+                let key = self
+                    .order
+                    .get(&card.card.id)
+                    .cloned()
+                    .unwrap_or_else(|| (card.added_at, card.card.id.clone()));
+                self.order.insert(card.card.id.clone(), key.clone());
+                self.cards.insert(key, card);
+            }
+            ReviewEvent::Review { log, state } => {
+                if let Some(card) = self.order.get(&log.card_id).and_then(|key| self.cards.get_mut(key)) {
+                    card.state = state;
+                }
+                self.logs.push(log);
+            }
+            ReviewEvent::Removed { id } => {
+                if let Some(key) = self.order.remove(&id) {
+                    self.cards.remove(&key);
+                }
+                self.logs.retain(|log| log.card_id != id);
+            }
+            ReviewEvent::Parameters { parameters } => self.parameters = parameters,
+        }
+    }
+
+    fn append(&mut self, event: ReviewEvent) -> Result<()> {
+        let mut file = open_for_append(&self.path)?;
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        self.apply(event);
+        Ok(())
+    }
+
+    pub fn parameters(&self) -> &SchedulerParameters {
+        &self.parameters
+    }
+
+    pub fn set_parameters(&mut self, parameters: SchedulerParameters) -> Result<()> {
+        parameters.validate()?;
+        self.append(ReviewEvent::Parameters { parameters })
+    }
+
+    pub fn card(&self, id: &str) -> Option<&ReviewCard> {
+        self.order.get(id).and_then(|key| self.cards.get(key))
+    }
+
+    /// All cards, in the order they were added
+    pub fn cards(&self) -> impl Iterator<Item = &ReviewCard> {
+        self.cards.values()
+    }
+
+    /// Reviews in the order they were made
+    pub fn logs(&self) -> &[ReviewLog] {
+        &self.logs
+    }
+
+    /// Add `cards` to `deck` as new cards. Cards already in the store keep their
+    /// schedule and get the new text and deck. Returns how many were new.
+    pub fn add_cards(&mut self, cards: Vec<Flashcard>, deck: &str, now: u64) -> Result<usize> {
+        let mut added = 0;
+        for card in cards {
+            let entry = match self.card(&card.id) {
+                Some(existing) => ReviewCard {
+                    card,
+                    deck: deck.to_string(),
+                    ..existing.clone()
+                },
+                None => {
+                    added += 1;
+                    ReviewCard {
+                        card,
+                        deck: deck.to_string(),
+                        added_at: now,
+                        state: CardState {
+                            due: now,
+                            ..CardState::default()
+                        },
+                    }
+                }
+            };
+            self.append(ReviewEvent::Card { card: entry })?;
+        }
+        Ok(added)
+    }
+
+    /// Remove cards and their reviews; returns how many were found
+    pub fn remove_cards(&mut self, ids: &[String]) -> Result<usize> {
+        let mut removed = 0;
+        for id in ids {
+            if self.order.contains_key(id) {
+                self.append(ReviewEvent::Removed { id: id.clone() })?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Cards due at `now`. New cards are limited to what is left of `new_per_day` after
+    /// the new cards reviewed in the 24 hours before.
+    pub fn due(&self, query: &DueQuery, now: u64) -> DueQueue {
+        let in_deck = |card: &ReviewCard| match &query.deck {
+            Some(deck) => card.deck == *deck || card.deck.starts_with(&format!("{}::", deck)),
+            None => true,
+        };
+        let introduced = self
+            .logs
+            .iter()
+            .filter(|log| log.phase == Phase::New && log.reviewed_at + DAY > now)
+            .filter(|log| self.card(&log.card_id).is_some_and(in_deck))
+            .count();
+
+        let mut learning = Vec::new();
+        let mut review = Vec::new();
+        let mut new = Vec::new();
+        for card in self.cards.values().filter(|card| in_deck(card) && card.state.due <= now) {
+            match card.state.phase {
+                Phase::Learning | Phase::Relearning => learning.push(card),
+                Phase::Review => review.push(card),
+                Phase::New => new.push(card),
+            }
+        }
+        learning.sort_by_key(|card| card.state.due);
+        review.sort_by_key(|card| card.state.due);
+        new.truncate(self.parameters.new_per_day.saturating_sub(introduced));
+
+        let (learning_count, review_count, new_count) = (learning.len(), review.len(), new.len());
+        let cards = learning
+            .into_iter()
+            .chain(review)
+            .chain(new)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|card| DueCard {
+                card: card.clone(),
+                next_due: self.parameters.preview(&card.state, now).map(|state| state.due),
+            })
+            .collect();
+        DueQueue {
+            cards,
+            learning: learning_count,
+            review: review_count,
+            new: new_count,
+        }
+    }
+
+    /// Record a review of card `id` and schedule it
+    pub fn review(&mut self, id: &str, rating: Rating, now: u64, duration_ms: Option<u64>) -> Result<ReviewCard> {
+        let card = self
+            .card(id)
+            .ok_or_else(|| StudyNestError::ConfigError(format!("Unknown card: {}", id)))?;
+        let before = &card.state;
+        let state = self.parameters.schedule(before, rating, now);
+        let log = ReviewLog {
+            card_id: id.to_string(),
+            rating,
+            reviewed_at: now,
+            duration_ms,
+            phase: before.phase,
+            elapsed_days: before.elapsed_days(now),
+            scheduled_days: before
+                .last_review
+                .map_or(0.0, |last| before.due.saturating_sub(last) as f64 / DAY as f64),
+        };
+        self.append(ReviewEvent::Review { log, state })?;
+        Ok(self.card(id).cloned().expect("reviewed card is in the store"))
+    }
+
+    /// Fit the weights to the review history, keeping them when they predict recall
+    /// better than the current ones
+    pub fn optimize(&mut self, iterations: usize) -> Result<OptimizationReport> {
+        let report = optimize_weights(&self.logs, &self.parameters.weights, iterations)?;
+        if report.updated {
+            self.set_weights(report.weights.clone())?;
+        }
+        Ok(report)
+    }
+
+    /// Replace the FSRS weights, keeping the other parameters
+    pub fn set_weights(&mut self, weights: Vec<f64>) -> Result<()> {
+        let parameters = SchedulerParameters {
+            weights,
+            ..self.parameters.clone()
+        };
+        self.set_parameters(parameters)
+    }
+}
@@ -0,0 +1,657 @@
+//! Summaries of documents longer than the chat model's context
+//!
+//! Source text is split into paragraphs, starting a new one at each heading, and packed
+//! into chunks of a token budget, cutting before headings where possible. Each chunk is
+//! summarized (map) and consecutive summaries are merged group by group until one is
+//! left (reduce). Key terms are extracted per chunk as JSON and merged without the model.
+//!
+//! Every model reply is kept in a checkpoint file keyed by a hash of its prompt, so a
+//! summary that was cancelled or timed out continues where it stopped when it is asked
+//! for again with the same checkpoint.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{ChatEngine, FinishReason, GenerationControl};
+use crate::error::{Result, StudyNestError};
+use crate::study::{SourceRef, SourceText};
+
+/// Tokens of the prompt besides the source text: instructions and chat template
+const PROMPT_OVERHEAD: usize = 256;
+
+const BULLETS_PROMPT: &str = "You write study notes. Summarize the text as concise Markdown \
+bullet points (\"- \"), one idea per bullet, keeping definitions, formulas, names, dates and \
+examples. Reply with the bullet points only, in the language of the text.";
+
+const OUTLINE_PROMPT: &str = "You write study notes. Summarize the text as a Markdown outline \
+that follows its structure: \"##\" headings for the topics, with nested \"- \" bullet points \
+for what is said about each. Reply with the outline only, in the language of the text.";
+
+const ABSTRACT_PROMPT: &str = "You write abstracts. Summarize the text in one paragraph of \
+prose of at most 200 words, covering its subject, main points and conclusions. Reply with \
+the paragraph only, in the language of the text.";
+
+const KEY_TERMS_PROMPT: &str = "You build glossaries for students. List the key terms the text \
+defines or relies on, with a one-sentence definition of each as the text uses it. Reply \
+with JSON only, in this form:
+{\"terms\": [{\"term\": \"Osmosis\", \"definition\": \"Movement of water across a membrane.\"}]}
+Write in the language of the text.";
+
+/// Form of a summary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStyle {
+    /// Bullet-point study notes
+    #[default]
+    Bullets,
+    /// Headings with bullet points, following the document's structure
+    Outline,
+    /// One paragraph of prose
+    Abstract,
+    /// Glossary of the terms the document defines
+    KeyTerms,
+}
+
+impl SummaryStyle {
+    fn system_prompt(self) -> &'static str {
+        match self {
+            SummaryStyle::Bullets => BULLETS_PROMPT,
+            SummaryStyle::Outline => OUTLINE_PROMPT,
+            SummaryStyle::Abstract => ABSTRACT_PROMPT,
+            SummaryStyle::KeyTerms => KEY_TERMS_PROMPT,
+        }
+    }
+}
+
+/// A term and what it means in the document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyTerm {
+    pub term: String,
+    pub definition: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceRef>,
+}
+
+/// The model's list of key terms
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TermsReply {
+    Terms { terms: Vec<DraftTerm> },
+    List(Vec<DraftTerm>),
+}
+
+#[derive(Debug, Deserialize)]
+struct DraftTerm {
+    #[serde(alias = "name")]
+    term: String,
+    #[serde(alias = "meaning", default)]
+    definition: String,
+}
+
+/// Summarization settings
+#[derive(Debug, Clone)]
+pub struct SummaryConfig {
+    pub style: SummaryStyle,
+    /// Tokens of source text, or of summaries being merged, given to the model at once;
+    /// lowered to what the context holds
+    pub chunk_tokens: usize,
+    /// Token budget of each summary
+    pub max_tokens: usize,
+    /// File keeping finished steps, to continue an interrupted summary
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            style: SummaryStyle::default(),
+            chunk_tokens: 2048,
+            max_tokens: 512,
+            checkpoint: None,
+        }
+    }
+}
+
+impl SummaryConfig {
+    pub fn with_style(mut self, style: SummaryStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn with_chunk_tokens(mut self, tokens: usize) -> Self {
+        self.chunk_tokens = tokens;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+}
+
+/// Step of a summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryStage {
+    /// Summarizing chunks of the source text
+    Map,
+    /// Merging summaries
+    Reduce,
+}
+
+/// Reported after each chunk or group of summaries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryProgress {
+    pub stage: SummaryStage,
+    /// 0 for the chunks, then 1, 2, ... for each round of merging
+    pub level: usize,
+    pub done: usize,
+    pub total: usize,
+    /// Whether the step was taken from the checkpoint
+    pub resumed: bool,
+    /// Where the chunk starts, for the map stage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceRef>,
+}
+
+/// A finished or interrupted summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub style: SummaryStyle,
+    /// Markdown; when interrupted, the summaries finished at the last level
+    pub text: String,
+    /// Glossary of the `key_terms` style
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terms: Vec<KeyTerm>,
+    /// Chunks the source text was split into
+    pub chunks: usize,
+    /// Rounds of merging
+    pub levels: usize,
+    /// Steps taken from the checkpoint
+    pub resumed: usize,
+    /// `cancelled` or `timeout` when interrupted; asking again with the same checkpoint
+    /// continues from there
+    pub finish_reason: FinishReason,
+}
+
+/// A paragraph, or a piece of one that is too long for a chunk
+struct Block {
+    text: String,
+    source: SourceRef,
+    heading: bool,
+}
+
+/// Source text packed to the token budget
+struct Chunk {
+    text: String,
+    source: SourceRef,
+}
+
+impl Chunk {
+    /// Where the chunk starts, unless the text came without one
+    fn source_ref(&self) -> Option<SourceRef> {
+        (self.source != SourceRef::default()).then(|| self.source.clone())
+    }
+}
+
+/// Model replies by prompt hash
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) replies: HashMap<String, String>,
+}
+
+impl Checkpoint {
+    pub(crate) fn load(path: Option<&Path>) -> Self {
+        path.and_then(|path| fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, path: Option<&Path>) -> Result<()> {
+        let Some(path) = path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(path: Option<&Path>) {
+        if let Some(path) = path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn prompt_key(system: &str, prompt: &str) -> String {
+    sha1_smol::Sha1::from(format!("{}\n\n{}", system, prompt)).digest().to_string()
+}
+
+/// Markdown headings and numbered section titles such as `2.3 Osmosis`
+fn is_heading(line: &str) -> bool {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return true;
+    }
+    let mut words = line.split_whitespace();
+    let numbered = words
+        .next()
+        .is_some_and(|w| w.chars().next().is_some_and(|c| c.is_ascii_digit()) && w.chars().all(|c| c.is_ascii_digit() || c == '.'));
+    numbered
+        && words.next().is_some_and(|w| w.chars().next().is_some_and(char::is_uppercase))
+        && line.chars().count() <= 80
+        && !line.ends_with(['.', ',', ';', ':'])
+}
+
+/// Paragraphs of `source`, with headings starting new ones
+fn blocks(source: &SourceText) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for paragraph in source.text.split("\n\n") {
+        let mut current: Vec<&str> = Vec::new();
+        for line in paragraph.lines().filter(|line| !line.trim().is_empty()) {
+            if is_heading(line) && !current.is_empty() {
+                blocks.push(Block {
+                    text: current.join("\n"),
+                    source: source.source.clone(),
+                    heading: is_heading(current[0]),
+                });
+                current.clear();
+            }
+            current.push(line);
+        }
+        if !current.is_empty() {
+            blocks.push(Block {
+                text: current.join("\n"),
+                source: source.source.clone(),
+                heading: is_heading(current[0]),
+            });
+        }
+    }
+    blocks
+}
+
+/// Writes summaries with a chat model
+pub struct Summarizer {
+    config: SummaryConfig,
+}
+
+impl Summarizer {
+    pub fn new(config: SummaryConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &SummaryConfig {
+        &self.config
+    }
+
+    /// Summarize `sources` in the configured style, calling `progress` after each
+    /// step. Stops early when `control` cancels the request or its time runs out; the
+    /// steps finished by then are kept in the checkpoint.
+    pub fn summarize(
+        &self,
+        chat: &mut ChatEngine,
+        sources: &[SourceText],
+        title: Option<&str>,
+        control: &GenerationControl,
+        progress: &mut dyn FnMut(&SummaryProgress),
+    ) -> Result<Summary> {
+        let max_tokens = self.config.max_tokens.max(16);
+        let budget = self
+            .config
+            .chunk_tokens
+            .min(chat.max_context().saturating_sub(max_tokens + PROMPT_OVERHEAD));
+        // Merging needs room for at least two summaries
+        if budget < 2 * max_tokens {
+            return Err(StudyNestError::ContextLengthExceeded(format!(
+                "{} tokens of text per chunk leave no room to merge summaries of {} tokens",
+                budget, max_tokens
+            )));
+        }
+        let chunks = self.chunks(chat, sources, budget)?;
+        if chunks.is_empty() {
+            return Err(StudyNestError::ConfigError("Nothing to summarize".to_string()));
+        }
+
+        let checkpoint_path = self.config.checkpoint.as_deref();
+        let mut checkpoint = Checkpoint::load(checkpoint_path);
+        let mut run = Run {
+            chat,
+            control,
+            checkpoint: &mut checkpoint,
+            checkpoint_path,
+            max_tokens,
+            resumed: 0,
+        };
+        let style = self.config.style;
+        let about = title.map(|t| format!(" of \"{}\"", t)).unwrap_or_default();
+
+        if style == SummaryStyle::KeyTerms {
+            let mut terms: Vec<KeyTerm> = Vec::new();
+            let mut finish_reason = FinishReason::Stop;
+            let mut skipped = 0;
+            for (index, chunk) in chunks.iter().enumerate() {
+                let prompt = format!("Part {} of {}{}:\n\n{}", index + 1, chunks.len(), about, chunk.text);
+                let reply = match run.json::<TermsReply>(style.system_prompt(), &prompt)? {
+                    Step::Done(reply, resumed) => {
+                        report(progress, SummaryStage::Map, 0, index + 1, chunks.len(), resumed, chunk);
+                        reply
+                    }
+                    Step::Interrupted(reason) => {
+                        finish_reason = reason;
+                        break;
+                    }
+                    Step::Skipped => {
+                        skipped += 1;
+                        continue;
+                    }
+                };
+                let drafts = match reply {
+                    TermsReply::Terms { terms } | TermsReply::List(terms) => terms,
+                };
+                for draft in drafts {
+                    let term = draft.term.trim().to_string();
+                    let definition = draft.definition.trim().to_string();
+                    if term.is_empty() || terms.iter().any(|t| t.term.to_lowercase() == term.to_lowercase()) {
+                        continue;
+                    }
+                    terms.push(KeyTerm {
+                        term,
+                        definition,
+                        source: chunk.source_ref(),
+                    });
+                }
+            }
+            if skipped == chunks.len() {
+                // None of the replies could be used, so there is nothing to resume
+                run.finish();
+                return Err(StudyNestError::ModelError(
+                    "the model did not list key terms as JSON".to_string(),
+                ));
+            }
+            if finish_reason == FinishReason::Stop {
+                run.finish();
+            }
+            let text = terms
+                .iter()
+                .map(|t| format!("- **{}**: {}", t.term, t.definition))
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(Summary {
+                style,
+                text,
+                terms,
+                chunks: chunks.len(),
+                levels: 0,
+                resumed: run.resumed,
+                finish_reason,
+            });
+        }
+
+        // Map
+        let mut summaries = Vec::with_capacity(chunks.len());
+        for (index, chunk) in chunks.iter().enumerate() {
+            let prompt = format!("Part {} of {}{}:\n\n{}", index + 1, chunks.len(), about, chunk.text);
+            match run.text(style.system_prompt(), &prompt)? {
+                Step::Done(summary, resumed) => {
+                    report(progress, SummaryStage::Map, 0, index + 1, chunks.len(), resumed, chunk);
+                    summaries.push(summary);
+                }
+                Step::Interrupted(reason) => return Ok(run.partial(style, &summaries, chunks.len(), 0, reason)),
+                Step::Skipped => unreachable!("text replies are never skipped"),
+            }
+        }
+
+        // Reduce
+        let mut level = 0;
+        while summaries.len() > 1 {
+            level += 1;
+            let groups = self.groups(run.chat, &summaries, budget)?;
+            let total = groups.len();
+            let mut merged = Vec::with_capacity(total);
+            for (index, group) in groups.into_iter().enumerate() {
+                let summary = if group.len() == 1 {
+                    // A single summary left over at the end moves up as it is
+                    summaries[group[0]].clone()
+                } else {
+                    let parts: Vec<String> = group
+                        .iter()
+                        .enumerate()
+                        .map(|(n, &i)| format!("### Part {}\n\n{}", n + 1, summaries[i]))
+                        .collect();
+                    let prompt = format!(
+                        "Summaries of consecutive parts{}:\n\n{}\n\nMerge them into one summary in \
+                         the same form, without repeating points.",
+                        about,
+                        parts.join("\n\n")
+                    );
+                    match run.text(style.system_prompt(), &prompt)? {
+                        Step::Done(summary, resumed) => {
+                            progress(&SummaryProgress {
+                                stage: SummaryStage::Reduce,
+                                level,
+                                done: index + 1,
+                                total,
+                                resumed,
+                                source: None,
+                            });
+                            summary
+                        }
+                        Step::Interrupted(reason) => {
+                            return Ok(run.partial(style, &summaries, chunks.len(), level - 1, reason))
+                        }
+                        Step::Skipped => unreachable!("text replies are never skipped"),
+                    }
+                };
+                merged.push(summary);
+            }
+            summaries = merged;
+        }
+
+        run.finish();
+        Ok(Summary {
+            style,
+            text: summaries.pop().unwrap_or_default(),
+            terms: Vec::new(),
+            chunks: chunks.len(),
+            levels: level,
+            resumed: run.resumed,
+            finish_reason: FinishReason::Stop,
+        })
+    }
+
+    /// Pack the paragraphs of `sources` into chunks of at most `budget` tokens
+    fn chunks(&self, chat: &ChatEngine, sources: &[SourceText], budget: usize) -> Result<Vec<Chunk>> {
+        let mut chunks = Vec::new();
+        let mut current: Option<(Chunk, usize)> = None;
+        for source in sources {
+            for block in blocks(source) {
+                let tokens = chat.count_tokens(&block.text)?;
+                let pieces = if tokens > budget {
+                    // Split a long paragraph by characters, with some room to spare
+                    let chars = block.text.chars().count();
+                    let piece_chars = (chars * budget * 9 / 10 / tokens).max(1);
+                    crate::rag::chunk_text(&block.text, piece_chars, 0)
+                        .into_iter()
+                        .map(|(_, text)| {
+                            let tokens = chat.count_tokens(&text)?;
+                            Ok((text, tokens, false))
+                        })
+                        .collect::<Result<Vec<_>>>()?
+                } else {
+                    vec![(block.text, tokens, block.heading)]
+                };
+
+                for (text, tokens, heading) in pieces {
+                    match &mut current {
+                        // Start a new chunk at a heading once this one is half full
+                        Some((chunk, used))
+                            if *used + tokens <= budget && !(heading && *used >= budget / 2) =>
+                        {
+                            chunk.text.push_str("\n\n");
+                            chunk.text.push_str(&text);
+                            *used += tokens;
+                        }
+                        _ => {
+                            let next = Chunk {
+                                text,
+                                source: block.source.clone(),
+                            };
+                            if let Some((full, _)) = current.replace((next, tokens)) {
+                                chunks.push(full);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        chunks.extend(current.map(|(chunk, _)| chunk));
+        Ok(chunks)
+    }
+
+    /// Consecutive summaries to merge together, at least two per group except a last
+    /// one left over
+    fn groups(&self, chat: &ChatEngine, summaries: &[String], budget: usize) -> Result<Vec<Vec<usize>>> {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut used = 0;
+        for (index, summary) in summaries.iter().enumerate() {
+            let tokens = chat.count_tokens(summary)? + 8;
+            match groups.last_mut() {
+                Some(group) if group.len() < 2 || used + tokens <= budget => {
+                    group.push(index);
+                    used += tokens;
+                }
+                _ => {
+                    groups.push(vec![index]);
+                    used = tokens;
+                }
+            }
+        }
+        Ok(groups)
+    }
+}
+
+fn report(
+    progress: &mut dyn FnMut(&SummaryProgress),
+    stage: SummaryStage,
+    level: usize,
+    done: usize,
+    total: usize,
+    resumed: bool,
+    chunk: &Chunk,
+) {
+    progress(&SummaryProgress {
+        stage,
+        level,
+        done,
+        total,
+        resumed,
+        source: chunk.source_ref(),
+    });
+}
+
+/// Outcome of asking the model for one step
+pub(crate) enum Step<T> {
+    /// The reply, and whether it came from the checkpoint
+    Done(T, bool),
+    Interrupted(FinishReason),
+    /// The model did not answer in JSON
+    Skipped,
+}
+
+/// Model calls of one summary, through the checkpoint
+pub(crate) struct Run<'a> {
+    pub(crate) chat: &'a mut ChatEngine,
+    pub(crate) control: &'a GenerationControl,
+    pub(crate) checkpoint: &'a mut Checkpoint,
+    pub(crate) checkpoint_path: Option<&'a Path>,
+    pub(crate) max_tokens: usize,
+    pub(crate) resumed: usize,
+}
+
+impl Run<'_> {
+    fn text(&mut self, system: &str, prompt: &str) -> Result<Step<String>> {
+        let key = prompt_key(system, prompt);
+        if let Some(reply) = self.checkpoint.replies.get(&key) {
+            self.resumed += 1;
+            return Ok(Step::Done(reply.clone(), true));
+        }
+        if let Some(reason) = self.control.interruption() {
+            return Ok(Step::Interrupted(reason));
+        }
+        let (reply, finish_reason) = self.chat.complete(system, prompt, self.max_tokens, self.control)?;
+        match finish_reason {
+            FinishReason::Cancelled | FinishReason::Timeout => Ok(Step::Interrupted(finish_reason)),
+            // A summary cut at the token budget is still usable
+            FinishReason::Stop | FinishReason::Length => {
+                let reply = reply.trim().to_string();
+                self.keep(key, reply.clone())?;
+                Ok(Step::Done(reply, false))
+            }
+        }
+    }
+
+    pub(crate) fn json<T: serde::de::DeserializeOwned>(&mut self, system: &str, prompt: &str) -> Result<Step<T>> {
+        let key = prompt_key(system, prompt);
+        if let Some(reply) = self.checkpoint.replies.get(&key) {
+            if let Ok(value) = crate::chat::parse_json(reply) {
+                self.resumed += 1;
+                return Ok(Step::Done(value, true));
+            }
+        }
+        if let Some(reason) = self.control.interruption() {
+            return Ok(Step::Interrupted(reason));
+        }
+        match self.chat.complete_json::<serde_json::Value>(system, prompt, self.max_tokens, self.control) {
+            Ok(value) => {
+                let parsed = serde_json::from_value(value.clone());
+                self.keep(key, value.to_string())?;
+                match parsed {
+                    Ok(reply) => Ok(Step::Done(reply, false)),
+                    Err(e) => {
+                        eprintln!("[StudyNest] Skipping a part the model did not answer in JSON: {}", e);
+                        Ok(Step::Skipped)
+                    }
+                }
+            }
+            Err(e) => match self.control.interruption() {
+                Some(reason) => Ok(Step::Interrupted(reason)),
+                None => {
+                    eprintln!("[StudyNest] Skipping a part the model did not answer in JSON: {}", e);
+                    Ok(Step::Skipped)
+                }
+            },
+        }
+    }
+
+    fn keep(&mut self, key: String, reply: String) -> Result<()> {
+        self.checkpoint.replies.insert(key, reply);
+        self.checkpoint.save(self.checkpoint_path)
+    }
+
+    /// The finished summary no longer needs its checkpoint
+    fn finish(&self) {
+        Checkpoint::remove(self.checkpoint_path);
+    }
+
+    fn partial(&self, style: SummaryStyle, summaries: &[String], chunks: usize, levels: usize, reason: FinishReason) -> Summary {
+        Summary {
+            style,
+            text: summaries.join("\n\n"),
+            terms: Vec::new(),
+            chunks,
+            levels,
+            resumed: self.resumed,
+            finish_reason: reason,
+        }
+    }
+}
@@ -0,0 +1,756 @@
+//! Text-to-Speech (TTS) module using Orpheus with the SNAC decoder, or Spark-TTS
+//!
+//! Orpheus keeps a steady voice for about fifteen seconds of speech per generation, so
+//! longer text is read a few sentences at a time and the pieces are joined with a short
+//! pause. Markdown notes are turned into plain sentences first so that headings, list
+//! markers and links are not spelled out.
+//!
+//! Spark-TTS checkpoints are recognized by their `BiCodec` directory. Spark speaks as a
+//! female or male voice at a chosen pitch and speed, or clones a reference recording.
+//!
+//! `TtsEngine::synthesize_streaming` hands out audio while it is generated: codec frames are
+//! decoded a few at a time as the LM writes them, so playback can start after a fraction of
+//! a second instead of after the whole text.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{FinishReason, GenerationControl};
+use crate::device::get_device;
+use crate::device::DeviceType;
+use crate::error::{Result, StudyNestError};
+
+use candle_core::DType;
+#[cfg(feature = "onnx")]
+use crane_core::models::orpheus::{OrpheusLM, OrpheusTTS, SnacFrameCodec};
+use crane_core::models::sparktts::{
+    self, BiCodecFrameCodec, Gender, SparkTTS, SparkVoice, VoicePrompt, GLOBAL_TOKENS,
+};
+
+pub use crane_core::generation::audio_streamer::AudioChunk;
+
+pub use crane_core::models::orpheus::{DEFAULT_VOICE, SAMPLE_RATE, VOICES};
+pub use crane_core::models::sparktts::Level;
+
+/// Voices of Spark-TTS when it is not cloning a recording
+pub const SPARK_VOICES: [&str; 2] = ["female", "male"];
+
+/// TTS configuration
+#[derive(Debug, Clone)]
+pub struct TtsConfig {
+    pub model_path: String,
+    /// SNAC 24 kHz decoder; looked for in and next to the model directory when not given
+    pub snac_model_path: Option<String>,
+    pub device: DeviceType,
+    pub dtype: DType,
+    /// One of `VOICES` for Orpheus, or of `SPARK_VOICES`; the model's default when not set
+    pub voice: Option<String>,
+    /// Pitch of the Spark voice
+    pub pitch: Level,
+    /// Speaking rate of the Spark voice
+    pub speed: Level,
+    /// Recording whose voice Spark clones instead, and what is said in it
+    pub prompt_audio: Option<String>,
+    pub prompt_text: Option<String>,
+    /// Text read in one generation, in characters
+    pub max_chunk_chars: usize,
+    /// Token budget of one generation; about 80 tokens per second of speech for Orpheus
+    /// and 50 for Spark
+    pub max_tokens: usize,
+    /// Silence between pieces
+    pub pause_ms: u64,
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "checkpoints/orpheus-3b-0.1-ft".to_string(),
+            snac_model_path: None,
+            device: DeviceType::Auto,
+            dtype: DType::F16,
+            voice: None,
+            pitch: Level::Moderate,
+            speed: Level::Moderate,
+            prompt_audio: None,
+            prompt_text: None,
+            max_chunk_chars: 200,
+            max_tokens: 1400,
+            pause_ms: 300,
+        }
+    }
+}
+
+impl TtsConfig {
+    pub fn with_model_path(mut self, path: impl Into<String>) -> Self {
+        self.model_path = path.into();
+        self
+    }
+
+    pub fn with_snac_model_path(mut self, path: impl Into<String>) -> Self {
+        self.snac_model_path = Some(path.into());
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceType) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn with_voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = Some(voice.into());
+        self
+    }
+
+    pub fn with_pitch(mut self, pitch: Level) -> Self {
+        self.pitch = pitch;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: Level) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_prompt_audio(mut self, path: impl Into<String>, transcript: Option<String>) -> Self {
+        self.prompt_audio = Some(path.into());
+        self.prompt_text = transcript;
+        self
+    }
+
+    pub fn with_max_chunk_chars(mut self, max_chars: usize) -> Self {
+        self.max_chunk_chars = max_chars.max(1);
+        self
+    }
+
+    /// Whether `model_path` holds a Spark-TTS checkpoint
+    pub fn is_spark(&self) -> bool {
+        Path::new(&self.model_path).join("BiCodec").is_dir()
+    }
+}
+
+/// Reported after each piece is spoken
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtsProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Synthesized speech, mono at the model's sample rate
+#[derive(Debug, Clone)]
+pub struct TtsResult {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub duration_ms: u64,
+    pub processing_time_ms: u64,
+    /// Pieces the text was read in
+    pub chunks: usize,
+    /// `Cancelled` or `Timeout` when only the start of the text was read
+    pub finish_reason: FinishReason,
+}
+
+enum Backend {
+    #[cfg(feature = "onnx")]
+    Orpheus(Box<OrpheusTTS>),
+    Spark {
+        model: Box<SparkTTS>,
+        /// Tokens of `prompt_audio`, read once
+        prompt: Option<VoicePrompt>,
+        /// Global tokens of the speaker Spark made up for the voice settings, so that the
+        /// voice stays the same from one piece, and one call, to the next
+        speaker: Option<Vec<u32>>,
+    },
+}
+
+/// Text-to-Speech engine
+pub struct TtsEngine {
+    config: TtsConfig,
+    backend: Backend,
+}
+
+impl TtsEngine {
+    /// Create a new TTS engine
+    pub fn new(config: TtsConfig) -> Result<Self> {
+        eprintln!("[StudyNest] Initializing TTS engine with model: {}", config.model_path);
+
+        if !Path::new(&config.model_path).exists() {
+            return Err(StudyNestError::ConfigError(format!(
+                "Model path does not exist: {}. Please download the model first.",
+                config.model_path
+            )));
+        }
+
+        let device = get_device(config.device)?;
+        let backend = if config.is_spark() {
+            let model = SparkTTS::from_pretrained(&config.model_path, &device, &config.dtype)
+                .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+            Backend::Spark {
+                model: Box::new(model),
+                prompt: None,
+                speaker: None,
+            }
+        } else {
+            Self::orpheus(&config, &device)?
+        };
+
+        let mut engine = Self { config, backend };
+        if let Some(voice) = engine.config.voice.clone() {
+            engine.set_voice(&voice)?;
+        }
+        let (audio, text) = (engine.config.prompt_audio.clone(), engine.config.prompt_text.clone());
+        engine.set_prompt_audio(audio.as_deref(), text.as_deref())?;
+
+        eprintln!("[StudyNest] TTS engine initialized on {}", engine.config.device);
+        Ok(engine)
+    }
+
+    #[cfg(feature = "onnx")]
+    fn orpheus(config: &TtsConfig, device: &candle_core::Device) -> Result<Backend> {
+        let model = OrpheusTTS::new(
+            &config.model_path,
+            config.snac_model_path.as_deref(),
+            device,
+            &config.dtype,
+        )
+        .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        Ok(Backend::Orpheus(Box::new(model)))
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn orpheus(_config: &TtsConfig, _device: &candle_core::Device) -> Result<Backend> {
+        Err(StudyNestError::FeatureNotEnabled(
+            "ONNX feature not enabled. Compile with --features onnx for Orpheus TTS support.".to_string()
+        ))
+    }
+
+    pub fn config(&self) -> &TtsConfig {
+        &self.config
+    }
+
+    /// Voices the loaded model speaks in
+    pub fn voices(&self) -> &'static [&'static str] {
+        match self.backend {
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(_) => &VOICES,
+            Backend::Spark { .. } => &SPARK_VOICES,
+        }
+    }
+
+    /// The voice used when none is set
+    pub fn default_voice(&self) -> &'static str {
+        self.voices()[0]
+    }
+
+    /// The voice in use
+    pub fn voice(&self) -> &str {
+        self.config.voice.as_deref().unwrap_or(self.default_voice())
+    }
+
+    /// Speak with another of the model's voices from now on
+    pub fn set_voice(&mut self, voice: &str) -> Result<()> {
+        if !self.voices().contains(&voice) {
+            return Err(StudyNestError::ConfigError(format!(
+                "Unknown voice {}; available voices: {}",
+                voice,
+                self.voices().join(", ")
+            )));
+        }
+        if self.voice() != voice {
+            self.forget_speaker();
+        }
+        self.config.voice = Some(voice.to_string());
+        Ok(())
+    }
+
+    /// Pitch and speed of the Spark voice; a cloned voice keeps those of its recording
+    pub fn set_style(&mut self, pitch: Level, speed: Level) {
+        if (self.config.pitch, self.config.speed) != (pitch, speed) {
+            self.forget_speaker();
+        }
+        self.config.pitch = pitch;
+        self.config.speed = speed;
+    }
+
+    /// Have Spark make up a new speaker for the next piece
+    fn forget_speaker(&mut self) {
+        match &mut self.backend {
+            Backend::Spark { speaker, .. } => *speaker = None,
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(_) => {}
+        }
+    }
+
+    /// Clone the voice of a recording with Spark, or stop cloning with `None`. The
+    /// recording is only read again when the path or transcript change.
+    pub fn set_prompt_audio(&mut self, path: Option<&str>, transcript: Option<&str>) -> Result<()> {
+        let unchanged = self.config.prompt_audio.as_deref() == path
+            && self.config.prompt_text.as_deref() == transcript;
+        match &mut self.backend {
+            Backend::Spark { model, prompt, speaker } => {
+                if unchanged && (prompt.is_some() || path.is_none()) {
+                    return Ok(());
+                }
+                *speaker = None;
+                *prompt = match path {
+                    Some(path) => {
+                        let samples = crate::stt::SttEngine::load_audio(path)?;
+                        eprintln!("[StudyNest] Reading the voice of {}", path);
+                        Some(
+                            model
+                                .voice_prompt(&samples, transcript)
+                                .map_err(|e| StudyNestError::AudioError(e.to_string()))?,
+                        )
+                    }
+                    None => None,
+                };
+            }
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(_) => {
+                if path.is_some() {
+                    return Err(StudyNestError::ConfigError(
+                        "Voice cloning needs a Spark-TTS model".to_string(),
+                    ));
+                }
+            }
+        }
+        self.config.prompt_audio = path.map(str::to_string);
+        self.config.prompt_text = transcript.map(str::to_string);
+        Ok(())
+    }
+
+    /// Sample rate of the audio the loaded model produces
+    pub fn sample_rate(&self) -> u32 {
+        match self.backend {
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(_) => SAMPLE_RATE,
+            Backend::Spark { .. } => sparktts::SAMPLE_RATE,
+        }
+    }
+
+    /// Read `text` aloud, a few sentences at a time, calling `progress` after each piece.
+    /// Stops between pieces, or within one, when the request is cancelled or times out.
+    pub fn synthesize(
+        &mut self,
+        text: &str,
+        control: &GenerationControl,
+        mut progress: impl FnMut(&TtsProgress),
+    ) -> Result<TtsResult> {
+        let start = std::time::Instant::now();
+        let chunks = split_text(&speakable_text(text), self.config.max_chunk_chars);
+        if chunks.is_empty() {
+            return Err(StudyNestError::ConfigError("No text to read aloud".to_string()));
+        }
+        eprintln!("[StudyNest] Reading {} pieces aloud as {}", chunks.len(), self.voice());
+
+        let sample_rate = self.sample_rate();
+        let pause = vec![0.0; (sample_rate as u64 * self.config.pause_ms / 1000) as usize];
+        let mut samples = Vec::new();
+        let mut finish_reason = FinishReason::Stop;
+        for (i, chunk) in chunks.iter().enumerate() {
+            if let Some(reason) = control.interruption() {
+                finish_reason = reason;
+                break;
+            }
+            // Audio generated before an interruption is still worth hearing
+            match self.speak(chunk, control)? {
+                Some(audio) => {
+                    if !samples.is_empty() {
+                        samples.extend_from_slice(&pause);
+                    }
+                    samples.extend(audio);
+                }
+                None => eprintln!("[StudyNest] No speech was generated for piece {}", i + 1),
+            }
+            if let Some(reason) = control.interruption() {
+                finish_reason = reason;
+                break;
+            }
+            progress(&TtsProgress { done: i + 1, total: chunks.len() });
+        }
+
+        Ok(TtsResult {
+            duration_ms: samples.len() as u64 * 1000 / sample_rate as u64,
+            samples,
+            sample_rate,
+            processing_time_ms: start.elapsed().as_millis() as u64,
+            chunks: chunks.len(),
+            finish_reason,
+        })
+    }
+
+    /// Speak one piece
+    fn speak(&mut self, text: &str, control: &GenerationControl) -> Result<Option<Vec<f32>>> {
+        let voice = self.voice().to_string();
+        let audio = match &mut self.backend {
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(model) => {
+                let mut gen_config = OrpheusLM::generation_config(self.config.max_tokens);
+                gen_config.cancellation = control.cancellation.clone();
+                gen_config.deadline = control.deadline;
+                let codes = model
+                    .generate_codes(text, Some(&voice), &gen_config)
+                    .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+                if codes[0].is_empty() {
+                    return Ok(None);
+                }
+                model.decode(&codes)
+            }
+            Backend::Spark { model, prompt, speaker } => {
+                let mut gen_config = SparkTTS::generation_config(self.config.max_tokens);
+                gen_config.cancellation = control.cancellation.clone();
+                gen_config.deadline = control.deadline;
+                let spark_voice = spark_voice(&self.config, &voice, prompt.as_ref(), speaker.as_ref())?;
+                let (global, semantic) = model
+                    .generate_tokens(text, &spark_voice, &gen_config)
+                    .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+                if global.len() < GLOBAL_TOKENS || semantic.is_empty() {
+                    return Ok(None);
+                }
+                speaker.get_or_insert_with(|| global.clone());
+                model.bicodec.detokenize(&global, &semantic)
+            }
+        };
+        audio
+            .and_then(|audio| Ok(audio.flatten_all()?.to_vec1::<f32>()?))
+            .map(Some)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+
+    /// Read `text` aloud like `synthesize`, handing each piece of audio to `on_chunk` as soon
+    /// as it is decoded. Chunks are numbered across the whole text, the pauses between
+    /// pieces are part of them, and the final one is marked `last` and carries no samples.
+    /// The result holds no samples.
+    pub fn synthesize_streaming(
+        &mut self,
+        text: &str,
+        control: &GenerationControl,
+        mut on_chunk: impl FnMut(&AudioChunk) -> Result<()>,
+    ) -> Result<TtsResult> {
+        let start = std::time::Instant::now();
+        let chunks = split_text(&speakable_text(text), self.config.max_chunk_chars);
+        if chunks.is_empty() {
+            return Err(StudyNestError::ConfigError("No text to read aloud".to_string()));
+        }
+        eprintln!("[StudyNest] Streaming {} pieces aloud as {}", chunks.len(), self.voice());
+
+        let sample_rate = self.sample_rate();
+        let pause = vec![0.0; (sample_rate as u64 * self.config.pause_ms / 1000) as usize];
+        let mut index = 0;
+        let mut sent = 0;
+        let mut pause_due = false;
+        let mut finish_reason = FinishReason::Stop;
+        for (i, chunk) in chunks.iter().enumerate() {
+            if let Some(reason) = control.interruption() {
+                finish_reason = reason;
+                break;
+            }
+            let mut spoke = false;
+            self.speak_streaming(chunk, control, &mut |samples| {
+                if samples.is_empty() {
+                    return Ok(());
+                }
+                let samples = if std::mem::take(&mut pause_due) {
+                    [pause.as_slice(), &samples].concat()
+                } else {
+                    samples
+                };
+                spoke = true;
+                sent += samples.len();
+                index += 1;
+                on_chunk(&AudioChunk { index: index - 1, samples, last: false })
+            })?;
+            if spoke {
+                pause_due = true;
+            } else {
+                eprintln!("[StudyNest] No speech was generated for piece {}", i + 1);
+            }
+            if let Some(reason) = control.interruption() {
+                finish_reason = reason;
+                break;
+            }
+        }
+        on_chunk(&AudioChunk { index, samples: Vec::new(), last: true })?;
+
+        Ok(TtsResult {
+            duration_ms: sent as u64 * 1000 / sample_rate as u64,
+            samples: Vec::new(),
+            sample_rate,
+            processing_time_ms: start.elapsed().as_millis() as u64,
+            chunks: chunks.len(),
+            finish_reason,
+        })
+    }
+
+    /// Speak one piece, passing audio to `sink` while it is generated
+    fn speak_streaming(
+        &mut self,
+        text: &str,
+        control: &GenerationControl,
+        sink: &mut dyn FnMut(Vec<f32>) -> Result<()>,
+    ) -> Result<()> {
+        let voice = self.voice().to_string();
+        // the sink's own errors, e.g. a closed connection, are returned as they are
+        let mut sink_error = None;
+        let mut forward = |chunk: AudioChunk| -> anyhow::Result<()> {
+            sink(chunk.samples).map_err(|e| {
+                let message = e.to_string();
+                sink_error = Some(e);
+                anyhow::anyhow!(message)
+            })
+        };
+        let generated = match &mut self.backend {
+            #[cfg(feature = "onnx")]
+            Backend::Orpheus(model) => {
+                let mut gen_config = OrpheusLM::generation_config(self.config.max_tokens);
+                gen_config.cancellation = control.cancellation.clone();
+                gen_config.deadline = control.deadline;
+                model.synthesize_streaming(
+                    text,
+                    Some(&voice),
+                    &gen_config,
+                    SnacFrameCodec::streaming_config(),
+                    &mut forward,
+                )
+            }
+            Backend::Spark { model, prompt, speaker } => {
+                let mut gen_config = SparkTTS::generation_config(self.config.max_tokens);
+                gen_config.cancellation = control.cancellation.clone();
+                gen_config.deadline = control.deadline;
+                let spark_voice = spark_voice(&self.config, &voice, prompt.as_ref(), speaker.as_ref())?;
+                model
+                    .synthesize_streaming(
+                        text,
+                        &spark_voice,
+                        &gen_config,
+                        BiCodecFrameCodec::streaming_config(),
+                        &mut forward,
+                    )
+                    .map(|global| {
+                        if global.len() == GLOBAL_TOKENS {
+                            speaker.get_or_insert(global);
+                        }
+                    })
+            }
+        };
+        match (generated, sink_error) {
+            (Ok(()), _) => Ok(()),
+            (Err(_), Some(e)) => Err(e),
+            (Err(e), None) => Err(StudyNestError::ModelError(e.to_string())),
+        }
+    }
+
+    /// Write synthesized speech as a 16-bit WAV file
+    pub fn save_wav<P: AsRef<Path>>(&self, result: &TtsResult, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let audio = candle_core::Tensor::from_slice(&result.samples, result.samples.len(), &candle_core::Device::Cpu)
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))?;
+        sparktts::save_wav(&audio, &path.to_string_lossy(), result.sample_rate)
+            .map_err(|e| StudyNestError::AudioError(format!("Failed to write WAV file: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// How Spark speaks a piece: as the cloned recording, as the speaker it made up earlier,
+/// or as a new speaker described by the voice, pitch and speed
+fn spark_voice(
+    config: &TtsConfig,
+    voice: &str,
+    prompt: Option<&VoicePrompt>,
+    speaker: Option<&Vec<u32>>,
+) -> Result<SparkVoice> {
+    Ok(match (prompt, speaker) {
+        (Some(prompt), _) => SparkVoice::Clone(prompt.clone()),
+        (None, Some(global_tokens)) => SparkVoice::Clone(VoicePrompt {
+            global_tokens: global_tokens.clone(),
+            semantic_tokens: Vec::new(),
+            transcript: None,
+        }),
+        (None, None) => SparkVoice::Control {
+            gender: voice
+                .parse::<Gender>()
+                .map_err(|e| StudyNestError::ConfigError(e.to_string()))?,
+            pitch: config.pitch,
+            speed: config.speed,
+        },
+    })
+}
+
+/// Samples as 16-bit little-endian PCM
+pub fn pcm_s16le(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|&s| ((s * 32767.0).round().clamp(-32768.0, 32767.0) as i16).to_le_bytes())
+        .collect()
+}
+
+/// Plain sentences from markdown: headings, emphasis, list markers, links and code
+/// blocks are dropped, keeping the words. Headings, list items and table rows are read
+/// as sentences of their own.
+pub fn speakable_text(markdown: &str) -> String {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    let mut in_code = false;
+    for line in markdown.lines().map(str::trim) {
+        if line.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let (block, mut text) = block_text(line);
+        if (block || text.is_empty()) && !current.is_empty() {
+            paragraphs.push(std::mem::take(&mut current));
+        }
+        if text.is_empty() {
+            continue;
+        }
+        if block {
+            if !text.ends_with(['.', '!', '?', ':', ';']) {
+                text.push('.');
+            }
+            paragraphs.push(text);
+        } else {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(&text);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+    paragraphs.join("\n")
+}
+
+/// Whether a line is a block of its own (heading, list item, quote or table row), and
+/// its text without markup
+fn block_text(line: &str) -> (bool, String) {
+    // rules and table separators
+    if line.chars().all(|c| matches!(c, '-' | '=' | '*' | '_' | '|' | ':' | ' ')) {
+        return (!line.is_empty(), String::new());
+    }
+    let numbered = line
+        .split_once(". ")
+        .filter(|(n, _)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    let (block, text) = if let Some(rest) = line.strip_prefix('#') {
+        (true, rest.trim_start_matches('#'))
+    } else if let Some(rest) = line.strip_prefix(['-', '*', '+']).filter(|r| r.starts_with(' ')) {
+        (true, rest)
+    } else if let Some(rest) = line.strip_prefix('>') {
+        (true, rest)
+    } else if line.starts_with('|') {
+        let cells: Vec<String> = line
+            .split('|')
+            .map(|cell| strip_inline_markdown(cell.trim()))
+            .filter(|cell| !cell.is_empty())
+            .collect();
+        return (true, cells.join(", "));
+    } else if let Some((_, rest)) = numbered {
+        (true, rest)
+    } else {
+        (false, line)
+    };
+    (block, strip_inline_markdown(text.trim()))
+}
+
+/// Keep the text of links and images and drop emphasis and code markers
+fn strip_inline_markdown(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '_' | '`' => {}
+            '!' if chars.peek() == Some(&'[') => {}
+            ']' if chars.peek() == Some(&'(') => {
+                // skip the link target
+                for c in chars.by_ref() {
+                    if c == ')' {
+                        break;
+                    }
+                }
+            }
+            '[' => {}
+            _ => out.push(c),
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Split text into pieces of whole sentences of at most `max_chars` characters;
+/// overlong sentences are cut at commas or spaces
+pub fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for paragraph in text.lines().map(str::trim).filter(|p| !p.is_empty()) {
+        for sentence in sentences(paragraph) {
+            for part in cut(sentence, max_chars) {
+                if !current.is_empty() && current.chars().count() + 1 + part.chars().count() > max_chars {
+                    pieces.push(std::mem::take(&mut current));
+                }
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(part);
+            }
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Sentences of a paragraph, ending at `.`, `!`, `?` or `;` followed by a space
+pub(crate) fn sentences(paragraph: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = paragraph.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_end = chars.peek().is_none_or(|&(_, next)| next.is_whitespace());
+        if matches!(c, '.' | '!' | '?' | ';' | '。' | '！' | '？') && at_end {
+            let end = i + c.len_utf8();
+            let sentence = paragraph[start..end].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = end;
+        }
+    }
+    let rest = paragraph[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+    sentences
+}
+
+/// Cut a sentence longer than `max_chars` after the last comma, or else the last space,
+/// that keeps the part within the limit
+fn cut(sentence: &str, max_chars: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = sentence;
+    while rest.chars().count() > max_chars {
+        let limit = rest.char_indices().nth(max_chars).map_or(rest.len(), |(i, _)| i);
+        let head = &rest[..limit];
+        let at = head
+            .rfind(", ")
+            .map(|i| i + 1)
+            .or_else(|| head.rfind(' '))
+            .filter(|&i| i > 0)
+            .unwrap_or(limit);
+        parts.push(rest[..at].trim());
+        rest = rest[at..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+/// Voices of Orpheus; `TtsEngine::voices` lists those of the loaded model
+pub fn list_available_voices() -> Vec<&'static str> {
+    VOICES.to_vec()
+}
@@ -0,0 +1,697 @@
+//! Spoken conversation: listen, transcribe, reply and read the reply aloud
+//!
+//! `VoiceSession` takes 16 kHz microphone audio in pieces of any size. The Silero voice
+//! activity detector marks where the user stops talking; the utterance is then transcribed
+//! and sent to the chat model, and the reply is read aloud a sentence at a time while it is
+//! still being written. Speaking over the reply (barge-in) cancels it and tells the client
+//! to stop playing it. Replies run on threads of their own, so audio keeps being read in
+//! the meantime.
+//!
+//! Playback is left to the client, which should cancel its own echo: the reply picked up
+//! by the microphone would otherwise interrupt itself.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::chat::{CancellationToken, ChatEngine, FinishReason, GenerationControl};
+use crate::error::{Result, StudyNestError};
+use crate::stt::SttEngine;
+use crate::tts::{self, TtsEngine};
+
+#[cfg(feature = "onnx")]
+use crane_core::models::silero_vad::{Vad, VadConfig, CHUNKS_SR16K};
+
+/// Sample rate of the audio a session listens to
+pub const SAMPLE_RATE: u32 = 16000;
+
+/// Voice session configuration
+#[derive(Debug, Clone)]
+pub struct VoiceConfig {
+    /// Silence that ends an utterance
+    pub min_silence_ms: usize,
+    /// Shorter stretches of speech are taken for noise
+    pub min_speech_ms: usize,
+    /// Audio kept before and after the detected speech
+    pub speech_pad_ms: usize,
+    /// Speech over a reply that cancels it while the user is still talking; with `None`,
+    /// the reply is only cancelled once the new utterance is complete
+    pub barge_in_ms: Option<usize>,
+    /// Silero VAD model; downloaded when not given
+    pub vad_model_path: Option<String>,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            min_silence_ms: 700,
+            min_speech_ms: 250,
+            speech_pad_ms: 200,
+            barge_in_ms: Some(300),
+            vad_model_path: None,
+        }
+    }
+}
+
+impl VoiceConfig {
+    pub fn with_min_silence_ms(mut self, ms: usize) -> Self {
+        self.min_silence_ms = ms;
+        self
+    }
+
+    pub fn with_barge_in_ms(mut self, ms: Option<usize>) -> Self {
+        self.barge_in_ms = ms;
+        self
+    }
+
+    pub fn with_vad_model_path(mut self, path: impl Into<String>) -> Self {
+        self.vad_model_path = Some(path.into());
+        self
+    }
+}
+
+/// What happens in a session, in order. Each utterance starts a new `turn`.
+#[derive(Debug, Clone)]
+pub enum VoiceEvent {
+    /// The user started speaking
+    SpeechStarted,
+    /// What the user said; empty when nothing was understood, and then no reply follows
+    Transcript { turn: usize, text: String },
+    /// A sentence of the reply, about to be spoken
+    Sentence { turn: usize, text: String },
+    /// Audio of the reply, mono at `sample_rate`
+    Audio { turn: usize, samples: Vec<f32>, sample_rate: u32 },
+    /// The whole reply text, partial when it was interrupted
+    Reply { turn: usize, text: String, finish_reason: FinishReason },
+    /// All audio of the reply has been sent
+    Spoken { turn: usize },
+    /// The user spoke over the reply: stop playing it and drop its queued audio
+    Interrupted { turn: usize },
+    Error { turn: usize, message: String },
+}
+
+/// Cuts streamed text into sentences as soon as each is complete
+#[derive(Debug, Default)]
+pub struct SentenceSplitter {
+    buffer: String,
+}
+
+impl SentenceSplitter {
+    /// Add text and return the sentences it completes
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+        let mut done = Vec::new();
+        while let Some(i) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=i).collect();
+            done.extend(tts::sentences(line.trim()).into_iter().map(str::to_string));
+        }
+        // The last sentence may still go on, e.g. after the "3." of "3.5"
+        let parts = tts::sentences(&self.buffer);
+        if let [complete @ .., last] = parts.as_slice() {
+            if !complete.is_empty() {
+                let keep = self.buffer.rfind(last).unwrap_or(0);
+                done.extend(complete.iter().map(|s| s.to_string()));
+                self.buffer.drain(..keep);
+            }
+        }
+        done
+    }
+
+    /// What is left once the text is complete
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = rest.trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    }
+}
+
+/// Silero VAD fed one model chunk at a time
+#[cfg(feature = "onnx")]
+struct Detector {
+    vad: Vad,
+}
+
+#[cfg(feature = "onnx")]
+impl Detector {
+    const CHUNK: usize = CHUNKS_SR16K;
+
+    fn new(config: &VoiceConfig) -> Result<Self> {
+        let mut vad_config = VadConfig::new(config.min_silence_ms, SAMPLE_RATE as usize);
+        vad_config.use_cpu = true;
+        vad_config.min_speech = config.min_speech_ms;
+        vad_config.speech_pad = config.speech_pad_ms;
+        let mut vad = Vad::new(vad_config);
+        vad.load(config.vad_model_path.as_deref().unwrap_or_default())
+            .map_err(|e| StudyNestError::ModelError(format!("Failed to load VAD model: {}", e)))?;
+        Ok(Self { vad })
+    }
+
+    fn push(&mut self, chunk: &[f32]) -> Result<()> {
+        self.vad.segment_audio(chunk)
+            .map(|_| ())
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+
+    fn is_speaking(&self) -> bool {
+        self.vad.is_triggered()
+    }
+
+    /// Stretches of speech so far, in samples since the session began
+    fn segments(&self) -> Vec<(usize, usize)> {
+        self.vad.get_segments().into_owned()
+    }
+
+    /// Close the stretch of speech going on
+    fn flush(&mut self) -> Result<()> {
+        self.vad.flush()
+            .map(|_| ())
+            .map_err(|e| StudyNestError::ModelError(e.to_string()))
+    }
+}
+
+#[cfg(not(feature = "onnx"))]
+enum Detector {}
+
+#[cfg(not(feature = "onnx"))]
+impl Detector {
+    const CHUNK: usize = 512;
+
+    fn new(_config: &VoiceConfig) -> Result<Self> {
+        Err(StudyNestError::FeatureNotEnabled(
+            "ONNX feature not enabled. Compile with --features onnx for voice activity detection.".to_string()
+        ))
+    }
+
+    fn push(&mut self, _chunk: &[f32]) -> Result<()> {
+        match *self {}
+    }
+
+    fn is_speaking(&self) -> bool {
+        match *self {}
+    }
+
+    fn segments(&self) -> Vec<(usize, usize)> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match *self {}
+    }
+}
+
+/// The reply to one utterance, being written and spoken
+struct Reply {
+    turn: usize,
+    cancellation: CancellationToken,
+    threads: Vec<JoinHandle<()>>,
+    /// When the audio sent so far ends if it is played as it arrives
+    playback_end: Arc<Mutex<Instant>>,
+    interrupted: bool,
+}
+
+impl Reply {
+    /// Still being written, spoken or played
+    fn is_active(&self) -> bool {
+        !self.interrupted
+            && (self.threads.iter().any(|t| !t.is_finished())
+                || Instant::now() < *self.playback_end.lock().unwrap())
+    }
+}
+
+/// Who is talking: follows the detector chunk by chunk, numbers the turns and stops the
+/// reply when the user speaks over it
+struct TurnTaking {
+    /// Speech over a reply that cancels it, in samples
+    barge_in: Option<usize>,
+    /// Where the stretch of speech going on started, in samples
+    speech_start: Option<usize>,
+    turn: usize,
+    reply: Option<Reply>,
+    events: mpsc::Sender<VoiceEvent>,
+}
+
+impl TurnTaking {
+    fn new(config: &VoiceConfig, events: mpsc::Sender<VoiceEvent>) -> Self {
+        Self {
+            barge_in: config.barge_in_ms.map(|ms| ms * SAMPLE_RATE as usize / 1000),
+            speech_start: None,
+            turn: 0,
+            reply: None,
+            events,
+        }
+    }
+
+    /// Follow the detector after a chunk ending at sample `heard`; true when an
+    /// utterance just ended
+    fn step(&mut self, speaking: bool, heard: usize) -> bool {
+        match self.speech_start {
+            None if speaking => {
+                self.speech_start = Some(heard);
+                let _ = self.events.send(VoiceEvent::SpeechStarted);
+            }
+            Some(start) if speaking => {
+                let barged_in = self.barge_in.is_some_and(|samples| heard - start >= samples);
+                if barged_in {
+                    self.interrupt();
+                }
+            }
+            Some(_) => {
+                self.speech_start = None;
+                return true;
+            }
+            None => {}
+        }
+        false
+    }
+
+    /// Nobody is speaking
+    fn is_listening(&self) -> bool {
+        self.speech_start.is_none()
+    }
+
+    /// End of input: speech still going on is over
+    fn end_speech(&mut self) {
+        self.speech_start = None;
+    }
+
+    /// Stop the current reply if it is still written, spoken or played
+    fn interrupt(&mut self) {
+        let Some(reply) = &mut self.reply else {
+            return;
+        };
+        if reply.is_active() {
+            reply.cancellation.cancel();
+            reply.interrupted = true;
+            eprintln!("[StudyNest] Reply {} interrupted", reply.turn);
+            let _ = self.events.send(VoiceEvent::Interrupted { turn: reply.turn });
+        }
+    }
+
+    /// Start the next turn; a new utterance supersedes the reply to the previous one
+    fn next_turn(&mut self) -> usize {
+        self.interrupt();
+        if let Some(reply) = &self.reply {
+            reply.cancellation.cancel();
+        }
+        self.turn += 1;
+        self.turn
+    }
+
+    /// Wait until the current reply has been written and spoken
+    fn wait(&mut self) {
+        if let Some(reply) = &mut self.reply {
+            for thread in reply.threads.drain(..) {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Spoken conversation with a chat model
+pub struct VoiceSession {
+    config: VoiceConfig,
+    detector: Detector,
+    stt: Arc<SttEngine>,
+    chat: Arc<Mutex<ChatEngine>>,
+    tts: Arc<Mutex<TtsEngine>>,
+    events: mpsc::Sender<VoiceEvent>,
+    /// Audio heard from sample `audio_start` on
+    audio: Vec<f32>,
+    audio_start: usize,
+    /// Samples waiting for a whole VAD chunk
+    pending: Vec<f32>,
+    /// Samples given to the detector
+    heard: usize,
+    /// Stretches of speech already answered
+    segments_seen: usize,
+    turns: TurnTaking,
+}
+
+impl VoiceSession {
+    /// Start a session; events arrive on the returned receiver
+    pub fn new(
+        stt: SttEngine,
+        chat: ChatEngine,
+        tts: TtsEngine,
+        config: VoiceConfig,
+    ) -> Result<(Self, mpsc::Receiver<VoiceEvent>)> {
+        let detector = Detector::new(&config)?;
+        let (events, receiver) = mpsc::channel();
+        let session = Self {
+            turns: TurnTaking::new(&config, events.clone()),
+            config,
+            detector,
+            stt: Arc::new(stt),
+            chat: Arc::new(Mutex::new(chat)),
+            tts: Arc::new(Mutex::new(tts)),
+            events,
+            audio: Vec::new(),
+            audio_start: 0,
+            pending: Vec::new(),
+            heard: 0,
+            segments_seen: 0,
+        };
+        Ok((session, receiver))
+    }
+
+    /// The chat engine, e.g. to set the system prompt or read the history
+    pub fn chat(&self) -> &Arc<Mutex<ChatEngine>> {
+        &self.chat
+    }
+
+    /// The TTS engine, e.g. to change the voice
+    pub fn tts(&self) -> &Arc<Mutex<TtsEngine>> {
+        &self.tts
+    }
+
+    /// Listen to 16 kHz mono samples
+    pub fn push_audio(&mut self, samples: &[f32]) -> Result<()> {
+        self.audio.extend_from_slice(samples);
+        self.pending.extend_from_slice(samples);
+        let whole = self.pending.len() / Detector::CHUNK * Detector::CHUNK;
+        let pending = std::mem::take(&mut self.pending);
+        for chunk in pending[..whole].chunks(Detector::CHUNK) {
+            self.detector.push(chunk)?;
+            self.heard += chunk.len();
+            self.step()?;
+        }
+        self.pending = pending[whole..].to_vec();
+        Ok(())
+    }
+
+    /// Listen to a WAV file of any format, as if it were spoken into the microphone
+    pub fn push_wav(&mut self, path: &str) -> Result<()> {
+        self.push_audio(&SttEngine::load_audio(path)?)
+    }
+
+    /// End of input: speech still going on is taken as a finished utterance
+    pub fn finish(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.detector.push(&pending)?;
+            self.heard += pending.len();
+        }
+        self.detector.flush()?;
+        self.turns.end_speech();
+        self.end_utterance();
+        Ok(())
+    }
+
+    /// Wait until the current reply has been written and spoken
+    pub fn wait(&mut self) {
+        self.turns.wait();
+    }
+
+    /// Stop the current reply, as when the user speaks over it
+    pub fn interrupt(&mut self) {
+        self.turns.interrupt();
+    }
+
+    /// Follow the detector after one chunk
+    fn step(&mut self) -> Result<()> {
+        if self.turns.step(self.detector.is_speaking(), self.heard) {
+            self.end_utterance();
+        }
+
+        if self.turns.is_listening() {
+            // Keep enough audio before the next stretch of speech for its padding
+            let keep = self.config.speech_pad_ms * SAMPLE_RATE as usize / 1000 + 2 * Detector::CHUNK;
+            let drop = self.heard.saturating_sub(keep).saturating_sub(self.audio_start);
+            if drop > 0 {
+                self.audio.drain(..drop.min(self.audio.len()));
+                self.audio_start += drop;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer the speech since the last utterance, unless it was too short to count
+    fn end_utterance(&mut self) {
+        let segments = self.detector.segments();
+        if segments.len() <= self.segments_seen {
+            return;
+        }
+        let start = segments[self.segments_seen].0;
+        let end = segments[segments.len() - 1].1
+            + self.config.speech_pad_ms * SAMPLE_RATE as usize / 1000;
+        self.segments_seen = segments.len();
+
+        let from = start.saturating_sub(self.audio_start).min(self.audio.len());
+        let to = end.saturating_sub(self.audio_start).min(self.audio.len());
+        let utterance = self.audio[from..to].to_vec();
+        self.start_reply(utterance);
+    }
+
+    fn start_reply(&mut self, utterance: Vec<f32>) {
+        let turn = self.turns.next_turn();
+        let cancellation = CancellationToken::new();
+        let control = GenerationControl {
+            cancellation: Some(cancellation.clone()),
+            deadline: None,
+        };
+        let playback_end = Arc::new(Mutex::new(Instant::now()));
+        let (sentences, to_speak) = mpsc::channel();
+
+        let writer = {
+            let (stt, chat, events, control) =
+                (self.stt.clone(), self.chat.clone(), self.events.clone(), control.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = write_reply(turn, &utterance, &stt, &chat, &control, &events, sentences) {
+                    let _ = events.send(VoiceEvent::Error { turn, message: e.to_string() });
+                }
+            })
+        };
+        let speaker = {
+            let (tts, events, playback_end) =
+                (self.tts.clone(), self.events.clone(), playback_end.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = speak_reply(turn, &tts, &control, &events, &playback_end, to_speak) {
+                    let _ = events.send(VoiceEvent::Error { turn, message: e.to_string() });
+                }
+                let _ = events.send(VoiceEvent::Spoken { turn });
+            })
+        };
+
+        self.turns.reply = Some(Reply {
+            turn,
+            cancellation,
+            threads: vec![writer, speaker],
+            playback_end,
+            interrupted: false,
+        });
+    }
+}
+
+/// Transcribe the utterance and stream the reply, passing on each sentence once written
+fn write_reply(
+    turn: usize,
+    utterance: &[f32],
+    stt: &SttEngine,
+    chat: &Mutex<ChatEngine>,
+    control: &GenerationControl,
+    events: &mpsc::Sender<VoiceEvent>,
+    sentences: mpsc::Sender<String>,
+) -> Result<()> {
+    let text = stt.transcribe_audio(utterance)?.text;
+    let _ = events.send(VoiceEvent::Transcript { turn, text: text.clone() });
+    if text.trim().is_empty() || control.interruption().is_some() {
+        return Ok(());
+    }
+
+    let say = |sentence: String| {
+        let _ = events.send(VoiceEvent::Sentence { turn, text: sentence.clone() });
+        let _ = sentences.send(sentence);
+    };
+    let mut splitter = SentenceSplitter::default();
+    let mut streamed = false;
+    let reply = chat.lock().unwrap().chat_streaming_with_control(&text, control, |delta| {
+        streamed |= !delta.is_empty();
+        splitter.push(delta).into_iter().for_each(say);
+    })?;
+    if control.interruption().is_none() {
+        // Only streamed text is spoken, so a reply that never streamed would go unheard
+        if !streamed {
+            return Err(StudyNestError::ModelError(format!(
+                "The chat model streamed no text for a reply of {} characters ({:?})",
+                reply.content.len(),
+                reply.finish_reason
+            )));
+        }
+        splitter.finish().into_iter().for_each(say);
+    }
+    let _ = events.send(VoiceEvent::Reply {
+        turn,
+        text: reply.content,
+        finish_reason: reply.finish_reason,
+    });
+    Ok(())
+}
+
+/// Speak the sentences of the reply as they come, until the writer is done
+fn speak_reply(
+    turn: usize,
+    tts: &Mutex<TtsEngine>,
+    control: &GenerationControl,
+    events: &mpsc::Sender<VoiceEvent>,
+    playback_end: &Mutex<Instant>,
+    sentences: mpsc::Receiver<String>,
+) -> Result<()> {
+    for sentence in sentences {
+        if control.interruption().is_some() {
+            break;
+        }
+        if tts::speakable_text(&sentence).trim().is_empty() {
+            continue;
+        }
+        let mut tts = tts.lock().unwrap();
+        let sample_rate = tts.sample_rate();
+        tts.synthesize_streaming(&sentence, control, |chunk| {
+            if chunk.samples.is_empty() || control.interruption().is_some() {
+                return Ok(());
+            }
+            let duration = Duration::from_secs_f64(chunk.samples.len() as f64 / sample_rate as f64);
+            let mut end = playback_end.lock().unwrap();
+            *end = (*end).max(Instant::now()) + duration;
+            let _ = events.send(VoiceEvent::Audio {
+                turn,
+                samples: chunk.samples.clone(),
+                sample_rate,
+            });
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(pieces: &[&str]) -> (Vec<String>, Option<String>) {
+        let mut splitter = SentenceSplitter::default();
+        let sentences = pieces.iter().flat_map(|piece| splitter.push(piece)).collect();
+        (sentences, splitter.finish())
+    }
+
+    #[test]
+    fn splitter_passes_on_sentences_once_the_next_one_starts() {
+        let mut splitter = SentenceSplitter::default();
+        assert!(splitter.push("Paris is the").is_empty());
+        assert!(splitter.push(" capital.").is_empty());
+        assert_eq!(splitter.push(" It lies on"), ["Paris is the capital."]);
+        assert_eq!(splitter.push(" the Seine! Visit"), ["It lies on the Seine!"]);
+        assert_eq!(splitter.finish().as_deref(), Some("Visit"));
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn splitter_waits_for_the_end_of_a_number() {
+        let (sentences, rest) = split(&["It is 3.", "5 degrees", ". Cold"]);
+        assert_eq!(sentences, ["It is 3.5 degrees."]);
+        assert_eq!(rest.as_deref(), Some("Cold"));
+    }
+
+    #[test]
+    fn splitter_ends_sentences_at_line_breaks() {
+        let (sentences, rest) = split(&["- eggs\n- fl", "our\n\n", "Mix them"]);
+        assert_eq!(sentences, ["- eggs", "- flour"]);
+        assert_eq!(rest.as_deref(), Some("Mix them"));
+    }
+
+    #[test]
+    fn splitter_keeps_nothing_of_blank_text() {
+        assert_eq!(split(&["  ", "\n"]), (Vec::new(), None));
+    }
+
+    /// A reply still being played for `playing_for`
+    fn reply(turn: usize, playing_for: Duration) -> Reply {
+        Reply {
+            turn,
+            cancellation: CancellationToken::new(),
+            threads: Vec::new(),
+            playback_end: Arc::new(Mutex::new(Instant::now() + playing_for)),
+            interrupted: false,
+        }
+    }
+
+    fn turn_taking(barge_in_ms: Option<usize>) -> (TurnTaking, mpsc::Receiver<VoiceEvent>) {
+        let (events, receiver) = mpsc::channel();
+        let config = VoiceConfig::default().with_barge_in_ms(barge_in_ms);
+        (TurnTaking::new(&config, events), receiver)
+    }
+
+    fn interruptions(events: &mpsc::Receiver<VoiceEvent>) -> Vec<usize> {
+        events
+            .try_iter()
+            .filter_map(|e| match e {
+                VoiceEvent::Interrupted { turn } => Some(turn),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utterance_ends_when_speech_stops() {
+        let (mut turns, events) = turn_taking(Some(300));
+        assert!(!turns.step(false, 512));
+        assert!(events.try_recv().is_err());
+
+        assert!(!turns.step(true, 1024));
+        assert!(matches!(events.try_recv(), Ok(VoiceEvent::SpeechStarted)));
+        assert!(!turns.step(true, 1536));
+        assert!(!turns.is_listening());
+        assert!(turns.step(false, 2048));
+        assert!(turns.is_listening());
+        assert!(events.try_recv().is_err());
+        assert_eq!(turns.next_turn(), 1);
+    }
+
+    #[test]
+    fn speaking_over_a_reply_interrupts_it_once() {
+        let (mut turns, events) = turn_taking(Some(300));
+        let barge_in = 300 * SAMPLE_RATE as usize / 1000;
+        assert_eq!(turns.next_turn(), 1);
+        turns.reply = Some(reply(1, Duration::from_secs(60)));
+
+        turns.step(true, 1000);
+        turns.step(true, 1000 + barge_in - 1);
+        assert!(interruptions(&events).is_empty());
+        turns.step(true, 1000 + barge_in);
+        assert_eq!(interruptions(&events), [1]);
+        assert!(turns.reply.as_ref().unwrap().cancellation.is_cancelled());
+
+        // Neither more speech nor the next turn interrupt it again
+        turns.step(true, 1000 + 2 * barge_in);
+        assert!(turns.step(false, 1000 + 3 * barge_in));
+        assert_eq!(turns.next_turn(), 2);
+        assert!(interruptions(&events).is_empty());
+    }
+
+    #[test]
+    fn without_barge_in_the_next_utterance_interrupts_the_reply() {
+        let (mut turns, events) = turn_taking(None);
+        turns.next_turn();
+        turns.reply = Some(reply(1, Duration::from_secs(60)));
+
+        turns.step(true, 0);
+        turns.step(true, 10 * SAMPLE_RATE as usize);
+        assert!(interruptions(&events).is_empty());
+        assert!(turns.step(false, 11 * SAMPLE_RATE as usize));
+        assert_eq!(turns.next_turn(), 2);
+        assert_eq!(interruptions(&events), [1]);
+    }
+
+    #[test]
+    fn a_finished_reply_is_not_interrupted() {
+        let (mut turns, events) = turn_taking(Some(300));
+        turns.next_turn();
+        turns.reply = Some(reply(1, Duration::ZERO));
+
+        turns.step(true, 0);
+        turns.step(true, SAMPLE_RATE as usize);
+        turns.interrupt();
+        assert!(interruptions(&events).is_empty());
+        assert!(!turns.reply.as_ref().unwrap().cancellation.is_cancelled());
+    }
+}
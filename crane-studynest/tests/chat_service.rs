@@ -0,0 +1,297 @@
+//! The chat service binary, driven over stdin and stdout like the Electron app does.
+//!
+//! The chat model is a tiny Qwen2 checkpoint with random weights and a byte-level BPE
+//! tokenizer without merges, written to a temporary directory.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use candle_core::{DType, Device, Tensor};
+use serde_json::{json, Value};
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+
+const HIDDEN: usize = 64;
+const INTERMEDIATE: usize = 128;
+const HEADS: usize = 4;
+const KV_HEADS: usize = 2;
+/// Larger than the tokenizer's vocabulary, as the warmup feeds ids up to 546
+const VOCAB: usize = 640;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("studynest-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn added_token(id: usize, content: &str) -> Value {
+    json!({
+        "id": id,
+        "content": content,
+        "single_word": false,
+        "lstrip": false,
+        "rstrip": false,
+        "normalized": false,
+        "special": true
+    })
+}
+
+fn write_tiny_qwen2(dir: &Path) {
+    let mut alphabet: Vec<char> = ByteLevel::alphabet().into_iter().collect();
+    alphabet.sort();
+    let vocab: serde_json::Map<String, Value> = alphabet
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.to_string(), json!(i)))
+        .collect();
+    let (endoftext, im_start, im_end) = (vocab.len(), vocab.len() + 1, vocab.len() + 2);
+
+    let head_dim = HIDDEN / HEADS;
+    let config = json!({
+        "architectures": ["Qwen2ForCausalLM"],
+        "model_type": "qwen2",
+        "hidden_size": HIDDEN,
+        "intermediate_size": INTERMEDIATE,
+        "num_attention_heads": HEADS,
+        "num_key_value_heads": KV_HEADS,
+        "num_hidden_layers": 1,
+        "max_position_embeddings": 4096,
+        "max_window_layers": 1,
+        "sliding_window": 4096,
+        "use_sliding_window": false,
+        "vocab_size": VOCAB,
+        "rms_norm_eps": 1e-6,
+        "rope_theta": 10000.0,
+        "hidden_act": "silu",
+        "tie_word_embeddings": false,
+        "eos_token_id": im_end
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [
+            added_token(endoftext, "<|endoftext|>"),
+            added_token(im_start, "<|im_start|>"),
+            added_token(im_end, "<|im_end|>")
+        ],
+        "normalizer": null,
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": false, "use_regex": true},
+        "post_processor": null,
+        "decoder": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": false, "use_regex": true},
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "vocab": vocab,
+            "merges": []
+        }
+    });
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+    let tokenizer_config = json!({
+        "eos_token": "<|im_end|>",
+        "pad_token": "<|endoftext|>",
+        "chat_template": "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}"
+    });
+    std::fs::write(dir.join("tokenizer_config.json"), tokenizer_config.to_string()).unwrap();
+
+    let device = Device::Cpu;
+    let randn = |shape: &[usize]| {
+        Tensor::randn(0f32, 0.02, shape, &device)
+            .unwrap()
+            .to_dtype(DType::BF16)
+            .unwrap()
+    };
+    let ones = |len: usize| Tensor::ones(len, DType::BF16, &device).unwrap();
+    let kv = KV_HEADS * head_dim;
+    let mut tensors = HashMap::from([
+        ("model.embed_tokens.weight".to_string(), randn(&[VOCAB, HIDDEN])),
+        ("model.norm.weight".to_string(), ones(HIDDEN)),
+        ("lm_head.weight".to_string(), randn(&[VOCAB, HIDDEN])),
+    ]);
+    for (name, tensor) in [
+        ("self_attn.q_proj.weight", randn(&[HIDDEN, HIDDEN])),
+        ("self_attn.q_proj.bias", randn(&[HIDDEN])),
+        ("self_attn.k_proj.weight", randn(&[kv, HIDDEN])),
+        ("self_attn.k_proj.bias", randn(&[kv])),
+        ("self_attn.v_proj.weight", randn(&[kv, HIDDEN])),
+        ("self_attn.v_proj.bias", randn(&[kv])),
+        ("self_attn.o_proj.weight", randn(&[HIDDEN, HIDDEN])),
+        ("mlp.gate_proj.weight", randn(&[INTERMEDIATE, HIDDEN])),
+        ("mlp.up_proj.weight", randn(&[INTERMEDIATE, HIDDEN])),
+        ("mlp.down_proj.weight", randn(&[HIDDEN, INTERMEDIATE])),
+        ("input_layernorm.weight", ones(HIDDEN)),
+        ("post_attention_layernorm.weight", ones(HIDDEN)),
+    ] {
+        tensors.insert(format!("model.layers.0.{name}"), tensor);
+    }
+    candle_core::safetensors::save(&tensors, dir.join("model.safetensors")).unwrap();
+}
+
+fn send(stdin: &mut ChildStdin, request: Value) {
+    writeln!(stdin, "{request}").unwrap();
+}
+
+/// Read stdout lines into `seen` until the response to `id`
+fn wait_for(lines: &Receiver<String>, id: &str, seen: &mut Vec<String>) -> Value {
+    loop {
+        let line = lines.recv_timeout(Duration::from_secs(120)).unwrap();
+        seen.push(line.clone());
+        let parsed: Value = serde_json::from_str(&line).unwrap_or_else(|e| panic!("{e}: {line:?}"));
+        if parsed["id"] == id {
+            return parsed;
+        }
+    }
+}
+
+struct Service {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    reader: JoinHandle<()>,
+}
+
+/// Start the service in `dir` and load the tiny model written to `dir/tiny-qwen2`
+fn start_service(dir: &Path, seen: &mut Vec<String>) -> Service {
+    let model = dir.join("tiny-qwen2");
+    std::fs::create_dir_all(&model).unwrap();
+    write_tiny_qwen2(&model);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chat-service"))
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (lines_tx, lines) = mpsc::channel();
+    let reader = std::thread::spawn(move || {
+        for line in stdout.lines() {
+            lines_tx.send(line.unwrap()).unwrap();
+        }
+    });
+
+    send(&mut stdin, json!({"id": "init", "method": "initialize", "params": {"model_path": model}}));
+    let init = wait_for(&lines, "init", seen);
+    assert!(init.get("error").is_none(), "{init}");
+    Service { child, stdin, lines, reader }
+}
+
+/// Close stdin, wait for the service to exit and collect the lines it wrote last
+fn stop_service(service: Service, seen: &mut Vec<String>) {
+    let Service { mut child, stdin, lines, reader } = service;
+    drop(stdin);
+    let status = child.wait().unwrap();
+    reader.join().unwrap();
+    assert!(status.success());
+    seen.extend(lines.try_iter());
+}
+
+/// Send a request and wait for its result
+fn call(service: &mut Service, id: &str, method: &str, params: Value, seen: &mut Vec<String>) -> Value {
+    send(&mut service.stdin, json!({"id": id, "method": method, "params": params}));
+    let response = wait_for(&service.lines, id, seen);
+    assert!(response.get("error").is_none(), "{response}");
+    response["result"].clone()
+}
+
+#[test]
+fn concurrent_requests_only_write_json_lines() {
+    let dir = temp_dir("chat-service");
+    let mut seen = Vec::new();
+    let mut service = start_service(&dir, &mut seen);
+
+    // Both chats generate (one after the other, as generation is serialized) while the
+    // second is cancelled from a third request
+    for (id, content) in [("a", "hello there"), ("b", "tell me more")] {
+        send(&mut service.stdin, json!({
+            "id": id,
+            "method": "chat",
+            "params": {"model": "", "messages": [{"role": "user", "content": content}], "timeout_ms": 3000}
+        }));
+    }
+    send(&mut service.stdin, json!({"id": "c", "method": "cancel", "params": {"request_id": "b"}}));
+    stop_service(service, &mut seen);
+
+    let responses: Vec<Value> = seen
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line:?}")))
+        .collect();
+    for id in ["a", "b", "c"] {
+        let response = responses
+            .iter()
+            .find(|r| r["id"] == id)
+            .unwrap_or_else(|| panic!("no response to {id}: {seen:?}"));
+        assert!(response.get("error").is_none(), "{response}");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sessions_keep_and_apply_their_settings() {
+    let dir = temp_dir("session-settings");
+    let mut seen = Vec::new();
+    let mut service = start_service(&dir, &mut seen);
+
+    let created = call(
+        &mut service,
+        "create",
+        "create_session",
+        json!({"name": "Biology", "model": "tiny-qwen2", "max_tokens": 1, "temperature": 0.2}),
+        &mut seen,
+    );
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(created["model"], "tiny-qwen2");
+    assert_eq!(created["settings"]["max_tokens"], 1);
+    assert_eq!(created["settings"]["temperature"], 0.2);
+
+    // The reply stops at the session's one token, with random weights practically never
+    // at the end of sequence token first
+    let chat = |service: &mut Service, request_id: &str, extra: Value, seen: &mut Vec<String>| {
+        let mut params = json!({"model": "", "session_id": id, "messages": [{"role": "user", "content": "hi"}]});
+        params.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        call(service, request_id, "chat", params, seen)
+    };
+    let reply = chat(&mut service, "chat-1", json!({}), &mut seen);
+    assert_eq!(reply["finish_reason"], "length", "{reply}");
+
+    let updated = call(
+        &mut service,
+        "update",
+        "update_session",
+        json!({"id": id, "max_tokens": 2, "top_p": 0.5}),
+        &mut seen,
+    );
+    assert_eq!(updated["model"], "tiny-qwen2");
+    assert_eq!(updated["settings"]["max_tokens"], 2);
+    assert_eq!(updated["settings"]["temperature"], 0.2);
+    assert_eq!(updated["settings"]["top_p"], 0.5);
+
+    let unknown = json!({"id": "bad", "method": "update_session", "params": {"id": id, "model": "missing"}});
+    send(&mut service.stdin, unknown);
+    assert!(wait_for(&service.lines, "bad", &mut seen).get("error").is_some());
+
+    // A request's own max_tokens applies to that reply only
+    let reply = chat(&mut service, "chat-2", json!({"max_tokens": 1}), &mut seen);
+    assert_eq!(reply["finish_reason"], "length", "{reply}");
+
+    let session = call(&mut service, "load", "load_session", json!({"id": id}), &mut seen);
+    assert_eq!(session["settings"]["max_tokens"], 2);
+    assert_eq!(session["messages"].as_array().unwrap().len(), 4);
+
+    stop_service(service, &mut seen);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
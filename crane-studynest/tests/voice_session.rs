@@ -0,0 +1,160 @@
+//! Spoken conversations through `VoiceSession`, with the models of the voice demo.
+//!
+//! The questions are read aloud by the TTS model into WAV files, which are then fed to
+//! the session like recordings. The models are large, so the tests are ignored by
+//! default; with the checkpoints below installed, run them with
+//! `cargo test --features onnx --test voice_session -- --ignored`.
+
+#![cfg(feature = "onnx")]
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use crane_studynest::chat::{FinishReason, GenerationControl};
+use crane_studynest::prelude::*;
+
+const STT_MODEL: &str = "checkpoints/moonshine-tiny";
+const CHAT_MODEL: &str = "checkpoints/Qwen2.5-0.5B-Instruct";
+const TTS_MODEL: &str = "checkpoints/orpheus-3b-0.1-ft";
+
+/// The models, unless one of them is not installed
+fn models_present() -> bool {
+    let missing: Vec<&str> = [STT_MODEL, CHAT_MODEL, TTS_MODEL]
+        .into_iter()
+        .filter(|dir| !Path::new(dir).is_dir())
+        .collect();
+    if !missing.is_empty() {
+        eprintln!("Skipping, models not installed: {}", missing.join(", "));
+    }
+    missing.is_empty()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("studynest-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Read `text` aloud into a WAV file, followed by `silence_ms` of silence
+fn say(tts: &mut TtsEngine, text: &str, silence_ms: usize, path: &Path) -> String {
+    let mut result = tts.synthesize(text, &GenerationControl::default(), |_| {}).unwrap();
+    let silence = result.sample_rate as usize * silence_ms / 1000;
+    result.samples.extend(std::iter::repeat_n(0.0, silence));
+    tts.save_wav(&result, path).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// The engines, and the questions read aloud by the TTS model before it joins the session
+fn start(texts: &[(&str, usize)], dir: &Path) -> (VoiceSession, Receiver<VoiceEvent>, Vec<String>) {
+    let mut tts = TtsEngine::new(TtsConfig::default().with_model_path(TTS_MODEL)).unwrap();
+    let wavs = texts
+        .iter()
+        .enumerate()
+        .map(|(i, (text, silence_ms))| say(&mut tts, text, *silence_ms, &dir.join(format!("{i}.wav"))))
+        .collect();
+    let stt = SttEngine::new(SttConfig::default().with_model_path(STT_MODEL)).unwrap();
+    let chat = ChatEngine::new(ChatConfig::default().with_model_path(CHAT_MODEL).with_max_tokens(64)).unwrap();
+    let (session, events) = VoiceSession::new(stt, chat, tts, VoiceConfig::default()).unwrap();
+    (session, events, wavs)
+}
+
+fn transcript(events: &[VoiceEvent], turn: usize) -> Option<&str> {
+    events.iter().find_map(|e| match e {
+        VoiceEvent::Transcript { turn: t, text } if *t == turn => Some(text.as_str()),
+        _ => None,
+    })
+}
+
+fn reply(events: &[VoiceEvent], turn: usize) -> Option<(&str, FinishReason)> {
+    events.iter().find_map(|e| match e {
+        VoiceEvent::Reply { turn: t, text, finish_reason } if *t == turn => Some((text.as_str(), *finish_reason)),
+        _ => None,
+    })
+}
+
+/// Samples of reply audio sent for `turn`
+fn audio(events: &[VoiceEvent], turn: usize) -> usize {
+    events
+        .iter()
+        .map(|e| match e {
+            VoiceEvent::Audio { turn: t, samples, sample_rate } if *t == turn => {
+                assert!(*sample_rate > 0);
+                samples.len()
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+fn assert_answered(events: &[VoiceEvent], turn: usize, heard: &str) {
+    let text = transcript(events, turn).unwrap_or_else(|| panic!("no transcript for turn {turn}: {events:?}"));
+    assert!(text.to_lowercase().contains(heard), "turn {turn} heard {text:?}");
+    let (text, finish_reason) = reply(events, turn).unwrap_or_else(|| panic!("no reply for turn {turn}: {events:?}"));
+    assert!(!text.trim().is_empty());
+    assert!(matches!(finish_reason, FinishReason::Stop | FinishReason::Length), "{finish_reason:?}");
+    assert!(audio(events, turn) > 0, "no audio for turn {turn}");
+    assert!(events.iter().any(|e| matches!(e, VoiceEvent::Spoken { turn: t } if *t == turn)));
+}
+
+#[test]
+#[ignore = "needs the moonshine-tiny, Qwen2.5-0.5B-Instruct and orpheus-3b-0.1-ft checkpoints"]
+fn answers_a_spoken_question() {
+    if !models_present() {
+        return;
+    }
+    let dir = temp_dir("voice-answer");
+    let (mut session, events, wavs) = start(&[("What is the capital of France?", 1000)], &dir);
+
+    session.push_wav(&wavs[0]).unwrap();
+    session.finish().unwrap();
+    session.wait();
+    let events: Vec<VoiceEvent> = events.try_iter().collect();
+
+    assert!(matches!(events.first(), Some(VoiceEvent::SpeechStarted)), "{events:?}");
+    assert_answered(&events, 1, "france");
+    assert!(!events.iter().any(|e| matches!(e, VoiceEvent::Interrupted { .. } | VoiceEvent::Error { .. })));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "needs the moonshine-tiny, Qwen2.5-0.5B-Instruct and orpheus-3b-0.1-ft checkpoints"]
+fn speaking_over_a_reply_interrupts_it() {
+    if !models_present() {
+        return;
+    }
+    let dir = temp_dir("voice-barge-in");
+    let (mut session, events, wavs) = start(
+        &[
+            ("Tell me a long story about a dragon.", 1000),
+            ("Stop. What is the capital of Italy?", 0),
+        ],
+        &dir,
+    );
+
+    // The first question ends in silence, so its reply starts; the second is spoken
+    // over it, and the reply stops before the second question is over
+    session.push_wav(&wavs[0]).unwrap();
+    session.push_wav(&wavs[1]).unwrap();
+    let before_end: Vec<VoiceEvent> = events.try_iter().collect();
+    assert!(
+        before_end.iter().any(|e| matches!(e, VoiceEvent::Interrupted { turn: 1 })),
+        "{before_end:?}"
+    );
+
+    session.finish().unwrap();
+    session.wait();
+    let events: Vec<VoiceEvent> = before_end.into_iter().chain(events.try_iter()).collect();
+
+    let interrupted = events
+        .iter()
+        .position(|e| matches!(e, VoiceEvent::Interrupted { turn: 1 }))
+        .unwrap();
+    let second = events
+        .iter()
+        .position(|e| matches!(e, VoiceEvent::Transcript { turn: 2, .. }))
+        .unwrap();
+    assert!(interrupted < second);
+    assert_eq!(events.iter().filter(|e| matches!(e, VoiceEvent::Interrupted { .. })).count(), 1);
+    assert_answered(&events, 2, "italy");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -13,6 +13,8 @@ interface ChatRequest {
   messages: ChatMessage[];
   temperature?: number;
   max_tokens?: number;
+  session_id?: string;
+  timeout_ms?: number;
 }
 
 interface ChatResponse {
@@ -21,6 +23,429 @@ interface ChatResponse {
     content: string;
   };
   done: boolean;
+  finish_reason?: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneEmbeddingResponse {
+  model: string;
+  dimension: number;
+  embeddings: number[][];
+  prompt_tokens: number;
+}
+
+export interface CraneCitation {
+  marker: number;
+  document_id: string;
+  title: string;
+  source?: string;
+  page: number;
+  offset: number;
+  length: number;
+  score: number;
+  text: string;
+}
+
+export interface CraneDocumentInfo {
+  id: string;
+  title: string;
+  source?: string;
+  course?: string;
+  pages: number;
+  chunks: number;
+  embedding_model: string;
+  created_at: number;
+}
+
+export interface CraneAskRequest {
+  question: string;
+  session_id?: string;
+  document_ids?: string[];
+  course?: string;
+  after?: number;
+  before?: number;
+  top_k?: number;
+  embedding_model?: string;
+  rerank?: boolean;
+  reranker_model?: string;
+  timeout_ms?: number;
+}
+
+export interface CraneSearchRequest {
+  query: string;
+  mode?: 'hybrid' | 'keyword' | 'semantic';
+  top_k?: number;
+  document_ids?: string[];
+  course?: string;
+  after?: number;
+  before?: number;
+  embedding_model?: string;
+  rerank?: boolean;
+  reranker_model?: string;
+}
+
+export interface CraneSearchHit {
+  document_id: string;
+  title: string;
+  source?: string;
+  page: number;
+  offset: number;
+  length: number;
+  score: number;
+  keyword_rank?: number;
+  semantic_rank?: number;
+  snippet: string;
+  /** [start, end) character ranges of query terms in `snippet` */
+  highlights: Array<[number, number]>;
+}
+
+export interface CraneSearchResponse {
+  mode: 'hybrid' | 'keyword' | 'semantic';
+  hits: CraneSearchHit[];
+}
+
+export interface CraneSearchImagesRequest {
+  query: string;
+  /** Image files to search */
+  paths?: string[];
+  /** Directory whose images, including subdirectories, are searched too */
+  dir?: string;
+  top_k?: number;
+  model?: string;
+}
+
+export interface CraneImageHit {
+  path: string;
+  /** Probability that the query describes the image */
+  score: number;
+}
+
+export interface CraneSearchImagesResponse {
+  model: string;
+  hits: CraneImageHit[];
+}
+
+export interface CraneImageLabel {
+  label: string;
+  probability: number;
+}
+
+export interface CraneClassifyImageResponse {
+  model: string;
+  /** Most likely first; labels are scored independently */
+  labels: CraneImageLabel[];
+}
+
+export interface CraneSourceRef {
+  document_id?: string;
+  title?: string;
+  page?: number;
+  offset?: number;
+}
+
+export interface CraneFlashcard {
+  id: string;
+  kind: 'basic' | 'cloze';
+  /** Question, or cloze text with {{c1::...}} deletions */
+  front: string;
+  /** Answer, or extra information for a cloze card */
+  back: string;
+  tags: string[];
+  source?: CraneSourceRef;
+}
+
+export interface CraneFlashcardsRequest {
+  document_id?: string;
+  pages?: number[];
+  text?: string;
+  title?: string;
+  kinds?: Array<'basic' | 'cloze'>;
+  max_cards?: number;
+  tags?: string[];
+  timeout_ms?: number;
+}
+
+export interface CraneFlashcardSet {
+  cards: CraneFlashcard[];
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneExportFlashcardsRequest {
+  cards: CraneFlashcard[];
+  format: 'csv' | 'apkg';
+  deck?: string;
+  path?: string;
+}
+
+export interface CraneQuizQuestion {
+  id: string;
+  kind: 'multiple_choice' | 'true_false' | 'short_answer';
+  /** Question, or the statement to judge for true/false */
+  prompt: string;
+  /** Choices in display order; empty for short answers */
+  options: string[];
+  correct_option?: number;
+  /** Correct option, or the model answer of a short-answer question */
+  answer: string;
+  key_points?: string[];
+  explanation: string;
+  source?: CraneSourceRef;
+}
+
+export interface CraneQuiz {
+  id: string;
+  title?: string;
+  questions: CraneQuizQuestion[];
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneAnswerGrade {
+  question_id: string;
+  /** 0 to 1 */
+  score: number;
+  correct: boolean;
+  feedback: string;
+  missing?: string[];
+}
+
+export interface CraneQuizResult {
+  grades: CraneAnswerGrade[];
+  score: number;
+  correct: number;
+  total: number;
+}
+
+export interface CraneQuizRequest {
+  document_id?: string;
+  pages?: number[];
+  text?: string;
+  title?: string;
+  kinds?: Array<'multiple_choice' | 'true_false' | 'short_answer'>;
+  max_questions?: number;
+  /** Options per multiple-choice question, 4 by default */
+  options?: number;
+  timeout_ms?: number;
+}
+
+export interface CraneGradeQuizRequest {
+  questions: CraneQuizQuestion[];
+  /** Answers keyed by question id: option text or letter, true/false, or free text */
+  answers: Record<string, string>;
+  pass_score?: number;
+  timeout_ms?: number;
+}
+
+export interface CraneCardState {
+  phase: 'new' | 'learning' | 'review' | 'relearning';
+  /** Unix milliseconds */
+  due: number;
+  stability: number;
+  difficulty: number;
+  step: number;
+  reps: number;
+  lapses: number;
+  last_review?: number;
+}
+
+export interface CraneReviewCard {
+  card: CraneFlashcard;
+  deck: string;
+  added_at: number;
+  state: CraneCardState;
+}
+
+export interface CraneDueQueue {
+  /** Each card with when it would be due again after again, hard, good and easy */
+  cards: Array<CraneReviewCard & { next_due: [number, number, number, number] }>;
+  learning: number;
+  review: number;
+  new: number;
+}
+
+export interface CraneSchedulerParameters {
+  weights: number[];
+  desired_retention: number;
+  maximum_interval_days: number;
+  learning_steps: number[];
+  relearning_steps: number[];
+  new_per_day: number;
+}
+
+export interface CraneOptimizationReport {
+  reviews: number;
+  loss_before: number;
+  loss_after: number;
+  updated: boolean;
+  weights: number[];
+}
+
+export type CraneRating = 'again' | 'hard' | 'good' | 'easy';
+
+export interface CraneReviewSettingsRequest {
+  desired_retention?: number;
+  maximum_interval_days?: number;
+  learning_steps?: number[];
+  relearning_steps?: number[];
+  new_per_day?: number;
+}
+
+export type CraneSummaryStyle = 'bullets' | 'outline' | 'abstract' | 'key_terms';
+
+export interface CraneKeyTerm {
+  term: string;
+  definition: string;
+  source?: CraneSourceRef;
+}
+
+export interface CraneSummary {
+  style: CraneSummaryStyle;
+  text: string;
+  /** Only for the key_terms style */
+  terms?: CraneKeyTerm[];
+  chunks: number;
+  levels: number;
+  /** Steps taken from the checkpoint of an earlier, interrupted run */
+  resumed: number;
+  /** 'cancelled' or 'timeout' leave a partial summary; send the same request again to resume */
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneSummaryProgress {
+  stage: 'map' | 'reduce';
+  level: number;
+  done: number;
+  total: number;
+  resumed: boolean;
+  source?: CraneSourceRef;
+  request_id: string;
+}
+
+export interface CraneSummarizeRequest {
+  document_id?: string;
+  pages?: number[];
+  text?: string;
+  title?: string;
+  style?: CraneSummaryStyle;
+  max_tokens?: number;
+  timeout_ms?: number;
+}
+
+export interface CraneLectureNotes {
+  title: string;
+  /** The notes as Markdown; times link to the recording */
+  markdown: string;
+  topics: Array<{
+    title: string;
+    start_ms: number;
+    end_ms: number;
+    points: Array<{ text: string; at_ms: number }>;
+  }>;
+  glossary?: Array<{ term: string; definition: string; at_ms?: number }>;
+  /** The cleaned transcript */
+  transcript: Array<{ start_ms: number; end_ms: number; text: string }>;
+  duration_ms: number;
+  resumed: number;
+  /** 'cancelled' or 'timeout' leave partial notes; send the same request again to resume */
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneLectureProgress {
+  stage: 'transcribe' | 'notes' | 'glossary';
+  done: number;
+  total: number;
+  resumed: boolean;
+  request_id: string;
+}
+
+export interface CraneLectureNotesRequest {
+  /** WAV file of the recording */
+  path: string;
+  title?: string;
+  stt_model?: string;
+  glossary?: boolean;
+  /** Where times link to; a file:// URL of the recording by default */
+  audio_link?: string;
+  max_tokens?: number;
+  timeout_ms?: number;
+}
+
+export interface CraneReadAloud {
+  /** WAV file written, mono: 24 kHz from Orpheus, 16 kHz from Spark-TTS */
+  path: string;
+  duration_ms: number;
+  sample_rate: number;
+  chunks: number;
+  /** The file was already there from an earlier request */
+  cached: boolean;
+  /** 'cancelled' or 'timeout' leave the audio read so far */
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneReadAloudProgress {
+  done: number;
+  total: number;
+  request_id: string;
+}
+
+export interface CraneReadAloudRequest {
+  /** Plain text or Markdown notes */
+  text: string;
+  /** WAV file to write; a file named after the text and voice by default */
+  path?: string;
+  voice?: string;
+  /** Spark-TTS only */
+  pitch?: CraneVoiceLevel;
+  speed?: CraneVoiceLevel;
+  /** WAV recording whose voice Spark-TTS clones */
+  prompt_audio?: string;
+  /** What is said in the recording */
+  prompt_text?: string;
+  tts_model?: string;
+  timeout_ms?: number;
+}
+
+/** Audio of `readAloudStream`, sent as soon as it is decoded */
+export interface CraneAudioChunk {
+  index: number;
+  /** Base64 of mono 16-bit little-endian samples */
+  pcm: string;
+  sample_rate: number;
+  /** Nothing follows; carries no samples */
+  last: boolean;
+  request_id: string;
+}
+
+export interface CraneReadAloudStream {
+  duration_ms: number;
+  sample_rate: number;
+  /** Audio chunks sent, including the last, empty one */
+  audio_chunks: number;
+  chunks: number;
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export type CraneVoiceLevel = 'very_low' | 'low' | 'moderate' | 'high' | 'very_high';
+
+export interface CraneAskResponse extends ChatResponse {
+  citations: CraneCitation[];
+  sources: CraneCitation[];
+}
+
+export interface CraneModelInfo {
+  name: string;
+  path: string;
+  kind: 'chat' | 'vision' | 'asr' | 'tts' | 'embedding' | 'reranker' | 'unknown';
+  architecture?: string;
+  model_type?: string;
+  chat_model_type?: 'qwen25' | 'qwen3';
+  parameters?: number;
+  context_length?: number;
+  hidden_size?: number;
+  num_layers?: number;
+  vocab_size?: number;
+  torch_dtype?: string;
+  quantization?: string;
+  tokenizer_files: string[];
+  size_bytes: number;
 }
 
 export class CraneService {
@@ -32,6 +457,7 @@ export class CraneService {
     resolve: (value: any) => void;
     reject: (error: any) => void;
   }> = new Map();
+  private progressListeners: Map<number, (progress: any) => void> = new Map();
 
   constructor() {}
 
@@ -184,12 +610,128 @@ export class CraneService {
     return response;
   }
 
-  async listModels(): Promise<string[]> {
-    const response = await this.sendRequest('list_models', {});
+  async cancel(requestId: number): Promise<boolean> {
+    return this.sendRequest('cancel', { request_id: String(requestId) });
+  }
+
+  /** Id that the next request will be sent with, e.g. to cancel a chat later */
+  nextRequestId(): number {
+    return this.requestId;
+  }
+
+  async listModels(kind?: CraneModelInfo['kind']): Promise<CraneModelInfo[]> {
+    const response = await this.sendRequest('list_models', kind ? { kind } : {});
     return response;
   }
 
-  private sendRequest(method: string, params: any): Promise<any> {
+  async embed(input: string | string[], model?: string): Promise<CraneEmbeddingResponse> {
+    return this.sendRequest('embed', model ? { input, model } : { input });
+  }
+
+  async ingest(document: { path?: string; text?: string; title?: string; course?: string; embedding_model?: string }): Promise<CraneDocumentInfo> {
+    return this.sendRequest('ingest', document);
+  }
+
+  async listDocuments(): Promise<CraneDocumentInfo[]> {
+    return this.sendRequest('list_documents', {});
+  }
+
+  async deleteDocument(id: string): Promise<string> {
+    return this.sendRequest('delete_document', { id });
+  }
+
+  async ask(request: CraneAskRequest): Promise<CraneAskResponse> {
+    if (!this.isInitialized) {
+      throw new Error('Service not initialized. Call initialize() first.');
+    }
+    return this.sendRequest('ask', request);
+  }
+
+  async search(request: CraneSearchRequest): Promise<CraneSearchResponse> {
+    return this.sendRequest('search', request);
+  }
+
+  async searchImages(request: CraneSearchImagesRequest): Promise<CraneSearchImagesResponse> {
+    return this.sendRequest('search_images', request);
+  }
+
+  async classifyImage(path: string, labels: string[], model?: string): Promise<CraneClassifyImageResponse> {
+    return this.sendRequest('classify_image', { path, labels, model });
+  }
+
+  async generateFlashcards(request: CraneFlashcardsRequest): Promise<CraneFlashcardSet> {
+    return this.sendRequest('generate_flashcards', request);
+  }
+
+  /** Returns the written path, or the CSV text when no path is given */
+  async exportFlashcards(request: CraneExportFlashcardsRequest): Promise<string> {
+    return this.sendRequest('export_flashcards', request);
+  }
+
+  async summarize(
+    request: CraneSummarizeRequest,
+    onProgress?: (progress: CraneSummaryProgress) => void
+  ): Promise<CraneSummary> {
+    return this.sendRequest('summarize', request, onProgress);
+  }
+
+  async lectureNotes(
+    request: CraneLectureNotesRequest,
+    onProgress?: (progress: CraneLectureProgress) => void
+  ): Promise<CraneLectureNotes> {
+    return this.sendRequest('lecture_notes', request, onProgress);
+  }
+
+  async readAloud(
+    request: CraneReadAloudRequest,
+    onProgress?: (progress: CraneReadAloudProgress) => void
+  ): Promise<CraneReadAloud> {
+    return this.sendRequest('read_aloud', request, onProgress);
+  }
+
+  /** Read aloud without a file, receiving PCM while it is generated; `path` is ignored */
+  async readAloudStream(
+    request: CraneReadAloudRequest,
+    onAudio?: (chunk: CraneAudioChunk) => void
+  ): Promise<CraneReadAloudStream> {
+    return this.sendRequest('read_aloud_stream', request, onAudio);
+  }
+
+  async generateQuiz(request: CraneQuizRequest): Promise<CraneQuiz> {
+    return this.sendRequest('generate_quiz', request);
+  }
+
+  async gradeQuiz(request: CraneGradeQuizRequest): Promise<CraneQuizResult> {
+    return this.sendRequest('grade_quiz', request);
+  }
+
+  /** Schedule cards for review; returns how many were not scheduled yet */
+  async addCards(cards: CraneFlashcard[], deck?: string): Promise<{ added: number }> {
+    return this.sendRequest('add_cards', { cards, deck });
+  }
+
+  async removeCards(ids: string[]): Promise<{ removed: number }> {
+    return this.sendRequest('remove_cards', { ids });
+  }
+
+  async cardsDue(request: { deck?: string; limit?: number } = {}): Promise<CraneDueQueue> {
+    return this.sendRequest('cards_due', request);
+  }
+
+  async reviewCard(cardId: string, rating: CraneRating, durationMs?: number): Promise<CraneReviewCard> {
+    return this.sendRequest('review_card', { card_id: cardId, rating, duration_ms: durationMs });
+  }
+
+  /** Changes the given settings and returns them all */
+  async reviewSettings(request: CraneReviewSettingsRequest = {}): Promise<CraneSchedulerParameters> {
+    return this.sendRequest('review_settings', request);
+  }
+
+  async optimizeScheduler(): Promise<CraneOptimizationReport> {
+    return this.sendRequest('optimize_scheduler', {});
+  }
+
+  private sendRequest(method: string, params: any, onProgress?: (progress: any) => void): Promise<any> {
     return new Promise((resolve, reject) => {
       if (!this.process || !this.process.stdin) {
         reject(new Error('Service not running'));
@@ -197,9 +739,22 @@ export class CraneService {
       }
 
       const id = this.requestId++;
-      this.pendingRequests.set(id, { resolve, reject });
+      this.pendingRequests.set(id, {
+        resolve: (value) => {
+          this.progressListeners.delete(id);
+          resolve(value);
+        },
+        reject: (error) => {
+          this.progressListeners.delete(id);
+          reject(error);
+        }
+      });
+      if (onProgress) {
+        this.progressListeners.set(id, onProgress);
+      }
 
       const request = {
+        id: String(id),
         method,
         params
       };
@@ -212,16 +767,27 @@ export class CraneService {
         this.process.stdin.write(requestStr);
       } catch (error) {
         this.pendingRequests.delete(id);
+        this.progressListeners.delete(id);
         console.error('[CraneService] Failed to write request:', error);
         reject(error);
       }
 
-      // Timeout: 5 minutes for initialize, 2 minutes for chat, 30 seconds for others
-      const timeout = method === 'initialize' ? 300000 : method === 'chat' ? 120000 : 30000;
+      // Summaries, lecture notes and reading aloud report progress and bound themselves
+      // with timeout_ms, so they get no client timeout; generation gets as long as chat
+      if (['summarize', 'lecture_notes', 'read_aloud', 'read_aloud_stream'].includes(method)) {
+        return;
+      }
+      // Timeout: 5 minutes for initialize, 2 minutes for chat and generation, 30 seconds for others
+      const generating = ['chat', 'ask', 'generate_flashcards', 'generate_quiz', 'grade_quiz'].includes(method);
+      const timeout = method === 'initialize' ? 300000 : generating ? 120000 : 30000;
       setTimeout(() => {
         if (this.pendingRequests.has(id)) {
           this.pendingRequests.delete(id);
+          this.progressListeners.delete(id);
           console.error('[CraneService] Request timeout after', timeout / 1000, 'seconds for method:', method);
+          if (generating) {
+            this.cancel(id).catch(() => {});
+          }
           reject(new Error(`Request timeout after ${timeout / 1000}s`));
         }
       }, timeout);
@@ -231,14 +797,30 @@ export class CraneService {
   private handleResponse(response: any): void {
     console.log('[CraneService] Received response:', JSON.stringify(response).substring(0, 200));
     
-    // Get the oldest pending request (FIFO)
-    const entries = Array.from(this.pendingRequests.entries());
-    if (entries.length === 0) {
-      console.warn('[CraneService] Received response but no pending requests');
+    // Progress and audio notifications carry the id of their request instead of their own
+    if (response.method === 'progress' || response.method === 'audio') {
+      const listener = this.progressListeners.get(Number(response.params?.request_id));
+      if (listener) {
+        listener(response.params);
+      }
       return;
     }
 
-    const [id, { resolve, reject }] = entries[0];
+    // Match by echoed id, falling back to the oldest pending request (FIFO)
+    const echoedId = response.id !== undefined ? Number(response.id) : NaN;
+    let id: number;
+    if (this.pendingRequests.has(echoedId)) {
+      id = echoedId;
+    } else {
+      const first = this.pendingRequests.keys().next();
+      if (first.done) {
+        console.warn('[CraneService] Received response but no pending requests');
+        return;
+      }
+      id = first.value;
+    }
+
+    const { resolve, reject } = this.pendingRequests.get(id)!;
     this.pendingRequests.delete(id);
 
     if (response.error) {
@@ -313,6 +313,213 @@ function setupIpcHandlers() {
     }
   });
 
+  ipcMain.handle('crane:embed', async (_event, input: string | string[], model?: string) => {
+    try {
+      return await craneService.embed(input, model);
+    } catch (error: any) {
+      console.error('Crane embed error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:ingest', async (_event, document) => {
+    try {
+      return await craneService.ingest(document);
+    } catch (error: any) {
+      console.error('Crane ingest error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:listDocuments', async () => {
+    try {
+      return await craneService.listDocuments();
+    } catch (error: any) {
+      console.error('Failed to list crane documents:', error);
+      return [];
+    }
+  });
+
+  ipcMain.handle('crane:deleteDocument', async (_event, id: string) => {
+    try {
+      await craneService.deleteDocument(id);
+      return true;
+    } catch (error: any) {
+      console.error('Failed to delete crane document:', error);
+      return false;
+    }
+  });
+
+  ipcMain.handle('crane:ask', async (_event, request) => {
+    try {
+      return await craneService.ask(request);
+    } catch (error: any) {
+      console.error('Crane ask error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:search', async (_event, request) => {
+    try {
+      return await craneService.search(request);
+    } catch (error: any) {
+      console.error('Crane search error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:searchImages', async (_event, request) => {
+    try {
+      return await craneService.searchImages(request);
+    } catch (error: any) {
+      console.error('Crane image search error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:classifyImage', async (_event, path, labels, model) => {
+    try {
+      return await craneService.classifyImage(path, labels, model);
+    } catch (error: any) {
+      console.error('Crane image classification error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:generateFlashcards', async (_event, request) => {
+    try {
+      return await craneService.generateFlashcards(request);
+    } catch (error: any) {
+      console.error('Crane flashcard generation error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:exportFlashcards', async (_event, request) => {
+    try {
+      return await craneService.exportFlashcards(request);
+    } catch (error: any) {
+      console.error('Crane flashcard export error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:summarize', async (event, request) => {
+    try {
+      return await craneService.summarize(request, (progress) => {
+        event.sender.send('crane:summaryProgress', progress);
+      });
+    } catch (error: any) {
+      console.error('Crane summarization error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:lectureNotes', async (event, request) => {
+    try {
+      return await craneService.lectureNotes(request, (progress) => {
+        event.sender.send('crane:lectureProgress', progress);
+      });
+    } catch (error: any) {
+      console.error('Crane lecture notes error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:readAloud', async (event, request) => {
+    try {
+      return await craneService.readAloud(request, (progress) => {
+        event.sender.send('crane:readAloudProgress', progress);
+      });
+    } catch (error: any) {
+      console.error('Crane read aloud error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:readAloudStream', async (event, request) => {
+    try {
+      return await craneService.readAloudStream(request, (chunk) => {
+        event.sender.send('crane:readAloudAudio', chunk);
+      });
+    } catch (error: any) {
+      console.error('Crane read aloud stream error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:generateQuiz', async (_event, request) => {
+    try {
+      return await craneService.generateQuiz(request);
+    } catch (error: any) {
+      console.error('Crane quiz generation error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:gradeQuiz', async (_event, request) => {
+    try {
+      return await craneService.gradeQuiz(request);
+    } catch (error: any) {
+      console.error('Crane quiz grading error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:addCards', async (_event, cards, deck) => {
+    try {
+      return await craneService.addCards(cards, deck);
+    } catch (error: any) {
+      console.error('Crane add cards error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:removeCards', async (_event, ids) => {
+    try {
+      return await craneService.removeCards(ids);
+    } catch (error: any) {
+      console.error('Crane remove cards error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:cardsDue', async (_event, request) => {
+    try {
+      return await craneService.cardsDue(request);
+    } catch (error: any) {
+      console.error('Crane due cards error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:reviewCard', async (_event, cardId, rating, durationMs) => {
+    try {
+      return await craneService.reviewCard(cardId, rating, durationMs);
+    } catch (error: any) {
+      console.error('Crane review error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:reviewSettings', async (_event, request) => {
+    try {
+      return await craneService.reviewSettings(request);
+    } catch (error: any) {
+      console.error('Crane review settings error:', error);
+      throw error;
+    }
+  });
+
+  ipcMain.handle('crane:optimizeScheduler', async () => {
+    try {
+      return await craneService.optimizeScheduler();
+    } catch (error: any) {
+      console.error('Crane scheduler optimization error:', error);
+      throw error;
+    }
+  });
+
   ipcMain.handle('crane:isRunning', async () => {
     return craneService.isRunning();
   });
@@ -30,6 +30,54 @@ contextBridge.exposeInMainWorld('electron', {
     initialize: (modelPath: string) => ipcRenderer.invoke('crane:initialize', modelPath),
     chat: (payload: any) => ipcRenderer.invoke('crane:chat', payload),
     listModels: () => ipcRenderer.invoke('crane:listModels'),
+    embed: (input: string | string[], model?: string) => ipcRenderer.invoke('crane:embed', input, model),
+    ingest: (document: any) => ipcRenderer.invoke('crane:ingest', document),
+    listDocuments: () => ipcRenderer.invoke('crane:listDocuments'),
+    deleteDocument: (id: string) => ipcRenderer.invoke('crane:deleteDocument', id),
+    ask: (request: any) => ipcRenderer.invoke('crane:ask', request),
+    search: (request: any) => ipcRenderer.invoke('crane:search', request),
+    searchImages: (request: any) => ipcRenderer.invoke('crane:searchImages', request),
+    classifyImage: (path: string, labels: string[], model?: string) =>
+      ipcRenderer.invoke('crane:classifyImage', path, labels, model),
+    generateFlashcards: (request: any) => ipcRenderer.invoke('crane:generateFlashcards', request),
+    exportFlashcards: (request: any) => ipcRenderer.invoke('crane:exportFlashcards', request),
+    summarize: (request: any) => ipcRenderer.invoke('crane:summarize', request),
+    onSummaryProgress: (callback: (progress: any) => void) => {
+      ipcRenderer.on('crane:summaryProgress', (_event, progress) => callback(progress));
+    },
+    removeSummaryProgressListener: () => {
+      ipcRenderer.removeAllListeners('crane:summaryProgress');
+    },
+    lectureNotes: (request: any) => ipcRenderer.invoke('crane:lectureNotes', request),
+    onLectureProgress: (callback: (progress: any) => void) => {
+      ipcRenderer.on('crane:lectureProgress', (_event, progress) => callback(progress));
+    },
+    removeLectureProgressListener: () => {
+      ipcRenderer.removeAllListeners('crane:lectureProgress');
+    },
+    readAloud: (request: any) => ipcRenderer.invoke('crane:readAloud', request),
+    onReadAloudProgress: (callback: (progress: any) => void) => {
+      ipcRenderer.on('crane:readAloudProgress', (_event, progress) => callback(progress));
+    },
+    removeReadAloudProgressListener: () => {
+      ipcRenderer.removeAllListeners('crane:readAloudProgress');
+    },
+    readAloudStream: (request: any) => ipcRenderer.invoke('crane:readAloudStream', request),
+    onReadAloudAudio: (callback: (chunk: any) => void) => {
+      ipcRenderer.on('crane:readAloudAudio', (_event, chunk) => callback(chunk));
+    },
+    removeReadAloudAudioListener: () => {
+      ipcRenderer.removeAllListeners('crane:readAloudAudio');
+    },
+    generateQuiz: (request: any) => ipcRenderer.invoke('crane:generateQuiz', request),
+    gradeQuiz: (request: any) => ipcRenderer.invoke('crane:gradeQuiz', request),
+    addCards: (cards: any[], deck?: string) => ipcRenderer.invoke('crane:addCards', cards, deck),
+    removeCards: (ids: string[]) => ipcRenderer.invoke('crane:removeCards', ids),
+    cardsDue: (request?: any) => ipcRenderer.invoke('crane:cardsDue', request),
+    reviewCard: (cardId: string, rating: string, durationMs?: number) =>
+      ipcRenderer.invoke('crane:reviewCard', cardId, rating, durationMs),
+    reviewSettings: (request?: any) => ipcRenderer.invoke('crane:reviewSettings', request),
+    optimizeScheduler: () => ipcRenderer.invoke('crane:optimizeScheduler'),
     isRunning: () => ipcRenderer.invoke('crane:isRunning'),
     listAvailableModels: () => ipcRenderer.invoke('crane:listAvailableModels'),
     getModelInfo: (modelId: string) => ipcRenderer.invoke('crane:getModelInfo', modelId),
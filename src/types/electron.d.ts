@@ -19,6 +19,229 @@ export interface DBMessage {
   timestamp: number;
 }
 
+export interface CraneCitation {
+  marker: number;
+  document_id: string;
+  title: string;
+  source?: string;
+  page: number;
+  offset: number;
+  length: number;
+  score: number;
+  text: string;
+}
+
+export interface CraneDocumentInfo {
+  id: string;
+  title: string;
+  source?: string;
+  course?: string;
+  pages: number;
+  chunks: number;
+  embedding_model: string;
+  created_at: number;
+}
+
+export interface CraneSearchHit {
+  document_id: string;
+  title: string;
+  source?: string;
+  page: number;
+  offset: number;
+  length: number;
+  score: number;
+  keyword_rank?: number;
+  semantic_rank?: number;
+  snippet: string;
+  highlights: Array<[number, number]>;
+}
+
+export interface CraneSourceRef {
+  document_id?: string;
+  title?: string;
+  page?: number;
+  offset?: number;
+}
+
+export interface CraneFlashcard {
+  id: string;
+  kind: 'basic' | 'cloze';
+  /** Question, or cloze text with {{c1::...}} deletions */
+  front: string;
+  /** Answer, or extra information for a cloze card */
+  back: string;
+  tags: string[];
+  source?: CraneSourceRef;
+}
+
+export interface CraneQuizQuestion {
+  id: string;
+  kind: 'multiple_choice' | 'true_false' | 'short_answer';
+  /** Question, or the statement to judge for true/false */
+  prompt: string;
+  /** Choices in display order; empty for short answers */
+  options: string[];
+  correct_option?: number;
+  /** Correct option, or the model answer of a short-answer question */
+  answer: string;
+  key_points?: string[];
+  explanation: string;
+  source?: CraneSourceRef;
+}
+
+export interface CraneQuiz {
+  id: string;
+  title?: string;
+  questions: CraneQuizQuestion[];
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneAnswerGrade {
+  question_id: string;
+  /** 0 to 1 */
+  score: number;
+  correct: boolean;
+  feedback: string;
+  missing?: string[];
+}
+
+export interface CraneQuizResult {
+  grades: CraneAnswerGrade[];
+  score: number;
+  correct: number;
+  total: number;
+}
+
+export type CraneSummaryStyle = 'bullets' | 'outline' | 'abstract' | 'key_terms';
+
+export interface CraneKeyTerm {
+  term: string;
+  definition: string;
+  source?: CraneSourceRef;
+}
+
+export interface CraneSummary {
+  style: CraneSummaryStyle;
+  text: string;
+  terms?: CraneKeyTerm[];
+  chunks: number;
+  levels: number;
+  resumed: number;
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneSummaryProgress {
+  stage: 'map' | 'reduce';
+  level: number;
+  done: number;
+  total: number;
+  resumed: boolean;
+  source?: CraneSourceRef;
+  request_id: string;
+}
+
+export interface CraneLectureNotes {
+  title: string;
+  markdown: string;
+  topics: Array<{
+    title: string;
+    start_ms: number;
+    end_ms: number;
+    points: Array<{ text: string; at_ms: number }>;
+  }>;
+  glossary?: Array<{ term: string; definition: string; at_ms?: number }>;
+  transcript: Array<{ start_ms: number; end_ms: number; text: string }>;
+  duration_ms: number;
+  resumed: number;
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneLectureProgress {
+  stage: 'transcribe' | 'notes' | 'glossary';
+  done: number;
+  total: number;
+  resumed: boolean;
+  request_id: string;
+}
+
+export interface CraneReadAloud {
+  path: string;
+  duration_ms: number;
+  sample_rate: number;
+  chunks: number;
+  cached: boolean;
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneReadAloudProgress {
+  done: number;
+  total: number;
+  request_id: string;
+}
+
+export interface CraneAudioChunk {
+  index: number;
+  /** Base64 of mono 16-bit little-endian samples */
+  pcm: string;
+  sample_rate: number;
+  last: boolean;
+  request_id: string;
+}
+
+export interface CraneReadAloudStream {
+  duration_ms: number;
+  sample_rate: number;
+  audio_chunks: number;
+  chunks: number;
+  finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout';
+}
+
+export interface CraneCardState {
+  phase: 'new' | 'learning' | 'review' | 'relearning';
+  /** Unix milliseconds */
+  due: number;
+  stability: number;
+  difficulty: number;
+  step: number;
+  reps: number;
+  lapses: number;
+  last_review?: number;
+}
+
+export interface CraneReviewCard {
+  card: CraneFlashcard;
+  deck: string;
+  added_at: number;
+  state: CraneCardState;
+}
+
+export interface CraneDueQueue {
+  /** Each card with when it would be due again after again, hard, good and easy */
+  cards: Array<CraneReviewCard & { next_due: [number, number, number, number] }>;
+  learning: number;
+  review: number;
+  new: number;
+}
+
+export interface CraneSchedulerParameters {
+  weights: number[];
+  desired_retention: number;
+  maximum_interval_days: number;
+  learning_steps: number[];
+  relearning_steps: number[];
+  new_per_day: number;
+}
+
+export interface CraneOptimizationReport {
+  reviews: number;
+  loss_before: number;
+  loss_after: number;
+  updated: boolean;
+  weights: number[];
+}
+
+export type CraneRating = 'again' | 'hard' | 'good' | 'easy';
+
 export interface ElectronAPI {
   platform: string;
   versions: {
@@ -44,7 +267,157 @@ export interface ElectronAPI {
     start: () => Promise<{ success: boolean; error?: string }>;
     initialize: (modelPath: string) => Promise<{ success: boolean; error?: string }>;
     chat: (payload: any) => Promise<any>;
-    listModels: () => Promise<string[]>;
+    listModels: () => Promise<Array<{
+      name: string;
+      path: string;
+      kind: 'chat' | 'vision' | 'asr' | 'tts' | 'embedding' | 'reranker' | 'unknown';
+      parameters?: number;
+      context_length?: number;
+      size_bytes: number;
+    }>>;
+    embed: (input: string | string[], model?: string) => Promise<{
+      model: string;
+      dimension: number;
+      embeddings: number[][];
+      prompt_tokens: number;
+    }>;
+    ingest: (document: { path?: string; text?: string; title?: string; course?: string; embedding_model?: string }) => Promise<CraneDocumentInfo>;
+    listDocuments: () => Promise<CraneDocumentInfo[]>;
+    deleteDocument: (id: string) => Promise<boolean>;
+    ask: (request: {
+      question: string;
+      session_id?: string;
+      document_ids?: string[];
+      course?: string;
+      after?: number;
+      before?: number;
+      top_k?: number;
+      embedding_model?: string;
+      rerank?: boolean;
+      reranker_model?: string;
+      timeout_ms?: number;
+    }) => Promise<{
+      message: { role: string; content: string };
+      done: boolean;
+      finish_reason?: 'stop' | 'length' | 'cancelled' | 'timeout';
+      citations: CraneCitation[];
+      sources: CraneCitation[];
+    }>;
+    search: (request: {
+      query: string;
+      mode?: 'hybrid' | 'keyword' | 'semantic';
+      top_k?: number;
+      document_ids?: string[];
+      course?: string;
+      after?: number;
+      before?: number;
+      embedding_model?: string;
+      rerank?: boolean;
+      reranker_model?: string;
+    }) => Promise<{ mode: 'hybrid' | 'keyword' | 'semantic'; hits: CraneSearchHit[] }>;
+    searchImages: (request: {
+      query: string;
+      paths?: string[];
+      dir?: string;
+      top_k?: number;
+      model?: string;
+    }) => Promise<{ model: string; hits: Array<{ path: string; score: number }> }>;
+    classifyImage: (
+      path: string,
+      labels: string[],
+      model?: string
+    ) => Promise<{ model: string; labels: Array<{ label: string; probability: number }> }>;
+    generateFlashcards: (request: {
+      document_id?: string;
+      pages?: number[];
+      text?: string;
+      title?: string;
+      kinds?: Array<'basic' | 'cloze'>;
+      max_cards?: number;
+      tags?: string[];
+      timeout_ms?: number;
+    }) => Promise<{ cards: CraneFlashcard[]; finish_reason: 'stop' | 'length' | 'cancelled' | 'timeout' }>;
+    exportFlashcards: (request: {
+      cards: CraneFlashcard[];
+      format: 'csv' | 'apkg';
+      deck?: string;
+      path?: string;
+    }) => Promise<string>;
+    summarize: (request: {
+      document_id?: string;
+      pages?: number[];
+      text?: string;
+      title?: string;
+      style?: CraneSummaryStyle;
+      max_tokens?: number;
+      timeout_ms?: number;
+    }) => Promise<CraneSummary>;
+    onSummaryProgress: (callback: (progress: CraneSummaryProgress) => void) => void;
+    removeSummaryProgressListener: () => void;
+    lectureNotes: (request: {
+      path: string;
+      title?: string;
+      stt_model?: string;
+      glossary?: boolean;
+      audio_link?: string;
+      max_tokens?: number;
+      timeout_ms?: number;
+    }) => Promise<CraneLectureNotes>;
+    onLectureProgress: (callback: (progress: CraneLectureProgress) => void) => void;
+    removeLectureProgressListener: () => void;
+    readAloud: (request: {
+      text: string;
+      path?: string;
+      voice?: string;
+      pitch?: 'very_low' | 'low' | 'moderate' | 'high' | 'very_high';
+      speed?: 'very_low' | 'low' | 'moderate' | 'high' | 'very_high';
+      prompt_audio?: string;
+      prompt_text?: string;
+      tts_model?: string;
+      timeout_ms?: number;
+    }) => Promise<CraneReadAloud>;
+    onReadAloudProgress: (callback: (progress: CraneReadAloudProgress) => void) => void;
+    removeReadAloudProgressListener: () => void;
+    readAloudStream: (request: {
+      text: string;
+      voice?: string;
+      pitch?: 'very_low' | 'low' | 'moderate' | 'high' | 'very_high';
+      speed?: 'very_low' | 'low' | 'moderate' | 'high' | 'very_high';
+      prompt_audio?: string;
+      prompt_text?: string;
+      tts_model?: string;
+      timeout_ms?: number;
+    }) => Promise<CraneReadAloudStream>;
+    onReadAloudAudio: (callback: (chunk: CraneAudioChunk) => void) => void;
+    removeReadAloudAudioListener: () => void;
+    generateQuiz: (request: {
+      document_id?: string;
+      pages?: number[];
+      text?: string;
+      title?: string;
+      kinds?: Array<'multiple_choice' | 'true_false' | 'short_answer'>;
+      max_questions?: number;
+      options?: number;
+      timeout_ms?: number;
+    }) => Promise<CraneQuiz>;
+    gradeQuiz: (request: {
+      questions: CraneQuizQuestion[];
+      answers: Record<string, string>;
+      pass_score?: number;
+      timeout_ms?: number;
+    }) => Promise<CraneQuizResult>;
+    addCards: (cards: CraneFlashcard[], deck?: string) => Promise<{ added: number }>;
+    removeCards: (ids: string[]) => Promise<{ removed: number }>;
+    cardsDue: (request?: { deck?: string; limit?: number }) => Promise<CraneDueQueue>;
+    reviewCard: (cardId: string, rating: CraneRating, durationMs?: number) => Promise<CraneReviewCard>;
+    reviewSettings: (request?: {
+      desired_retention?: number;
+      maximum_interval_days?: number;
+      learning_steps?: number[];
+      relearning_steps?: number[];
+      new_per_day?: number;
+    }) => Promise<CraneSchedulerParameters>;
+    optimizeScheduler: () => Promise<CraneOptimizationReport>;
     isRunning: () => Promise<boolean>;
     listAvailableModels: () => Promise<Array<{
       id: string;